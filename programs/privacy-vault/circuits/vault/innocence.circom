@@ -72,6 +72,7 @@ template ProofOfInnocence(levels) {
     signal input nullifierHash;
 
     // Association set identifier (which set are we proving membership in)
+    // A u16 on chain, taken as a whole field element without a range check
     signal input associationSetId;
 
     // Timestamp or block number (proves the set was valid at this time)
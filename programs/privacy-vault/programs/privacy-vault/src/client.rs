@@ -0,0 +1,1174 @@
+//! Off-chain helpers for building privacy vault transactions.
+//! Enabled with the `client` feature; proof generation also needs the `prover` feature.
+
+use aes_gcm_siv::aead::{Aead, KeyInit, Payload};
+use aes_gcm_siv::{Aes256GcmSiv, Key, Nonce};
+use anchor_lang::prelude::{AccountMeta, ProgramError, Pubkey};
+use anchor_lang::solana_program::instruction::InstructionError;
+use borsh::BorshDeserialize;
+use groth16_solana::errors::Groth16Error;
+use light_client::indexer::{AddressWithTree, CompressedAccount, Indexer, IndexerError, TreeInfo};
+use light_client::rpc::{Rpc, RpcError};
+use light_compressed_account::{
+    compressed_account::CompressedAccountData, CompressedAccountError, TreeType,
+};
+use light_hasher::{DataHasher, Hasher, HasherError, Poseidon, Sha256};
+use light_sdk::{
+    address::v2::derive_address,
+    instruction::{PackedAccounts, PackedTreeInfos, SystemAccountMetaConfig, ValidityProof},
+    LightDiscriminator,
+};
+use std::collections::HashMap;
+#[cfg(feature = "prover")]
+use {
+    circom_prover::{prover::ProofLib, witness::WitnessFn, CircomProver},
+    groth16_solana::groth16::Groth16Verifier,
+    groth16_solana::proof_parser::circom_prover::{
+        convert_proof, convert_proof_to_compressed, convert_public_inputs,
+    },
+    light_sdk::instruction::CompressedProof,
+    num_bigint::BigUint,
+};
+#[cfg(feature = "vk-json")]
+use {
+    groth16_solana::groth16::Groth16Verifyingkey, num_bigint::BigUint, serde_json::Value,
+    std::path::Path,
+};
+
+use crate::note::{compute_commitment, compute_nullifier_hash, compute_view_tag, Note};
+use crate::{
+    derive_deposit_address, derive_nullifier_address, derive_token_deposit_address, root_index_for,
+    DepositAccount, DepositReceipt, ErrorCode, Hex, TokenDepositAccount, INNOCENCE_BITMAP,
+    INNOCENCE_PROOF, NULLIFIER_DOMAIN, STATE_TREE_HEIGHT,
+};
+
+/// Merkle inclusion data for a compressed account, ready to feed a circuit
+/// and the `input_root_index` instruction argument.
+#[derive(Clone, Debug)]
+pub struct MerkleInputs {
+    pub leaf_index: u32,
+    pub path_elements: Vec<[u8; 32]>,
+    pub root: [u8; 32],
+    /// Index of `root` in the tree's on-chain root history
+    pub root_index: u16,
+    pub merkle_tree: Pubkey,
+}
+
+/// Fetches the Merkle proof for `account_hash` from the indexer.
+pub async fn fetch_merkle_inputs<R>(
+    rpc: &mut R,
+    account_hash: [u8; 32],
+) -> Result<MerkleInputs, IndexerError>
+where
+    R: Indexer,
+{
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![account_hash], None)
+        .await?
+        .value
+        .items
+        .into_iter()
+        .next()
+        .ok_or_else(|| IndexerError::CustomError("No merkle proof for account".to_string()))?;
+
+    Ok(MerkleInputs {
+        leaf_index: merkle_proof.leaf_index as u32,
+        path_elements: merkle_proof.proof,
+        root: merkle_proof.root,
+        root_index: root_index_for(merkle_proof.root_seq),
+        merkle_tree: merkle_proof.merkle_tree,
+    })
+}
+
+/// Reasons `DepositTree::proof_for_commitment` fails.
+#[derive(Debug)]
+pub enum DepositTreeError {
+    Indexer(IndexerError),
+    Hasher(HasherError),
+    /// The deposit's leaf hash could not be computed
+    CompressedAccount(CompressedAccountError),
+    /// No `DepositAccount` in the tree holds the commitment
+    UnknownCommitment,
+    /// The indexer's account hash is not the leaf the deposit's fields hash to
+    LeafMismatch,
+    /// The path is not `STATE_TREE_HEIGHT` long or does not lead from the leaf to the root
+    InvalidPath,
+}
+
+impl From<IndexerError> for DepositTreeError {
+    fn from(error: IndexerError) -> Self {
+        Self::Indexer(error)
+    }
+}
+
+impl From<HasherError> for DepositTreeError {
+    fn from(error: HasherError) -> Self {
+        Self::Hasher(error)
+    }
+}
+
+impl From<CompressedAccountError> for DepositTreeError {
+    fn from(error: CompressedAccountError) -> Self {
+        Self::CompressedAccount(error)
+    }
+}
+
+/// The SOL deposits of one state tree, for building withdraw membership paths.
+///
+/// `proof_for_commitment` fetches a deposit's path from the indexer and checks it before
+/// returning it. It recomputes the leaf from the deposit's fields as the compressed account
+/// hash the tree stores, then hashes that leaf up the path to the indexer's root. A path that
+/// would not verify against the on-chain root fails here, with the reason, instead of after
+/// proving.
+#[derive(Clone, Debug)]
+pub struct DepositTree {
+    pub merkle_tree: Pubkey,
+    deposits: HashMap<[u8; 32], DepositCandidate>,
+}
+
+impl DepositTree {
+    /// Loads every `DepositAccount` the indexer holds in `merkle_tree`.
+    pub async fn fetch<R>(rpc: &mut R, merkle_tree: Pubkey) -> Result<Self, IndexerError>
+    where
+        R: Indexer,
+    {
+        let deposits = fetch_deposits(rpc)
+            .await?
+            .into_iter()
+            .filter(|candidate| candidate.account.tree_info.tree == merkle_tree)
+            .map(|candidate| (candidate.deposit.commitment.value, candidate))
+            .collect();
+        Ok(Self {
+            merkle_tree,
+            deposits,
+        })
+    }
+
+    /// The checked membership path of the deposit holding `commitment`.
+    pub async fn proof_for_commitment<R>(
+        &self,
+        rpc: &mut R,
+        commitment: &[u8; 32],
+    ) -> Result<MerkleInputs, DepositTreeError>
+    where
+        R: Indexer,
+    {
+        let candidate = self
+            .deposits
+            .get(commitment)
+            .ok_or(DepositTreeError::UnknownCommitment)?;
+        let leaf = deposit_leaf_hash(candidate)?;
+        if leaf != candidate.account.hash {
+            return Err(DepositTreeError::LeafMismatch);
+        }
+
+        let merkle_inputs = fetch_merkle_inputs(rpc, leaf).await?;
+        if merkle_inputs.path_elements.len() != STATE_TREE_HEIGHT {
+            return Err(DepositTreeError::InvalidPath);
+        }
+        let root = merkle_root_from_path(
+            &leaf,
+            merkle_inputs.leaf_index,
+            &merkle_inputs.path_elements,
+        )?;
+        if root != merkle_inputs.root {
+            return Err(DepositTreeError::InvalidPath);
+        }
+
+        Ok(merkle_inputs)
+    }
+}
+
+/// The state tree leaf of a deposit: its compressed account hash, with the data hash
+/// recomputed from the `DepositAccount` fields rather than taken from the indexer.
+fn deposit_leaf_hash(candidate: &DepositCandidate) -> Result<[u8; 32], DepositTreeError> {
+    let account = &candidate.account;
+    let compressed_account = light_compressed_account::compressed_account::CompressedAccount {
+        owner: account.owner.to_bytes().into(),
+        lamports: account.lamports,
+        address: account.address,
+        data: Some(CompressedAccountData {
+            discriminator: DepositAccount::LIGHT_DISCRIMINATOR,
+            data: Vec::new(),
+            data_hash: candidate.deposit.hash::<Poseidon>()?,
+        }),
+    };
+    Ok(compressed_account.hash(
+        &account.tree_info.tree.to_bytes().into(),
+        &account.leaf_index,
+        false,
+    )?)
+}
+
+/// Hashes `leaf` up `path_elements`, taking the side at each level from the bits of
+/// `leaf_index`, as the circuits' Merkle checks do.
+pub fn merkle_root_from_path(
+    leaf: &[u8; 32],
+    leaf_index: u32,
+    path_elements: &[[u8; 32]],
+) -> Result<[u8; 32], HasherError> {
+    let mut node = *leaf;
+    for (level, sibling) in path_elements.iter().enumerate() {
+        node = if (leaf_index >> level) & 1 == 0 {
+            Poseidon::hashv(&[node.as_slice(), sibling.as_slice()])?
+        } else {
+            Poseidon::hashv(&[sibling.as_slice(), node.as_slice()])?
+        };
+    }
+    Ok(node)
+}
+
+/// Validity proof and packed remaining accounts for one instruction that CPIs into Light.
+#[derive(Clone, Debug)]
+pub struct LightContext {
+    /// Light system accounts followed by the packed trees, appended after the Anchor accounts
+    pub remaining_accounts: Vec<AccountMeta>,
+    pub system_accounts_offset: u8,
+    pub proof: ValidityProof,
+    /// Packed trees of `input_hashes` and `new_addresses`, in the order they were given
+    pub packed_tree_infos: PackedTreeInfos,
+    /// Packed index of the state tree new compressed accounts are written to
+    pub output_state_tree_index: u8,
+}
+
+/// Which state tree `build_light_context_with` writes new compressed accounts to.
+///
+/// Every deposit in one tree shares that tree's leaves as its anonymity set, so the choice
+/// trades anonymity against capacity:
+/// - `Random` spreads deposits over all v1 state trees, fragmenting the pool's anonymity set
+///   across them, but never fills one tree faster than the others.
+/// - `Fixed` concentrates the pool's deposits in one tree, so each withdrawal hides among all
+///   of them, until that tree is full and the integrator has to move on to another.
+/// - `LeastFull` picks the v1 state tree holding the fewest of `program_id`'s compressed
+///   accounts. It spreads the pool's own load for capacity; leaves written by other programs
+///   are not counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputTreeStrategy {
+    #[default]
+    Random,
+    Fixed(Pubkey),
+    LeastFull,
+}
+
+/// Fetches a validity proof and packs everything a Light CPI instruction of `program_id`
+/// needs into its remaining accounts.
+///
+/// `input_hashes` are the compressed accounts the instruction reads or updates and
+/// `new_addresses` the addresses it creates. The output tree is a random v1 state tree,
+/// the kind `read_spendable_root` reads deposit roots from; use `build_light_context_with`
+/// to choose it.
+pub async fn build_light_context<R>(
+    rpc: &mut R,
+    program_id: &Pubkey,
+    input_hashes: Vec<[u8; 32]>,
+    new_addresses: Vec<AddressWithTree>,
+) -> Result<LightContext, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    build_light_context_with(
+        rpc,
+        program_id,
+        input_hashes,
+        new_addresses,
+        OutputTreeStrategy::default(),
+    )
+    .await
+}
+
+/// `build_light_context` with the output state tree picked by `output_tree`.
+pub async fn build_light_context_with<R>(
+    rpc: &mut R,
+    program_id: &Pubkey,
+    input_hashes: Vec<[u8; 32]>,
+    new_addresses: Vec<AddressWithTree>,
+    output_tree: OutputTreeStrategy,
+) -> Result<LightContext, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut packed_accounts = PackedAccounts::default();
+    packed_accounts.add_system_accounts_v2(SystemAccountMetaConfig::new(*program_id))?;
+
+    let rpc_result = rpc
+        .get_validity_proof(input_hashes, new_addresses, None)
+        .await?
+        .value;
+    let packed_tree_infos = rpc_result.pack_tree_infos(&mut packed_accounts);
+    let output_state_tree_index = select_output_tree(rpc, program_id, output_tree)
+        .await?
+        .pack_output_tree_index(&mut packed_accounts)?;
+
+    let (remaining_accounts, system_accounts_offset, _) = packed_accounts.to_account_metas();
+
+    Ok(LightContext {
+        remaining_accounts,
+        system_accounts_offset: system_accounts_offset as u8,
+        proof: rpc_result.proof,
+        packed_tree_infos,
+        output_state_tree_index,
+    })
+}
+
+async fn select_output_tree<R>(
+    rpc: &mut R,
+    program_id: &Pubkey,
+    output_tree: OutputTreeStrategy,
+) -> Result<TreeInfo, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    match output_tree {
+        OutputTreeStrategy::Random => rpc.get_random_state_tree_info_v1(),
+        OutputTreeStrategy::Fixed(tree) => rpc
+            .get_state_tree_infos()
+            .into_iter()
+            .find(|info| info.tree == tree)
+            .ok_or_else(|| RpcError::CustomError(format!("State tree {} is not registered", tree))),
+        OutputTreeStrategy::LeastFull => {
+            let accounts = rpc
+                .get_compressed_accounts_by_owner(program_id, None, None)
+                .await?
+                .value
+                .items;
+            let mut counts = HashMap::new();
+            for account in &accounts {
+                *counts.entry(account.tree_info.tree).or_insert(0usize) += 1;
+            }
+            rpc.get_state_tree_infos()
+                .into_iter()
+                .filter(|info| info.tree_type == TreeType::StateV1)
+                .min_by_key(|info| counts.get(&info.tree).copied().unwrap_or(0))
+                .ok_or_else(|| RpcError::CustomError("No v1 state tree is registered".to_string()))
+        }
+    }
+}
+
+/// A compressed address an instruction would create is already taken.
+///
+/// The Light system program rejects a CPI that creates an existing address, and a failed CPI
+/// aborts the whole transaction, so the program never sees the error and can't map it to one
+/// of its own. Clients check the address before sending to tell the user which case they hit.
+#[derive(Debug)]
+pub enum NewAddressError {
+    Indexer(IndexerError),
+    /// A deposit record for this commitment and asset already exists
+    CommitmentAlreadyExists,
+    /// The note's nullifier address exists, so the note was already spent
+    NullifierAlreadyUsed,
+}
+
+impl From<IndexerError> for NewAddressError {
+    fn from(error: IndexerError) -> Self {
+        Self::Indexer(error)
+    }
+}
+
+/// Fails with `CommitmentAlreadyExists` if `deposit_address` already holds a deposit.
+///
+/// `deposit_address` is the `[DEPOSIT, commitment, ...]` address the deposit instruction
+/// derives, which for token and native deposits includes the mint or `NATIVE_DEPOSIT`.
+pub async fn check_deposit_address<R>(
+    rpc: &mut R,
+    deposit_address: [u8; 32],
+) -> Result<(), NewAddressError>
+where
+    R: Indexer,
+{
+    if address_exists(rpc, deposit_address).await? {
+        return Err(NewAddressError::CommitmentAlreadyExists);
+    }
+    Ok(())
+}
+
+/// Fails with `NullifierAlreadyUsed` if the note behind `nullifier_hash` was already spent.
+///
+/// Checks the address the withdraw paths create, under `NULLIFIER_DOMAIN` in
+/// `ADDRESS_TREE_V2`.
+pub async fn check_nullifier_unspent<R>(
+    rpc: &mut R,
+    nullifier_hash: &[u8; 32],
+) -> Result<(), NewAddressError>
+where
+    R: Indexer,
+{
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let (address, _) = derive_nullifier_address(nullifier_hash, &NULLIFIER_DOMAIN, &address_tree);
+    if address_exists(rpc, address).await? {
+        return Err(NewAddressError::NullifierAlreadyUsed);
+    }
+    Ok(())
+}
+
+async fn address_exists<R>(rpc: &mut R, address: [u8; 32]) -> Result<bool, IndexerError>
+where
+    R: Indexer,
+{
+    Ok(rpc
+        .get_compressed_account(address, None)
+        .await?
+        .value
+        .is_some())
+}
+
+/// One error type for privacy vault clients to match on, instead of raw custom codes.
+///
+/// Failed transactions surface as `InstructionError::Custom(code)` or
+/// `ProgramError::Custom(code)`; the `From` impls sort the code into the program's own
+/// `ErrorCode`s, Anchor framework errors and Groth16 decompression failures. Codes from the
+/// Light system program overlap the program's range and can't be told apart by number, so an
+/// address collision is reported through `check_deposit_address`/`check_nullifier_unspent`
+/// rather than decoded.
+#[derive(Debug)]
+pub enum VaultError {
+    /// One of the privacy vault's `ErrorCode`s
+    Program(ErrorCode),
+    /// An Anchor framework error (codes below 6000), e.g. a failed account constraint
+    Anchor(u32),
+    /// A proof point is not on the curve and failed to decompress in `groth16-solana`
+    ProofDecompression,
+    /// A deposit record for this commitment and asset already exists
+    CommitmentAlreadyExists,
+    /// The note's nullifier address exists, so the note was already spent
+    NullifierAlreadyUsed,
+    Rpc(RpcError),
+    Indexer(IndexerError),
+    /// A custom code this crate does not know
+    Custom(u32),
+    /// A runtime error that is not a custom code, e.g. insufficient funds for a transfer
+    Runtime(String),
+}
+
+/// The program's error codes in declaration order, so `PROGRAM_ERRORS[i]` is code `6000 + i`
+const PROGRAM_ERRORS: &[ErrorCode] = &[
+    ErrorCode::AccountNotEnoughKeys,
+    ErrorCode::NullifierAlreadyUsed,
+    ErrorCode::InvalidProof,
+    ErrorCode::InvalidMerkleRoot,
+    ErrorCode::VerifyingKeyNotGenerated,
+    ErrorCode::InvalidRecipient,
+    ErrorCode::InvalidVaultTokenAccount,
+    ErrorCode::Unauthorized,
+    ErrorCode::DevModeDisabled,
+    ErrorCode::InvalidBatchSize,
+    ErrorCode::WithdrawalsNotYetEnabled,
+    ErrorCode::UnknownAssociationSet,
+    ErrorCode::InvalidVaultAuthority,
+    ErrorCode::InvalidDepositStateTree,
+    ErrorCode::NullifierNotExpired,
+    ErrorCode::NullifierCheckpointNotReached,
+    ErrorCode::RootSnapshotStale,
+    ErrorCode::MerkleTreeNotAllowed,
+    ErrorCode::MerkleTreeAllowlistTooLong,
+    ErrorCode::InvalidStream,
+    ErrorCode::StreamNotDue,
+    ErrorCode::StreamExhausted,
+    ErrorCode::InvalidSystemAccountsOffset,
+    ErrorCode::InsufficientVaultBalance,
+    ErrorCode::InvalidAssociationSetId,
+    ErrorCode::EncryptedNoteTooLong,
+    ErrorCode::RecipientMismatch,
+    ErrorCode::ProofNotExpired,
+    ErrorCode::InvalidRootIndex,
+    ErrorCode::InvalidCommitment,
+    ErrorCode::InvalidCpiSigner,
+    ErrorCode::InvalidPermitSignature,
+    ErrorCode::PermitExpired,
+    ErrorCode::InvalidPermitNonce,
+    ErrorCode::UntrustedOracle,
+    ErrorCode::InvalidOracleData,
+    ErrorCode::OracleConditionNotMet,
+    ErrorCode::OracleAllowlistTooLong,
+    ErrorCode::CrankTooSoon,
+    ErrorCode::RecipientNotAllowlisted,
+    ErrorCode::RecipientAllowlistActive,
+    ErrorCode::InvalidPoolId,
+    ErrorCode::PoolMismatch,
+    ErrorCode::PoolStateTreeMismatch,
+    ErrorCode::PoolStateTreeAllowlisted,
+    ErrorCode::PoolStateTreeRegistered,
+    ErrorCode::PoolLimitReached,
+    ErrorCode::ConfigAlreadyInitialized,
+    ErrorCode::AssociationSetRootMismatch,
+];
+
+impl VaultError {
+    /// A message for the user saying what went wrong and what to do about it
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Program(code) => format!("{}: {}", code.name(), code),
+            Self::Anchor(code) => match code {
+                100..=999 => format!(
+                    "Instruction data was rejected (Anchor error {}); rebuild the client \
+                     against the deployed program's IDL",
+                    code
+                ),
+                2000..=2999 => format!(
+                    "An account constraint failed (Anchor error {}); check the accounts and \
+                     signers passed to the instruction",
+                    code
+                ),
+                3000..=3999 => format!(
+                    "An account could not be loaded (Anchor error {}); check that it exists \
+                     and belongs to the program",
+                    code
+                ),
+                _ => format!("Anchor framework error {}", code),
+            },
+            Self::ProofDecompression => "A proof point failed to decompress; regenerate the \
+                                         proof and check it is sent unmodified"
+                .to_string(),
+            Self::CommitmentAlreadyExists => {
+                "A deposit for this commitment already exists; create a new note".to_string()
+            }
+            Self::NullifierAlreadyUsed => "This note has already been withdrawn".to_string(),
+            Self::Rpc(error) => {
+                format!("RPC request failed: {}; retry or check the endpoint", error)
+            }
+            Self::Indexer(error) => format!(
+                "Indexer request failed: {}; retry or check that the indexer is in sync",
+                error
+            ),
+            Self::Custom(code) => format!(
+                "Unknown custom error {}, possibly from the Light system program; check the \
+                 transaction logs",
+                code
+            ),
+            Self::Runtime(error) => format!("The runtime rejected the instruction: {}", error),
+        }
+    }
+}
+
+impl From<ErrorCode> for VaultError {
+    fn from(code: ErrorCode) -> Self {
+        Self::Program(code)
+    }
+}
+
+impl From<u32> for VaultError {
+    fn from(code: u32) -> Self {
+        if code == u32::from(Groth16Error::DecompressingG1Failed)
+            || code == u32::from(Groth16Error::DecompressingG2Failed)
+        {
+            return Self::ProofDecompression;
+        }
+        match code {
+            100..=5999 => Self::Anchor(code),
+            _ => code
+                .checked_sub(6000)
+                .and_then(|index| PROGRAM_ERRORS.get(index as usize))
+                .map_or(Self::Custom(code), |error| Self::Program(*error)),
+        }
+    }
+}
+
+impl From<ProgramError> for VaultError {
+    fn from(error: ProgramError) -> Self {
+        match error {
+            ProgramError::Custom(code) => code.into(),
+            other => Self::Runtime(other.to_string()),
+        }
+    }
+}
+
+impl From<InstructionError> for VaultError {
+    fn from(error: InstructionError) -> Self {
+        match error {
+            InstructionError::Custom(code) => code.into(),
+            other => Self::Runtime(other.to_string()),
+        }
+    }
+}
+
+impl From<NewAddressError> for VaultError {
+    fn from(error: NewAddressError) -> Self {
+        match error {
+            NewAddressError::Indexer(error) => Self::Indexer(error),
+            NewAddressError::CommitmentAlreadyExists => Self::CommitmentAlreadyExists,
+            NewAddressError::NullifierAlreadyUsed => Self::NullifierAlreadyUsed,
+        }
+    }
+}
+
+impl From<RpcError> for VaultError {
+    fn from(error: RpcError) -> Self {
+        Self::Rpc(error)
+    }
+}
+
+impl From<IndexerError> for VaultError {
+    fn from(error: IndexerError) -> Self {
+        Self::Indexer(error)
+    }
+}
+
+/// A deposit whose view tag matches the scanning wallet's view key.
+#[derive(Clone, Debug)]
+pub struct DepositCandidate {
+    pub account: CompressedAccount,
+    pub deposit: DepositAccount,
+}
+
+/// Lists deposits whose `view_tag` matches `view_key`.
+///
+/// A match only means the deposit is likely the wallet's: one in 256 foreign
+/// deposits matches by chance, so callers still re-derive their notes and compare
+/// commitments. Deposits without a view tag are skipped.
+pub async fn find_my_deposits<R>(
+    rpc: &mut R,
+    view_key: &[u8; 32],
+) -> Result<Vec<DepositCandidate>, IndexerError>
+where
+    R: Indexer,
+{
+    let mut candidates = Vec::new();
+    for candidate in fetch_deposits(rpc).await? {
+        let Some(view_tag) = candidate.deposit.view_tag else {
+            continue;
+        };
+        let expected = compute_view_tag(view_key, &candidate.deposit.commitment.value)
+            .map_err(|e| IndexerError::CustomError(e.to_string()))?;
+        if view_tag == expected {
+            candidates.push(candidate);
+        }
+    }
+
+    Ok(candidates)
+}
+
+/// Domain separator for the nonce of an encrypted note backup.
+pub const ENCRYPTED_NOTE_DOMAIN: &[u8] = b"privacy-vault-encrypted-note";
+
+/// Encrypts `note` for the `encrypted_note` argument of `deposit`.
+///
+/// AES-256-GCM-SIV under `recovery_key`, with the note's commitment as associated data
+/// and `Sha256(ENCRYPTED_NOTE_DOMAIN || commitment)[..12]` as nonce, so the backup only
+/// opens against the deposit it was made for. The nonce is deterministic, which GCM-SIV
+/// tolerates: re-encrypting the same note yields the same ciphertext and reveals nothing
+/// more. `recovery_key` should be derived from the wallet seed, and not be the view key,
+/// which may be shared with watch-only wallets.
+pub fn encrypt_note(recovery_key: &[u8; 32], note: &Note) -> Result<Vec<u8>, HasherError> {
+    let commitment = compute_commitment(&note.nullifier, &note.secret)?;
+    let nonce = encrypted_note_nonce(&commitment)?;
+    let ciphertext = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(recovery_key))
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &note.to_bytes(),
+                aad: &commitment,
+            },
+        )
+        .expect("note plaintext is far below the AES-GCM-SIV length limit");
+    Ok(ciphertext)
+}
+
+/// Opens an `encrypted_note` stored on the deposit with `commitment`.
+///
+/// Returns `None` when the backup was not made with `recovery_key` for this deposit, or
+/// when the note inside does not hash to `commitment`.
+pub fn decrypt_note(
+    recovery_key: &[u8; 32],
+    commitment: &[u8; 32],
+    ciphertext: &[u8],
+) -> Option<Note> {
+    let nonce = encrypted_note_nonce(commitment).ok()?;
+    let plaintext = Aes256GcmSiv::new(Key::<Aes256GcmSiv>::from_slice(recovery_key))
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: ciphertext,
+                aad: commitment,
+            },
+        )
+        .ok()?;
+    let note = Note::from_bytes(&plaintext).ok()?;
+    (compute_commitment(&note.nullifier, &note.secret).ok()? == *commitment).then_some(note)
+}
+
+/// Recovers every note whose deposit carries a backup encrypted under `recovery_key`.
+///
+/// Scans all deposits, so it needs nothing but the key. Withdrawals close the deposit
+/// account, so only notes that are still unspent are found.
+pub async fn recover_notes<R>(
+    rpc: &mut R,
+    recovery_key: &[u8; 32],
+) -> Result<Vec<(DepositCandidate, Note)>, IndexerError>
+where
+    R: Indexer,
+{
+    let mut notes = Vec::new();
+    for candidate in fetch_deposits(rpc).await? {
+        let Some(ciphertext) = candidate.deposit.encrypted_note.as_ref() else {
+            continue;
+        };
+        if let Some(note) = decrypt_note(
+            recovery_key,
+            &candidate.deposit.commitment.value,
+            ciphertext,
+        ) {
+            notes.push((candidate, note));
+        }
+    }
+
+    Ok(notes)
+}
+
+fn encrypted_note_nonce(commitment: &[u8; 32]) -> Result<[u8; 12], HasherError> {
+    let hash = Sha256::hashv(&[ENCRYPTED_NOTE_DOMAIN, commitment.as_slice()])?;
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&hash[..12]);
+    Ok(nonce)
+}
+
+/// Sum of `amount` over every `TokenDepositAccount` the indexer knows of, keyed by mint.
+///
+/// This is the total ever deposited, not the balance still held. Token deposits stay open
+/// when they are withdrawn, and a nullifier records neither the deposit nor the mint it spent,
+/// so no withdrawal can be subtracted. For what a pool holds now, read the balance of its
+/// `vault_token_address(mint)`. Native `deposit_sol` deposits count under the wrapped SOL mint.
+pub async fn total_by_mint<R>(rpc: &mut R) -> Result<HashMap<Pubkey, u64>, IndexerError>
+where
+    R: Indexer,
+{
+    let accounts = rpc
+        .get_compressed_accounts_by_owner(&crate::ID, None, None)
+        .await?
+        .value
+        .items;
+
+    let mut totals = HashMap::new();
+    for account in accounts {
+        let Some(data) = account.data.as_ref() else {
+            continue;
+        };
+        if data.discriminator != TokenDepositAccount::LIGHT_DISCRIMINATOR {
+            continue;
+        }
+        let Ok(deposit) = TokenDepositAccount::deserialize(&mut data.data.as_slice()) else {
+            continue;
+        };
+        let total = totals
+            .entry(Pubkey::new_from_array(deposit.token_mint_hash.value))
+            .or_insert(0u64);
+        *total = total.saturating_add(deposit.amount);
+    }
+
+    Ok(totals)
+}
+
+/// Every SOL `DepositAccount` the indexer knows of.
+async fn fetch_deposits<R>(rpc: &mut R) -> Result<Vec<DepositCandidate>, IndexerError>
+where
+    R: Indexer,
+{
+    let accounts = rpc
+        .get_compressed_accounts_by_owner(&crate::ID, None, None)
+        .await?
+        .value
+        .items;
+
+    let mut deposits = Vec::new();
+    for account in accounts {
+        let Some(data) = account.data.as_ref() else {
+            continue;
+        };
+        if data.discriminator != DepositAccount::LIGHT_DISCRIMINATOR {
+            continue;
+        }
+        let Ok(deposit) = DepositAccount::deserialize(&mut data.data.as_slice()) else {
+            continue;
+        };
+        deposits.push(DepositCandidate { account, deposit });
+    }
+
+    Ok(deposits)
+}
+
+/// Every address a withdrawal of one note touches, derived in the address tree the
+/// program accepts (`ADDRESS_TREE_V2`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WithdrawPlan {
+    pub address_tree: Pubkey,
+    pub commitment: [u8; 32],
+    pub nullifier_hash: [u8; 32],
+    /// Deposit account `deposit` created for the commitment
+    pub deposit_address: [u8; 32],
+    /// Nullifier address the withdraw creates
+    pub nullifier_address: [u8; 32],
+    /// Record `prove_innocence` creates, if an association set was given
+    pub innocence_address: Option<[u8; 32]>,
+    /// `InnocenceBitmapAccount` the note's first `prove_innocence` creates
+    pub innocence_bitmap_address: [u8; 32],
+}
+
+impl WithdrawPlan {
+    /// New addresses for the withdraw instruction's `get_validity_proof` call.
+    pub fn withdraw_new_addresses(&self) -> Vec<AddressWithTree> {
+        vec![AddressWithTree {
+            address: self.nullifier_address,
+            tree: self.address_tree,
+        }]
+    }
+
+    /// New addresses for the `prove_innocence` validity proof; empty without an association set.
+    pub fn innocence_new_addresses(&self) -> Vec<AddressWithTree> {
+        self.innocence_address
+            .iter()
+            .map(|address| AddressWithTree {
+                address: *address,
+                tree: self.address_tree,
+            })
+            .collect()
+    }
+
+    /// Extra new address for the note's first `prove_innocence`, which creates its bitmap.
+    pub fn innocence_bitmap_new_address(&self) -> AddressWithTree {
+        AddressWithTree {
+            address: self.innocence_bitmap_address,
+            tree: self.address_tree,
+        }
+    }
+}
+
+/// Derives the deposit, nullifier and optional innocence-proof addresses for `note`.
+///
+/// Token notes use the `derive_token_deposit_address` seeds of `deposit_token`,
+/// SOL notes the `derive_deposit_address` seeds of `deposit` in pool `pool_id`. Only
+/// `deposit` takes a pool id, so `pool_id` is ignored for token notes.
+pub fn plan_withdraw(
+    note: &Note,
+    pool_id: u16,
+    association_set_id: Option<u16>,
+) -> Result<WithdrawPlan, HasherError> {
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let commitment = compute_commitment(&note.nullifier, &note.secret)?;
+    let nullifier_hash = compute_nullifier_hash(&note.nullifier)?;
+
+    let (deposit_address, _) = match note.mint {
+        Some(mint) => derive_token_deposit_address(&commitment, Some(&mint), &address_tree),
+        None => derive_deposit_address(&commitment, pool_id, &address_tree),
+    };
+    let (nullifier_address, _) =
+        derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree);
+    let innocence_address = association_set_id.map(|id| {
+        derive_address(
+            &[INNOCENCE_PROOF, &nullifier_hash, &id.to_le_bytes()],
+            &address_tree,
+            &crate::ID,
+        )
+        .0
+    });
+    let (innocence_bitmap_address, _) = derive_address(
+        &[INNOCENCE_BITMAP, &nullifier_hash],
+        &address_tree,
+        &crate::ID,
+    );
+
+    Ok(WithdrawPlan {
+        address_tree,
+        commitment,
+        nullifier_hash,
+        deposit_address,
+        nullifier_address,
+        innocence_address,
+        innocence_bitmap_address,
+    })
+}
+
+/// Reasons `deposit` return data is rejected as a receipt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReceiptError {
+    /// Return data was set by another program
+    WrongProgram(Pubkey),
+    InvalidLength,
+    /// `address` is not the deposit address of `commitment` in the given pool
+    AddressMismatch,
+}
+
+/// Decodes the `DepositReceipt` a `deposit` transaction returned.
+///
+/// Takes the `program_id` and `data` of the transaction's return data. The runtime
+/// strips trailing zero bytes from recorded return data, so `data` is zero-padded
+/// back to `DepositReceipt::LEN` before decoding. The receipt's address must be the
+/// `derive_deposit_address` address of the commitment in pool `pool_id` and `ADDRESS_TREE_V2`.
+pub fn parse_deposit_receipt(
+    program_id: &Pubkey,
+    data: &[u8],
+    pool_id: u16,
+) -> Result<DepositReceipt, ReceiptError> {
+    if program_id != &crate::ID {
+        return Err(ReceiptError::WrongProgram(*program_id));
+    }
+    if data.len() > DepositReceipt::LEN {
+        return Err(ReceiptError::InvalidLength);
+    }
+    let mut bytes = [0u8; DepositReceipt::LEN];
+    bytes[..data.len()].copy_from_slice(data);
+    let receipt = DepositReceipt::deserialize(&mut bytes.as_slice())
+        .map_err(|_| ReceiptError::InvalidLength)?;
+
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let (address, _) = derive_deposit_address(&receipt.commitment, pool_id, &address_tree);
+    if address != receipt.address {
+        return Err(ReceiptError::AddressMismatch);
+    }
+
+    Ok(receipt)
+}
+
+/// Lowercase hex of a commitment, nullifier hash or address, as the program's `msg!` logs
+/// print it, so client output can be searched for in transaction logs.
+pub fn to_hex(bytes: &[u8; 32]) -> String {
+    Hex(bytes).to_string()
+}
+
+/// Base58 of a commitment, nullifier hash or address, the encoding explorers show
+/// compressed account addresses in.
+pub fn to_base58(bytes: &[u8; 32]) -> String {
+    bs58::encode(bytes).into_string()
+}
+
+/// Depth of the trees `innocence.circom` proves membership in
+#[cfg(feature = "prover")]
+pub const INNOCENCE_TREE_LEVELS: usize = 10;
+
+/// Reasons `generate_innocence_proof` fails.
+#[cfg(feature = "prover")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InnocenceProofError {
+    Hasher(HasherError),
+    /// A Merkle proof does not have `INNOCENCE_TREE_LEVELS` path elements
+    InvalidPathLength(usize),
+    /// Witness generation, proving or proof conversion failed
+    Prover(String),
+}
+
+#[cfg(feature = "prover")]
+impl From<HasherError> for InnocenceProofError {
+    fn from(error: HasherError) -> Self {
+        Self::Hasher(error)
+    }
+}
+
+/// Proves that `note` is in both the deposit tree and an association set, for
+/// `prove_innocence`.
+///
+/// Builds the `innocence.circom` inputs, whose public inputs are
+/// `[depositRoot, associationSetRoot, nullifierHash, associationSetId, timestamp]`, proves
+/// with `witness_fn` and the proving key at `zkey_path`, and returns the compressed proof
+/// with the note's nullifier hash. Path indices are the bits of each proof's `leaf_index`.
+/// `prove_innocence` checks the proof against the clock's unix timestamp, so `timestamp`
+/// must be the one the transaction lands at.
+///
+/// The witness generator is built by the integrator from `build/innocence_js`, e.g. with
+/// `rust_witness::witness!(innocence)`. Debug builds verify the proof locally, with
+/// circom and, once the key has been generated, with groth16-solana like the program does.
+#[cfg(feature = "prover")]
+pub fn generate_innocence_proof(
+    note: &Note,
+    deposit_merkle_proof: &MerkleInputs,
+    association_set_proof: &MerkleInputs,
+    association_set_id: u16,
+    timestamp: u64,
+    witness_fn: WitnessFn,
+    zkey_path: &str,
+) -> Result<(CompressedProof, [u8; 32]), InnocenceProofError> {
+    for merkle_proof in [deposit_merkle_proof, association_set_proof] {
+        if merkle_proof.path_elements.len() != INNOCENCE_TREE_LEVELS {
+            return Err(InnocenceProofError::InvalidPathLength(
+                merkle_proof.path_elements.len(),
+            ));
+        }
+    }
+    let nullifier_hash = compute_nullifier_hash(&note.nullifier)?;
+
+    let field = |bytes: &[u8; 32]| vec![BigUint::from_bytes_be(bytes).to_string()];
+    let path_elements = |merkle_proof: &MerkleInputs| -> Vec<String> {
+        merkle_proof
+            .path_elements
+            .iter()
+            .map(|element| BigUint::from_bytes_be(element).to_string())
+            .collect()
+    };
+    let path_indices = |merkle_proof: &MerkleInputs| -> Vec<String> {
+        (0..INNOCENCE_TREE_LEVELS)
+            .map(|level| ((merkle_proof.leaf_index >> level) & 1).to_string())
+            .collect()
+    };
+
+    let mut proof_inputs = HashMap::new();
+    proof_inputs.insert("depositRoot", field(&deposit_merkle_proof.root));
+    proof_inputs.insert("associationSetRoot", field(&association_set_proof.root));
+    proof_inputs.insert("nullifierHash", field(&nullifier_hash));
+    proof_inputs.insert("associationSetId", vec![association_set_id.to_string()]);
+    proof_inputs.insert("timestamp", vec![timestamp.to_string()]);
+    proof_inputs.insert("nullifier", field(&note.nullifier));
+    proof_inputs.insert("secret", field(&note.secret));
+    proof_inputs.insert("depositPathElements", path_elements(deposit_merkle_proof));
+    proof_inputs.insert("depositPathIndices", path_indices(deposit_merkle_proof));
+    proof_inputs.insert(
+        "associationPathElements",
+        path_elements(association_set_proof),
+    );
+    proof_inputs.insert(
+        "associationPathIndices",
+        path_indices(association_set_proof),
+    );
+
+    let circuit_inputs = serde_json::to_string(&proof_inputs)
+        .map_err(|e| InnocenceProofError::Prover(e.to_string()))?;
+    let proof = CircomProver::prove(
+        ProofLib::Arkworks,
+        witness_fn,
+        circuit_inputs,
+        zkey_path.to_string(),
+    )
+    .map_err(|e| InnocenceProofError::Prover(e.to_string()))?;
+
+    debug_assert!(
+        CircomProver::verify(ProofLib::Arkworks, proof.clone(), zkey_path.to_string())
+            .unwrap_or(false),
+        "Local circom proof verification should pass"
+    );
+
+    let (proof_a_uncompressed, proof_b_uncompressed, proof_c_uncompressed) =
+        convert_proof(&proof.proof).map_err(|e| InnocenceProofError::Prover(e.to_string()))?;
+
+    #[cfg(debug_assertions)]
+    if !crate::is_placeholder_key(crate::INNOCENCE_KEY.vk) {
+        let public_inputs: [[u8; 32]; 5] = convert_public_inputs(&proof.pub_inputs);
+        let verified = Groth16Verifier::new(
+            &proof_a_uncompressed,
+            &proof_b_uncompressed,
+            &proof_c_uncompressed,
+            &public_inputs,
+            crate::INNOCENCE_KEY.vk,
+        )
+        .and_then(|mut verifier| verifier.verify());
+        debug_assert!(
+            verified.is_ok(),
+            "Local groth16-solana verification should pass"
+        );
+    }
+
+    let (a, b, c) = convert_proof_to_compressed(
+        &proof_a_uncompressed,
+        &proof_b_uncompressed,
+        &proof_c_uncompressed,
+    )
+    .map_err(|e| InnocenceProofError::Prover(e.to_string()))?;
+
+    Ok((CompressedProof { a, b, c }, nullifier_hash))
+}
+
+/// Reasons `load_verifying_key_from_json` fails.
+#[cfg(feature = "vk-json")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyingKeyError {
+    /// The file could not be read
+    Io(String),
+    /// The file is not valid JSON
+    Json(String),
+    /// The named field is missing or is not a well-formed point, or `IC` does not hold
+    /// `nPublic + 1` points
+    InvalidField(&'static str),
+}
+
+/// A Groth16 verifying key read at runtime. Owns the IC points that `Groth16Verifyingkey`
+/// borrows; `as_groth16` lends it in the form `Groth16Verifier::new` takes.
+#[cfg(feature = "vk-json")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadedVerifyingKey {
+    pub nr_pubinputs: usize,
+    pub vk_alpha_g1: [u8; 64],
+    pub vk_beta_g2: [u8; 128],
+    pub vk_gamma_g2: [u8; 128],
+    pub vk_delta_g2: [u8; 128],
+    pub vk_ic: Vec<[u8; 64]>,
+}
+
+#[cfg(feature = "vk-json")]
+impl LoadedVerifyingKey {
+    pub fn as_groth16(&self) -> Groth16Verifyingkey<'_> {
+        Groth16Verifyingkey {
+            nr_pubinputs: self.nr_pubinputs,
+            vk_alpha_g1: self.vk_alpha_g1,
+            vk_beta_g2: self.vk_beta_g2,
+            vk_gamma_g2: self.vk_gamma_g2,
+            vk_delta_g2: self.vk_delta_g2,
+            vk_ic: &self.vk_ic,
+        }
+    }
+}
+
+/// Reads a snarkjs `verification_key.json` into the byte layout `build.rs` bakes into
+/// `verifying_key.rs`, so off-chain tools can check proofs for any circuit without a rebuild.
+///
+/// G1 points are `x || y` and G2 points `x_c1 || x_c0 || y_c1 || y_c0`, each coordinate 32
+/// big-endian bytes, as the alt_bn128 syscalls expect. Only the key's shape is checked; a
+/// point that is not on the curve fails later, in `Groth16Verifier::verify`. The on-chain
+/// program always uses its compiled-in keys.
+#[cfg(feature = "vk-json")]
+pub fn load_verifying_key_from_json(
+    path: impl AsRef<Path>,
+) -> Result<LoadedVerifyingKey, VerifyingKeyError> {
+    let json = std::fs::read_to_string(path).map_err(|e| VerifyingKeyError::Io(e.to_string()))?;
+    let vkey: Value =
+        serde_json::from_str(&json).map_err(|e| VerifyingKeyError::Json(e.to_string()))?;
+
+    let nr_pubinputs = vkey["nPublic"]
+        .as_u64()
+        .ok_or(VerifyingKeyError::InvalidField("nPublic"))? as usize;
+    let vk_ic = vkey["IC"]
+        .as_array()
+        .filter(|ic| ic.len() == nr_pubinputs + 1)
+        .ok_or(VerifyingKeyError::InvalidField("IC"))?
+        .iter()
+        .map(|point| json_g1(point).ok_or(VerifyingKeyError::InvalidField("IC")))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(LoadedVerifyingKey {
+        nr_pubinputs,
+        vk_alpha_g1: json_g1(&vkey["vk_alpha_1"])
+            .ok_or(VerifyingKeyError::InvalidField("vk_alpha_1"))?,
+        vk_beta_g2: json_g2(&vkey["vk_beta_2"])
+            .ok_or(VerifyingKeyError::InvalidField("vk_beta_2"))?,
+        vk_gamma_g2: json_g2(&vkey["vk_gamma_2"])
+            .ok_or(VerifyingKeyError::InvalidField("vk_gamma_2"))?,
+        vk_delta_g2: json_g2(&vkey["vk_delta_2"])
+            .ok_or(VerifyingKeyError::InvalidField("vk_delta_2"))?,
+        vk_ic,
+    })
+}
+
+/// A decimal field element from the JSON as 32 big-endian bytes
+#[cfg(feature = "vk-json")]
+fn json_field(value: &Value) -> Option<[u8; 32]> {
+    let digits = BigUint::parse_bytes(value.as_str()?.as_bytes(), 10)?.to_bytes_be();
+    if digits.len() > 32 {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    bytes[32 - digits.len()..].copy_from_slice(&digits);
+    Some(bytes)
+}
+
+/// `[x, y, z]` affine G1 point as `x || y`
+#[cfg(feature = "vk-json")]
+fn json_g1(point: &Value) -> Option<[u8; 64]> {
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(&json_field(&point[0])?);
+    bytes[32..].copy_from_slice(&json_field(&point[1])?);
+    Some(bytes)
+}
+
+/// `[[x_c0, x_c1], [y_c0, y_c1], z]` G2 point as `x_c1 || x_c0 || y_c1 || y_c0`
+#[cfg(feature = "vk-json")]
+fn json_g2(point: &Value) -> Option<[u8; 128]> {
+    let mut bytes = [0u8; 128];
+    bytes[..32].copy_from_slice(&json_field(&point[0][1])?);
+    bytes[32..64].copy_from_slice(&json_field(&point[0][0])?);
+    bytes[64..96].copy_from_slice(&json_field(&point[1][1])?);
+    bytes[96..].copy_from_slice(&json_field(&point[1][0])?);
+    Some(bytes)
+}
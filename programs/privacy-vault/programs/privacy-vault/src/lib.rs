@@ -2,7 +2,13 @@
 #![allow(deprecated)]
 
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::sysvar::{
+    self,
+    instructions::{load_current_index_checked, load_instruction_at_checked},
+};
+use anchor_spl::associated_token::{self, get_associated_token_address, AssociatedToken};
+use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer};
 
 #[cfg(not(feature = "no-entrypoint"))]
 use solana_security_txt::security_txt;
@@ -19,9 +25,11 @@ security_txt! {
     acknowledgements: "Built for Solana Privacy Hack 2026. Based on Vitalik Buterin's Privacy Pools paper."
 }
 use borsh::{BorshDeserialize, BorshSerialize};
-use groth16_solana::groth16::Groth16Verifier;
+use groth16_solana::decompression::{decompress_g1, decompress_g2};
+use groth16_solana::groth16::{Groth16Verifier, Groth16Verifyingkey};
+use light_hasher::hash_to_field_size::hash_to_bn254_field_size_be;
 use light_hasher::to_byte_array::ToByteArray;
-use light_hasher::{Hasher, HasherError, Sha256};
+use light_hasher::{Hasher, HasherError, Poseidon, Sha256};
 use light_sdk::account::{poseidon::LightAccount as LightAccountPoseidon, LightAccount};
 use light_sdk::cpi::v2::CpiAccounts;
 use light_sdk::{
@@ -42,90 +50,183 @@ pub const LIGHT_CPI_SIGNER: CpiSigner =
     derive_light_cpi_signer!("9zvpj82hnzpjFhYGVL6tT3Bh3GBAoaJnVxe8ZsDqMwnu");
 
 // Seeds for PDA derivation
-pub const VAULT: &[u8] = b"vault";
 pub const DEPOSIT: &[u8] = b"deposit";
 pub const NULLIFIER: &[u8] = b"nullifier";
 pub const INNOCENCE_PROOF: &[u8] = b"innocence";
+pub const CONFIG: &[u8] = b"config";
+pub const ASSOCIATION_SET: &[u8] = b"association_set";
+pub const ROOT_SNAPSHOT: &[u8] = b"root_snapshot";
+pub const ROOT_SNAPSHOT_RING: &[u8] = b"root_snapshot_ring";
+pub const STREAM: &[u8] = b"stream";
+pub const INNOCENCE_BITMAP: &[u8] = b"innocence_bitmap";
+pub const PERMIT_DELEGATE: &[u8] = b"permit_delegate";
+pub const PERMIT_NONCE: &[u8] = b"permit_nonce";
+pub const POOL: &[u8] = b"pool";
+// Takes the mint's place in native SOL deposit addresses, so they never collide with wSOL
+pub const NATIVE_DEPOSIT: &[u8] = b"native";
 
-// Include the generated verifying key module
-pub mod verifying_key;
+// Domain prefix of the message a deposit_with_permit permit signs
+pub const DEPOSIT_PERMIT: &[u8] = b"PRIVACY_VAULT_DEPOSIT_PERMIT";
 
-#[program]
-pub mod privacy_vault {
-    use groth16_solana::decompression::{decompress_g1, decompress_g2};
+// Deployment tag folded into nullifier addresses; all zeros keeps the original derivation
+pub const NULLIFIER_DOMAIN: [u8; 8] = [0u8; 8];
 
-    use super::*;
+// Reported by program_info
+pub const PROGRAM_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const CIRCUIT_VERSION: u8 = 1;
 
-    /// Initialize a new privacy vault
-    /// Creates a compressed account to track vault state
-    pub fn initialize_vault<'info>(
-        ctx: Context<'_, '_, '_, 'info, GenericAnchorAccounts<'info>>,
-        proof: ValidityProof,
-        address_tree_info: PackedAddressTreeInfo,
-        output_state_tree_index: u8,
-        system_accounts_offset: u8,
-    ) -> Result<()> {
-        let light_cpi_accounts = CpiAccounts::new(
-            ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
-            crate::LIGHT_CPI_SIGNER,
-        );
+// Height of the v1 state trees deposits are appended to
+pub const STATE_TREE_HEIGHT: usize = 26;
 
-        let address_tree_pubkey = address_tree_info
-            .get_tree_pubkey(&light_cpi_accounts)
-            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+// BN254 scalar field modulus, big-endian; deposit commitments must be below it
+pub const BN254_FIELD_MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x28, 0x33, 0xe8, 0x48, 0x79, 0xb9, 0x70, 0x91, 0x43, 0xe1, 0xf5, 0x93, 0xf0, 0x00, 0x00, 0x01,
+];
 
-        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
-            msg!("Invalid address tree");
-            return Err(ProgramError::InvalidAccountData.into());
-        }
+// BN254 base field modulus, big-endian; compressed proof coordinates must be below it
+pub const BN254_BASE_FIELD_MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x97, 0x81, 0x6a, 0x91, 0x68, 0x71, 0xca, 0x8d, 0x3c, 0x20, 0x8c, 0x16, 0xd8, 0x7c, 0xfd, 0x47,
+];
 
-        let (address, address_seed) = derive_address(
-            &[VAULT, ctx.accounts.signer.key().as_ref()],
-            &address_tree_pubkey,
-            &crate::ID,
-        );
+// Length of a v1 state tree's root history. The indexer's `root_seq` counts every root
+// ever written; the slot holding that root is `root_seq % ROOT_HISTORY_SIZE`.
+pub const ROOT_HISTORY_SIZE: u64 = 2400;
 
-        let mut vault_account = LightAccount::<VaultAccount>::new_init(
-            &crate::ID,
-            Some(address),
-            output_state_tree_index,
-        );
+// Denominator of fee_bps; a relayer fee is at most the whole withdrawn amount
+pub const BPS_DENOMINATOR: u16 = 10_000;
 
-        vault_account.authority = ctx.accounts.signer.key();
-        vault_account.total_deposits = 0;
-        vault_account.total_withdrawals = 0;
+// Maximum number of independent proofs verified by a single withdraw_many call
+pub const MAX_WITHDRAW_BATCH: usize = 4;
 
-        msg!("Initialized vault for authority: {}", ctx.accounts.signer.key());
+// Slots a root snapshot stays usable by withdraw_with_root (~a blockhash lifetime)
+pub const MAX_ROOT_SNAPSHOT_AGE_SLOTS: u64 = 150;
 
-        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
-            .with_light_account(vault_account)?
-            .with_new_addresses(&[
-                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(0))
-            ])
-            .invoke(light_cpi_accounts)?;
+// Roots kept by a tree's RootSnapshotRing, and the slots between two crank_merkle_snapshot
+// calls; together they span MAX_ROOT_SNAPSHOT_AGE_SLOTS
+pub const ROOT_SNAPSHOT_RING_SIZE: usize = 16;
+pub const MIN_CRANK_INTERVAL_SLOTS: u64 = 10;
 
-        Ok(())
-    }
+// Maximum number of state trees in the config's merkle_tree_allowlist
+pub const MAX_ALLOWLISTED_TREES: usize = 8;
+
+// Maximum number of registered sub-pools, one state tree each in the config's pool_state_trees
+pub const MAX_POOLS: usize = 8;
+
+// Maximum number of oracle programs in the config's oracle_owner_allowlist
+pub const MAX_ALLOWLISTED_ORACLES: usize = 8;
+
+// Depth of the recipient allowlist tree behind the config's recipient_allowlist_root
+pub const RECIPIENT_ALLOWLIST_LEVELS: usize = 16;
+
+// Maximum number of scheduled deposits (pre-committed notes) in one deposit stream
+pub const MAX_STREAM_DEPOSITS: usize = 16;
+
+// Association set ids below this are bits of InnocenceBitmapAccount::satisfied_sets
+pub const BITMAP_ASSOCIATION_SETS: u16 = 64;
+
+// Maximum length of a deposit's encrypted note backup (a token note encrypts to 126 bytes)
+pub const MAX_ENCRYPTED_NOTE_LEN: usize = 128;
+
+// Include the generated verifying key module
+pub mod verifying_key;
+
+// Forgeable test key, see the module docs; never enable `test-mock-vk` for a deployed build
+#[cfg(feature = "test-mock-vk")]
+pub mod verifying_key_mock;
+
+// Verifying keys paired with their circuit's public-input count
+#[cfg(not(feature = "test-mock-vk"))]
+pub const WITHDRAW_KEY: CircuitKey<5> = CircuitKey::new(&verifying_key::VERIFYINGKEY_WITHDRAW);
+#[cfg(feature = "test-mock-vk")]
+pub const WITHDRAW_KEY: CircuitKey<5> = CircuitKey::new(&verifying_key_mock::VERIFYINGKEY_WITHDRAW);
+#[cfg(not(feature = "test-mock-vk"))]
+pub const INNOCENCE_KEY: CircuitKey<5> = CircuitKey::new(&verifying_key::VERIFYINGKEY_INNOCENCE);
+// The mock key accepts any 5 public inputs, so it stands in for the innocence circuit too
+#[cfg(feature = "test-mock-vk")]
+pub const INNOCENCE_KEY: CircuitKey<5> =
+    CircuitKey::new(&verifying_key_mock::VERIFYINGKEY_WITHDRAW);
+
+// Note commitment and nullifier hashing
+pub mod note;
+
+// Off-chain helpers for integrators
+#[cfg(feature = "client")]
+pub mod client;
+
+#[program]
+pub mod privacy_vault {
+    use super::*;
 
     /// Deposit funds into the privacy pool
     /// Creates a commitment that can later be used to withdraw
+    /// `view_tag` is an optional scanning hint, see `note::compute_view_tag`
+    /// `encrypted_note` is an optional backup of the note, see `client::encrypt_note`; at most
+    /// `MAX_ENCRYPTED_NOTE_LEN` bytes and, like the view tag, not part of the account hash
+    /// If the config pins a `deposit_state_tree`, the output tree must be that tree.
+    /// `reference` is an optional caller-chosen tag (e.g. an order id) echoed in the receipt
+    /// Returns a `DepositReceipt` as return data, see `client::parse_deposit_receipt`
+    /// `opening` is a debugging aid that puts the note's nullifier and secret on chain and
+    /// fails with `InvalidCommitment` unless they hash to `commitment`. It reveals the note to
+    /// anyone reading the transaction, so it is only accepted while `dev_mode` is set.
+    /// `pool_id` 0 is the default pool, whose output tree must pass the config's tree
+    /// allowlist (`MerkleTreeNotAllowed`). Any other id deposits into that registered sub-pool:
+    /// the `pool` account must be its `PoolAccount`, the output tree must be the pool's
+    /// state tree (instead of the config's pin), and the address is derived by
+    /// `derive_deposit_address` under the pool's namespace.
     #[allow(clippy::too_many_arguments)]
     pub fn deposit<'info>(
-        ctx: Context<'_, '_, '_, 'info, GenericAnchorAccounts<'info>>,
+        ctx: Context<'_, '_, '_, 'info, DepositAccounts<'info>>,
         proof: ValidityProof,
         address_tree_info: PackedAddressTreeInfo,
         output_state_tree_index: u8,
         system_accounts_offset: u8,
         commitment: [u8; 32],  // Poseidon(nullifier, secret)
         amount: u64,
-    ) -> Result<()> {
+        view_tag: Option<u8>,
+        encrypted_note: Option<Vec<u8>>,
+        reference: Option<[u8; 32]>,
+        opening: Option<NoteOpening>,
+        pool_id: u16,
+    ) -> Result<DepositReceipt> {
+        check_commitment(&commitment)?;
+        if let Some(opening) = &opening {
+            if !ctx.accounts.config.dev_mode {
+                msg!("Deposit openings are only accepted in dev mode");
+                return Err(ErrorCode::DevModeDisabled.into());
+            }
+            check_note_opening(&commitment, opening)?;
+        }
+        if let Some(encrypted_note) = &encrypted_note {
+            require!(
+                encrypted_note.len() <= MAX_ENCRYPTED_NOTE_LEN,
+                ErrorCode::EncryptedNoteTooLong
+            );
+        }
+
         let light_cpi_accounts = CpiAccounts::new(
             ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
             crate::LIGHT_CPI_SIGNER,
         );
 
+        // Deposits go to the sub-pool's state tree, or else to the config's pinned
+        // deposit state tree, if it set one
+        let output_tree = light_cpi_accounts
+            .get_tree_account_info(output_state_tree_index as usize)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?
+            .key();
+        check_pool(
+            &ctx.accounts.config,
+            ctx.accounts.pool.as_deref(),
+            pool_id,
+            &output_tree,
+        )?;
+        if pool_id == 0 {
+            check_deposit_state_tree(&ctx.accounts.config, &output_tree)?;
+        }
+
         let address_tree_pubkey = address_tree_info
             .get_tree_pubkey(&light_cpi_accounts)
             .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
@@ -136,11 +237,8 @@ pub mod privacy_vault {
         }
 
         // Derive deposit address from commitment
-        let (address, address_seed) = derive_address(
-            &[DEPOSIT, &commitment],
-            &address_tree_pubkey,
-            &crate::ID,
-        );
+        let (address, address_seed) =
+            derive_deposit_address(&commitment, pool_id, &address_tree_pubkey);
 
         let mut deposit_account = LightAccountPoseidon::<DepositAccount>::new_init(
             &crate::ID,
@@ -148,14 +246,29 @@ pub mod privacy_vault {
             output_state_tree_index,
         );
 
+        let clock = Clock::get()?;
         deposit_account.commitment = Commitment::new(commitment);
         deposit_account.amount = amount;
-        deposit_account.timestamp = Clock::get()?.unix_timestamp as u64;
+        deposit_account.timestamp = clock.unix_timestamp as u64;
+        deposit_account.view_tag = view_tag;
+        deposit_account.encrypted_note = encrypted_note;
+
+        if let Some(pool) = ctx.accounts.pool.as_mut() {
+            pool.deposit_count = pool
+                .deposit_count
+                .checked_add(1)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            pool.total_deposits = pool
+                .total_deposits
+                .checked_add(amount)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
 
         msg!(
-            "Deposit created with commitment: {:?}, amount: {} lamports",
-            commitment,
-            amount
+            "Deposit created with commitment: {}, amount: {} lamports, pool: {}",
+            Hex(&commitment),
+            amount,
+            pool_id
         );
 
         LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
@@ -165,14 +278,28 @@ pub mod privacy_vault {
             ])
             .invoke(light_cpi_accounts)?;
 
-        Ok(())
+        Ok(DepositReceipt {
+            address,
+            commitment,
+            amount,
+            reference: reference.unwrap_or_default(),
+            slot: clock.slot,
+        })
     }
 
     /// Withdraw funds from the privacy pool
     /// Verifies ZK proof and checks nullifier hasn't been used
+    /// The `recipient` account must be the proof's `recipient` (`RecipientMismatch`
+    /// otherwise), so a relayer cannot redirect the payout.
+    /// While the config has a `recipient_allowlist_root`, `recipient_proof` must show the
+    /// recipient is on that allowlist (`RecipientNotAllowlisted` otherwise).
+    /// A sub-pool's notes are withdrawn with its `pool_id` and `pool` account: the input tree
+    /// must be the pool's state tree, which stands in for the config's tree allowlist.
+    /// Nullifiers stay in one namespace across pools, see `derive_nullifier_address`.
+    /// Returns the new nullifier address as return data
     #[allow(clippy::too_many_arguments)]
     pub fn withdraw<'info>(
-        ctx: Context<'_, '_, '_, 'info, WithdrawAccounts<'info>>,
+        ctx: Context<'_, '_, '_, 'info, WithdrawRecipientAccounts<'info>>,
         proof: ValidityProof,
         address_tree_info: PackedAddressTreeInfo,
         output_state_tree_index: u8,
@@ -180,14 +307,33 @@ pub mod privacy_vault {
         input_root_index: u16,
         nullifier_hash: [u8; 32],
         recipient: Pubkey,
+        pool_id: u16,
+        recipient_proof: Option<RecipientAllowlistProof>,
         zk_proof: CompressedProof,
-    ) -> Result<()> {
+    ) -> Result<[u8; 32]> {
+        // The payout account is the one the proof commits to
+        require!(
+            ctx.accounts.recipient.key() == recipient,
+            ErrorCode::RecipientMismatch
+        );
+        check_recipient_allowlisted(&ctx.accounts.config, &recipient, recipient_proof.as_ref())?;
+        // A sub-pool's notes are spent from its own state tree
+        check_pool(
+            &ctx.accounts.config,
+            ctx.accounts.pool.as_deref(),
+            pool_id,
+            ctx.accounts.input_merkle_tree.key,
+        )?;
+
         let light_cpi_accounts = CpiAccounts::new(
             ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
             crate::LIGHT_CPI_SIGNER,
         );
 
+        // Withdrawals open once the config's launch window has passed
+        check_withdrawals_enabled(&ctx.accounts.config)?;
+
         let address_tree_pubkey = address_tree_info
             .get_tree_pubkey(&light_cpi_accounts)
             .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
@@ -198,17 +344,16 @@ pub mod privacy_vault {
         }
 
         // Create nullifier account to prevent double-spending
-        let (nullifier_address, nullifier_seed) = derive_address(
-            &[NULLIFIER, &nullifier_hash],
-            &address_tree_pubkey,
-            &crate::ID,
-        );
+        let (nullifier_address, nullifier_seed) =
+            derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_pubkey);
 
-        // Get Merkle root for proof verification
-        let expected_root = read_state_merkle_tree_root(
-            &ctx.accounts.input_merkle_tree.to_account_info(),
-            input_root_index,
-        )?;
+        // Get Merkle root for proof verification; a sub-pool's tree stands in for the allowlist
+        let input_merkle_tree = ctx.accounts.input_merkle_tree.to_account_info();
+        let expected_root = if pool_id == 0 {
+            read_spendable_root(&ctx.accounts.config, &input_merkle_tree, input_root_index)?
+        } else {
+            read_tree_root(&input_merkle_tree, input_root_index)?
+        };
 
         // Construct public inputs for ZK verification
         // Circuit inputs: [root, nullifierHash, recipient, relayer, fee]
@@ -224,37 +369,7 @@ pub mod privacy_vault {
         ];
 
         // Verify Groth16 proof
-        let proof_a = decompress_g1(&zk_proof.a).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let proof_b = decompress_g2(&zk_proof.b).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let proof_c = decompress_g1(&zk_proof.c).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let mut verifier = Groth16Verifier::new(
-            &proof_a,
-            &proof_b,
-            &proof_c,
-            &public_inputs,
-            &crate::verifying_key::VERIFYINGKEY_WITHDRAW,
-        )
-        .map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        verifier.verify().map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
+        verify_groth16(&zk_proof, &public_inputs, &WITHDRAW_KEY)?;
 
         // Create nullifier account (prevents double-spending)
         let mut nullifier_account = LightAccount::<NullifierAccount>::new_init(
@@ -264,12 +379,31 @@ pub mod privacy_vault {
         );
         nullifier_account.nullifier_hash = nullifier_hash;
         nullifier_account.used_at = Clock::get()?.unix_timestamp as u64;
+        record_spend_checkpoint(
+            &mut nullifier_account,
+            &ctx.accounts.config,
+            ctx.accounts.input_merkle_tree.key(),
+            input_root_index,
+            expected_root,
+        )?;
+        if let Some(pool) = ctx.accounts.pool.as_mut() {
+            pool.withdrawal_count = pool
+                .withdrawal_count
+                .checked_add(1)
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+        }
 
         msg!(
-            "Withdrawal verified. Nullifier: {:?}, Recipient: {}",
-            nullifier_hash,
+            "Withdrawal verified. Nullifier: {}, Recipient: {}",
+            Hex(&nullifier_hash),
             recipient
         );
+        emit!(WithdrawEvent {
+            nullifier_hash,
+            recipient,
+            amount: 0,
+            mint: spl_token::native_mint::ID,
+        });
 
         LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
             .with_light_account(nullifier_account)?
@@ -278,30 +412,49 @@ pub mod privacy_vault {
             ])
             .invoke(light_cpi_accounts)?;
 
-        Ok(())
+        Ok(nullifier_address)
     }
 
-    /// Generate proof of innocence
-    /// Proves deposit is in an approved association set without revealing which deposit
+    /// Withdraw only while an external oracle account satisfies `predicate`
+    /// Same as `withdraw`, plus an `oracle` account whose owner must be on the config's
+    /// `oracle_owner_allowlist` (`UntrustedOracle` otherwise; an empty list trusts none).
+    /// The oracle value is decoded by `OraclePredicate::read_value` and compared with
+    /// `OraclePredicate::holds`; fails with `OracleConditionNotMet` if it does not hold.
+    /// Returns the new nullifier address as return data
     #[allow(clippy::too_many_arguments)]
-    pub fn prove_innocence<'info>(
-        ctx: Context<'_, '_, '_, 'info, ProveInnocenceAccounts<'info>>,
+    pub fn withdraw_conditional<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawConditionalAccounts<'info>>,
         proof: ValidityProof,
         address_tree_info: PackedAddressTreeInfo,
         output_state_tree_index: u8,
         system_accounts_offset: u8,
         input_root_index: u16,
-        association_set_root: [u8; 32],
         nullifier_hash: [u8; 32],
-        association_set_id: u8,
+        recipient: Pubkey,
         zk_proof: CompressedProof,
-    ) -> Result<()> {
+        predicate: OraclePredicate,
+    ) -> Result<[u8; 32]> {
+        check_recipient_allowlist_unset(&ctx.accounts.config)?;
+        // The payout account is the one the proof commits to
+        require!(
+            ctx.accounts.recipient.key() == recipient,
+            ErrorCode::RecipientMismatch
+        );
+        check_oracle_predicate(
+            &ctx.accounts.config,
+            &ctx.accounts.oracle.to_account_info(),
+            &predicate,
+        )?;
+
         let light_cpi_accounts = CpiAccounts::new(
             ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
             crate::LIGHT_CPI_SIGNER,
         );
 
+        // Withdrawals open once the config's launch window has passed
+        check_withdrawals_enabled(&ctx.accounts.config)?;
+
         let address_tree_pubkey = address_tree_info
             .get_tree_pubkey(&light_cpi_accounts)
             .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
@@ -311,112 +464,93 @@ pub mod privacy_vault {
             return Err(ProgramError::InvalidAccountData.into());
         }
 
-        // Create innocence proof record
-        let (proof_address, proof_seed) = derive_address(
-            &[INNOCENCE_PROOF, &nullifier_hash, &[association_set_id]],
-            &address_tree_pubkey,
-            &crate::ID,
-        );
+        let (nullifier_address, nullifier_seed) =
+            derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_pubkey);
 
-        // Get deposit tree root
-        let deposit_root = read_state_merkle_tree_root(
-            &ctx.accounts.deposit_merkle_tree.to_account_info(),
+        let expected_root = read_spendable_root(
+            &ctx.accounts.config,
+            &ctx.accounts.input_merkle_tree.to_account_info(),
             input_root_index,
         )?;
 
-        // Verify ZK proof of membership in both trees
-        // Circuit inputs: [depositRoot, associationSetRoot, nullifierHash, associationSetId, timestamp]
-        let mut association_set_id_bytes = [0u8; 32];
-        association_set_id_bytes[31] = association_set_id;
-
-        let timestamp = Clock::get()?.unix_timestamp as u64;
-        let mut timestamp_bytes = [0u8; 32];
-        timestamp_bytes[24..32].copy_from_slice(&timestamp.to_be_bytes());
-
+        // Circuit inputs: [root, nullifierHash, recipient, relayer, fee]
         let public_inputs: [[u8; 32]; 5] = [
-            deposit_root,
-            association_set_root,
+            expected_root,
             nullifier_hash,
-            association_set_id_bytes,
-            timestamp_bytes,
+            recipient.to_bytes(),
+            [0u8; 32], // No relayer for now
+            [0u8; 32], // No fee for now
         ];
 
-        let proof_a = decompress_g1(&zk_proof.a).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let proof_b = decompress_g2(&zk_proof.b).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let proof_c = decompress_g1(&zk_proof.c).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let mut verifier = Groth16Verifier::new(
-            &proof_a,
-            &proof_b,
-            &proof_c,
-            &public_inputs,
-            &crate::verifying_key::VERIFYINGKEY_INNOCENCE,
-        )
-        .map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        verifier.verify().map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
+        verify_groth16(&zk_proof, &public_inputs, &WITHDRAW_KEY)?;
 
-        // Store innocence proof on-chain
-        let mut innocence_account = LightAccount::<InnocenceProofAccount>::new_init(
+        let mut nullifier_account = LightAccount::<NullifierAccount>::new_init(
             &crate::ID,
-            Some(proof_address),
+            Some(nullifier_address),
             output_state_tree_index,
         );
-        innocence_account.nullifier_hash = nullifier_hash;
-        innocence_account.association_set_id = association_set_id;
-        innocence_account.proven_at = Clock::get()?.unix_timestamp as u64;
+        nullifier_account.nullifier_hash = nullifier_hash;
+        nullifier_account.used_at = Clock::get()?.unix_timestamp as u64;
+        record_spend_checkpoint(
+            &mut nullifier_account,
+            &ctx.accounts.config,
+            ctx.accounts.input_merkle_tree.key(),
+            input_root_index,
+            expected_root,
+        )?;
 
         msg!(
-            "Innocence proven for nullifier: {:?}, association set: {}",
-            nullifier_hash,
-            association_set_id
+            "Conditional withdrawal verified. Nullifier: {}, Recipient: {}, Oracle: {}",
+            Hex(&nullifier_hash),
+            recipient,
+            ctx.accounts.oracle.key()
         );
+        emit!(WithdrawEvent {
+            nullifier_hash,
+            recipient,
+            amount: 0,
+            mint: spl_token::native_mint::ID,
+        });
 
         LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
-            .with_light_account(innocence_account)?
+            .with_light_account(nullifier_account)?
             .with_new_addresses(&[
-                address_tree_info.into_new_address_params_assigned_packed(proof_seed, Some(0))
+                address_tree_info.into_new_address_params_assigned_packed(nullifier_seed, Some(0))
             ])
             .invoke(light_cpi_accounts)?;
 
-        Ok(())
+        Ok(nullifier_address)
     }
 
-    /// Deposit SPL tokens into the privacy pool
-    /// Creates a commitment for token deposits
+    /// Withdraw against the root held in a `RootSnapshot` instead of the live tree
+    /// Same as `withdraw`, but the proof is checked against `root_snapshot.root`, which
+    /// must be at most `MAX_ROOT_SNAPSHOT_AGE_SLOTS` old (`RootSnapshotStale` otherwise).
+    /// Returns the new nullifier address as return data
     #[allow(clippy::too_many_arguments)]
-    pub fn deposit_token<'info>(
-        ctx: Context<'_, '_, '_, 'info, DepositTokenAccounts<'info>>,
+    pub fn withdraw_with_root<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawWithRootAccounts<'info>>,
         proof: ValidityProof,
         address_tree_info: PackedAddressTreeInfo,
         output_state_tree_index: u8,
         system_accounts_offset: u8,
-        commitment: [u8; 32],
-        amount: u64,
-    ) -> Result<()> {
+        nullifier_hash: [u8; 32],
+        recipient: Pubkey,
+        zk_proof: CompressedProof,
+    ) -> Result<[u8; 32]> {
+        check_recipient_allowlist_unset(&ctx.accounts.config)?;
+        let root_snapshot = &ctx.accounts.root_snapshot;
+        check_root_snapshot(root_snapshot, Clock::get()?.slot)?;
+        check_merkle_tree_allowed(&ctx.accounts.config, &root_snapshot.merkle_tree)?;
+
         let light_cpi_accounts = CpiAccounts::new(
             ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
             crate::LIGHT_CPI_SIGNER,
         );
 
+        // Withdrawals open once the config's launch window has passed
+        check_withdrawals_enabled(&ctx.accounts.config)?;
+
         let address_tree_pubkey = address_tree_info
             .get_tree_pubkey(&light_cpi_accounts)
             .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
@@ -426,72 +560,100 @@ pub mod privacy_vault {
             return Err(ProgramError::InvalidAccountData.into());
         }
 
-        // Transfer tokens from user to vault
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.user_token_account.to_account_info(),
-            to: ctx.accounts.vault_token_account.to_account_info(),
-            authority: ctx.accounts.signer.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
+        // Create nullifier account to prevent double-spending
+        let (nullifier_address, nullifier_seed) =
+            derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_pubkey);
 
-        // Derive deposit address including token mint
-        let token_mint = ctx.accounts.user_token_account.mint;
-        let (address, address_seed) = derive_address(
-            &[DEPOSIT, &commitment, token_mint.as_ref()],
-            &address_tree_pubkey,
-            &crate::ID,
-        );
+        // Circuit inputs: [root, nullifierHash, recipient, relayer, fee]
+        let relayer_bytes = [0u8; 32]; // No relayer for now
+        let fee_bytes = [0u8; 32]; // No fee for now
 
-        let mut deposit_account = LightAccountPoseidon::<TokenDepositAccount>::new_init(
+        let public_inputs: [[u8; 32]; 5] = [
+            root_snapshot.root,
+            nullifier_hash,
+            recipient.to_bytes(),
+            relayer_bytes,
+            fee_bytes,
+        ];
+
+        verify_groth16(&zk_proof, &public_inputs, &WITHDRAW_KEY)?;
+
+        let mut nullifier_account = LightAccount::<NullifierAccount>::new_init(
             &crate::ID,
-            Some(address),
+            Some(nullifier_address),
             output_state_tree_index,
         );
-
-        deposit_account.commitment = Commitment::new(commitment);
-        deposit_account.token_mint_hash = TokenMintHash::from_pubkey(&token_mint);
-        deposit_account.amount = amount;
-        deposit_account.timestamp = Clock::get()?.unix_timestamp as u64;
+        nullifier_account.nullifier_hash = nullifier_hash;
+        nullifier_account.used_at = Clock::get()?.unix_timestamp as u64;
+        record_spend_checkpoint(
+            &mut nullifier_account,
+            &ctx.accounts.config,
+            root_snapshot.merkle_tree,
+            root_snapshot.root_index,
+            root_snapshot.root,
+        )?;
 
         msg!(
-            "Token deposit created: commitment {:?}, amount {}, mint {}",
-            commitment,
-            amount,
-            token_mint
+            "Withdrawal verified against snapshot from slot {}. Nullifier: {}, Recipient: {}",
+            root_snapshot.slot,
+            Hex(&nullifier_hash),
+            recipient
         );
+        emit!(WithdrawEvent {
+            nullifier_hash,
+            recipient,
+            amount: 0,
+            mint: spl_token::native_mint::ID,
+        });
 
         LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
-            .with_light_account_poseidon(deposit_account)?
+            .with_light_account(nullifier_account)?
             .with_new_addresses(&[
-                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(0))
+                address_tree_info.into_new_address_params_assigned_packed(nullifier_seed, Some(0))
             ])
             .invoke(light_cpi_accounts)?;
 
-        Ok(())
+        Ok(nullifier_address)
     }
 
-    /// Withdraw SPL tokens from the privacy pool
-    /// Verifies ZK proof and transfers tokens to recipient
+    /// Withdraw against a deposit root the caller passes as an argument
+    /// For integrators that track roots themselves: no tree account is read. `root` is
+    /// only accepted if it equals `root_snapshot.root`, or if `root_snapshot_ring` published
+    /// it within `MAX_ROOT_SNAPSHOT_AGE_SLOTS` (`InvalidMerkleRoot` otherwise), with the same
+    /// allowlist check as `withdraw_with_root`. Exactly one of the two is passed. Snapshots
+    /// and rings are program-owned and only ever written from a real tree's root history,
+    /// so a forged root has nothing to match.
+    /// Returns the new nullifier address as return data
     #[allow(clippy::too_many_arguments)]
-    pub fn withdraw_token<'info>(
-        ctx: Context<'_, '_, '_, 'info, WithdrawTokenAccounts<'info>>,
+    pub fn withdraw_explicit_root<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawExplicitRootAccounts<'info>>,
         proof: ValidityProof,
         address_tree_info: PackedAddressTreeInfo,
         output_state_tree_index: u8,
         system_accounts_offset: u8,
-        input_root_index: u16,
+        root: [u8; 32],
         nullifier_hash: [u8; 32],
-        amount: u64,
+        recipient: Pubkey,
         zk_proof: CompressedProof,
-    ) -> Result<()> {
+    ) -> Result<[u8; 32]> {
+        check_recipient_allowlist_unset(&ctx.accounts.config)?;
+        let (merkle_tree, root_index, root_slot) = find_explicit_root(
+            ctx.accounts.root_snapshot.as_deref(),
+            ctx.accounts.root_snapshot_ring.as_deref(),
+            &root,
+            Clock::get()?.slot,
+        )?;
+        check_merkle_tree_allowed(&ctx.accounts.config, &merkle_tree)?;
+
         let light_cpi_accounts = CpiAccounts::new(
             ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
             crate::LIGHT_CPI_SIGNER,
         );
 
+        // Withdrawals open once the config's launch window has passed
+        check_withdrawals_enabled(&ctx.accounts.config)?;
+
         let address_tree_pubkey = address_tree_info
             .get_tree_pubkey(&light_cpi_accounts)
             .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
@@ -501,85 +663,24 @@ pub mod privacy_vault {
             return Err(ProgramError::InvalidAccountData.into());
         }
 
-        // Create nullifier account
-        let (nullifier_address, nullifier_seed) = derive_address(
-            &[NULLIFIER, &nullifier_hash],
-            &address_tree_pubkey,
-            &crate::ID,
-        );
+        // Create nullifier account to prevent double-spending
+        let (nullifier_address, nullifier_seed) =
+            derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_pubkey);
 
-        // Get Merkle root
-        let expected_root = read_state_merkle_tree_root(
-            &ctx.accounts.input_merkle_tree.to_account_info(),
-            input_root_index,
-        )?;
-
-        // Construct public inputs
-        let recipient = ctx.accounts.recipient_token_account.owner;
-        let relayer_bytes = [0u8; 32];
-        let fee_bytes = [0u8; 32];
+        // Circuit inputs: [root, nullifierHash, recipient, relayer, fee]
+        let relayer_bytes = [0u8; 32]; // No relayer for now
+        let fee_bytes = [0u8; 32]; // No fee for now
 
         let public_inputs: [[u8; 32]; 5] = [
-            expected_root,
+            root,
             nullifier_hash,
             recipient.to_bytes(),
             relayer_bytes,
             fee_bytes,
         ];
 
-        // Verify ZK proof
-        let proof_a = decompress_g1(&zk_proof.a).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let proof_b = decompress_g2(&zk_proof.b).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let proof_c = decompress_g1(&zk_proof.c).map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        let mut verifier = Groth16Verifier::new(
-            &proof_a,
-            &proof_b,
-            &proof_c,
-            &public_inputs,
-            &crate::verifying_key::VERIFYINGKEY_WITHDRAW,
-        )
-        .map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        verifier.verify().map_err(|e| {
-            let code: u32 = e.into();
-            Error::from(ProgramError::Custom(code))
-        })?;
-
-        // Transfer tokens from vault to recipient using PDA authority
-        let vault_bump = ctx.bumps.vault_authority;
-        let token_mint = ctx.accounts.vault_token_account.mint;
-        let seeds = &[
-            b"vault_authority".as_ref(),
-            token_mint.as_ref(),
-            &[vault_bump],
-        ];
-        let signer_seeds = &[&seeds[..]];
-
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.vault_token_account.to_account_info(),
-            to: ctx.accounts.recipient_token_account.to_account_info(),
-            authority: ctx.accounts.vault_authority.to_account_info(),
-        };
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
-        token::transfer(cpi_ctx, amount)?;
+        verify_groth16(&zk_proof, &public_inputs, &WITHDRAW_KEY)?;
 
-        // Create nullifier account
         let mut nullifier_account = LightAccount::<NullifierAccount>::new_init(
             &crate::ID,
             Some(nullifier_address),
@@ -587,13 +688,26 @@ pub mod privacy_vault {
         );
         nullifier_account.nullifier_hash = nullifier_hash;
         nullifier_account.used_at = Clock::get()?.unix_timestamp as u64;
+        record_spend_checkpoint(
+            &mut nullifier_account,
+            &ctx.accounts.config,
+            merkle_tree,
+            root_index,
+            root,
+        )?;
 
         msg!(
-            "Token withdrawal: nullifier {:?}, amount {}, mint {}",
-            nullifier_hash,
-            amount,
-            token_mint
+            "Withdrawal verified against explicit root from slot {}. Nullifier: {}, Recipient: {}",
+            root_slot,
+            Hex(&nullifier_hash),
+            recipient
         );
+        emit!(WithdrawEvent {
+            nullifier_hash,
+            recipient,
+            amount: 0,
+            mint: spl_token::native_mint::ID,
+        });
 
         LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
             .with_light_account(nullifier_account)?
@@ -602,194 +716,3321 @@ pub mod privacy_vault {
             ])
             .invoke(light_cpi_accounts)?;
 
-        Ok(())
+        Ok(nullifier_address)
     }
 
-    /// Deposit SOL into the privacy pool
-    /// Transfers SOL to vault PDA and records commitment
-    pub fn deposit_sol<'info>(
-        ctx: Context<'_, '_, '_, 'info, DepositSolAccounts<'info>>,
-        commitment: [u8; 32],
-        amount: u64,
+    /// Withdraw a batch of up to MAX_WITHDRAW_BATCH independent notes in one transaction
+    /// Each entry `(input_root_index, nullifier_hash, recipient, zk_proof)` is verified
+    /// exactly like `withdraw`, then every nullifier is created in a single Light CPI.
+    /// Any failing entry fails the whole batch.
+    ///
+    /// Verification cost scales with the batch (one pairing check per proof), so a
+    /// full batch needs a raised compute budget; only the CPI and fee are shared.
+    /// Returns the new nullifier addresses as return data, in batch order
+    #[allow(clippy::too_many_arguments)]
+    pub fn withdraw_many<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_infos: Vec<PackedAddressTreeInfo>,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        input_root_indices: Vec<u16>,
+        nullifier_hashes: Vec<[u8; 32]>,
+        recipients: Vec<Pubkey>,
+        zk_proofs: Vec<CompressedProof>,
+    ) -> Result<Vec<[u8; 32]>> {
+        check_recipient_allowlist_unset(&ctx.accounts.config)?;
+        let batch_size = nullifier_hashes.len();
+        if batch_size == 0
+            || batch_size > MAX_WITHDRAW_BATCH
+            || address_tree_infos.len() != batch_size
+            || input_root_indices.len() != batch_size
+            || recipients.len() != batch_size
+            || zk_proofs.len() != batch_size
+        {
+            msg!("Invalid withdraw batch size: {}", batch_size);
+            return Err(ErrorCode::InvalidBatchSize.into());
+        }
+        // Screen every proof before any entry spends compute on decompression
+        for zk_proof in zk_proofs.iter() {
+            check_proof_encoding(zk_proof)?;
+        }
+        for (i, nullifier_hash) in nullifier_hashes.iter().enumerate() {
+            if nullifier_hashes[..i].contains(nullifier_hash) {
+                return Err(ErrorCode::NullifierAlreadyUsed.into());
+            }
+        }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // Withdrawals open once the config's launch window has passed
+        check_withdrawals_enabled(&ctx.accounts.config)?;
+
+        let used_at = Clock::get()?.unix_timestamp as u64;
+        let mut cpi = LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof);
+        let mut new_addresses = Vec::with_capacity(batch_size);
+        let mut nullifier_addresses = Vec::with_capacity(batch_size);
+
+        for (i, address_tree_info) in address_tree_infos.iter().enumerate() {
+            let address_tree_pubkey = address_tree_info
+                .get_tree_pubkey(&light_cpi_accounts)
+                .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+            if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+                msg!("Invalid address tree");
+                return Err(ProgramError::InvalidAccountData.into());
+            }
+
+            // Get Merkle root for this entry's proof
+            let expected_root = read_spendable_root(
+                &ctx.accounts.config,
+                &ctx.accounts.input_merkle_tree.to_account_info(),
+                input_root_indices[i],
+            )?;
+
+            // Circuit inputs: [root, nullifierHash, recipient, relayer, fee]
+            let public_inputs: [[u8; 32]; 5] = [
+                expected_root,
+                nullifier_hashes[i],
+                recipients[i].to_bytes(),
+                [0u8; 32], // No relayer for now
+                [0u8; 32], // No fee for now
+            ];
+
+            verify_groth16(&zk_proofs[i], &public_inputs, &WITHDRAW_KEY)?;
+
+            let (nullifier_address, nullifier_seed) = derive_nullifier_address(
+                &nullifier_hashes[i],
+                &NULLIFIER_DOMAIN,
+                &address_tree_pubkey,
+            );
+
+            let mut nullifier_account = LightAccount::<NullifierAccount>::new_init(
+                &crate::ID,
+                Some(nullifier_address),
+                output_state_tree_index,
+            );
+            nullifier_account.nullifier_hash = nullifier_hashes[i];
+            nullifier_account.used_at = used_at;
+            record_spend_checkpoint(
+                &mut nullifier_account,
+                &ctx.accounts.config,
+                ctx.accounts.input_merkle_tree.key(),
+                input_root_indices[i],
+                expected_root,
+            )?;
+
+            cpi = cpi.with_light_account(nullifier_account)?;
+            nullifier_addresses.push(nullifier_address);
+            new_addresses.push(
+                address_tree_info
+                    .into_new_address_params_assigned_packed(nullifier_seed, Some(i as u8)),
+            );
+
+            msg!(
+                "Batch withdrawal {} verified. Nullifier: {}, Recipient: {}",
+                i,
+                Hex(&nullifier_hashes[i]),
+                recipients[i]
+            );
+            emit!(WithdrawEvent {
+                nullifier_hash: nullifier_hashes[i],
+                recipient: recipients[i],
+                amount: 0,
+                mint: spl_token::native_mint::ID,
+            });
+        }
+
+        cpi.with_new_addresses(&new_addresses)
+            .invoke(light_cpi_accounts)?;
+
+        Ok(nullifier_addresses)
+    }
+
+    /// Generate proof of innocence
+    /// Proves deposit is in an approved association set without revealing which deposit
+    /// For ids below `BITMAP_ASSOCIATION_SETS`, also sets bit `association_set_id` in the
+    /// nullifier's `InnocenceBitmapAccount`, created on its first such proof
+    /// (`innocence_bitmap: None`) and updated after that. Higher ids have no bit and must pass
+    /// `innocence_bitmap: None`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn prove_innocence<'info>(
+        ctx: Context<'_, '_, '_, 'info, ProveInnocenceAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        input_root_index: u16,
+        association_set_root: [u8; 32],
+        nullifier_hash: [u8; 32],
+        association_set_id: u16,
+        innocence_bitmap: Option<InnocenceBitmapState>,
+        zk_proof: CompressedProof,
     ) -> Result<()> {
-        // Transfer SOL from signer to vault PDA
-        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-            ctx.accounts.signer.key,
-            ctx.accounts.vault.key,
-            amount,
+        check_association_set(
+            &ctx.accounts.association_set.to_account_info(),
+            &association_set_root,
+        )?;
+        let has_bitmap_bit = association_set_id < BITMAP_ASSOCIATION_SETS;
+        if !has_bitmap_bit && innocence_bitmap.is_some() {
+            msg!("Association set {} has no bitmap bit", association_set_id);
+            return Err(ErrorCode::InvalidAssociationSetId.into());
+        }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
         );
 
-        anchor_lang::solana_program::program::invoke(
-            &transfer_ix,
+        let address_tree_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        // Create innocence proof record
+        let (proof_address, proof_seed) = derive_address(
             &[
-                ctx.accounts.signer.to_account_info(),
-                ctx.accounts.vault.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
+                INNOCENCE_PROOF,
+                &nullifier_hash,
+                &association_set_id.to_le_bytes(),
             ],
+            &address_tree_pubkey,
+            &crate::ID,
+        );
+
+        // Get deposit tree root
+        let deposit_root = read_spendable_root(
+            &ctx.accounts.config,
+            &ctx.accounts.deposit_merkle_tree.to_account_info(),
+            input_root_index,
         )?;
 
+        // Verify ZK proof of membership in both trees
+        // Circuit inputs: [depositRoot, associationSetRoot, nullifierHash, associationSetId, timestamp]
+        let association_set_id_bytes = encode_u64_be_field(association_set_id as u64);
+
+        let timestamp = Clock::get()?.unix_timestamp as u64;
+        let timestamp_bytes = encode_u64_be_field(timestamp);
+
+        let public_inputs: [[u8; 32]; 5] = [
+            deposit_root,
+            association_set_root,
+            nullifier_hash,
+            association_set_id_bytes,
+            timestamp_bytes,
+        ];
+
+        verify_groth16(&zk_proof, &public_inputs, &INNOCENCE_KEY)?;
+
+        // Store innocence proof on-chain
+        let mut innocence_account = LightAccount::<InnocenceProofAccount>::new_init(
+            &crate::ID,
+            Some(proof_address),
+            output_state_tree_index,
+        );
+        innocence_account.nullifier_hash = nullifier_hash;
+        innocence_account.association_set_id = association_set_id;
+        innocence_account.proven_at = Clock::get()?.unix_timestamp as u64;
+        innocence_account.expires_at =
+            innocence_proof_expiry(&ctx.accounts.config, innocence_account.proven_at)?;
+
+        // Record the set in the nullifier's bitmap, creating it on the first proof; sets
+        // without a bit leave the bitmap alone
+        let mut new_addresses =
+            vec![address_tree_info.into_new_address_params_assigned_packed(proof_seed, Some(0))];
+        let mut bitmap_account = match innocence_bitmap {
+            Some(bitmap) => Some(LightAccount::<InnocenceBitmapAccount>::new_mut(
+                &crate::ID,
+                &bitmap.account_meta,
+                InnocenceBitmapAccount {
+                    nullifier_hash,
+                    satisfied_sets: bitmap.satisfied_sets,
+                },
+            )?),
+            None if has_bitmap_bit => {
+                let (bitmap_address, bitmap_seed) = derive_address(
+                    &[INNOCENCE_BITMAP, &nullifier_hash],
+                    &address_tree_pubkey,
+                    &crate::ID,
+                );
+                new_addresses.push(
+                    address_tree_info.into_new_address_params_assigned_packed(bitmap_seed, Some(1)),
+                );
+                let mut bitmap_account = LightAccount::<InnocenceBitmapAccount>::new_init(
+                    &crate::ID,
+                    Some(bitmap_address),
+                    output_state_tree_index,
+                );
+                bitmap_account.nullifier_hash = nullifier_hash;
+                Some(bitmap_account)
+            }
+            None => None,
+        };
+        if let Some(bitmap_account) = &mut bitmap_account {
+            bitmap_account.satisfied_sets |= 1u64 << association_set_id;
+        }
+
         msg!(
-            "SOL deposit: {} lamports, commitment: {:?}",
-            amount,
-            commitment
+            "Innocence proven for nullifier: {}, association set: {}",
+            Hex(&nullifier_hash),
+            association_set_id
         );
 
+        let mut cpi = LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(innocence_account)?;
+        if let Some(bitmap_account) = bitmap_account {
+            cpi = cpi.with_light_account(bitmap_account)?;
+        }
+        cpi.with_new_addresses(&new_addresses)
+            .invoke(light_cpi_accounts)?;
+
         Ok(())
     }
 
-    /// Withdraw SOL from the privacy pool
-    /// Simplified version - verifies basic parameters and transfers SOL
-    /// Full ZK verification handled separately via withdraw instruction
-    pub fn withdraw_sol<'info>(
-        ctx: Context<'_, '_, '_, 'info, WithdrawSolAccounts<'info>>,
-        nullifier_hash: [u8; 32],
+    /// Create the canonical vault token account for a mint
+    /// The account is the ATA owned by the `[b"vault_authority", mint]` PDA; calling
+    /// this again for an initialized mint is a no-op
+    pub fn initialize_token_vault(ctx: Context<InitializeTokenVaultAccounts>) -> Result<()> {
+        let cpi_accounts = associated_token::Create {
+            payer: ctx.accounts.signer.to_account_info(),
+            associated_token: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.associated_token_program.to_account_info();
+        associated_token::create_idempotent(CpiContext::new(cpi_program, cpi_accounts))?;
+
+        msg!(
+            "Token vault for mint {}: {}",
+            ctx.accounts.mint.key(),
+            ctx.accounts.vault_token_account.key()
+        );
+
+        Ok(())
+    }
+
+    /// Deposit SPL tokens into the privacy pool
+    /// Creates a commitment for token deposits
+    /// `payer` covers fees and `depositor` authorizes the transfer, so a sponsor can pay
+    /// The output tree must pass the config's tree allowlist (`MerkleTreeNotAllowed`) and,
+    /// if the config pins a `deposit_state_tree`, be that tree (`InvalidDepositStateTree`)
+    /// Returns the new deposit address as return data
+    #[allow(clippy::too_many_arguments)]
+    pub fn deposit_token<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositTokenAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        commitment: [u8; 32],
         amount: u64,
-    ) -> Result<()> {
-        let vault_bump = ctx.bumps.vault;
+    ) -> Result<[u8; 32]> {
+        check_commitment(&commitment)?;
 
-        // Transfer SOL from vault PDA to recipient
-        let seeds = &[
-            b"vault".as_ref(),
-            &[vault_bump],
-        ];
-        let signer_seeds = &[&seeds[..]];
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.payer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
 
-        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
-            ctx.accounts.vault.key,
-            ctx.accounts.recipient.key,
-            amount,
+        // Default-pool deposits go to the config's pinned deposit state tree, if it set one
+        let output_tree = light_cpi_accounts
+            .get_tree_account_info(output_state_tree_index as usize)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?
+            .key();
+        check_deposit_state_tree(&ctx.accounts.config, &output_tree)?;
+
+        let address_tree_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        // Transfer tokens from the depositor to the vault
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, amount)?;
+
+        // Derive deposit address including token mint
+        let token_mint = ctx.accounts.user_token_account.mint;
+        let (address, address_seed) =
+            derive_token_deposit_address(&commitment, Some(&token_mint), &address_tree_pubkey);
+
+        let mut deposit_account = LightAccountPoseidon::<TokenDepositAccount>::new_init(
+            &crate::ID,
+            Some(address),
+            output_state_tree_index,
         );
 
-        anchor_lang::solana_program::program::invoke_signed(
-            &transfer_ix,
-            &[
-                ctx.accounts.vault.to_account_info(),
-                ctx.accounts.recipient.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-            signer_seeds,
-        )?;
+        deposit_account.commitment = Commitment::new(commitment);
+        deposit_account.token_mint_hash = TokenMintHash::from_pubkey(&token_mint);
+        deposit_account.amount = amount;
+        deposit_account.timestamp = Clock::get()?.unix_timestamp as u64;
 
         msg!(
-            "SOL withdrawal: {} lamports to {}, nullifier: {:?}",
+            "Token deposit created: commitment {}, amount {}, mint {}",
+            Hex(&commitment),
             amount,
-            ctx.accounts.recipient.key(),
-            nullifier_hash
+            token_mint
         );
 
-        Ok(())
-    }
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account_poseidon(deposit_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(0))
+            ])
+            .invoke(light_cpi_accounts)?;
+
+        Ok(address)
+    }
+
+    /// Deposit SPL tokens on an off-chain permit instead of the owner's signature
+    /// The owner approves the `[PERMIT_DELEGATE]` PDA as delegate of `user_token_account`
+    /// once; each deposit then carries their Ed25519 signature over `deposit_permit_message`,
+    /// checked via instruction introspection, so a relayer can submit it alone. The permit
+    /// binds the commitment and amount, and its nonce must equal the owner's `PermitNonce`,
+    /// which is incremented here, so each permit is spent at most once.
+    /// The output tree is checked against the allowlist and the pin like in `deposit_token`.
+    /// Returns the new deposit address as return data
+    #[allow(clippy::too_many_arguments)]
+    pub fn deposit_with_permit<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositWithPermitAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        commitment: [u8; 32],
+        amount: u64,
+        permit: DepositPermit,
+    ) -> Result<[u8; 32]> {
+        check_commitment(&commitment)?;
+
+        let owner = ctx.accounts.user_token_account.owner;
+        let token_mint = ctx.accounts.user_token_account.mint;
+        verify_ed25519_instruction(
+            &ctx.accounts.instructions_sysvar.to_account_info(),
+            &owner,
+            &permit.signature,
+            &deposit_permit_message(
+                &owner,
+                &token_mint,
+                &commitment,
+                amount,
+                permit.nonce,
+                permit.deadline,
+            ),
+        )?;
+        require!(
+            Clock::get()?.unix_timestamp <= permit.deadline,
+            ErrorCode::PermitExpired
+        );
+
+        let permit_nonce = &mut ctx.accounts.permit_nonce;
+        if permit.nonce != permit_nonce.nonce {
+            msg!(
+                "Permit nonce {} does not match the owner's next nonce {}",
+                permit.nonce,
+                permit_nonce.nonce
+            );
+            return Err(ErrorCode::InvalidPermitNonce.into());
+        }
+        permit_nonce.owner = owner;
+        permit_nonce.nonce = permit_nonce
+            .nonce
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        permit_nonce.bump = ctx.bumps.permit_nonce;
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.payer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // Default-pool deposits go to the config's pinned deposit state tree, if it set one
+        let output_tree = light_cpi_accounts
+            .get_tree_account_info(output_state_tree_index as usize)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?
+            .key();
+        check_deposit_state_tree(&ctx.accounts.config, &output_tree)?;
+
+        let address_tree_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        // Pull the tokens as the owner's delegate; the token program enforces the allowance
+        let seeds = &[PERMIT_DELEGATE, &[ctx.bumps.permit_delegate]];
+        let signer_seeds = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.user_token_account.to_account_info(),
+            to: ctx.accounts.vault_token_account.to_account_info(),
+            authority: ctx.accounts.permit_delegate.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, amount)?;
+
+        // Same record as deposit_token, so withdrawals don't care how the deposit was made
+        let (address, address_seed) =
+            derive_token_deposit_address(&commitment, Some(&token_mint), &address_tree_pubkey);
+
+        let mut deposit_account = LightAccountPoseidon::<TokenDepositAccount>::new_init(
+            &crate::ID,
+            Some(address),
+            output_state_tree_index,
+        );
+
+        deposit_account.commitment = Commitment::new(commitment);
+        deposit_account.token_mint_hash = TokenMintHash::from_pubkey(&token_mint);
+        deposit_account.amount = amount;
+        deposit_account.timestamp = Clock::get()?.unix_timestamp as u64;
+
+        msg!(
+            "Permit deposit created: commitment {}, amount {}, mint {}, nonce {}",
+            Hex(&commitment),
+            amount,
+            token_mint,
+            permit.nonce
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account_poseidon(deposit_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(0))
+            ])
+            .invoke(light_cpi_accounts)?;
+
+        Ok(address)
+    }
+
+    /// Withdraw SPL tokens from the privacy pool
+    /// Verifies ZK proof and transfers tokens to recipient
+    /// The nullifier is created before the transfer, so the note is spent before value moves
+    /// Returns the new nullifier address as return data
+    #[allow(clippy::too_many_arguments)]
+    pub fn withdraw_token<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawTokenAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        input_root_index: u16,
+        nullifier_hash: [u8; 32],
+        amount: u64,
+        zk_proof: CompressedProof,
+    ) -> Result<[u8; 32]> {
+        check_recipient_allowlist_unset(&ctx.accounts.config)?;
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // Withdrawals open once the config's launch window has passed
+        check_withdrawals_enabled(&ctx.accounts.config)?;
+
+        let address_tree_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        // Create nullifier account
+        let (nullifier_address, nullifier_seed) =
+            derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_pubkey);
+
+        // Get Merkle root
+        let expected_root = read_spendable_root(
+            &ctx.accounts.config,
+            &ctx.accounts.input_merkle_tree.to_account_info(),
+            input_root_index,
+        )?;
+
+        // Construct public inputs
+        let recipient = ctx.accounts.recipient_token_account.owner;
+        let relayer_bytes = [0u8; 32];
+        let fee_bytes = [0u8; 32];
+
+        let public_inputs: [[u8; 32]; 5] = [
+            expected_root,
+            nullifier_hash,
+            recipient.to_bytes(),
+            relayer_bytes,
+            fee_bytes,
+        ];
+
+        // Verify ZK proof
+        verify_groth16(&zk_proof, &public_inputs, &WITHDRAW_KEY)?;
+
+        let vault_bump = ctx.bumps.vault_authority;
+        let token_mint = ctx.accounts.vault_token_account.mint;
+        check_vault_authority(&ctx.accounts.vault_authority.key(), &token_mint, vault_bump)?;
+
+        // Create nullifier account
+        let mut nullifier_account = LightAccount::<NullifierAccount>::new_init(
+            &crate::ID,
+            Some(nullifier_address),
+            output_state_tree_index,
+        );
+        nullifier_account.nullifier_hash = nullifier_hash;
+        nullifier_account.used_at = Clock::get()?.unix_timestamp as u64;
+        record_spend_checkpoint(
+            &mut nullifier_account,
+            &ctx.accounts.config,
+            ctx.accounts.input_merkle_tree.key(),
+            input_root_index,
+            expected_root,
+        )?;
+
+        msg!(
+            "Token withdrawal: nullifier {}, amount {}, mint {}",
+            Hex(&nullifier_hash),
+            amount,
+            token_mint
+        );
+        emit!(WithdrawEvent {
+            nullifier_hash,
+            recipient,
+            amount,
+            mint: token_mint,
+        });
+
+        // Mark the note spent before any tokens leave the vault
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(nullifier_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(nullifier_seed, Some(0))
+            ])
+            .invoke(light_cpi_accounts)?;
+
+        // Transfer tokens from vault to recipient using PDA authority
+        let seeds = &[
+            b"vault_authority".as_ref(),
+            token_mint.as_ref(),
+            &[vault_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let cpi_accounts = Transfer {
+            from: ctx.accounts.vault_token_account.to_account_info(),
+            to: ctx.accounts.recipient_token_account.to_account_info(),
+            authority: ctx.accounts.vault_authority.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer_seeds);
+        token::transfer(cpi_ctx, amount)?;
+
+        Ok(nullifier_address)
+    }
+
+    /// Deposit SOL into the privacy pool
+    /// Transfers SOL to vault PDA and records commitment
+    /// The record is a `TokenDepositAccount` with `TokenMintHash::native()` as its mint, at
+    /// `[DEPOSIT, commitment, NATIVE_DEPOSIT]` so it stays apart from wSOL token deposits
+    /// The output tree is checked against the allowlist and the pin like in `deposit_token`.
+    /// Returns the new deposit address as return data
+    #[allow(clippy::too_many_arguments)]
+    pub fn deposit_sol<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositSolAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        commitment: [u8; 32],
+        amount: u64,
+    ) -> Result<[u8; 32]> {
+        check_commitment(&commitment)?;
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // Default-pool deposits go to the config's pinned deposit state tree, if it set one
+        let output_tree = light_cpi_accounts
+            .get_tree_account_info(output_state_tree_index as usize)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?
+            .key();
+        check_deposit_state_tree(&ctx.accounts.config, &output_tree)?;
+
+        let address_tree_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        // Transfer SOL from signer to vault PDA
+        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+            ctx.accounts.signer.key,
+            ctx.accounts.vault.key,
+            amount,
+        );
+
+        anchor_lang::solana_program::program::invoke(
+            &transfer_ix,
+            &[
+                ctx.accounts.signer.to_account_info(),
+                ctx.accounts.vault.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        let (address, address_seed) =
+            derive_token_deposit_address(&commitment, None, &address_tree_pubkey);
+
+        let mut deposit_account = LightAccountPoseidon::<TokenDepositAccount>::new_init(
+            &crate::ID,
+            Some(address),
+            output_state_tree_index,
+        );
+
+        deposit_account.commitment = Commitment::new(commitment);
+        deposit_account.token_mint_hash = TokenMintHash::native();
+        deposit_account.amount = amount;
+        deposit_account.timestamp = Clock::get()?.unix_timestamp as u64;
+
+        msg!(
+            "SOL deposit: {} lamports, commitment: {}",
+            amount,
+            Hex(&commitment)
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account_poseidon(deposit_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(0))
+            ])
+            .invoke(light_cpi_accounts)?;
+
+        Ok(address)
+    }
+
+    /// Withdraw SOL from the privacy pool
+    /// Verifies ZK proof and transfers SOL from the vault PDA to recipient, like
+    /// `withdraw_token`; the proof's `recipient` input is the `recipient` account
+    /// The nullifier is created before the transfer, so the note is spent before value moves
+    /// Returns the new nullifier address as return data
+    #[allow(clippy::too_many_arguments)]
+    pub fn withdraw_sol<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawSolAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        input_root_index: u16,
+        nullifier_hash: [u8; 32],
+        amount: u64,
+        zk_proof: CompressedProof,
+    ) -> Result<[u8; 32]> {
+        check_recipient_allowlist_unset(&ctx.accounts.config)?;
+        // Reject self-transfers back into the vault and transfers to the system program
+        let recipient = ctx.accounts.recipient.key();
+        if recipient == ctx.accounts.vault.key() || recipient == ctx.accounts.system_program.key() {
+            msg!("Invalid recipient: {}", recipient);
+            return Err(ErrorCode::InvalidRecipient.into());
+        }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // Withdrawals open once the config's launch window has passed
+        check_withdrawals_enabled(&ctx.accounts.config)?;
+
+        let address_tree_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        // Create nullifier account
+        let (nullifier_address, nullifier_seed) =
+            derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_pubkey);
+
+        // Get Merkle root
+        let expected_root = read_spendable_root(
+            &ctx.accounts.config,
+            &ctx.accounts.input_merkle_tree.to_account_info(),
+            input_root_index,
+        )?;
+
+        // Circuit inputs: [root, nullifierHash, recipient, relayer, fee]
+        let public_inputs: [[u8; 32]; 5] = [
+            expected_root,
+            nullifier_hash,
+            recipient.to_bytes(),
+            [0u8; 32], // No relayer for now
+            [0u8; 32], // No fee for now
+        ];
+
+        // Verify ZK proof
+        verify_groth16(&zk_proof, &public_inputs, &WITHDRAW_KEY)?;
+
+        check_vault_balance(&ctx.accounts.vault.to_account_info(), amount)?;
+
+        let mut nullifier_account = LightAccount::<NullifierAccount>::new_init(
+            &crate::ID,
+            Some(nullifier_address),
+            output_state_tree_index,
+        );
+        nullifier_account.nullifier_hash = nullifier_hash;
+        nullifier_account.used_at = Clock::get()?.unix_timestamp as u64;
+        record_spend_checkpoint(
+            &mut nullifier_account,
+            &ctx.accounts.config,
+            ctx.accounts.input_merkle_tree.key(),
+            input_root_index,
+            expected_root,
+        )?;
+
+        // Mark the note spent before any lamports leave the vault
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(nullifier_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(nullifier_seed, Some(0))
+            ])
+            .invoke(light_cpi_accounts)?;
+
+        let vault_bump = ctx.bumps.vault;
+
+        // Transfer SOL from vault PDA to recipient
+        let seeds = &[
+            b"vault".as_ref(),
+            &[vault_bump],
+        ];
+        let signer_seeds = &[&seeds[..]];
+
+        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+            ctx.accounts.vault.key,
+            ctx.accounts.recipient.key,
+            amount,
+        );
+
+        anchor_lang::solana_program::program::invoke_signed(
+            &transfer_ix,
+            &[
+                ctx.accounts.vault.to_account_info(),
+                ctx.accounts.recipient.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+            signer_seeds,
+        )?;
+
+        msg!(
+            "SOL withdrawal: {} lamports to {}, nullifier: {}",
+            amount,
+            ctx.accounts.recipient.key(),
+            Hex(&nullifier_hash)
+        );
+        emit!(WithdrawEvent {
+            nullifier_hash,
+            recipient,
+            amount,
+            mint: spl_token::native_mint::ID,
+        });
+
+        Ok(nullifier_address)
+    }
+
+    /// Report the deployed program version and verifying keys
+    /// State-free and callable by anyone; the result is returned as return data
+    pub fn program_info(_ctx: Context<ProgramInfoAccounts>) -> Result<ProgramInfo> {
+        let keys = [
+            ("withdraw", &crate::verifying_key::VERIFYINGKEY_WITHDRAW),
+            ("innocence", &crate::verifying_key::VERIFYINGKEY_INNOCENCE),
+        ];
+
+        let mut circuits = Vec::with_capacity(keys.len());
+        for (name, vk) in keys {
+            let circuit = CircuitInfo {
+                name: name.to_string(),
+                version: CIRCUIT_VERSION,
+                nr_pubinputs: vk.nr_pubinputs as u8,
+                vk_id: verifying_key_id(vk)?,
+                generated: !is_placeholder_key(vk),
+            };
+            msg!(
+                "circuit {} v{}: {} public inputs, vk {}, generated: {}",
+                circuit.name,
+                circuit.version,
+                circuit.nr_pubinputs,
+                Hex(&circuit.vk_id),
+                circuit.generated
+            );
+            circuits.push(circuit);
+        }
+
+        msg!("privacy-vault v{}", PROGRAM_VERSION);
+
+        Ok(ProgramInfo {
+            version: PROGRAM_VERSION.to_string(),
+            circuits,
+        })
+    }
+
+    /// Create the program config PDA with the signer as admin
+    /// Only the program's upgrade authority can call it, so nobody can front-run the deploy
+    /// and take the admin role. `dev_mode` starts disabled; only the admin can turn it on via
+    /// `set_dev_mode`. The config is a singleton `[CONFIG]` PDA, so calling this again once it
+    /// exists fails with `ConfigAlreadyInitialized` instead of overwriting the admin settings.
+    /// Also fails with `InvalidCpiSigner` if `LIGHT_CPI_SIGNER` was built for another program id.
+    pub fn initialize_config(ctx: Context<InitializeConfigAccounts>) -> Result<()> {
+        check_light_cpi_signer()?;
+        let config = &mut ctx.accounts.config;
+        // A freshly created account is zeroed, so any admin means it was set up before
+        require!(
+            config.admin == Pubkey::default(),
+            ErrorCode::ConfigAlreadyInitialized
+        );
+        config.admin = ctx.accounts.signer.key();
+        config.dev_mode = false;
+        config.bump = ctx.bumps.config;
+        config.nullifier_ttl = 0;
+        config.merkle_tree_allowlist = Vec::new();
+        config.innocence_proof_ttl = 0;
+        config.oracle_owner_allowlist = Vec::new();
+        config.recipient_allowlist_root = [0u8; 32];
+        config.pool_state_trees = Vec::new();
+        config.withdrawals_enabled_at_slot = 0;
+        config.deposit_state_tree = None;
+
+        msg!("Initialized config with admin: {}", config.admin);
+
+        Ok(())
+    }
+
+    /// Enable or disable dev-only instructions such as `close_nullifier`
+    /// Admin only. Never enable on a deployment holding real funds.
+    pub fn set_dev_mode(ctx: Context<AdminAccounts>, dev_mode: bool) -> Result<()> {
+        ctx.accounts.config.dev_mode = dev_mode;
+
+        msg!(
+            "Dev mode set to {} by {}",
+            dev_mode,
+            ctx.accounts.signer.key()
+        );
+
+        Ok(())
+    }
+
+    /// Set how long new nullifiers live before `gc_nullifier` may close them
+    /// Admin only. `0` disables expiry; existing nullifiers keep the `expires_at` they
+    /// were created with.
+    pub fn set_nullifier_ttl(ctx: Context<AdminAccounts>, nullifier_ttl: u64) -> Result<()> {
+        ctx.accounts.config.nullifier_ttl = nullifier_ttl;
+
+        msg!(
+            "Nullifier TTL set to {}s by {}",
+            nullifier_ttl,
+            ctx.accounts.signer.key()
+        );
+
+        Ok(())
+    }
+
+    /// Set how long new innocence records stay valid before `close_innocence_proof` may
+    /// close them
+    /// Admin only. `0` disables expiry; existing records keep the `expires_at` they were
+    /// proven with.
+    pub fn set_innocence_proof_ttl(
+        ctx: Context<AdminAccounts>,
+        innocence_proof_ttl: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.innocence_proof_ttl = innocence_proof_ttl;
+
+        msg!(
+            "Innocence proof TTL set to {}s by {}",
+            innocence_proof_ttl,
+            ctx.accounts.signer.key()
+        );
+
+        Ok(())
+    }
+
+    /// Replace the set of state trees root-reading instructions accept
+    /// Admin only. An empty list accepts any state tree until a sub-pool is registered, see
+    /// `check_merkle_tree_allowed`; at most `MAX_ALLOWLISTED_TREES` entries
+    /// (`MerkleTreeAllowlistTooLong` otherwise), none of them a sub-pool's state tree
+    /// (`PoolStateTreeAllowlisted`). Existing root snapshots of a removed tree stop working
+    /// in `withdraw_with_root`.
+    pub fn set_merkle_tree_allowlist(
+        ctx: Context<AdminAccounts>,
+        merkle_trees: Vec<Pubkey>,
+    ) -> Result<()> {
+        if merkle_trees.len() > MAX_ALLOWLISTED_TREES {
+            return Err(ErrorCode::MerkleTreeAllowlistTooLong.into());
+        }
+        if let Some(tree) = merkle_trees
+            .iter()
+            .find(|tree| ctx.accounts.config.pool_state_trees.contains(tree))
+        {
+            msg!("State tree {} belongs to a sub-pool", tree);
+            return Err(ErrorCode::PoolStateTreeAllowlisted.into());
+        }
+
+        msg!(
+            "Merkle tree allowlist set to {} trees by {}",
+            merkle_trees.len(),
+            ctx.accounts.signer.key()
+        );
+        ctx.accounts.config.merkle_tree_allowlist = merkle_trees;
+
+        Ok(())
+    }
+
+    /// Replace the set of oracle programs `withdraw_conditional` trusts
+    /// Admin only. An oracle account is trusted if one of these programs owns it; an empty
+    /// list trusts none. At most `MAX_ALLOWLISTED_ORACLES` entries
+    /// (`OracleAllowlistTooLong` otherwise).
+    pub fn set_oracle_owner_allowlist(
+        ctx: Context<AdminAccounts>,
+        oracle_owners: Vec<Pubkey>,
+    ) -> Result<()> {
+        if oracle_owners.len() > MAX_ALLOWLISTED_ORACLES {
+            return Err(ErrorCode::OracleAllowlistTooLong.into());
+        }
+
+        msg!(
+            "Oracle owner allowlist set to {} programs by {}",
+            oracle_owners.len(),
+            ctx.accounts.signer.key()
+        );
+        ctx.accounts.config.oracle_owner_allowlist = oracle_owners;
+
+        Ok(())
+    }
+
+    /// Restrict withdrawal recipients to an allowlist, e.g. of KYC'd addresses
+    /// Admin only. `recipient_allowlist_root` is the root of a `RECIPIENT_ALLOWLIST_LEVELS`
+    /// tree built with `recipient_allowlist_root`; zeros turn the allowlist off. While it is
+    /// set, `withdraw` requires a membership proof for its recipient and every other payout
+    /// path fails with `RecipientAllowlistActive`.
+    pub fn set_recipient_allowlist_root(
+        ctx: Context<AdminAccounts>,
+        recipient_allowlist_root: [u8; 32],
+    ) -> Result<()> {
+        ctx.accounts.config.recipient_allowlist_root = recipient_allowlist_root;
+
+        msg!(
+            "Recipient allowlist root set to {} by {}",
+            Hex(&recipient_allowlist_root),
+            ctx.accounts.signer.key()
+        );
+
+        Ok(())
+    }
+
+    /// Keep every withdraw path closed until `withdrawals_enabled_at_slot`
+    /// Admin only. `0` opens them immediately; a slot in the past has the same effect.
+    pub fn set_withdrawals_enabled_at_slot(
+        ctx: Context<AdminAccounts>,
+        withdrawals_enabled_at_slot: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.withdrawals_enabled_at_slot = withdrawals_enabled_at_slot;
+
+        msg!(
+            "Withdrawals enabled at slot {} by {}",
+            withdrawals_enabled_at_slot,
+            ctx.accounts.signer.key()
+        );
+
+        Ok(())
+    }
+
+    /// Pin default-pool deposits to one state tree
+    /// Admin only. `None` accepts any tree `check_merkle_tree_allowed` accepts; sub-pool
+    /// deposits always go to their pool's state tree.
+    pub fn set_deposit_state_tree(
+        ctx: Context<AdminAccounts>,
+        deposit_state_tree: Option<Pubkey>,
+    ) -> Result<()> {
+        ctx.accounts.config.deposit_state_tree = deposit_state_tree;
+
+        match deposit_state_tree {
+            Some(tree) => msg!(
+                "Deposits pinned to state tree {} by {}",
+                tree,
+                ctx.accounts.signer.key()
+            ),
+            None => msg!(
+                "Deposit state tree pin cleared by {}",
+                ctx.accounts.signer.key()
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Close a nullifier compressed account (dev/test cleanup)
+    /// Admin only, and fails with `DevModeDisabled` unless `dev_mode` is set.
+    /// The caller passes the nullifier's `CompressedAccountMeta` and stored fields;
+    /// the Light system program rejects the CPI unless they hash to the stored account.
+    ///
+    /// Light address trees never release an address, so the nullifier address
+    /// stays taken: the note remains spent and cannot be withdrawn again.
+    pub fn close_nullifier<'info>(
+        ctx: Context<'_, '_, '_, 'info, AdminAccounts<'info>>,
+        proof: ValidityProof,
+        system_accounts_offset: u8,
+        nullifier: NullifierState,
+    ) -> Result<()> {
+        if !ctx.accounts.config.dev_mode {
+            msg!("close_nullifier is only available in dev mode");
+            return Err(ErrorCode::DevModeDisabled.into());
+        }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        let nullifier_account = LightAccount::<NullifierAccount>::new_close(
+            &crate::ID,
+            &nullifier.account_meta,
+            nullifier.to_account(),
+        )?;
+
+        msg!("Closed nullifier: {}", Hex(&nullifier.nullifier_hash));
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(nullifier_account)?
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+
+    /// Close an expired nullifier account; anyone can call it
+    /// Fails with `NullifierNotExpired` before `expires_at` (or if it is 0), and with
+    /// `NullifierCheckpointNotReached` while the root the spend was proven against is
+    /// still in `merkle_tree`'s root history. See `check_nullifier_gc` for why closing
+    /// cannot re-enable the note.
+    pub fn gc_nullifier<'info>(
+        ctx: Context<'_, '_, '_, 'info, GcNullifierAccounts<'info>>,
+        proof: ValidityProof,
+        system_accounts_offset: u8,
+        nullifier: NullifierState,
+    ) -> Result<()> {
+        check_nullifier_gc(&nullifier, &ctx.accounts.merkle_tree.to_account_info())?;
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        let nullifier_account = LightAccount::<NullifierAccount>::new_close(
+            &crate::ID,
+            &nullifier.account_meta,
+            nullifier.to_account(),
+        )?;
+
+        msg!(
+            "Garbage-collected nullifier: {}",
+            Hex(&nullifier.nullifier_hash)
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(nullifier_account)?
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+
+    /// Refresh an `InnocenceProofAccount` with a proof against a new association set root
+    /// The record passed as `innocence_proof` is updated in place, so a set's root changing
+    /// costs no new address. `association_set_root` must be the set's current root. The proof is verified for the record's own `nullifier_hash`
+    /// and `association_set_id`, which the Light system program checks against the stored
+    /// account. `proven_at` and `expires_at` restart from now; the bitmap already has the bit.
+    pub fn reprove_innocence<'info>(
+        ctx: Context<'_, '_, '_, 'info, ReproveInnocenceAccounts<'info>>,
+        proof: ValidityProof,
+        system_accounts_offset: u8,
+        input_root_index: u16,
+        association_set_root: [u8; 32],
+        innocence_proof: InnocenceProofState,
+        zk_proof: CompressedProof,
+    ) -> Result<()> {
+        check_association_set(
+            &ctx.accounts.association_set.to_account_info(),
+            &association_set_root,
+        )?;
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        let deposit_root = read_spendable_root(
+            &ctx.accounts.config,
+            &ctx.accounts.deposit_merkle_tree.to_account_info(),
+            input_root_index,
+        )?;
+
+        // Same circuit inputs as prove_innocence, taken from the stored record
+        let timestamp = Clock::get()?.unix_timestamp as u64;
+        let public_inputs: [[u8; 32]; 5] = [
+            deposit_root,
+            association_set_root,
+            innocence_proof.nullifier_hash,
+            encode_u64_be_field(innocence_proof.association_set_id as u64),
+            encode_u64_be_field(timestamp),
+        ];
+
+        verify_groth16(&zk_proof, &public_inputs, &INNOCENCE_KEY)?;
+
+        let mut innocence_account = LightAccount::<InnocenceProofAccount>::new_mut(
+            &crate::ID,
+            &innocence_proof.account_meta,
+            innocence_proof.to_account(),
+        )?;
+        innocence_account.proven_at = timestamp;
+        innocence_account.expires_at = innocence_proof_expiry(&ctx.accounts.config, timestamp)?;
+
+        msg!(
+            "Innocence re-proven for nullifier: {}, association set: {}",
+            Hex(&innocence_proof.nullifier_hash),
+            innocence_proof.association_set_id
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(innocence_account)?
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+
+    /// Close an expired `InnocenceProofAccount`; anyone can call it
+    /// Fails with `ProofNotExpired` before `expires_at` (or if it is 0). The caller passes the
+    /// record's `CompressedAccountMeta` and stored fields; the Light system program rejects
+    /// the CPI unless they hash to the stored account.
+    ///
+    /// Only the compressed account is reclaimed: Light address trees never release an
+    /// address, so the nullifier cannot prove innocence for the same set again. The
+    /// nullifier's `InnocenceBitmapAccount` is left as is.
+    pub fn close_innocence_proof<'info>(
+        ctx: Context<'_, '_, '_, 'info, GenericAnchorAccounts<'info>>,
+        proof: ValidityProof,
+        system_accounts_offset: u8,
+        innocence_proof: InnocenceProofState,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp as u64;
+        if innocence_proof.expires_at == 0 || now < innocence_proof.expires_at {
+            msg!(
+                "Innocence proof expires at {}, now {}",
+                innocence_proof.expires_at,
+                now
+            );
+            return Err(ErrorCode::ProofNotExpired.into());
+        }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        let innocence_account = LightAccount::<InnocenceProofAccount>::new_close(
+            &crate::ID,
+            &innocence_proof.account_meta,
+            innocence_proof.to_account(),
+        )?;
+
+        msg!(
+            "Closed innocence proof for nullifier: {}, association set: {}",
+            Hex(&innocence_proof.nullifier_hash),
+            innocence_proof.association_set_id
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(innocence_account)?
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+
+    /// Register an association set `prove_innocence` may prove membership in
+    /// Admin only. The set starts active with `root` as its current root, which every
+    /// innocence proof against the set must use; see `set_association_set_root`.
+    /// Any `u16` id is valid; only ids below `BITMAP_ASSOCIATION_SETS` get a bitmap bit.
+    pub fn register_association_set(
+        ctx: Context<RegisterAssociationSetAccounts>,
+        association_set_id: u16,
+        root: [u8; 32],
+    ) -> Result<()> {
+        let association_set = &mut ctx.accounts.association_set;
+        association_set.id = association_set_id;
+        association_set.active = true;
+        association_set.bump = ctx.bumps.association_set;
+        association_set.root = root;
+
+        msg!("Registered association set {}", association_set_id);
+
+        Ok(())
+    }
+
+    /// Activate or retire a registered association set
+    /// Admin only. Existing innocence records are kept; new proofs against an
+    /// inactive set fail with `UnknownAssociationSet`.
+    pub fn set_association_set_active(
+        ctx: Context<AssociationSetAdminAccounts>,
+        association_set_id: u16,
+        active: bool,
+    ) -> Result<()> {
+        ctx.accounts.association_set.active = active;
+
+        msg!("Association set {} active: {}", association_set_id, active);
+
+        Ok(())
+    }
+
+    /// Publish a new root for a registered association set
+    /// Admin only. Proofs against the previous root fail with `AssociationSetRootMismatch`
+    /// from then on; existing records are refreshed with `reprove_innocence`.
+    pub fn set_association_set_root(
+        ctx: Context<AssociationSetAdminAccounts>,
+        association_set_id: u16,
+        root: [u8; 32],
+    ) -> Result<()> {
+        ctx.accounts.association_set.root = root;
+
+        msg!(
+            "Association set {} root: {}",
+            association_set_id,
+            Hex(&root)
+        );
+
+        Ok(())
+    }
+
+    /// Register sub-pool `pool_id`, whose deposits and withdrawals use `state_tree`
+    /// Admin only. Pool 0 is the default pool and cannot be registered (`InvalidPoolId`).
+    /// The tree must be off the config's tree allowlist (`PoolStateTreeAllowlisted`) and
+    /// not belong to another pool (`PoolStateTreeRegistered`). It is added to the config's
+    /// `pool_state_trees`, at most `MAX_POOLS` of them (`PoolLimitReached`), which
+    /// default-pool paths reject, so they cannot spend the pool's notes; the pool's
+    /// `withdraw` checks its own tree instead.
+    pub fn register_pool(
+        ctx: Context<RegisterPoolAccounts>,
+        pool_id: u16,
+        state_tree: Pubkey,
+    ) -> Result<()> {
+        require!(pool_id != 0, ErrorCode::InvalidPoolId);
+        let config = &mut ctx.accounts.config;
+        require!(
+            !config.merkle_tree_allowlist.contains(&state_tree),
+            ErrorCode::PoolStateTreeAllowlisted
+        );
+        require!(
+            !config.pool_state_trees.contains(&state_tree),
+            ErrorCode::PoolStateTreeRegistered
+        );
+        require!(
+            config.pool_state_trees.len() < MAX_POOLS,
+            ErrorCode::PoolLimitReached
+        );
+        config.pool_state_trees.push(state_tree);
+
+        let pool = &mut ctx.accounts.pool;
+        pool.pool_id = pool_id;
+        pool.state_tree = state_tree;
+        pool.deposit_count = 0;
+        pool.total_deposits = 0;
+        pool.withdrawal_count = 0;
+        pool.bump = ctx.bumps.pool;
+
+        msg!("Registered pool {} on state tree {}", pool_id, state_tree);
+
+        Ok(())
+    }
+
+    /// Create the signer's `RootSnapshot` for a state tree and record root `root_index`
+    /// Permissionless; each signer keeps its own snapshot per tree, so nobody else can
+    /// move the root an integrator's pending proofs were made against.
+    pub fn initialize_root_snapshot(
+        ctx: Context<InitializeRootSnapshotAccounts>,
+        root_index: u16,
+    ) -> Result<()> {
+        let root_snapshot = &mut ctx.accounts.root_snapshot;
+        root_snapshot.authority = ctx.accounts.signer.key();
+        root_snapshot.merkle_tree = ctx.accounts.merkle_tree.key();
+        root_snapshot.bump = ctx.bumps.root_snapshot;
+        record_root_snapshot(
+            root_snapshot,
+            &ctx.accounts.config,
+            &ctx.accounts.merkle_tree.to_account_info(),
+            root_index,
+        )
+    }
+
+    /// Refresh the signer's `RootSnapshot` with root `root_index` of its tree
+    pub fn snapshot_root(ctx: Context<SnapshotRootAccounts>, root_index: u16) -> Result<()> {
+        record_root_snapshot(
+            &mut ctx.accounts.root_snapshot,
+            &ctx.accounts.config,
+            &ctx.accounts.merkle_tree.to_account_info(),
+            root_index,
+        )
+    }
+
+    /// Create the shared `RootSnapshotRing` of a state tree, publishing root `root_index`
+    /// Permissionless; the signer pays rent for the ring, which belongs to no one.
+    pub fn initialize_root_snapshot_ring(
+        ctx: Context<InitializeRootSnapshotRingAccounts>,
+        root_index: u16,
+    ) -> Result<()> {
+        let ring = &mut ctx.accounts.root_snapshot_ring;
+        ring.merkle_tree = ctx.accounts.merkle_tree.key();
+        ring.bump = ctx.bumps.root_snapshot_ring;
+        publish_root(
+            ring,
+            &ctx.accounts.config,
+            &ctx.accounts.merkle_tree.to_account_info(),
+            root_index,
+        )
+    }
+
+    /// Publish root `root_index` of a tree into its `RootSnapshotRing`
+    /// Permissionless, but fails with `CrankTooSoon` within `MIN_CRANK_INTERVAL_SLOTS` of the
+    /// previous crank. The oldest of the ring's `ROOT_SNAPSHOT_RING_SIZE` roots is replaced.
+    pub fn crank_merkle_snapshot(
+        ctx: Context<CrankMerkleSnapshotAccounts>,
+        root_index: u16,
+    ) -> Result<()> {
+        let ring = &mut ctx.accounts.root_snapshot_ring;
+        let since = Clock::get()?.slot.saturating_sub(ring.last_cranked_slot);
+        if since < MIN_CRANK_INTERVAL_SLOTS {
+            msg!("Root snapshot ring was cranked {} slots ago", since);
+            return Err(ErrorCode::CrankTooSoon.into());
+        }
+        publish_root(
+            ring,
+            &ctx.accounts.config,
+            &ctx.accounts.merkle_tree.to_account_info(),
+            root_index,
+        )
+    }
+
+    /// Create a deposit stream: `commitments.len()` deposits of `amount_per_interval`
+    /// lamports, one every `interval_slots` slots starting now
+    /// The depositor funds the stream PDA with the full schedule up front. Notes cannot be
+    /// generated on-chain without revealing their secrets, so the depositor pre-commits
+    /// one commitment per interval (see `note::derive_stream_note`).
+    pub fn create_stream(
+        ctx: Context<CreateStreamAccounts>,
+        stream_id: u64,
+        amount_per_interval: u64,
+        interval_slots: u64,
+        commitments: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        if amount_per_interval == 0
+            || interval_slots == 0
+            || commitments.is_empty()
+            || commitments.len() > MAX_STREAM_DEPOSITS
+        {
+            return Err(ErrorCode::InvalidStream.into());
+        }
+        for commitment in &commitments {
+            check_commitment(commitment)?;
+        }
+        let escrow = amount_per_interval
+            .checked_mul(commitments.len() as u64)
+            .ok_or(ErrorCode::InvalidStream)?;
+
+        // Escrow the whole schedule in the stream PDA, on top of its rent
+        let transfer_ix = anchor_lang::solana_program::system_instruction::transfer(
+            ctx.accounts.depositor.key,
+            &ctx.accounts.stream.key(),
+            escrow,
+        );
+        anchor_lang::solana_program::program::invoke(
+            &transfer_ix,
+            &[
+                ctx.accounts.depositor.to_account_info(),
+                ctx.accounts.stream.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
+
+        let stream = &mut ctx.accounts.stream;
+        stream.depositor = ctx.accounts.depositor.key();
+        stream.stream_id = stream_id;
+        stream.amount_per_interval = amount_per_interval;
+        stream.interval_slots = interval_slots;
+        stream.next_deposit_slot = Clock::get()?.slot;
+        stream.deposits_made = 0;
+        stream.commitments = commitments;
+        stream.bump = ctx.bumps.stream;
+
+        msg!(
+            "Stream {} created: {} deposits of {} lamports every {} slots",
+            stream_id,
+            stream.commitments.len(),
+            amount_per_interval,
+            interval_slots
+        );
+
+        Ok(())
+    }
+
+    /// Execute a stream's next scheduled deposit
+    /// Permissionless once `next_deposit_slot` is reached (`StreamNotDue` before). Moves
+    /// `amount_per_interval` lamports from the stream escrow to the SOL vault and creates the
+    /// deposit account for the next pre-committed commitment. Missed intervals can be
+    /// cranked back to back; the schedule does not shift.
+    /// Returns the deposit's receipt, with the stream address as its reference
+    pub fn crank_stream<'info>(
+        ctx: Context<'_, '_, '_, 'info, CrankStreamAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+    ) -> Result<DepositReceipt> {
+        let clock = Clock::get()?;
+        let stream = &ctx.accounts.stream;
+        let commitment = *stream
+            .commitments
+            .get(stream.deposits_made as usize)
+            .ok_or(ErrorCode::StreamExhausted)?;
+        if clock.slot < stream.next_deposit_slot {
+            msg!("Stream deposit is due at slot {}", stream.next_deposit_slot);
+            return Err(ErrorCode::StreamNotDue.into());
+        }
+        let amount = stream.amount_per_interval;
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // Deposits go to the config's pinned deposit state tree, if it set one
+        let output_tree = light_cpi_accounts
+            .get_tree_account_info(output_state_tree_index as usize)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?
+            .key();
+        check_deposit_state_tree(&ctx.accounts.config, &output_tree)?;
+
+        let address_tree_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        // Streams deposit into the default pool
+        let (address, address_seed) = derive_deposit_address(&commitment, 0, &address_tree_pubkey);
+
+        let mut deposit_account = LightAccountPoseidon::<DepositAccount>::new_init(
+            &crate::ID,
+            Some(address),
+            output_state_tree_index,
+        );
+        deposit_account.commitment = Commitment::new(commitment);
+        deposit_account.amount = amount;
+        deposit_account.timestamp = clock.unix_timestamp as u64;
+        deposit_account.view_tag = None;
+        deposit_account.encrypted_note = None;
+
+        // The stream PDA is program-owned, so its escrow is debited directly
+        ctx.accounts.stream.sub_lamports(amount)?;
+        ctx.accounts.vault.add_lamports(amount)?;
+
+        let stream = &mut ctx.accounts.stream;
+        stream.deposits_made += 1;
+        stream.next_deposit_slot = stream
+            .next_deposit_slot
+            .saturating_add(stream.interval_slots);
+
+        msg!(
+            "Stream {} deposit {} of {}: {} lamports",
+            stream.stream_id,
+            stream.deposits_made,
+            stream.commitments.len(),
+            amount
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account_poseidon(deposit_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(0))
+            ])
+            .invoke(light_cpi_accounts)?;
+
+        Ok(DepositReceipt {
+            address,
+            commitment,
+            amount,
+            reference: ctx.accounts.stream.key().to_bytes(),
+            slot: clock.slot,
+        })
+    }
+
+    /// Close a stream and refund its unspent escrow and rent to the depositor
+    /// Depositor only; deposits already cranked stay in the pool.
+    pub fn cancel_stream(ctx: Context<CancelStreamAccounts>) -> Result<()> {
+        let stream = &ctx.accounts.stream;
+        msg!(
+            "Stream {} cancelled after {} of {} deposits",
+            stream.stream_id,
+            stream.deposits_made,
+            stream.commitments.len()
+        );
+
+        Ok(())
+    }
+}
+
+/// Lowercase hex of a commitment, nullifier hash or other 32-byte value, for `msg!` logs
+/// 64 characters, where the array's `Debug` output takes up to 160 and is harder to match
+/// against explorers and client output (`client::to_hex`).
+pub struct Hex<'a>(pub &'a [u8; 32]);
+
+impl std::fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a `u64` as a 32-byte big-endian field element for circuit public inputs
+/// Layout: bytes `[0..24]` are zero and bytes `[24..32]` hold `value.to_be_bytes()`.
+/// Client proof builders must use the same encoding, or verification fails silently.
+pub fn encode_u64_be_field(value: u64) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[24..32].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+/// Absolute relayer fee for `fee_bps` basis points of `amount`, or `None` past 100%
+/// Computes `amount * fee_bps / BPS_DENOMINATOR` in `u128` and rounds down, so the relayer
+/// never gets more than `fee_bps` of the amount and tiny amounts can round to a zero fee.
+/// The withdraw circuits bind the absolute fee, so clients must compute the `fee` public
+/// input with this function and pass it through `encode_u64_be_field`.
+pub fn fee_from_bps(amount: u64, fee_bps: u16) -> Option<u64> {
+    if fee_bps > BPS_DENOMINATOR {
+        return None;
+    }
+    Some((amount as u128 * fee_bps as u128 / BPS_DENOMINATOR as u128) as u64)
+}
+
+/// Encodes a Light discriminator the way compressed account leaf hashes include it
+/// Layout: bytes `[24..32]` hold the discriminator and byte 23 the domain prefix `2`,
+/// i.e. `discriminator + 2^65`. Client proof builders recomputing deposit leaf hashes
+/// must use this rather than setting the prefix by hand.
+pub fn encode_discriminator(discriminator: [u8; 8]) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[24..32].copy_from_slice(&discriminator);
+    bytes[23] = 2;
+    bytes
+}
+
+/// Canonical vault token account for `mint`
+/// The ATA owned by the `[b"vault_authority", mint]` PDA, created by `initialize_token_vault`.
+pub fn vault_token_address(mint: &Pubkey) -> Pubkey {
+    let (vault_authority, _) =
+        Pubkey::find_program_address(&[b"vault_authority", mint.as_ref()], &crate::ID);
+    get_associated_token_address(&vault_authority, mint)
+}
+
+/// Leaf of the recipient allowlist: the recipient hashed to the BN254 field
+pub fn recipient_allowlist_leaf(recipient: &Pubkey) -> [u8; 32] {
+    hash_to_bn254_field_size_be(recipient.as_ref())
+}
+
+/// Root of a recipient allowlist for `set_recipient_allowlist_root`
+/// Unused leaves are zero. Panics with more than `2^RECIPIENT_ALLOWLIST_LEVELS` recipients.
+pub fn recipient_allowlist_root(
+    recipients: &[Pubkey],
+) -> std::result::Result<[u8; 32], HasherError> {
+    recipient_allowlist_tree(recipients, 0).map(|(root, _)| root)
+}
+
+/// Sibling path of `recipients[index]`, leaf first: a `RecipientAllowlistProof`'s `path`
+pub fn recipient_allowlist_path(
+    recipients: &[Pubkey],
+    index: usize,
+) -> std::result::Result<Vec<[u8; 32]>, HasherError> {
+    recipient_allowlist_tree(recipients, index).map(|(_, path)| path)
+}
+
+fn recipient_allowlist_tree(
+    recipients: &[Pubkey],
+    mut index: usize,
+) -> std::result::Result<([u8; 32], Vec<[u8; 32]>), HasherError> {
+    assert!(
+        recipients.len() <= 1 << RECIPIENT_ALLOWLIST_LEVELS,
+        "Allowlist has more than 2^{} recipients",
+        RECIPIENT_ALLOWLIST_LEVELS
+    );
+    let mut level: Vec<[u8; 32]> = recipients.iter().map(recipient_allowlist_leaf).collect();
+    let mut zero = [0u8; 32];
+    let mut path = Vec::with_capacity(RECIPIENT_ALLOWLIST_LEVELS);
+    for _ in 0..RECIPIENT_ALLOWLIST_LEVELS {
+        path.push(*level.get(index ^ 1).unwrap_or(&zero));
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&zero);
+                Poseidon::hashv(&[pair[0].as_slice(), right.as_slice()])
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        zero = Poseidon::hashv(&[zero.as_slice(), zero.as_slice()])?;
+        index /= 2;
+    }
+    Ok((level.first().copied().unwrap_or(zero), path))
+}
+
+/// Derives the address `deposit` creates for `commitment` in sub-pool `pool_id`
+/// Pool 0 keeps the `[DEPOSIT, commitment]` seeds of existing deposits; other pools use
+/// `[DEPOSIT, commitment, POOL, pool_id]`, so each pool has its own commitment namespace.
+pub fn derive_deposit_address(
+    commitment: &[u8; 32],
+    pool_id: u16,
+    address_tree_pubkey: &Pubkey,
+) -> ([u8; 32], [u8; 32]) {
+    if pool_id == 0 {
+        derive_address(&[DEPOSIT, commitment], address_tree_pubkey, &crate::ID)
+    } else {
+        derive_address(
+            &[DEPOSIT, commitment, POOL, &pool_id.to_le_bytes()],
+            address_tree_pubkey,
+            &crate::ID,
+        )
+    }
+}
+
+/// Derives the address `deposit_token`, `deposit_with_permit` or `deposit_sol` creates
+/// Token deposits use `[DEPOSIT, commitment, mint]` whichever path made them. `mint` is `None`
+/// for `deposit_sol`, whose `[DEPOSIT, commitment, NATIVE_DEPOSIT]` seeds keep native deposits
+/// apart from wSOL token deposits. These paths only deposit into the default pool.
+pub fn derive_token_deposit_address(
+    commitment: &[u8; 32],
+    mint: Option<&Pubkey>,
+    address_tree_pubkey: &Pubkey,
+) -> ([u8; 32], [u8; 32]) {
+    let asset = match mint {
+        Some(mint) => mint.as_ref(),
+        None => NATIVE_DEPOSIT,
+    };
+    derive_address(
+        &[DEPOSIT, commitment, asset],
+        address_tree_pubkey,
+        &crate::ID,
+    )
+}
+
+/// Derives the nullifier address for `nullifier_hash` under a deployment `domain`
+/// A zero domain keeps the `[NULLIFIER, nullifier_hash]` seeds of existing nullifiers.
+/// The domain is fixed per build (`NULLIFIER_DOMAIN`) rather than taken from callers:
+/// a caller-chosen domain would let the same note be spent once per domain. Sub-pools
+/// share it for the same reason, as the withdraw circuit does not bind the pool id.
+pub fn derive_nullifier_address(
+    nullifier_hash: &[u8; 32],
+    domain: &[u8; 8],
+    address_tree_pubkey: &Pubkey,
+) -> ([u8; 32], [u8; 32]) {
+    if *domain == [0u8; 8] {
+        derive_address(
+            &[NULLIFIER, nullifier_hash],
+            address_tree_pubkey,
+            &crate::ID,
+        )
+    } else {
+        derive_address(
+            &[NULLIFIER, domain, nullifier_hash],
+            address_tree_pubkey,
+            &crate::ID,
+        )
+    }
+}
+
+/// The `input_root_index` of the root the indexer reported as `root_seq`
+pub fn root_index_for(root_seq: u64) -> u16 {
+    (root_seq % ROOT_HISTORY_SIZE) as u16
+}
+
+/// Message a token owner signs to authorize one `deposit_with_permit`
+/// Domain-separated by program id, and binds everything the relayer chooses, so a permit
+/// can't be redirected to another commitment, amount, mint or program.
+pub fn deposit_permit_message(
+    owner: &Pubkey,
+    mint: &Pubkey,
+    commitment: &[u8; 32],
+    amount: u64,
+    nonce: u64,
+    deadline: i64,
+) -> Vec<u8> {
+    [
+        DEPOSIT_PERMIT,
+        crate::ID.as_ref(),
+        owner.as_ref(),
+        mint.as_ref(),
+        commitment.as_slice(),
+        &amount.to_le_bytes(),
+        &nonce.to_le_bytes(),
+        &deadline.to_le_bytes(),
+    ]
+    .concat()
+}
+
+/// Fails with `InvalidCommitment` unless `commitment` is a canonical BN254 field element
+/// The circuits reduce larger values, so no note would open the stored commitment and the
+/// deposit could never be withdrawn.
+fn check_commitment(commitment: &[u8; 32]) -> Result<()> {
+    if *commitment >= BN254_FIELD_MODULUS {
+        msg!(
+            "Commitment {} is not below the field modulus",
+            Hex(commitment)
+        );
+        return Err(ErrorCode::InvalidCommitment.into());
+    }
+    Ok(())
+}
+
+/// Fails with `InvalidCommitment` unless `opening` hashes to `commitment`
+/// Catches a client that hashed the note differently from `note::compute_commitment`, whose
+/// deposit no withdraw proof could ever open.
+fn check_note_opening(commitment: &[u8; 32], opening: &NoteOpening) -> Result<()> {
+    let expected = note::compute_commitment(&opening.nullifier, &opening.secret)
+        .map_err(|_| ErrorCode::InvalidCommitment)?;
+    if expected != *commitment {
+        msg!(
+            "Commitment does not match the opening, expected {}",
+            Hex(&expected)
+        );
+        return Err(ErrorCode::InvalidCommitment.into());
+    }
+    Ok(())
+}
+
+/// Fails with `InvalidCpiSigner` unless `LIGHT_CPI_SIGNER` was derived from `crate::ID`
+/// `derive_light_cpi_signer!` takes the program id as a literal, so a `declare_id!` change
+/// that misses it would otherwise only surface as a failed signature inside the Light CPI.
+fn check_light_cpi_signer() -> Result<()> {
+    let derived = Pubkey::create_program_address(
+        &[
+            light_sdk::constants::CPI_AUTHORITY_PDA_SEED,
+            &[LIGHT_CPI_SIGNER.bump],
+        ],
+        &crate::ID,
+    )
+    .map_err(|_| ErrorCode::InvalidCpiSigner)?;
+    if LIGHT_CPI_SIGNER.program_id != crate::ID.to_bytes()
+        || LIGHT_CPI_SIGNER.cpi_signer != derived.to_bytes()
+    {
+        msg!(
+            "LIGHT_CPI_SIGNER {} does not belong to program {}",
+            Pubkey::new_from_array(LIGHT_CPI_SIGNER.cpi_signer),
+            crate::ID
+        );
+        return Err(ErrorCode::InvalidCpiSigner.into());
+    }
+    Ok(())
+}
+
+/// Checks that the instruction preceding the current one is an Ed25519 program
+/// instruction verifying `signature` by `pubkey` over `message`.
+/// The Ed25519 program already verified the signature when the transaction executed;
+/// this only confirms it verified the expected data, read from its own instruction.
+fn verify_ed25519_instruction(
+    instructions_sysvar: &AccountInfo,
+    pubkey: &Pubkey,
+    signature: &[u8; 64],
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    if current_index == 0 {
+        msg!("Missing Ed25519 instruction");
+        return Err(ErrorCode::InvalidPermitSignature.into());
+    }
+    let ed25519_ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+
+    if ed25519_ix.program_id != ed25519_program::ID || !ed25519_ix.accounts.is_empty() {
+        msg!("Preceding instruction is not an Ed25519 verification");
+        return Err(ErrorCode::InvalidPermitSignature.into());
+    }
+
+    // Layout: [num_signatures: u8, padding: u8, Ed25519SignatureOffsets (7 x u16 LE), data...]
+    let data = &ed25519_ix.data;
+    if data.len() < 16 || data[0] != 1 {
+        return Err(ErrorCode::InvalidPermitSignature.into());
+    }
+    let read_u16 = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let signature_offset = read_u16(2) as usize;
+    let signature_ix_index = read_u16(4);
+    let pubkey_offset = read_u16(6) as usize;
+    let pubkey_ix_index = read_u16(8);
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+    let message_ix_index = read_u16(14);
+
+    // All data must live in the Ed25519 instruction itself
+    if signature_ix_index != u16::MAX || pubkey_ix_index != u16::MAX || message_ix_index != u16::MAX
+    {
+        return Err(ErrorCode::InvalidPermitSignature.into());
+    }
+
+    let signed_pubkey = data.get(pubkey_offset..pubkey_offset + 32);
+    let signed_signature = data.get(signature_offset..signature_offset + 64);
+    let signed_message = data.get(message_offset..message_offset + message_size);
+
+    if signed_pubkey != Some(pubkey.as_ref())
+        || signed_signature != Some(signature.as_slice())
+        || signed_message != Some(message)
+    {
+        msg!("Ed25519 instruction does not match the expected permit signature");
+        return Err(ErrorCode::InvalidPermitSignature.into());
+    }
+
+    Ok(())
+}
+
+/// Fails with `InvalidRootIndex` unless `root_index` is a slot of the root history
+fn check_root_index(root_index: u16) -> Result<()> {
+    if root_index as u64 >= ROOT_HISTORY_SIZE {
+        msg!(
+            "Root index {} is past the {}-root history",
+            root_index,
+            ROOT_HISTORY_SIZE
+        );
+        return Err(ErrorCode::InvalidRootIndex.into());
+    }
+    Ok(())
+}
+
+/// The Light system accounts: `remaining_accounts` from `system_accounts_offset` on
+/// An offset past the end fails with `InvalidSystemAccountsOffset` rather than panicking.
+fn system_accounts<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    system_accounts_offset: u8,
+) -> Result<&'a [AccountInfo<'info>]> {
+    remaining_accounts
+        .get(system_accounts_offset as usize..)
+        .ok_or_else(|| {
+            msg!(
+                "System accounts offset {} exceeds {} remaining accounts",
+                system_accounts_offset,
+                remaining_accounts.len()
+            );
+            ErrorCode::InvalidSystemAccountsOffset.into()
+        })
+}
+
+/// Fails with `MerkleTreeNotAllowed` unless `merkle_tree` is on the config's allowlist
+/// A sub-pool's state tree is never accepted. An empty allowlist accepts every other tree
+/// `read_state_merkle_tree_root` accepts, but only until the first sub-pool is registered;
+/// from then on the default pool reads only listed trees.
+fn check_merkle_tree_allowed(config: &ProgramConfig, merkle_tree: &Pubkey) -> Result<()> {
+    let listed = if config.merkle_tree_allowlist.is_empty() {
+        config.pool_state_trees.is_empty()
+    } else {
+        config.merkle_tree_allowlist.contains(merkle_tree)
+    };
+    if !listed || config.pool_state_trees.contains(merkle_tree) {
+        msg!("Merkle tree {} is not on the allowlist", merkle_tree);
+        return Err(ErrorCode::MerkleTreeNotAllowed.into());
+    }
+    Ok(())
+}
+
+/// Fails with `RecipientNotAllowlisted` unless `recipient_proof` places `recipient` under the
+/// config's `recipient_allowlist_root`. A zero root accepts any recipient without a proof.
+fn check_recipient_allowlisted(
+    config: &ProgramConfig,
+    recipient: &Pubkey,
+    recipient_proof: Option<&RecipientAllowlistProof>,
+) -> Result<()> {
+    if config.recipient_allowlist_root == [0u8; 32] {
+        return Ok(());
+    }
+    let Some(recipient_proof) = recipient_proof else {
+        msg!("Recipient {} has no allowlist proof", recipient);
+        return Err(ErrorCode::RecipientNotAllowlisted.into());
+    };
+    if recipient_proof.path.len() != RECIPIENT_ALLOWLIST_LEVELS
+        || (recipient_proof.leaf_index as u64) >> RECIPIENT_ALLOWLIST_LEVELS != 0
+    {
+        msg!(
+            "Allowlist path of {} siblings at leaf {} does not fit the tree",
+            recipient_proof.path.len(),
+            recipient_proof.leaf_index
+        );
+        return Err(ErrorCode::RecipientNotAllowlisted.into());
+    }
+
+    let mut node = recipient_allowlist_leaf(recipient);
+    for (level, sibling) in recipient_proof.path.iter().enumerate() {
+        let (left, right) = if (recipient_proof.leaf_index >> level) & 1 == 0 {
+            (&node, sibling)
+        } else {
+            (sibling, &node)
+        };
+        node = Poseidon::hashv(&[left.as_slice(), right.as_slice()])
+            .map_err(|_| ProgramError::InvalidArgument)?;
+    }
+
+    if node != config.recipient_allowlist_root {
+        msg!("Recipient {} is not on the allowlist", recipient);
+        return Err(ErrorCode::RecipientNotAllowlisted.into());
+    }
+    Ok(())
+}
+
+/// Fails with `RecipientAllowlistActive` while the config has a recipient allowlist
+/// Only `withdraw` takes a recipient membership proof, so the other payout paths stay
+/// closed rather than bypass the allowlist.
+fn check_recipient_allowlist_unset(config: &ProgramConfig) -> Result<()> {
+    if config.recipient_allowlist_root != [0u8; 32] {
+        msg!("Recipient allowlist is set; only withdraw can pay out");
+        return Err(ErrorCode::RecipientAllowlistActive.into());
+    }
+    Ok(())
+}
+
+/// Fails unless `oracle` is owned by an allowlisted oracle program and satisfies `predicate`
+/// `UntrustedOracle` for an unlisted owner, `InvalidOracleData` if the value can't be
+/// decoded, `OracleConditionNotMet` if the comparison is false.
+fn check_oracle_predicate(
+    config: &ProgramConfig,
+    oracle: &AccountInfo,
+    predicate: &OraclePredicate,
+) -> Result<()> {
+    if !config.oracle_owner_allowlist.contains(oracle.owner) {
+        msg!(
+            "Oracle {} is owned by untrusted program {}",
+            oracle.key,
+            oracle.owner
+        );
+        return Err(ErrorCode::UntrustedOracle.into());
+    }
+    let value = predicate
+        .read_value(&oracle.try_borrow_data()?[..])
+        .ok_or(ErrorCode::InvalidOracleData)?;
+    if !predicate.holds(value) {
+        msg!(
+            "Oracle value {} fails {:?} {}",
+            value,
+            predicate.comparison,
+            predicate.threshold
+        );
+        return Err(ErrorCode::OracleConditionNotMet.into());
+    }
+    Ok(())
+}
+
+/// Reads root `input_root_index` of a state tree, rejecting roots nothing was appended under
+/// An unwritten root history slot reads as all zeros and a fresh tree's root is the
+/// empty-tree root; proofs against either are trivially constructed, so both fail
+/// with `InvalidMerkleRoot`.
+fn read_spendable_root<'info>(
+    config: &ProgramConfig,
+    merkle_tree: &AccountInfo<'info>,
+    input_root_index: u16,
+) -> Result<[u8; 32]> {
+    check_merkle_tree_allowed(config, merkle_tree.key)?;
+    read_tree_root(merkle_tree, input_root_index)
+}
+
+/// `read_spendable_root` without the allowlist check, for a sub-pool's own state tree
+fn read_tree_root<'info>(
+    merkle_tree: &AccountInfo<'info>,
+    input_root_index: u16,
+) -> Result<[u8; 32]> {
+    check_root_index(input_root_index)?;
+    let root = read_state_merkle_tree_root(merkle_tree, input_root_index)?;
+    if root == [0u8; 32] || root == Poseidon::zero_bytes()[STATE_TREE_HEIGHT] {
+        msg!("Root {} is a genesis root", input_root_index);
+        return Err(ErrorCode::InvalidMerkleRoot.into());
+    }
+    Ok(root)
+}
+
+/// Copies root `root_index` of `merkle_tree` into `snapshot`, stamped with the current slot
+/// The root goes through `read_spendable_root`, so genesis roots and trees off the
+/// allowlist are never snapshotted.
+fn record_root_snapshot<'info>(
+    snapshot: &mut RootSnapshot,
+    config: &ProgramConfig,
+    merkle_tree: &AccountInfo<'info>,
+    root_index: u16,
+) -> Result<()> {
+    snapshot.root = read_spendable_root(config, merkle_tree, root_index)?;
+    snapshot.root_index = root_index;
+    snapshot.slot = Clock::get()?.slot;
+
+    msg!(
+        "Snapshot of root {} of tree {} at slot {}",
+        root_index,
+        snapshot.merkle_tree,
+        snapshot.slot
+    );
+
+    Ok(())
+}
+
+/// Writes root `root_index` of `merkle_tree` over the oldest entry of `ring`
+/// Read through `read_spendable_root`, like `record_root_snapshot`.
+fn publish_root<'info>(
+    ring: &mut RootSnapshotRing,
+    config: &ProgramConfig,
+    merkle_tree: &AccountInfo<'info>,
+    root_index: u16,
+) -> Result<()> {
+    let slot = Clock::get()?.slot;
+    ring.push(PublishedRoot {
+        root: read_spendable_root(config, merkle_tree, root_index)?,
+        root_index,
+        slot,
+    });
+    ring.last_cranked_slot = slot;
+
+    msg!(
+        "Published root {} of tree {} at slot {}",
+        root_index,
+        ring.merkle_tree,
+        slot
+    );
+
+    Ok(())
+}
+
+/// Fails with `RootSnapshotStale` once `snapshot` is older than `MAX_ROOT_SNAPSHOT_AGE_SLOTS`
+fn check_root_snapshot(snapshot: &RootSnapshot, slot: u64) -> Result<()> {
+    let age = slot.saturating_sub(snapshot.slot);
+    if age > MAX_ROOT_SNAPSHOT_AGE_SLOTS {
+        msg!("Root snapshot is {} slots old", age);
+        return Err(ErrorCode::RootSnapshotStale.into());
+    }
+    Ok(())
+}
+
+/// Finds `root` in the root source passed to `withdraw_explicit_root`
+/// Returns the tree, root index and slot the root was copied or published at.
+fn find_explicit_root(
+    root_snapshot: Option<&RootSnapshot>,
+    root_snapshot_ring: Option<&RootSnapshotRing>,
+    root: &[u8; 32],
+    slot: u64,
+) -> Result<(Pubkey, u16, u64)> {
+    match (root_snapshot, root_snapshot_ring) {
+        (Some(root_snapshot), None) => {
+            check_root_snapshot(root_snapshot, slot)?;
+            if *root != root_snapshot.root {
+                msg!(
+                    "Root does not match the snapshot from slot {}",
+                    root_snapshot.slot
+                );
+                return Err(ErrorCode::InvalidMerkleRoot.into());
+            }
+            Ok((
+                root_snapshot.merkle_tree,
+                root_snapshot.root_index,
+                root_snapshot.slot,
+            ))
+        }
+        (None, Some(ring)) => match ring.find_recent(root, slot) {
+            Some(entry) => Ok((ring.merkle_tree, entry.root_index, entry.slot)),
+            None => {
+                msg!(
+                    "Root was not published for {} in the last {} slots",
+                    ring.merkle_tree,
+                    MAX_ROOT_SNAPSHOT_AGE_SLOTS
+                );
+                Err(ErrorCode::InvalidMerkleRoot.into())
+            }
+        },
+        _ => {
+            msg!("Pass exactly one of root_snapshot and root_snapshot_ring");
+            Err(ErrorCode::InvalidMerkleRoot.into())
+        }
+    }
+}
+
+/// Stamps a new nullifier with its TTL expiry and the root its spend was proven against
+/// `used_at` must already be set; `expires_at` stays 0 while `nullifier_ttl` is 0.
+fn record_spend_checkpoint(
+    nullifier: &mut NullifierAccount,
+    config: &ProgramConfig,
+    merkle_tree: Pubkey,
+    root_index: u16,
+    root: [u8; 32],
+) -> Result<()> {
+    nullifier.expires_at = if config.nullifier_ttl == 0 {
+        0
+    } else {
+        nullifier
+            .used_at
+            .checked_add(config.nullifier_ttl)
+            .ok_or(ProgramError::ArithmeticOverflow)?
+    };
+    nullifier.merkle_tree = merkle_tree;
+    nullifier.root_index = root_index;
+    nullifier.root = root;
+    Ok(())
+}
+
+/// Expiry of an innocence record proven at `proven_at`; 0 while `innocence_proof_ttl` is 0
+fn innocence_proof_expiry(config: &ProgramConfig, proven_at: u64) -> Result<u64> {
+    if config.innocence_proof_ttl == 0 {
+        return Ok(0);
+    }
+    Ok(proven_at
+        .checked_add(config.innocence_proof_ttl)
+        .ok_or(ProgramError::ArithmeticOverflow)?)
+}
+
+/// Checks a nullifier may be garbage-collected: its TTL has passed and its checkpoint
+/// root has left `merkle_tree`'s root history.
+///
+/// Replay safety does not depend on the nullifier account. A withdrawal spends a note by
+/// creating the nullifier address, and Light address trees never release an address, so
+/// after the account is closed a replay still fails creating it. Closing reclaims the
+/// compressed account only, never address-tree capacity.
+///
+/// The checkpoint keeps closing conservative on top of that. Roots are written to a
+/// cyclic history slot by slot, so once the slot at `root_index` no longer holds `root`,
+/// every root up to and including the one the spend was proven against has been
+/// overwritten, and no proof built against the pre-spend tree can still be verified.
+fn check_nullifier_gc(nullifier: &NullifierState, merkle_tree: &AccountInfo) -> Result<()> {
+    let now = Clock::get()?.unix_timestamp as u64;
+    if nullifier.expires_at == 0 || now < nullifier.expires_at {
+        msg!("Nullifier expires at {}, now {}", nullifier.expires_at, now);
+        return Err(ErrorCode::NullifierNotExpired.into());
+    }
+
+    if *merkle_tree.key != nullifier.merkle_tree {
+        msg!("Merkle tree {} is not the spend's tree", merkle_tree.key);
+        return Err(ProgramError::InvalidAccountData.into());
+    }
+    let root = read_state_merkle_tree_root(merkle_tree, nullifier.root_index)?;
+    if root == nullifier.root {
+        msg!(
+            "Checkpoint root {} is still in the root history",
+            nullifier.root_index
+        );
+        return Err(ErrorCode::NullifierCheckpointNotReached.into());
+    }
+
+    Ok(())
+}
+
+/// Re-derives the `[b"vault_authority", mint]` PDA from `bump` and checks it is `vault_authority`
+/// Guards the signed transfer in `withdraw_token` independently of the accounts struct.
+fn check_vault_authority(vault_authority: &Pubkey, mint: &Pubkey, bump: u8) -> Result<()> {
+    let expected =
+        Pubkey::create_program_address(&[b"vault_authority", mint.as_ref(), &[bump]], &crate::ID)
+            .map_err(|_| ErrorCode::InvalidVaultAuthority)?;
+    if expected != *vault_authority {
+        msg!(
+            "Vault authority {} is not the PDA for mint {}",
+            vault_authority,
+            mint
+        );
+        return Err(ErrorCode::InvalidVaultAuthority.into());
+    }
+    Ok(())
+}
+
+/// Fails with `InsufficientVaultBalance` unless `vault` can pay out `amount`
+/// The vault is a data-less system account, so the payout may empty it (the runtime
+/// deletes it and the next deposit recreates it) but not leave it below rent exemption,
+/// which the system program would reject.
+fn check_vault_balance(vault: &AccountInfo, amount: u64) -> Result<()> {
+    let balance = vault.lamports();
+    let remaining = balance
+        .checked_sub(amount)
+        .ok_or(ErrorCode::InsufficientVaultBalance)?;
+    if remaining != 0 && remaining < Rent::get()?.minimum_balance(0) {
+        msg!(
+            "Withdrawing {} of {} lamports would leave the vault below rent exemption",
+            amount,
+            balance
+        );
+        return Err(ErrorCode::InsufficientVaultBalance.into());
+    }
+    Ok(())
+}
+
+/// Checks `info` is a registered, active `AssociationSet` whose root is `association_set_root`
+/// The account address is pinned to the set id by the accounts struct; an uninitialized
+/// PDA is still owned by the system program and fails here.
+fn check_association_set(info: &AccountInfo, association_set_root: &[u8; 32]) -> Result<()> {
+    if info.owner != &crate::ID || info.data_is_empty() {
+        msg!("Association set {} is not registered", info.key);
+        return Err(ErrorCode::UnknownAssociationSet.into());
+    }
+
+    let association_set = AssociationSet::try_deserialize(&mut &info.data.borrow()[..])?;
+    if !association_set.active {
+        msg!("Association set {} is inactive", association_set.id);
+        return Err(ErrorCode::UnknownAssociationSet.into());
+    }
+    if &association_set.root != association_set_root {
+        msg!(
+            "Association set {} root is {}, proof used {}",
+            association_set.id,
+            Hex(&association_set.root),
+            Hex(association_set_root)
+        );
+        return Err(ErrorCode::AssociationSetRootMismatch.into());
+    }
+
+    Ok(())
+}
+
+/// Checks the config's withdrawal window has opened
+/// Every withdraw path stays closed until `withdrawals_enabled_at_slot`, see
+/// `set_withdrawals_enabled_at_slot`.
+fn check_withdrawals_enabled(config: &ProgramConfig) -> Result<()> {
+    let slot = Clock::get()?.slot;
+    if slot < config.withdrawals_enabled_at_slot {
+        msg!(
+            "Withdrawals open at slot {}, current slot {}",
+            config.withdrawals_enabled_at_slot,
+            slot
+        );
+        return Err(ErrorCode::WithdrawalsNotYetEnabled.into());
+    }
+
+    Ok(())
+}
+
+/// Checks `output_tree` against the config's pinned deposit state tree
+/// The tree must also pass `check_merkle_tree_allowed`, so default-pool deposits land where
+/// default-pool withdrawals can read them.
+fn check_deposit_state_tree(config: &ProgramConfig, output_tree: &Pubkey) -> Result<()> {
+    check_merkle_tree_allowed(config, output_tree)?;
+    if let Some(tree) = config.deposit_state_tree {
+        if tree != *output_tree {
+            msg!("Deposits must use state tree {}, got {}", tree, output_tree);
+            return Err(ErrorCode::InvalidDepositStateTree.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the optional `pool` account against `pool_id` and the state tree `tree`
+/// Pool 0 takes no pool account and must not use a sub-pool's state tree; any other pool
+/// must pass its `PoolAccount`, and `tree` must be the state tree it was registered with.
+fn check_pool(
+    config: &ProgramConfig,
+    pool: Option<&PoolAccount>,
+    pool_id: u16,
+    tree: &Pubkey,
+) -> Result<()> {
+    match pool {
+        None if pool_id == 0 => {
+            if config.pool_state_trees.contains(tree) {
+                msg!("State tree {} belongs to a sub-pool", tree);
+                return Err(ErrorCode::PoolStateTreeMismatch.into());
+            }
+            Ok(())
+        }
+        Some(pool) if pool.pool_id == pool_id => {
+            if pool.state_tree != *tree {
+                msg!(
+                    "Pool {} uses state tree {}, got {}",
+                    pool_id,
+                    pool.state_tree,
+                    tree
+                );
+                return Err(ErrorCode::PoolStateTreeMismatch.into());
+            }
+            Ok(())
+        }
+        _ => Err(ErrorCode::PoolMismatch.into()),
+    }
+}
+
+/// A verifying key tagged with the number of public inputs `N` its circuit takes
+/// `new` runs in const context, so a key whose `nr_pubinputs` or IC length disagrees
+/// with `N` fails the build, and `verify_groth16` only accepts `[[u8; 32]; N]` inputs.
+pub struct CircuitKey<const N: usize> {
+    pub vk: &'static Groth16Verifyingkey<'static>,
+}
+
+impl<const N: usize> CircuitKey<N> {
+    pub const fn new(vk: &'static Groth16Verifyingkey<'static>) -> Self {
+        assert!(vk.nr_pubinputs == N, "nr_pubinputs does not match N");
+        assert!(vk.vk_ic.len() == N + 1, "vk_ic length does not match N");
+        Self { vk }
+    }
+}
+
+/// Verifies a compressed Groth16 proof against `key` and its `N` public inputs
+/// Placeholder keys are rejected before any pairing work.
+fn verify_groth16<const N: usize>(
+    zk_proof: &CompressedProof,
+    public_inputs: &[[u8; 32]; N],
+    key: &CircuitKey<N>,
+) -> Result<()> {
+    if is_placeholder_key(key.vk) {
+        msg!("Verifying key has not been generated");
+        return Err(ErrorCode::VerifyingKeyNotGenerated.into());
+    }
+
+    check_proof_encoding(zk_proof)?;
+
+    #[cfg(feature = "measure_cu")]
+    msg!(
+        "groth16 verify start: {} CU remaining",
+        anchor_lang::solana_program::compute_units::sol_remaining_compute_units()
+    );
+
+    let proof_a = decompress_g1(&zk_proof.a).map_err(|e| {
+        let code: u32 = e.into();
+        Error::from(ProgramError::Custom(code))
+    })?;
+
+    let proof_b = decompress_g2(&zk_proof.b).map_err(|e| {
+        let code: u32 = e.into();
+        Error::from(ProgramError::Custom(code))
+    })?;
+
+    let proof_c = decompress_g1(&zk_proof.c).map_err(|e| {
+        let code: u32 = e.into();
+        Error::from(ProgramError::Custom(code))
+    })?;
+
+    // Setup and pairing failures both mean the proof does not verify for these inputs
+    let mut verifier = Groth16Verifier::new(&proof_a, &proof_b, &proof_c, public_inputs, key.vk)
+        .map_err(|e| {
+            let code: u32 = e.into();
+            msg!("Groth16 verifier setup failed: {}", code);
+            Error::from(ErrorCode::InvalidProof)
+        })?;
+
+    verifier.verify().map_err(|e| {
+        let code: u32 = e.into();
+        msg!("Groth16 verification failed: {}", code);
+        Error::from(ErrorCode::InvalidProof)
+    })?;
+
+    #[cfg(feature = "measure_cu")]
+    msg!(
+        "groth16 verify end: {} CU remaining ({} public inputs)",
+        anchor_lang::solana_program::compute_units::sol_remaining_compute_units(),
+        N
+    );
+
+    Ok(())
+}
+
+/// Identifies a verifying key by Sha256(delta || IC), the parts unique to each circuit setup
+fn verifying_key_id(vk: &Groth16Verifyingkey) -> Result<[u8; 32]> {
+    let mut inputs: Vec<&[u8]> = Vec::with_capacity(vk.vk_ic.len() + 1);
+    inputs.push(&vk.vk_delta_g2);
+    for ic in vk.vk_ic {
+        inputs.push(ic);
+    }
+    Sha256::hashv(&inputs).map_err(|_| Error::from(ProgramError::InvalidArgument))
+}
+
+/// Keys for circuits without a trusted setup yet ship with zeroed delta and IC.
+/// A zeroed delta makes the pairing check forgeable, so such keys are never used.
+pub(crate) fn is_placeholder_key(vk: &Groth16Verifyingkey) -> bool {
+    vk.vk_delta_g2 == [0u8; 128]
+}
+
+/// Cheap structural check of a compressed proof, run before the decompression syscalls
+/// Rejects all-zero points, points flagged as infinity and x coordinates outside the base
+/// field, which `decompress_g1`/`decompress_g2` would only reject after spending more compute.
+/// Relayers can run it off-chain to pre-screen submissions.
+pub fn check_proof_encoding(zk_proof: &CompressedProof) -> Result<()> {
+    if !is_well_formed_point(&zk_proof.a)
+        || !is_well_formed_point(&zk_proof.b)
+        || !is_well_formed_point(&zk_proof.c)
+    {
+        msg!("Malformed proof encoding");
+        return Err(ErrorCode::InvalidProof.into());
+    }
+    Ok(())
+}
+
+/// A big-endian compressed G1 (32 bytes) or G2 (64 bytes) point carries its flags in the top
+/// two bits of the first byte: 0x80 for the sign of y, 0x40 for the point at infinity.
+fn is_well_formed_point(bytes: &[u8]) -> bool {
+    if bytes.iter().all(|byte| *byte == 0) || bytes[0] & 0x40 != 0 {
+        return false;
+    }
+    bytes.chunks(32).enumerate().all(|(i, chunk)| {
+        let mut coordinate = [0u8; 32];
+        coordinate.copy_from_slice(chunk);
+        if i == 0 {
+            coordinate[0] &= 0x3f;
+        }
+        coordinate < BN254_BASE_FIELD_MODULUS
+    })
+}
+
+// ============ ACCOUNTS ============
+
+#[derive(Accounts)]
+pub struct GenericAnchorAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// The sub-pool's `PoolAccount`; `None` for pool 0. Matched to `pool_id` by check_pool
+    #[account(mut)]
+    pub pool: Option<Account<'info, PoolAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub input_merkle_tree: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawRecipientAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub input_merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: Must equal the proof's recipient, checked in `withdraw`
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+    /// The sub-pool's `PoolAccount`; `None` for pool 0. Matched to `pool_id` by check_pool
+    #[account(mut)]
+    pub pool: Option<Account<'info, PoolAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawConditionalAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub input_merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: Must equal the proof's recipient, checked in `withdraw_conditional`
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+    /// CHECK: Owner checked against the config's oracle_owner_allowlist
+    pub oracle: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawWithRootAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    pub root_snapshot: Account<'info, RootSnapshot>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawExplicitRootAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// The signer's pinned root; pass this or `root_snapshot_ring`, checked by find_explicit_root
+    pub root_snapshot: Option<Account<'info, RootSnapshot>>,
+    /// The tree's published roots; pass this or `root_snapshot`
+    pub root_snapshot_ring: Option<Account<'info, RootSnapshotRing>>,
+}
+
+#[derive(Accounts)]
+pub struct DepositSolAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: PDA vault that holds deposited SOL
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump,
+    )]
+    pub vault: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawSolAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub input_merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: PDA vault that holds deposited SOL
+    #[account(
+        mut,
+        seeds = [b"vault"],
+        bump,
+    )]
+    pub vault: UncheckedAccount<'info>,
+    /// CHECK: Recipient of withdrawn SOL
+    #[account(mut)]
+    pub recipient: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    proof: ValidityProof,
+    address_tree_info: PackedAddressTreeInfo,
+    output_state_tree_index: u8,
+    system_accounts_offset: u8,
+    input_root_index: u16,
+    association_set_root: [u8; 32],
+    nullifier_hash: [u8; 32],
+    association_set_id: u16,
+)]
+pub struct ProveInnocenceAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub deposit_merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: Registered and active, checked by check_association_set
+    #[account(
+        seeds = [ASSOCIATION_SET, &association_set_id.to_le_bytes()],
+        bump,
+    )]
+    pub association_set: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    proof: ValidityProof,
+    system_accounts_offset: u8,
+    input_root_index: u16,
+    association_set_root: [u8; 32],
+    innocence_proof: InnocenceProofState,
+)]
+pub struct ReproveInnocenceAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub deposit_merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: The record's set, registered and active, checked by check_association_set
+    #[account(
+        seeds = [ASSOCIATION_SET, &innocence_proof.association_set_id.to_le_bytes()],
+        bump,
+    )]
+    pub association_set: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositTokenAccounts<'info> {
+    /// Pays transaction and Light fees; may be a sponsor distinct from the depositor
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// Authority over `user_token_account`
+    pub depositor: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        address = vault_token_address(&user_token_account.mint)
+            @ ErrorCode::InvalidVaultTokenAccount,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct DepositWithPermitAccounts<'info> {
+    /// Relayer paying transaction fees, Light fees and the nonce account's rent
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// Delegated to `permit_delegate`; its owner signed the permit
+    #[account(mut)]
+    pub user_token_account: Account<'info, TokenAccount>,
+    #[account(
+        mut,
+        address = vault_token_address(&user_token_account.mint)
+            @ ErrorCode::InvalidVaultTokenAccount,
+    )]
+    pub vault_token_account: Account<'info, TokenAccount>,
+    /// CHECK: PDA delegate the owner approved; only signs transfers backed by a permit
+    #[account(
+        seeds = [PERMIT_DELEGATE],
+        bump,
+    )]
+    pub permit_delegate: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + PermitNonce::INIT_SPACE,
+        seeds = [PERMIT_NONCE, user_token_account.owner.as_ref()],
+        bump,
+    )]
+    pub permit_nonce: Account<'info, PermitNonce>,
+    /// CHECK: address constraint pins the instructions sysvar
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeTokenVaultAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub mint: Account<'info, Mint>,
+    /// CHECK: PDA authority for vault token transfers
+    #[account(
+        seeds = [b"vault_authority", mint.key().as_ref()],
+        bump,
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+    /// CHECK: Created by the associated token program if missing
+    #[account(
+        mut,
+        address = get_associated_token_address(&vault_authority.key(), &mint.key()),
+    )]
+    pub vault_token_account: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(
+    proof: ValidityProof,
+    address_tree_info: PackedAddressTreeInfo,
+    output_state_tree_index: u8,
+    system_accounts_offset: u8,
+    input_root_index: u16,
+    nullifier_hash: [u8; 32],
+    amount: u64,
+)]
+pub struct WithdrawTokenAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub input_merkle_tree: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub vault_token_account: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub recipient_token_account: Account<'info, TokenAccount>,
+    /// CHECK: PDA authority for vault token transfers
+    #[account(
+        seeds = [b"vault_authority", vault_token_account.mint.as_ref()],
+        bump,
+    )]
+    pub vault_authority: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
 }
 
-// ============ ACCOUNTS ============
+#[derive(Accounts)]
+pub struct ProgramInfoAccounts {}
 
 #[derive(Accounts)]
-pub struct GenericAnchorAccounts<'info> {
+pub struct InitializeConfigAccounts<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::PrivacyVault>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(signer.key())
+            @ ErrorCode::Unauthorized,
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = 8 + ProgramConfig::INIT_SPACE,
+        seeds = [CONFIG],
+        bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawAccounts<'info> {
+pub struct GcNullifierAccounts<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
-    /// CHECK: Validated by read_state_merkle_tree_root
-    pub input_merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: read_state_merkle_tree_root checks account owner, and discriminator
+    pub merkle_tree: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct DepositSolAccounts<'info> {
+pub struct AdminAccounts<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
-    /// CHECK: PDA vault that holds deposited SOL
     #[account(
         mut,
-        seeds = [b"vault"],
+        seeds = [CONFIG],
+        bump = config.bump,
+        constraint = config.admin == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+#[derive(Accounts)]
+#[instruction(association_set_id: u16)]
+pub struct RegisterAssociationSetAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+        constraint = config.admin == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + AssociationSet::INIT_SPACE,
+        seeds = [ASSOCIATION_SET, &association_set_id.to_le_bytes()],
         bump,
     )]
-    pub vault: UncheckedAccount<'info>,
+    pub association_set: Account<'info, AssociationSet>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct WithdrawSolAccounts<'info> {
+#[instruction(pool_id: u16)]
+pub struct RegisterPoolAccounts<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
-    /// CHECK: PDA vault that holds deposited SOL
     #[account(
         mut,
-        seeds = [b"vault"],
+        seeds = [CONFIG],
+        bump = config.bump,
+        constraint = config.admin == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + PoolAccount::INIT_SPACE,
+        seeds = [POOL, &pool_id.to_le_bytes()],
         bump,
     )]
-    pub vault: UncheckedAccount<'info>,
-    /// CHECK: Recipient of withdrawn SOL
-    #[account(mut)]
-    pub recipient: UncheckedAccount<'info>,
+    pub pool: Account<'info, PoolAccount>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct ProveInnocenceAccounts<'info> {
+pub struct InitializeRootSnapshotAccounts<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
     /// CHECK: Validated by read_state_merkle_tree_root
-    pub deposit_merkle_tree: UncheckedAccount<'info>,
+    pub merkle_tree: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + RootSnapshot::INIT_SPACE,
+        seeds = [ROOT_SNAPSHOT, merkle_tree.key().as_ref(), signer.key().as_ref()],
+        bump,
+    )]
+    pub root_snapshot: Account<'info, RootSnapshot>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct DepositTokenAccounts<'info> {
-    #[account(mut)]
+pub struct SnapshotRootAccounts<'info> {
     pub signer: Signer<'info>,
-    #[account(mut)]
-    pub user_token_account: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub vault_token_account: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub merkle_tree: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [ROOT_SNAPSHOT, merkle_tree.key().as_ref(), signer.key().as_ref()],
+        bump = root_snapshot.bump,
+    )]
+    pub root_snapshot: Account<'info, RootSnapshot>,
 }
 
 #[derive(Accounts)]
-#[instruction(
-    proof: ValidityProof,
-    address_tree_info: PackedAddressTreeInfo,
-    output_state_tree_index: u8,
-    system_accounts_offset: u8,
-    input_root_index: u16,
-    nullifier_hash: [u8; 32],
-    amount: u64,
-)]
-pub struct WithdrawTokenAccounts<'info> {
+pub struct InitializeRootSnapshotRingAccounts<'info> {
     #[account(mut)]
     pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
     /// CHECK: Validated by read_state_merkle_tree_root
-    pub input_merkle_tree: UncheckedAccount<'info>,
+    pub merkle_tree: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + RootSnapshotRing::INIT_SPACE,
+        seeds = [ROOT_SNAPSHOT_RING, merkle_tree.key().as_ref()],
+        bump,
+    )]
+    pub root_snapshot_ring: Account<'info, RootSnapshotRing>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CrankMerkleSnapshotAccounts<'info> {
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    /// CHECK: Validated by read_state_merkle_tree_root
+    pub merkle_tree: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        seeds = [ROOT_SNAPSHOT_RING, merkle_tree.key().as_ref()],
+        bump = root_snapshot_ring.bump,
+    )]
+    pub root_snapshot_ring: Account<'info, RootSnapshotRing>,
+}
+
+#[derive(Accounts)]
+#[instruction(stream_id: u64)]
+pub struct CreateStreamAccounts<'info> {
     #[account(mut)]
-    pub vault_token_account: Account<'info, TokenAccount>,
+    pub depositor: Signer<'info>,
+    #[account(
+        init,
+        payer = depositor,
+        space = 8 + StreamAccount::INIT_SPACE,
+        seeds = [STREAM, depositor.key().as_ref(), &stream_id.to_le_bytes()],
+        bump,
+    )]
+    pub stream: Account<'info, StreamAccount>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CrankStreamAccounts<'info> {
+    /// Anyone may crank; pays only the transaction and Light fees
     #[account(mut)]
-    pub recipient_token_account: Account<'info, TokenAccount>,
-    /// CHECK: PDA authority for vault token transfers
+    pub signer: Signer<'info>,
     #[account(
-        seeds = [b"vault_authority", vault_token_account.mint.as_ref()],
+        seeds = [CONFIG],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        mut,
+        seeds = [STREAM, stream.depositor.as_ref(), &stream.stream_id.to_le_bytes()],
+        bump = stream.bump,
+    )]
+    pub stream: Account<'info, StreamAccount>,
+    /// CHECK: PDA vault that holds deposited SOL
+    #[account(
+        mut,
+        seeds = [b"vault"],
         bump,
     )]
-    pub vault_authority: UncheckedAccount<'info>,
-    pub token_program: Program<'info, Token>,
+    pub vault: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelStreamAccounts<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        close = depositor,
+        has_one = depositor,
+        seeds = [STREAM, depositor.key().as_ref(), &stream.stream_id.to_le_bytes()],
+        bump = stream.bump,
+    )]
+    pub stream: Account<'info, StreamAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(association_set_id: u16)]
+pub struct AssociationSetAdminAccounts<'info> {
+    pub signer: Signer<'info>,
+    #[account(
+        seeds = [CONFIG],
+        bump = config.bump,
+        constraint = config.admin == signer.key() @ ErrorCode::Unauthorized,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(
+        mut,
+        seeds = [ASSOCIATION_SET, &association_set_id.to_le_bytes()],
+        bump = association_set.bump,
+    )]
+    pub association_set: Account<'info, AssociationSet>,
 }
 
 // ============ DATA STRUCTURES ============
 
-#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator)]
-pub struct VaultAccount {
-    pub authority: Pubkey,
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct ProgramInfo {
+    pub version: String,
+    pub circuits: Vec<CircuitInfo>,
+}
+
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct CircuitInfo {
+    pub name: String,
+    pub version: u8,
+    pub nr_pubinputs: u8,
+    pub vk_id: [u8; 32],
+    pub generated: bool,
+}
+
+/// Membership proof of a withdrawal recipient in the config's recipient allowlist
+/// `path` holds the `RECIPIENT_ALLOWLIST_LEVELS` siblings from `recipient_allowlist_path`,
+/// leaf first.
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct RecipientAllowlistProof {
+    pub leaf_index: u32,
+    pub path: Vec<[u8; 32]>,
+}
+
+/// A note's preimage, passed to `deposit` in dev mode to check the commitment on chain
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct NoteOpening {
+    pub nullifier: [u8; 32],
+    pub secret: [u8; 32],
+}
+
+/// Return data of `deposit`. Paired with the transaction signature it serves as an
+/// off-chain receipt; it names the deposit, not the payer.
+#[derive(Clone, Debug, PartialEq, Eq, AnchorSerialize, AnchorDeserialize)]
+pub struct DepositReceipt {
+    pub address: [u8; 32],
+    pub commitment: [u8; 32],
+    pub amount: u64,
+    /// Caller-supplied reference, all zeros when none was given
+    pub reference: [u8; 32],
+    pub slot: u64,
+}
+
+impl DepositReceipt {
+    /// Serialized size; the layout is fixed so truncated return data can be re-padded
+    pub const LEN: usize = 32 + 32 + 8 + 32 + 8;
+}
+
+/// Emitted by every withdraw path, once per nullifier created
+/// SOL withdrawals report `spl_token::native_mint::ID` as `mint`. The proof-only paths
+/// (`withdraw`, `withdraw_conditional`, `withdraw_many`, `withdraw_with_root` and
+/// `withdraw_explicit_root`) move no funds themselves, so they report the native mint and
+/// an `amount` of 0.
+#[event]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WithdrawEvent {
+    pub nullifier_hash: [u8; 32],
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub mint: Pubkey,
+}
+
+/// Program-wide settings, stored in the `[CONFIG]` PDA
+#[account]
+#[derive(InitSpace)]
+pub struct ProgramConfig {
+    pub admin: Pubkey,
+    pub dev_mode: bool,
+    pub bump: u8,
+    /// Seconds a new nullifier lives before `gc_nullifier` may close it; 0 = forever
+    pub nullifier_ttl: u64,
+    /// State trees root-reading instructions accept; empty = any state tree
+    #[max_len(MAX_ALLOWLISTED_TREES)]
+    pub merkle_tree_allowlist: Vec<Pubkey>,
+    /// Seconds a new innocence record stays valid before `close_innocence_proof` may
+    /// close it; 0 = forever
+    pub innocence_proof_ttl: u64,
+    /// Programs whose accounts `withdraw_conditional` accepts as oracles; empty = none
+    #[max_len(MAX_ALLOWLISTED_ORACLES)]
+    pub oracle_owner_allowlist: Vec<Pubkey>,
+    /// Root of the recipient allowlist `withdraw` checks payouts against; zeros = any
+    pub recipient_allowlist_root: [u8; 32],
+    /// State trees of the registered sub-pools, which default-pool paths never accept
+    #[max_len(MAX_POOLS)]
+    pub pool_state_trees: Vec<Pubkey>,
+    /// Slot from which withdraw paths accept proofs; 0 means immediately
+    pub withdrawals_enabled_at_slot: u64,
+    /// State tree default-pool deposits must write to; `None` accepts any allowlisted tree
+    pub deposit_state_tree: Option<Pubkey>,
+}
+
+/// Registry entry for an association set, stored in the `[ASSOCIATION_SET, id]` PDA
+#[account]
+#[derive(InitSpace)]
+pub struct AssociationSet {
+    pub id: u16,
+    pub active: bool,
+    pub bump: u8,
+    /// Current root of the set's tree; innocence proofs must use it as `associationSetRoot`
+    pub root: [u8; 32],
+}
+
+/// A registered sub-pool, stored in the `[POOL, pool_id]` PDA
+/// Only ever created by `register_pool`, so a `PoolAccount` with a given `pool_id` is
+/// that pool's PDA.
+#[account]
+#[derive(InitSpace)]
+pub struct PoolAccount {
+    pub pool_id: u16,
+    /// State tree the pool's deposits go to and its withdrawals prove against
+    pub state_tree: Pubkey,
+    pub deposit_count: u64,
+    /// Lamports deposited into the pool
     pub total_deposits: u64,
-    pub total_withdrawals: u64,
+    pub withdrawal_count: u64,
+    pub bump: u8,
+}
+
+/// Next permit nonce of a token owner, stored in the `[PERMIT_NONCE, owner]` PDA
+#[account]
+#[derive(InitSpace)]
+pub struct PermitNonce {
+    pub owner: Pubkey,
+    /// The nonce the owner's next `deposit_with_permit` permit must carry
+    pub nonce: u64,
+    pub bump: u8,
+}
+
+/// A state tree root copied by `snapshot_root`, stored in the
+/// `[ROOT_SNAPSHOT, merkle_tree, authority]` PDA and read by `withdraw_with_root` and
+/// `withdraw_explicit_root`
+#[account]
+#[derive(InitSpace)]
+pub struct RootSnapshot {
+    pub authority: Pubkey,
+    pub merkle_tree: Pubkey,
+    pub root: [u8; 32],
+    pub root_index: u16,
+    /// Slot the root was copied at
+    pub slot: u64,
+    pub bump: u8,
+}
+
+/// The last `ROOT_SNAPSHOT_RING_SIZE` roots of a state tree published by
+/// `crank_merkle_snapshot`, stored in the `[ROOT_SNAPSHOT_RING, merkle_tree]` PDA
+/// Unlike a `RootSnapshot` it has no authority: anyone may crank it and read it.
+/// `withdraw_explicit_root` accepts any root it published recently.
+#[account]
+#[derive(InitSpace)]
+pub struct RootSnapshotRing {
+    pub merkle_tree: Pubkey,
+    /// Entry the next crank overwrites, the oldest one once the ring has filled
+    pub next: u8,
+    pub last_cranked_slot: u64,
+    /// Unused entries are all zeros
+    pub entries: [PublishedRoot; ROOT_SNAPSHOT_RING_SIZE],
+    pub bump: u8,
+}
+
+impl RootSnapshotRing {
+    /// Overwrites the oldest entry with `entry`
+    pub fn push(&mut self, entry: PublishedRoot) {
+        self.entries[self.next as usize] = entry;
+        self.next = ((self.next as usize + 1) % ROOT_SNAPSHOT_RING_SIZE) as u8;
+    }
+
+    /// The entry that published `root`, if that was at most `MAX_ROOT_SNAPSHOT_AGE_SLOTS`
+    /// before `slot`
+    pub fn find_recent(&self, root: &[u8; 32], slot: u64) -> Option<&PublishedRoot> {
+        self.entries.iter().find(|entry| {
+            entry.root == *root
+                && entry.root != [0u8; 32]
+                && slot.saturating_sub(entry.slot) <= MAX_ROOT_SNAPSHOT_AGE_SLOTS
+        })
+    }
+}
+
+/// One root of a `RootSnapshotRing`, with the slot it was published at
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, AnchorSerialize, AnchorDeserialize, InitSpace,
+)]
+pub struct PublishedRoot {
+    pub root: [u8; 32],
+    pub root_index: u16,
+    pub slot: u64,
+}
+
+/// A recurring SOL deposit schedule, stored in the `[STREAM, depositor, stream_id]` PDA
+/// Lamports above rent are the escrow `crank_stream` draws from.
+#[account]
+#[derive(InitSpace)]
+pub struct StreamAccount {
+    pub depositor: Pubkey,
+    pub stream_id: u64,
+    pub amount_per_interval: u64,
+    pub interval_slots: u64,
+    /// First slot the next deposit may be cranked at
+    pub next_deposit_slot: u64,
+    /// Deposits executed so far; `commitments[deposits_made]` is the next one
+    pub deposits_made: u8,
+    #[max_len(MAX_STREAM_DEPOSITS)]
+    pub commitments: Vec<[u8; 32]>,
+    pub bump: u8,
+}
+
+/// How `withdraw_conditional` compares the oracle value with the threshold
+#[derive(Clone, Copy, Debug, PartialEq, Eq, AnchorSerialize, AnchorDeserialize)]
+pub enum OracleComparison {
+    GreaterThan,
+    GreaterOrEqual,
+    LessThan,
+    LessOrEqual,
+    Equal,
+    NotEqual,
+}
+
+/// Condition the oracle account of `withdraw_conditional` must satisfy
+/// The value is the little-endian `i64` at byte `offset` of the oracle account's data,
+/// compared as `value <comparison> threshold` in the oracle's own units and exponent.
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct OraclePredicate {
+    pub offset: u32,
+    pub comparison: OracleComparison,
+    pub threshold: i64,
 }
 
+impl OraclePredicate {
+    /// The oracle value in `data`, or `None` if fewer than 8 bytes follow `offset`
+    pub fn read_value(&self, data: &[u8]) -> Option<i64> {
+        let start = self.offset as usize;
+        let bytes = data.get(start..start.checked_add(8)?)?;
+        Some(i64::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    /// Whether `value <comparison> threshold`
+    pub fn holds(&self, value: i64) -> bool {
+        match self.comparison {
+            OracleComparison::GreaterThan => value > self.threshold,
+            OracleComparison::GreaterOrEqual => value >= self.threshold,
+            OracleComparison::LessThan => value < self.threshold,
+            OracleComparison::LessOrEqual => value <= self.threshold,
+            OracleComparison::Equal => value == self.threshold,
+            OracleComparison::NotEqual => value != self.threshold,
+        }
+    }
+}
+
+/// A SOL deposit note
+/// The data hash is generic over the hasher (`DataHasher::hash::<H>()`); the deposit
+/// paths store it through `LightAccountPoseidon`, matching the Poseidon circuits. The
+/// `#[hash]` fields are truncated to the field with `hash_to_field_size` whatever `H` is.
 #[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator, LightHasher)]
 pub struct DepositAccount {
     #[hash]
     pub commitment: Commitment,
     pub amount: u64,
     pub timestamp: u64,
+    /// Optional wallet scanning hint; not part of the account hash
+    #[skip]
+    pub view_tag: Option<u8>,
+    /// Optional encrypted backup of the note; not part of the account hash
+    #[skip]
+    pub encrypted_note: Option<Vec<u8>>,
 }
 
 #[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator, LightHasher)]
@@ -802,7 +4043,7 @@ pub struct TokenDepositAccount {
     pub timestamp: u64,
 }
 
-#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
 pub struct TokenMintHash {
     pub value: [u8; 32],
 }
@@ -811,6 +4052,69 @@ impl TokenMintHash {
     pub fn from_pubkey(pubkey: &Pubkey) -> Self {
         Self { value: pubkey.to_bytes() }
     }
+
+    /// Asset identifier for native SOL deposits: the wrapped SOL mint
+    pub fn native() -> Self {
+        Self::from_pubkey(&spl_token::native_mint::ID)
+    }
+
+    pub fn is_native(&self) -> bool {
+        *self == Self::native()
+    }
+}
+
+/// A SOL or token deposit, read through one set of accessors
+/// SOL deposits report `TokenMintHash::native()` as their asset. Stored layouts are
+/// unchanged, since both account hashes are fixed by the circuits.
+#[derive(Clone, Debug)]
+pub enum AnyDeposit {
+    Sol(DepositAccount),
+    Token(TokenDepositAccount),
+}
+
+impl AnyDeposit {
+    /// Decodes compressed account data by its Light discriminator
+    /// Returns `None` for other account types or data that does not deserialize.
+    pub fn from_account_data(discriminator: [u8; 8], data: &[u8]) -> Option<Self> {
+        let mut data = data;
+        if discriminator == DepositAccount::LIGHT_DISCRIMINATOR {
+            DepositAccount::deserialize(&mut data).ok().map(Self::Sol)
+        } else if discriminator == TokenDepositAccount::LIGHT_DISCRIMINATOR {
+            TokenDepositAccount::deserialize(&mut data)
+                .ok()
+                .map(Self::Token)
+        } else {
+            None
+        }
+    }
+
+    pub fn commitment(&self) -> &Commitment {
+        match self {
+            Self::Sol(deposit) => &deposit.commitment,
+            Self::Token(deposit) => &deposit.commitment,
+        }
+    }
+
+    pub fn amount(&self) -> u64 {
+        match self {
+            Self::Sol(deposit) => deposit.amount,
+            Self::Token(deposit) => deposit.amount,
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Self::Sol(deposit) => deposit.timestamp,
+            Self::Token(deposit) => deposit.timestamp,
+        }
+    }
+
+    pub fn asset(&self) -> TokenMintHash {
+        match self {
+            Self::Sol(_) => TokenMintHash::native(),
+            Self::Token(deposit) => deposit.token_mint_hash.clone(),
+        }
+    }
 }
 
 impl ToByteArray for TokenMintHash {
@@ -842,13 +4146,103 @@ impl ToByteArray for Commitment {
 pub struct NullifierAccount {
     pub nullifier_hash: [u8; 32],
     pub used_at: u64,
+    /// Unix time after which `gc_nullifier` may close the account; 0 = never
+    pub expires_at: u64,
+    /// Tree, root-history index and root the spend was proven against (the GC checkpoint)
+    pub merkle_tree: Pubkey,
+    pub root_index: u16,
+    pub root: [u8; 32],
+}
+
+/// An owner's off-chain authorization for one `deposit_with_permit`
+/// `signature` is the owner's Ed25519 signature over `deposit_permit_message`.
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct DepositPermit {
+    pub nonce: u64,
+    /// Unix time after which the permit is rejected
+    pub deadline: i64,
+    pub signature: [u8; 64],
+}
+
+/// A nullifier account as stored, passed to `close_nullifier` and `gc_nullifier`
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct NullifierState {
+    pub account_meta: CompressedAccountMeta,
+    pub nullifier_hash: [u8; 32],
+    pub used_at: u64,
+    pub expires_at: u64,
+    pub merkle_tree: Pubkey,
+    pub root_index: u16,
+    pub root: [u8; 32],
+}
+
+impl NullifierState {
+    /// The stored account, for the Light system program's hash check
+    fn to_account(&self) -> NullifierAccount {
+        NullifierAccount {
+            nullifier_hash: self.nullifier_hash,
+            used_at: self.used_at,
+            expires_at: self.expires_at,
+            merkle_tree: self.merkle_tree,
+            root_index: self.root_index,
+            root: self.root,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator)]
 pub struct InnocenceProofAccount {
     pub nullifier_hash: [u8; 32],
-    pub association_set_id: u8,
+    pub association_set_id: u16,
+    pub proven_at: u64,
+    /// Unix time after which `close_innocence_proof` may close the record; 0 = never
+    pub expires_at: u64,
+}
+
+/// An innocence record as stored, passed to `reprove_innocence` and `close_innocence_proof`
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct InnocenceProofState {
+    pub account_meta: CompressedAccountMeta,
+    pub nullifier_hash: [u8; 32],
+    pub association_set_id: u16,
     pub proven_at: u64,
+    pub expires_at: u64,
+}
+
+impl InnocenceProofState {
+    /// The stored account, for the Light system program's hash check
+    fn to_account(&self) -> InnocenceProofAccount {
+        InnocenceProofAccount {
+            nullifier_hash: self.nullifier_hash,
+            association_set_id: self.association_set_id,
+            proven_at: self.proven_at,
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+/// Every association set a nullifier has proven innocence for, one bit per set id
+/// Kept next to the per-set `InnocenceProofAccount`s at `[INNOCENCE_BITMAP, nullifier_hash]`,
+/// so one lookup answers whether a nullifier satisfies several sets.
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator)]
+pub struct InnocenceBitmapAccount {
+    pub nullifier_hash: [u8; 32],
+    /// Bit `id` is set once innocence was proven for association set `id`
+    pub satisfied_sets: u64,
+}
+
+impl InnocenceBitmapAccount {
+    /// Whether innocence was proven for every set in `mask`
+    pub fn satisfies(&self, mask: u64) -> bool {
+        self.satisfied_sets & mask == mask
+    }
+}
+
+/// An existing `InnocenceBitmapAccount`, passed to `prove_innocence` to update it
+#[derive(Clone, Debug, AnchorSerialize, AnchorDeserialize)]
+pub struct InnocenceBitmapState {
+    pub account_meta: CompressedAccountMeta,
+    pub satisfied_sets: u64,
 }
 
 // ============ ERRORS ============
@@ -863,4 +4257,94 @@ pub enum ErrorCode {
     InvalidProof,
     #[msg("Invalid Merkle root")]
     InvalidMerkleRoot,
+    #[msg("Verifying key has not been generated for this circuit")]
+    VerifyingKeyNotGenerated,
+    #[msg("Invalid recipient: cannot withdraw to the vault or the system program")]
+    InvalidRecipient,
+    #[msg("Vault token account is not the canonical vault_authority ATA for this mint")]
+    InvalidVaultTokenAccount,
+    #[msg("Signer is not the config admin")]
+    Unauthorized,
+    #[msg("Instruction is only available when dev_mode is enabled")]
+    DevModeDisabled,
+    #[msg("Withdraw batch is empty, too large, or its argument vectors differ in length")]
+    InvalidBatchSize,
+    #[msg("Withdrawals are not yet enabled for this pool")]
+    WithdrawalsNotYetEnabled,
+    #[msg("Association set is not registered or not active")]
+    UnknownAssociationSet,
+    #[msg("Vault authority is not the canonical PDA for this mint")]
+    InvalidVaultAuthority,
+    #[msg("Output state tree is not the config's deposit state tree")]
+    InvalidDepositStateTree,
+    #[msg("Nullifier has no TTL or it has not yet passed")]
+    NullifierNotExpired,
+    #[msg("Nullifier's checkpoint root is still in the deposit tree's root history")]
+    NullifierCheckpointNotReached,
+    #[msg("Root snapshot is older than MAX_ROOT_SNAPSHOT_AGE_SLOTS")]
+    RootSnapshotStale,
+    #[msg("Merkle tree is not on the config's merkle_tree_allowlist")]
+    MerkleTreeNotAllowed,
+    #[msg("Merkle tree allowlist is longer than MAX_ALLOWLISTED_TREES")]
+    MerkleTreeAllowlistTooLong,
+    #[msg("Stream needs a non-zero amount and interval and 1..=MAX_STREAM_DEPOSITS commitments")]
+    InvalidStream,
+    #[msg("Stream's next deposit is not due yet")]
+    StreamNotDue,
+    #[msg("Stream has made all of its scheduled deposits")]
+    StreamExhausted,
+    #[msg("System accounts offset is past the end of the remaining accounts")]
+    InvalidSystemAccountsOffset,
+    #[msg("Vault balance cannot cover the amount and stay rent exempt")]
+    InsufficientVaultBalance,
+    #[msg("Association set id has no bit in the innocence bitmap")]
+    InvalidAssociationSetId,
+    #[msg("Encrypted note exceeds MAX_ENCRYPTED_NOTE_LEN")]
+    EncryptedNoteTooLong,
+    #[msg("Recipient account does not match the proof's recipient")]
+    RecipientMismatch,
+    #[msg("Innocence proof has not expired yet")]
+    ProofNotExpired,
+    #[msg("Root index is outside the state tree's root history")]
+    InvalidRootIndex,
+    #[msg("Commitment is not a canonical BN254 field element or does not match its opening")]
+    InvalidCommitment,
+    #[msg("LIGHT_CPI_SIGNER was not derived from this program's id")]
+    InvalidCpiSigner,
+    #[msg("Missing or mismatched Ed25519 permit signature instruction")]
+    InvalidPermitSignature,
+    #[msg("Deposit permit has passed its deadline")]
+    PermitExpired,
+    #[msg("Deposit permit nonce is not the owner's next nonce")]
+    InvalidPermitNonce,
+    #[msg("Oracle account is not owned by a program on the oracle_owner_allowlist")]
+    UntrustedOracle,
+    #[msg("Oracle account data has no 8-byte value at the predicate's offset")]
+    InvalidOracleData,
+    #[msg("Oracle value does not satisfy the withdrawal predicate")]
+    OracleConditionNotMet,
+    #[msg("Oracle owner allowlist is longer than MAX_ALLOWLISTED_ORACLES")]
+    OracleAllowlistTooLong,
+    #[msg("Root snapshot ring was cranked less than MIN_CRANK_INTERVAL_SLOTS ago")]
+    CrankTooSoon,
+    #[msg("Recipient is not on the config's recipient allowlist")]
+    RecipientNotAllowlisted,
+    #[msg("Recipient allowlist is set; only withdraw can pay out")]
+    RecipientAllowlistActive,
+    #[msg("Pool id 0 is the default pool and cannot be registered")]
+    InvalidPoolId,
+    #[msg("Pool account does not match pool_id")]
+    PoolMismatch,
+    #[msg("State tree is not the pool's state tree")]
+    PoolStateTreeMismatch,
+    #[msg("A pool's state tree must not be on the merkle tree allowlist")]
+    PoolStateTreeAllowlisted,
+    #[msg("State tree is already registered to a sub-pool")]
+    PoolStateTreeRegistered,
+    #[msg("Config already tracks MAX_POOLS sub-pools")]
+    PoolLimitReached,
+    #[msg("Program config already initialized")]
+    ConfigAlreadyInitialized,
+    #[msg("Association set root does not match the set's current root")]
+    AssociationSetRootMismatch,
 }
@@ -0,0 +1,190 @@
+//! Note commitments shared by depositors and the vault circuits.
+//!
+//! Mirrors `circuits/vault/commitment.circom`:
+//!   Commitment    = Poseidon(nullifier, secret)
+//!   NullifierHash = Poseidon(nullifier)
+//!
+//! The `_with` variants take the hash function as a type parameter, so a second circuit
+//! family can reuse the note format; the plain functions are the Poseidon instances the
+//! deployed circuits use.
+//!
+//! `nullifier` and `secret` are 32-byte big-endian BN254 field elements. Values at or
+//! above the field modulus are rejected rather than reduced, since the circuit would
+//! see a different value than the one the note stores.
+//!
+//! View tags let a wallet skip most deposits when scanning for its own:
+//!   ViewTag = Sha256(VIEW_TAG_DOMAIN || view_key || commitment)[0]
+//!
+//! Deposit streams pre-commit one note per interval, derived from a single stream seed:
+//!   Nullifier_i = Poseidon(seed, i, 1)
+//!   Secret_i    = Poseidon(seed, i, 2)
+//!
+//! `Note` bundles a note's secrets for backup as a checksummed base58 string.
+
+use anchor_lang::prelude::Pubkey;
+use light_hasher::hash_to_field_size::hash_to_bn254_field_size_be;
+use light_hasher::{Hasher, HasherError, Poseidon, Sha256};
+
+/// Domain separator for view tag derivation.
+pub const VIEW_TAG_DOMAIN: &[u8] = b"privacy-vault-view-tag";
+
+/// Commitment stored in the deposit account for a `(nullifier, secret)` note.
+pub fn compute_commitment(
+    nullifier: &[u8; 32],
+    secret: &[u8; 32],
+) -> Result<[u8; 32], HasherError> {
+    compute_commitment_with::<Poseidon>(nullifier, secret)
+}
+
+/// `compute_commitment` under hash function `H`.
+pub fn compute_commitment_with<H: Hasher>(
+    nullifier: &[u8; 32],
+    secret: &[u8; 32],
+) -> Result<[u8; 32], HasherError> {
+    H::hashv(&[nullifier.as_slice(), secret.as_slice()])
+}
+
+/// Nullifier hash revealed when the note is withdrawn.
+pub fn compute_nullifier_hash(nullifier: &[u8; 32]) -> Result<[u8; 32], HasherError> {
+    compute_nullifier_hash_with::<Poseidon>(nullifier)
+}
+
+/// `compute_nullifier_hash` under hash function `H`.
+pub fn compute_nullifier_hash_with<H: Hasher>(
+    nullifier: &[u8; 32],
+) -> Result<[u8; 32], HasherError> {
+    H::hash(nullifier.as_slice())
+}
+
+/// One-byte tag a depositor attaches so the holder of `view_key` can recognise the deposit.
+/// Only the view key holder can compute it, but a matching tag is a 1-in-256 hint,
+/// not proof of ownership.
+pub fn compute_view_tag(view_key: &[u8; 32], commitment: &[u8; 32]) -> Result<u8, HasherError> {
+    let hash = Sha256::hashv(&[VIEW_TAG_DOMAIN, view_key.as_slice(), commitment.as_slice()])?;
+    Ok(hash[0])
+}
+
+/// Nullifier and secret of deposit `index` of a stream, so one backed-up `seed` recovers
+/// every note the stream deposits. `index` is big-endian in a 32-byte field element and
+/// `seed` must be a field element.
+pub fn derive_stream_note(
+    seed: &[u8; 32],
+    index: u32,
+) -> Result<([u8; 32], [u8; 32]), HasherError> {
+    let mut index_bytes = [0u8; 32];
+    index_bytes[28..].copy_from_slice(&index.to_be_bytes());
+    let mut domain = [0u8; 32];
+    domain[31] = 1;
+    let nullifier = Poseidon::hashv(&[seed.as_slice(), &index_bytes, &domain])?;
+    domain[31] = 2;
+    let secret = Poseidon::hashv(&[seed.as_slice(), &index_bytes, &domain])?;
+    Ok((nullifier, secret))
+}
+
+/// Version byte leading every exported note string.
+pub const NOTE_VERSION: u8 = 1;
+
+const NOTE_CHECKSUM_LEN: usize = 4;
+
+/// A depositor's note: everything needed to later withdraw the deposit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Note {
+    pub nullifier: [u8; 32],
+    pub secret: [u8; 32],
+    pub amount: u64,
+    /// Token mint for `deposit_token` notes, `None` for SOL
+    pub mint: Option<Pubkey>,
+}
+
+/// Reasons an exported note string is rejected on import.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NoteError {
+    InvalidBase58,
+    InvalidLength,
+    UnsupportedVersion(u8),
+    InvalidMintFlag(u8),
+    InvalidChecksum,
+}
+
+impl Note {
+    /// Encodes the note as a base58 string for backups and QR codes.
+    ///
+    /// Layout before encoding:
+    ///   version (1) || nullifier (32) || secret (32) || amount (8, BE)
+    ///   || mint flag (1) || mint (32, only if flag = 1) || checksum (4)
+    /// where checksum = Sha256(Sha256(everything before it))[..4].
+    pub fn to_base58(&self) -> String {
+        bs58::encode(self.to_bytes()).into_string()
+    }
+
+    /// Decodes a string produced by `to_base58`, rejecting corrupted or unknown input.
+    pub fn from_base58(encoded: &str) -> Result<Self, NoteError> {
+        let bytes = bs58::decode(encoded.trim())
+            .into_vec()
+            .map_err(|_| NoteError::InvalidBase58)?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// The checksummed byte layout `to_base58` encodes, also the plaintext of an
+    /// encrypted on-chain backup (`client::encrypt_note`).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(1 + 32 + 32 + 8 + 1 + 32 + NOTE_CHECKSUM_LEN);
+        bytes.push(NOTE_VERSION);
+        bytes.extend_from_slice(&self.nullifier);
+        bytes.extend_from_slice(&self.secret);
+        bytes.extend_from_slice(&self.amount.to_be_bytes());
+        match self.mint {
+            Some(mint) => {
+                bytes.push(1);
+                bytes.extend_from_slice(mint.as_ref());
+            }
+            None => bytes.push(0),
+        }
+        let checksum = note_checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+        bytes
+    }
+
+    /// Decodes bytes produced by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, NoteError> {
+        if bytes.len() < 1 + 32 + 32 + 8 + 1 + NOTE_CHECKSUM_LEN {
+            return Err(NoteError::InvalidLength);
+        }
+        let (payload, checksum) = bytes.split_at(bytes.len() - NOTE_CHECKSUM_LEN);
+        if note_checksum(payload) != checksum {
+            return Err(NoteError::InvalidChecksum);
+        }
+        if payload[0] != NOTE_VERSION {
+            return Err(NoteError::UnsupportedVersion(payload[0]));
+        }
+
+        let mut nullifier = [0u8; 32];
+        nullifier.copy_from_slice(&payload[1..33]);
+        let mut secret = [0u8; 32];
+        secret.copy_from_slice(&payload[33..65]);
+        let mut amount = [0u8; 8];
+        amount.copy_from_slice(&payload[65..73]);
+
+        let mint = match (payload[73], payload.len()) {
+            (0, 74) => None,
+            (1, 106) => Some(Pubkey::try_from(&payload[74..106]).unwrap()),
+            (0 | 1, _) => return Err(NoteError::InvalidLength),
+            (flag, _) => return Err(NoteError::InvalidMintFlag(flag)),
+        };
+
+        Ok(Self {
+            nullifier,
+            secret,
+            amount: u64::from_be_bytes(amount),
+            mint,
+        })
+    }
+}
+
+fn note_checksum(payload: &[u8]) -> [u8; NOTE_CHECKSUM_LEN] {
+    let first = Sha256::hash(payload).unwrap();
+    let second = Sha256::hash(&first).unwrap();
+    let mut checksum = [0u8; NOTE_CHECKSUM_LEN];
+    checksum.copy_from_slice(&second[..NOTE_CHECKSUM_LEN]);
+    checksum
+}
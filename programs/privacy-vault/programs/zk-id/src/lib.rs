@@ -2,10 +2,18 @@
 #![allow(deprecated)]
 
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::ed25519_program;
+use anchor_lang::solana_program::sysvar::{
+    self,
+    instructions::{load_current_index_checked, load_instruction_at_checked},
+};
+use anchor_lang::system_program;
 use borsh::{BorshDeserialize, BorshSerialize};
-use groth16_solana::groth16::Groth16Verifier;
+use groth16_solana::decompression::{decompress_g1, decompress_g2};
+use groth16_solana::groth16::{Groth16Verifier, Groth16Verifyingkey};
+use light_hasher::hash_to_field_size::hashv_to_bn254_field_size_be_const_array;
 use light_hasher::to_byte_array::ToByteArray;
-use light_hasher::{Hasher, HasherError, Sha256};
+use light_hasher::{Hasher, HasherError, Poseidon, Sha256};
 use light_sdk::account::{poseidon::LightAccount as LightAccountPoseidon, LightAccount};
 use light_sdk::cpi::v2::CpiAccounts;
 use light_sdk::{
@@ -28,29 +36,63 @@ pub const LIGHT_CPI_SIGNER: CpiSigner =
 pub const ISSUER: &[u8] = b"issuer";
 pub const CREDENTIAL: &[u8] = b"credential";
 pub const ZK_ID_CHECK: &[u8] = b"ZK_ID_CHECK";
+pub const CREDENTIAL_AUTHORIZATION: &[u8] = b"ZK_ID_CREDENTIAL_AUTHORIZATION";
+pub const VERIFICATION_ID: &[u8] = b"ZK_ID_VERIFICATION_ID";
 
 // Include the generated verifying key module
 pub mod verifying_key;
 
+// Forgeable test key, see the module docs; never enable `test-mock-vk` for a deployed build
+#[cfg(feature = "test-mock-vk")]
+pub mod verifying_key_mock;
+
+// Credential verifying key paired with the circuit's public-input count
+#[cfg(not(feature = "test-mock-vk"))]
+pub const CREDENTIAL_KEY: CircuitKey<8> = CircuitKey::new(&verifying_key::VERIFYINGKEY);
+#[cfg(feature = "test-mock-vk")]
+pub const CREDENTIAL_KEY: CircuitKey<8> = CircuitKey::new(&verifying_key_mock::VERIFYINGKEY);
+
+// Length of a v1 state tree's root history. The indexer's `root_seq` counts every root
+// ever written; the slot holding that root is `root_seq % ROOT_HISTORY_SIZE`.
+pub const ROOT_HISTORY_SIZE: u64 = 2400;
+
+// BN254 base field modulus, big-endian; compressed proof coordinates must be below it
+pub const BN254_BASE_FIELD_MODULUS: [u8; 32] = [
+    0x30, 0x64, 0x4e, 0x72, 0xe1, 0x31, 0xa0, 0x29, 0xb8, 0x50, 0x45, 0xb6, 0x81, 0x81, 0x58, 0x5d,
+    0x97, 0x81, 0x6a, 0x91, 0x68, 0x71, 0xca, 0x8d, 0x3c, 0x20, 0x8c, 0x16, 0xd8, 0x7c, 0xfd, 0x47,
+];
+
+/// Most credential proofs a single `zk_verify_credential_batch` call verifies
+pub const MAX_VERIFY_BATCH: usize = 4;
+
+/// Depth of an issuer's pre-authorized credential tree, see `add_credential_from_root`
+pub const AUTHORIZED_SET_LEVELS: usize = 16;
+
 #[program]
 pub mod zk_id {
 
-    use groth16_solana::decompression::{decompress_g1, decompress_g2};
-    use light_hasher::hash_to_field_size::hashv_to_bn254_field_size_be_const_array;
-
     use super::*;
 
     /// Creates a new issuer compressed account
+    /// `max_credentials` caps how many credentials it can issue, 0 for no cap.
+    /// Also creates the signer's `IssuerState` PDA, starting at epoch 0 with no revocation
+    /// root, so a repeated call fails with `IssuerAlreadyExists` before the Light CPI instead
+    /// of on the address collision.
+    /// Fails with `InvalidCpiSigner` if `LIGHT_CPI_SIGNER` was built for another program id.
     pub fn create_issuer<'info>(
-        ctx: Context<'_, '_, '_, 'info, GenericAnchorAccounts<'info>>,
+        ctx: Context<'_, '_, '_, 'info, CreateIssuerAccounts<'info>>,
         proof: ValidityProof,
         address_tree_info: PackedAddressTreeInfo,
         output_state_tree_index: u8,
         system_accounts_offset: u8,
+        max_credentials: u64,
     ) -> Result<()> {
+        check_light_cpi_signer()?;
+        create_issuer_state(ctx.accounts, ctx.bumps.issuer_state)?;
+
         let light_cpi_accounts = CpiAccounts::new(
             ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
             crate::LIGHT_CPI_SIGNER,
         );
 
@@ -77,6 +119,9 @@ pub mod zk_id {
 
         issuer_account.issuer_pubkey = ctx.accounts.signer.key();
         issuer_account.num_credentials_issued = 0;
+        issuer_account.max_credentials = max_credentials;
+        issuer_account.metadata_uri = None;
+        issuer_account.authorized_root = [0u8; 32];
 
         msg!(
             "Created issuer account for pubkey: {}",
@@ -95,9 +140,16 @@ pub mod zk_id {
 
     /// Creates a new credential compressed account storing a pubkey
     /// Requires a valid issuer account - only the issuer can create credentials
+    /// The credential is bound to the `issuer_epoch` in the signer's `IssuerState`.
+    ///
+    /// With `idempotent` set, a retry may pass the meta of the credential it already
+    /// created as `existing_credential_meta` (and a validity proof for that account
+    /// instead of the new address). The credential is re-emitted unchanged, which only
+    /// succeeds if it exists with the signer as issuer, and the issuer counter is not
+    /// incremented again.
     #[allow(clippy::too_many_arguments)]
     pub fn add_credential<'info>(
-        ctx: Context<'_, '_, '_, 'info, GenericAnchorAccounts<'info>>,
+        ctx: Context<'_, '_, '_, 'info, IssuerStateAccounts<'info>>,
         proof: ValidityProof,
         address_tree_info: PackedAddressTreeInfo,
         output_state_tree_index: u8,
@@ -105,24 +157,80 @@ pub mod zk_id {
         issuer_account_meta: CompressedAccountMeta,
         credential_pubkey: Pubkey,
         num_credentials_issued: u64,
+        max_credentials: u64,
+        metadata_uri: Option<[u8; 64]>,
+        authorized_root: [u8; 32],
+        idempotent: bool,
+        existing_credential_meta: Option<CompressedAccountMeta>,
     ) -> Result<()> {
+        let issuer_epoch = ctx.accounts.issuer_state.issuer_epoch;
         let light_cpi_accounts = CpiAccounts::new(
             ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
             crate::LIGHT_CPI_SIGNER,
         );
 
+        if let Some(credential_meta) = existing_credential_meta {
+            if !idempotent {
+                msg!("existing_credential_meta requires idempotent mode");
+                return Err(ProgramError::InvalidArgument.into());
+            }
+
+            let address_tree_pubkey = address_tree_info
+                .get_tree_pubkey(&light_cpi_accounts)
+                .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+            let (address, _) = derive_address(
+                &[CREDENTIAL, credential_pubkey.as_ref()],
+                &address_tree_pubkey,
+                &crate::ID,
+            );
+            if credential_meta.address != address {
+                msg!("Existing credential address mismatch");
+                return Err(ProgramError::InvalidArgument.into());
+            }
+
+            // Hash check in the CPI fails unless the credential exists with this issuer
+            let credential_account = LightAccountPoseidon::<CredentialAccount>::new_mut(
+                &crate::ID,
+                &credential_meta,
+                CredentialAccount {
+                    issuer: CredentialIssuer::new(ctx.accounts.signer.key(), issuer_epoch),
+                    credential_pubkey: CredentialPubkey::new(credential_pubkey),
+                },
+            )?;
+
+            msg!(
+                "Credential already exists for pubkey: {} (idempotent retry)",
+                credential_pubkey
+            );
+
+            LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+                .with_light_account_poseidon(credential_account)?
+                .invoke(light_cpi_accounts)?;
+
+            return Ok(());
+        }
+
         // Verify the issuer account - read it to ensure it exists and signer is the issuer
+        // The supplied fields are hashed into the input account; the Light system program
+        // rejects the CPI unless that hash is the stored leaf, so a spoofed
+        // `num_credentials_issued` (or issuer) never reaches state. Programs cannot read
+        // compressed account data any other way.
         let mut issuer_account = LightAccount::<IssuerAccount>::new_mut(
             &crate::ID,
             &issuer_account_meta,
             IssuerAccount {
                 issuer_pubkey: ctx.accounts.signer.key(),
                 num_credentials_issued,
+                max_credentials,
+                metadata_uri,
+                authorized_root,
             },
         )?;
 
-        // Increment the credential counter
+        // Increment the credential counter, within the issuer's cap
+        check_issuance_limit(&issuer_account)?;
         issuer_account.num_credentials_issued = issuer_account
             .num_credentials_issued
             .checked_add(1)
@@ -149,7 +257,7 @@ pub mod zk_id {
             output_state_tree_index,
         );
 
-        credential_account.issuer = ctx.accounts.signer.key();
+        credential_account.issuer = CredentialIssuer::new(ctx.accounts.signer.key(), issuer_epoch);
         credential_account.credential_pubkey = CredentialPubkey::new(credential_pubkey);
 
         msg!(
@@ -169,161 +277,1308 @@ pub mod zk_id {
         Ok(())
     }
 
-    /// Verifies a ZK proof of credential ownership and creates an encrypted event account.
+    /// Self-service credential claim authorized by an off-chain issuer signature
+    /// The holder signs and pays; the issuer's Ed25519 signature over
+    /// `credential_authorization_message(credential_pubkey, issuer_epoch)` is checked via
+    /// instruction introspection, so the transaction must carry an Ed25519 program
+    /// instruction for exactly that (issuer, signature, message) immediately before this one.
+    /// Binding the epoch keeps authorizations signed before a rotation from being replayed.
+    /// The issuer and its epoch come from the `issuer_state` account.
     #[allow(clippy::too_many_arguments)]
-    pub fn zk_verify_credential<'info>(
-        ctx: Context<'_, '_, '_, 'info, VerifyAccounts<'info>>,
+    pub fn add_credential_signed<'info>(
+        ctx: Context<'_, '_, '_, 'info, SignedCredentialAccounts<'info>>,
         proof: ValidityProof,
         address_tree_info: PackedAddressTreeInfo,
         output_state_tree_index: u8,
         system_accounts_offset: u8,
-        input_root_index: u16,
-        public_data: Vec<u8>,
-        credential_proof: CompressedProof,
-        issuer: [u8; 32],
-        nullifier: [u8; 32],
-        verification_id: [u8; 31],
+        issuer_account_meta: CompressedAccountMeta,
+        credential_pubkey: Pubkey,
+        num_credentials_issued: u64,
+        max_credentials: u64,
+        metadata_uri: Option<[u8; 64]>,
+        authorized_root: [u8; 32],
+        issuer_signature: [u8; 64],
     ) -> Result<()> {
+        let issuer = ctx.accounts.issuer_state.issuer;
+        let issuer_epoch = ctx.accounts.issuer_state.issuer_epoch;
+        verify_ed25519_instruction(
+            &ctx.accounts.instructions_sysvar.to_account_info(),
+            &issuer,
+            &issuer_signature,
+            &credential_authorization_message(&credential_pubkey, issuer_epoch),
+        )?;
+
         let light_cpi_accounts = CpiAccounts::new(
             ctx.accounts.signer.as_ref(),
-            &ctx.remaining_accounts[system_accounts_offset as usize..],
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
             crate::LIGHT_CPI_SIGNER,
         );
-        let address_pubkey = address_tree_info
+
+        // The CPI hash check fails unless this is the issuer's real account
+        let mut issuer_account = LightAccount::<IssuerAccount>::new_mut(
+            &crate::ID,
+            &issuer_account_meta,
+            IssuerAccount {
+                issuer_pubkey: issuer,
+                num_credentials_issued,
+                max_credentials,
+                metadata_uri,
+                authorized_root,
+            },
+        )?;
+
+        check_issuance_limit(&issuer_account)?;
+        issuer_account.num_credentials_issued = issuer_account
+            .num_credentials_issued
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        let address_tree_pubkey = address_tree_info
             .get_tree_pubkey(&light_cpi_accounts)
             .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
 
-        if address_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
             msg!("Invalid address tree");
             return Err(ProgramError::InvalidAccountData.into());
         }
 
         let (address, address_seed) = derive_address(
-            &[
-                ZK_ID_CHECK,
-                nullifier.as_slice(),
-                verification_id.as_slice(),
-            ],
-            &address_pubkey,
+            &[CREDENTIAL, credential_pubkey.as_ref()],
+            &address_tree_pubkey,
             &crate::ID,
         );
 
-        // Get root from input Merkle tree (example of reading on-chain state)
-        let expected_root = read_state_merkle_tree_root(
-            &ctx.accounts.input_merkle_tree.to_account_info(),
-            input_root_index,
+        let mut credential_account = LightAccountPoseidon::<CredentialAccount>::new_init(
+            &crate::ID,
+            Some(address),
+            output_state_tree_index,
+        );
+
+        credential_account.issuer = CredentialIssuer::new(issuer, issuer_epoch);
+        credential_account.credential_pubkey = CredentialPubkey::new(credential_pubkey);
+
+        msg!(
+            "Claimed credential for pubkey: {} signed by issuer {} (issuer credential count: {})",
+            credential_pubkey,
+            issuer,
+            issuer_account.num_credentials_issued
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(issuer_account)?
+            .with_light_account_poseidon(credential_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(1))
+            ])
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+
+    /// Self-service credential claim authorized by membership in the issuer's `authorized_root`
+    /// The holder signs and pays, passing `authorized_path`, the sibling path of
+    /// `authorized_leaf(credential_pubkey, issuer_epoch)` at `leaf_index`. The path is checked
+    /// on-chain against the issuer account's root (`InvalidAuthorizationPath` otherwise;
+    /// `AuthorizedRootNotSet` for a zero root), so the issuer does one `set_authorized_root`
+    /// for the whole set. Leaves bind the epoch, so a rotation retires the set.
+    /// The counter and `max_credentials` apply as in `add_credential`, and the credential
+    /// address makes each leaf claimable once. The issuer and its epoch come from the
+    /// `issuer_state` account.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_credential_from_root<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimCredentialAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        issuer_account_meta: CompressedAccountMeta,
+        credential_pubkey: Pubkey,
+        num_credentials_issued: u64,
+        max_credentials: u64,
+        metadata_uri: Option<[u8; 64]>,
+        authorized_root: [u8; 32],
+        leaf_index: u32,
+        authorized_path: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        let issuer = ctx.accounts.issuer_state.issuer;
+        let issuer_epoch = ctx.accounts.issuer_state.issuer_epoch;
+        check_authorized_path(
+            &authorized_root,
+            &credential_pubkey,
+            issuer_epoch,
+            leaf_index,
+            &authorized_path,
         )?;
 
-        let merkle_tree_pubkey = ctx.accounts.input_merkle_tree.key();
-        let merkle_tree_hashed =
-            hashv_to_bn254_field_size_be_const_array::<2>(&[&merkle_tree_pubkey.to_bytes()])
-                .unwrap();
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
 
-        let mut discriminator = [0u8; 32];
-        discriminator[24..].copy_from_slice(CredentialAccount::LIGHT_DISCRIMINATOR_SLICE);
-        let issuer_hashed = hashv_to_bn254_field_size_be_const_array::<2>(&[&issuer]).unwrap();
-        let account_owner_hashed =
-            hashv_to_bn254_field_size_be_const_array::<2>(&[&crate::ID.to_bytes()]).unwrap();
+        // The CPI hash check fails unless this is the issuer's real account and root
+        let mut issuer_account = LightAccount::<IssuerAccount>::new_mut(
+            &crate::ID,
+            &issuer_account_meta,
+            IssuerAccount {
+                issuer_pubkey: issuer,
+                num_credentials_issued,
+                max_credentials,
+                metadata_uri,
+                authorized_root,
+            },
+        )?;
 
-        let mut event_account = LightAccount::<EncryptedEventAccount>::new_init(
+        check_issuance_limit(&issuer_account)?;
+        issuer_account.num_credentials_issued = issuer_account
+            .num_credentials_issued
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        let address_tree_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_tree_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        let (address, address_seed) = derive_address(
+            &[CREDENTIAL, credential_pubkey.as_ref()],
+            &address_tree_pubkey,
+            &crate::ID,
+        );
+
+        let mut credential_account = LightAccountPoseidon::<CredentialAccount>::new_init(
             &crate::ID,
             Some(address),
             output_state_tree_index,
         );
-        event_account.data = public_data;
 
-        // Compute the data hash for the event account to use in ZK proof verification
-        // Use SHA256 with length prefix to match the flat hashing scheme
-        let mut hash_input = Vec::new();
-        hash_input.extend_from_slice(&(event_account.data.len() as u32).to_le_bytes());
-        hash_input.extend_from_slice(&event_account.data);
-        let mut event_data_hash =
-            Sha256::hash(&hash_input).map_err(|_| ProgramError::InvalidAccountData)?;
-        event_data_hash[0] = 0; // Ensure hash is in BN254 field
+        credential_account.issuer = CredentialIssuer::new(issuer, issuer_epoch);
+        credential_account.credential_pubkey = CredentialPubkey::new(credential_pubkey);
 
-        {
-            // Construct public inputs array for the circuit
-            // Order MUST match the circuit's public declaration exactly:
-            // owner_hashed, merkle_tree_hashed, discriminator, issuer_hashed, expectedRoot, public_encrypted_data_hash, public_data_hash
-            let mut padded_verification_id = [0u8; 32];
-            padded_verification_id[1..].copy_from_slice(&verification_id);
+        msg!(
+            "Claimed credential for pubkey: {} from the authorized set of issuer {} (issuer credential count: {})",
+            credential_pubkey,
+            issuer,
+            issuer_account.num_credentials_issued
+        );
 
-            let public_inputs: [[u8; 32]; 8] = [
-                account_owner_hashed,
-                merkle_tree_hashed,
-                discriminator,
-                issuer_hashed,
-                expected_root,
-                padded_verification_id,
-                event_data_hash, // This is public_encrypted_data_hash
-                nullifier,
-            ];
-            msg!("public_inputs {:?}", public_inputs);
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(issuer_account)?
+            .with_light_account_poseidon(credential_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(1))
+            ])
+            .invoke(light_cpi_accounts)?;
 
-            let proof_a = decompress_g1(&credential_proof.a).map_err(|e| {
-                let code: u32 = e.into();
-                Error::from(ProgramError::Custom(code))
-            })?;
+        Ok(())
+    }
 
-            let proof_b = decompress_g2(&credential_proof.b).map_err(|e| {
-                let code: u32 = e.into();
-                Error::from(ProgramError::Custom(code))
-            })?;
-            let proof_c = decompress_g1(&credential_proof.c).map_err(|e| {
-                let code: u32 = e.into();
-                Error::from(ProgramError::Custom(code))
-            })?;
+    /// Bumps the issuer's `issuer_epoch`, invalidating every credential it issued so far
+    /// Credentials hash the epoch they were issued in into `issuer_hashed`, which
+    /// `zk_verify_credential` recomputes from the current epoch, so proofs for older
+    /// credentials stop verifying until the issuer calls `reissue_credential`.
+    /// The epoch lives in the signer's `IssuerState`, so no Light CPI is needed.
+    pub fn rotate_issuer_epoch(ctx: Context<UpdateIssuerStateAccounts>) -> Result<()> {
+        let issuer_state = &mut ctx.accounts.issuer_state;
+        issuer_state.issuer_epoch = issuer_state
+            .issuer_epoch
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
 
-            // Verify the Groth16 proof
-            let mut verifier = Groth16Verifier::new(
-                &proof_a,
-                &proof_b,
-                &proof_c,
-                &public_inputs,
-                &crate::verifying_key::VERIFYINGKEY,
-            )
-            .map_err(|e| {
-                let code: u32 = e.into();
-                Error::from(ProgramError::Custom(code))
-            })?;
-
-            verifier.verify().map_err(|e| {
-                let code: u32 = e.into();
-                Error::from(ProgramError::Custom(code))
-            })?;
+        msg!(
+            "Rotated issuer {} to epoch {}",
+            issuer_state.issuer,
+            issuer_state.issuer_epoch
+        );
+
+        Ok(())
+    }
+
+    /// Re-binds an existing credential to the issuer's current epoch
+    /// Only the issuer can reissue: `issuer_state` is the signer's, and the credential's hash
+    /// check fails unless the signer issued it. The issuer counter is not touched.
+    pub fn reissue_credential<'info>(
+        ctx: Context<'_, '_, '_, 'info, IssuerStateAccounts<'info>>,
+        proof: ValidityProof,
+        system_accounts_offset: u8,
+        credential_meta: CompressedAccountMeta,
+        credential_pubkey: Pubkey,
+        credential_epoch: u64,
+    ) -> Result<()> {
+        let issuer_epoch = ctx.accounts.issuer_state.issuer_epoch;
+        if credential_epoch >= issuer_epoch {
+            msg!("Credential is already bound to epoch {}", credential_epoch);
+            return Err(ErrorCode::CredentialEpochCurrent.into());
         }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // Hash check in the CPI fails unless the credential exists with this issuer and epoch
+        let mut credential_account = LightAccountPoseidon::<CredentialAccount>::new_mut(
+            &crate::ID,
+            &credential_meta,
+            CredentialAccount {
+                issuer: CredentialIssuer::new(ctx.accounts.signer.key(), credential_epoch),
+                credential_pubkey: CredentialPubkey::new(credential_pubkey),
+            },
+        )?;
+        credential_account.issuer.issuer_epoch = issuer_epoch;
+
+        msg!(
+            "Reissued credential for pubkey: {} (epoch {} -> {})",
+            credential_pubkey,
+            credential_epoch,
+            issuer_epoch
+        );
+
         LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
-            .with_light_account(event_account)?
-            .with_new_addresses(&[
-                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(0))
-            ])
+            .with_light_account_poseidon(credential_account)?
             .invoke(light_cpi_accounts)?;
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct GenericAnchorAccounts<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
-}
-#[derive(Accounts)]
-pub struct VerifyAccounts<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
-    /// CHECK: read_state_merkle_tree_root checks account owner, and discriminator
-    pub input_merkle_tree: UncheckedAccount<'info>,
-}
+    /// Replaces the issuer's `revocation_root`
+    /// The root commits to a sorted Merkle tree of revoked `credential_pubkey` values, built
+    /// off-chain with `revocation_leaf`, so any number of credentials is revoked by one
+    /// update. Only the issuer can update it. Verification does not check it yet: that
+    /// needs the non-revocation circuit's key (see CLAUDE.md, Deferred Features).
+    pub fn update_revocation_root(
+        ctx: Context<UpdateIssuerStateAccounts>,
+        new_revocation_root: [u8; 32],
+    ) -> Result<()> {
+        let issuer_state = &mut ctx.accounts.issuer_state;
+        issuer_state.revocation_root = new_revocation_root;
 
-#[derive(
-    Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator, LightHasher,
-)]
-pub struct CredentialAccount {
-    #[hash]
-    pub issuer: Pubkey,
-    /// CredentialPubkey (is a Poseidon hash -> no need to annotate with #[hash])
-    pub credential_pubkey: CredentialPubkey,
+        msg!(
+            "Updated revocation root of issuer {}: {:?}",
+            issuer_state.issuer,
+            new_revocation_root
+        );
+
+        Ok(())
+    }
+
+    /// Sets or clears the issuer's `metadata_uri`, a pointer to its policy and branding
+    /// `new_metadata_uri` is UTF-8 padded with trailing zeros; anything `parse_metadata_uri`
+    /// rejects fails with `InvalidMetadataUri`. Only the issuer can set it. The URI is not an
+    /// input to any circuit or credential, so changing it never affects issued credentials.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_issuer_metadata<'info>(
+        ctx: Context<'_, '_, '_, 'info, GenericAnchorAccounts<'info>>,
+        proof: ValidityProof,
+        system_accounts_offset: u8,
+        issuer_account_meta: CompressedAccountMeta,
+        num_credentials_issued: u64,
+        max_credentials: u64,
+        metadata_uri: Option<[u8; 64]>,
+        authorized_root: [u8; 32],
+        new_metadata_uri: Option<[u8; 64]>,
+    ) -> Result<()> {
+        if let Some(uri) = &new_metadata_uri {
+            let Some(uri) = parse_metadata_uri(uri) else {
+                msg!("Metadata URI must be non-empty UTF-8 padded with zeros");
+                return Err(ErrorCode::InvalidMetadataUri.into());
+            };
+            msg!(
+                "Setting metadata URI of issuer {}: {}",
+                ctx.accounts.signer.key(),
+                uri
+            );
+        }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // The CPI hash check fails unless the signer is the issuer
+        let mut issuer_account = LightAccount::<IssuerAccount>::new_mut(
+            &crate::ID,
+            &issuer_account_meta,
+            IssuerAccount {
+                issuer_pubkey: ctx.accounts.signer.key(),
+                num_credentials_issued,
+                max_credentials,
+                metadata_uri,
+                authorized_root,
+            },
+        )?;
+        issuer_account.metadata_uri = new_metadata_uri;
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(issuer_account)?
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+
+    /// Replaces the issuer's `authorized_root`
+    /// The root commits to a Merkle tree of `authorized_leaf` values built off-chain with
+    /// `authorized_set_root`; holders in it claim their credential with
+    /// `add_credential_from_root`. Only the issuer can update it; a zero root turns
+    /// self-service claims off. Claimed credentials are unaffected by a later update.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_authorized_root<'info>(
+        ctx: Context<'_, '_, '_, 'info, GenericAnchorAccounts<'info>>,
+        proof: ValidityProof,
+        system_accounts_offset: u8,
+        issuer_account_meta: CompressedAccountMeta,
+        num_credentials_issued: u64,
+        max_credentials: u64,
+        metadata_uri: Option<[u8; 64]>,
+        authorized_root: [u8; 32],
+        new_authorized_root: [u8; 32],
+    ) -> Result<()> {
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        // The CPI hash check fails unless the signer is the issuer
+        let mut issuer_account = LightAccount::<IssuerAccount>::new_mut(
+            &crate::ID,
+            &issuer_account_meta,
+            IssuerAccount {
+                issuer_pubkey: ctx.accounts.signer.key(),
+                num_credentials_issued,
+                max_credentials,
+                metadata_uri,
+                authorized_root,
+            },
+        )?;
+        issuer_account.authorized_root = new_authorized_root;
+
+        msg!(
+            "Updated authorized root of issuer {}: {:?}",
+            ctx.accounts.signer.key(),
+            new_authorized_root
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(issuer_account)?
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+
+    /// Verifies a ZK proof of credential ownership and creates an encrypted event account.
+    /// The proof is checked against the current `issuer_epoch` in the issuer's `IssuerState`,
+    /// which is read directly, so verification never consumes the compressed issuer account.
+    ///
+    /// With `persist` false the proof is verified the same way but nothing is written and no
+    /// Light CPI is made, so nothing stops the same nullifier and `verification_id` from
+    /// being checked again. `proof`, `address_tree_info`, `output_state_tree_index` and
+    /// `system_accounts_offset` are then ignored.
+    /// Returns the event address as return data, or `None` when `persist` is false
+    #[allow(clippy::too_many_arguments)]
+    pub fn zk_verify_credential<'info>(
+        ctx: Context<'_, '_, '_, 'info, VerifyCredentialAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_info: PackedAddressTreeInfo,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        input_root_index: u16,
+        public_data: Vec<u8>,
+        credential_proof: CompressedProof,
+        nullifier: [u8; 32],
+        verification_id: [u8; 31],
+        persist: bool,
+    ) -> Result<Option<[u8; 32]>> {
+        // Get root from input Merkle tree (example of reading on-chain state)
+        check_root_index(input_root_index)?;
+        let expected_root = read_state_merkle_tree_root(
+            &ctx.accounts.input_merkle_tree.to_account_info(),
+            input_root_index,
+        )?;
+
+        let merkle_tree_pubkey = ctx.accounts.input_merkle_tree.key();
+        let merkle_tree_hashed =
+            hashv_to_bn254_field_size_be_const_array::<2>(&[&merkle_tree_pubkey.to_bytes()])
+                .unwrap();
+
+        // The circuit adds the domain prefix itself, so the public input is the bare discriminator
+        let discriminator = discriminator_field(CredentialAccount::LIGHT_DISCRIMINATOR);
+        let issuer_state = &ctx.accounts.issuer_state;
+        let issuer_hashed = issuer_hashed(&issuer_state.issuer, issuer_state.issuer_epoch)
+            .map_err(|_| ProgramError::InvalidArgument)?;
+
+        let account_owner_hashed =
+            hashv_to_bn254_field_size_be_const_array::<2>(&[&crate::ID.to_bytes()]).unwrap();
+
+        // Compute the data hash for the event account to use in ZK proof verification
+        // Use SHA256 with length prefix to match the flat hashing scheme
+        let mut hash_input = Vec::new();
+        hash_input.extend_from_slice(&(public_data.len() as u32).to_le_bytes());
+        hash_input.extend_from_slice(&public_data);
+        let mut event_data_hash =
+            Sha256::hash(&hash_input).map_err(|_| ProgramError::InvalidAccountData)?;
+        event_data_hash[0] = 0; // Ensure hash is in BN254 field
+
+        {
+            // Construct public inputs array for the circuit
+            // Order MUST match the circuit's public declaration exactly:
+            // owner_hashed, merkle_tree_hashed, discriminator, issuer_hashed, expectedRoot,
+            // verification_id, public_encrypted_data_hash, nullifier
+            let mut padded_verification_id = [0u8; 32];
+            padded_verification_id[1..].copy_from_slice(&verification_id);
+
+            let public_inputs: [[u8; 32]; 8] = [
+                account_owner_hashed,
+                merkle_tree_hashed,
+                discriminator,
+                issuer_hashed,
+                expected_root,
+                padded_verification_id,
+                event_data_hash, // This is public_encrypted_data_hash
+                nullifier,
+            ];
+            msg!("public_inputs {:?}", public_inputs);
+
+            // Verify the Groth16 proof
+            verify_groth16(&credential_proof, &public_inputs, &CREDENTIAL_KEY)?;
+        }
+        if !persist {
+            msg!("Credential verified without persisting an event");
+            return Ok(None);
+        }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        let address_pubkey = address_tree_info
+            .get_tree_pubkey(&light_cpi_accounts)
+            .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+        if address_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+            msg!("Invalid address tree");
+            return Err(ProgramError::InvalidAccountData.into());
+        }
+
+        let (address, address_seed) = derive_address(
+            &[
+                ZK_ID_CHECK,
+                nullifier.as_slice(),
+                verification_id.as_slice(),
+            ],
+            &address_pubkey,
+            &crate::ID,
+        );
+
+        let mut event_account = LightAccount::<EncryptedEventAccount>::new_init(
+            &crate::ID,
+            Some(address),
+            output_state_tree_index,
+        );
+        event_account.creator = ctx.accounts.signer.key();
+        event_account.data = public_data;
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(event_account)?
+            .with_new_addresses(&[
+                address_tree_info.into_new_address_params_assigned_packed(address_seed, Some(0))
+            ])
+            .invoke(light_cpi_accounts)?;
+
+        Ok(Some(address))
+    }
+
+    /// Verifies up to MAX_VERIFY_BATCH credential proofs and creates all their event
+    /// accounts in a single Light CPI. Entry `i` of each vector is checked exactly like
+    /// `zk_verify_credential`; any failing entry fails the whole batch.
+    /// `issuers[i]` names the entry's issuer, whose `IssuerState` must be among the remaining
+    /// accounts before `system_accounts_offset` (`MissingIssuerState` otherwise); entries
+    /// for the same issuer share one.
+    ///
+    /// Verification cost scales with the batch (one pairing check per entry), so
+    /// a full batch needs a raised compute budget; only the CPI is shared.
+    #[allow(clippy::too_many_arguments)]
+    pub fn zk_verify_credential_batch<'info>(
+        ctx: Context<'_, '_, '_, 'info, VerifyAccounts<'info>>,
+        proof: ValidityProof,
+        address_tree_infos: Vec<PackedAddressTreeInfo>,
+        output_state_tree_index: u8,
+        system_accounts_offset: u8,
+        input_root_indices: Vec<u16>,
+        public_data: Vec<Vec<u8>>,
+        credential_proofs: Vec<CompressedProof>,
+        issuers: Vec<Pubkey>,
+        nullifiers: Vec<[u8; 32]>,
+        verification_ids: Vec<[u8; 31]>,
+    ) -> Result<()> {
+        let batch_size = credential_proofs.len();
+        if batch_size == 0
+            || batch_size > MAX_VERIFY_BATCH
+            || address_tree_infos.len() != batch_size
+            || input_root_indices.len() != batch_size
+            || public_data.len() != batch_size
+            || issuers.len() != batch_size
+            || nullifiers.len() != batch_size
+            || verification_ids.len() != batch_size
+        {
+            msg!("Invalid verify batch size: {}", batch_size);
+            return Err(ErrorCode::InvalidBatchSize.into());
+        }
+        // Screen every proof before any entry spends compute on decompression
+        for zk_proof in credential_proofs.iter() {
+            check_proof_encoding(zk_proof)?;
+        }
+
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+        // system_accounts checked the offset, so the issuer states slice is in bounds
+        let issuer_state_accounts = &ctx.remaining_accounts[..system_accounts_offset as usize];
+
+        let merkle_tree_pubkey = ctx.accounts.input_merkle_tree.key();
+        let merkle_tree_hashed =
+            hashv_to_bn254_field_size_be_const_array::<2>(&[&merkle_tree_pubkey.to_bytes()])
+                .unwrap();
+        let discriminator = discriminator_field(CredentialAccount::LIGHT_DISCRIMINATOR);
+        let account_owner_hashed =
+            hashv_to_bn254_field_size_be_const_array::<2>(&[&crate::ID.to_bytes()]).unwrap();
+
+        let mut cpi = LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof);
+        let mut new_addresses = Vec::with_capacity(batch_size);
+
+        for (i, data) in public_data.into_iter().enumerate() {
+            let address_pubkey = address_tree_infos[i]
+                .get_tree_pubkey(&light_cpi_accounts)
+                .map_err(|_| ErrorCode::AccountNotEnoughKeys)?;
+
+            if address_pubkey.to_bytes() != light_sdk::constants::ADDRESS_TREE_V2 {
+                msg!("Invalid address tree");
+                return Err(ProgramError::InvalidAccountData.into());
+            }
+
+            let (address, address_seed) = derive_address(
+                &[
+                    ZK_ID_CHECK,
+                    nullifiers[i].as_slice(),
+                    verification_ids[i].as_slice(),
+                ],
+                &address_pubkey,
+                &crate::ID,
+            );
+
+            check_root_index(input_root_indices[i])?;
+            let expected_root = read_state_merkle_tree_root(
+                &ctx.accounts.input_merkle_tree.to_account_info(),
+                input_root_indices[i],
+            )?;
+
+            let issuer_state = find_issuer_state(issuer_state_accounts, &issuers[i])?;
+            let issuer_hashed = issuer_hashed(&issuer_state.issuer, issuer_state.issuer_epoch)
+                .map_err(|_| ProgramError::InvalidArgument)?;
+
+            let mut event_account = LightAccount::<EncryptedEventAccount>::new_init(
+                &crate::ID,
+                Some(address),
+                output_state_tree_index,
+            );
+            event_account.creator = ctx.accounts.signer.key();
+            event_account.data = data;
+
+            // Same length-prefixed SHA256 as zk_verify_credential
+            let mut hash_input = Vec::new();
+            hash_input.extend_from_slice(&(event_account.data.len() as u32).to_le_bytes());
+            hash_input.extend_from_slice(&event_account.data);
+            let mut event_data_hash =
+                Sha256::hash(&hash_input).map_err(|_| ProgramError::InvalidAccountData)?;
+            event_data_hash[0] = 0; // Ensure hash is in BN254 field
+
+            // Same public input order as zk_verify_credential
+            let mut padded_verification_id = [0u8; 32];
+            padded_verification_id[1..].copy_from_slice(&verification_ids[i]);
+
+            let public_inputs: [[u8; 32]; 8] = [
+                account_owner_hashed,
+                merkle_tree_hashed,
+                discriminator,
+                issuer_hashed,
+                expected_root,
+                padded_verification_id,
+                event_data_hash,
+                nullifiers[i],
+            ];
+
+            verify_groth16(&credential_proofs[i], &public_inputs, &CREDENTIAL_KEY)?;
+
+            cpi = cpi.with_light_account(event_account)?;
+            new_addresses.push(
+                address_tree_infos[i]
+                    .into_new_address_params_assigned_packed(address_seed, Some(i as u8)),
+            );
+
+            msg!(
+                "Batch credential {} verified. Nullifier: {:?}",
+                i,
+                nullifiers[i]
+            );
+        }
+
+        cpi.with_new_addresses(&new_addresses)
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+
+    /// Replaces an event account's ciphertext, e.g. after the relying party rotates its key
+    /// Only the event's creator can rotate: the account is loaded with the signer as
+    /// `creator`, so the CPI hash check fails for anyone else. The address, and with it the
+    /// nullifier and verification_id binding, is kept.
+    pub fn reencrypt_event<'info>(
+        ctx: Context<'_, '_, '_, 'info, GenericAnchorAccounts<'info>>,
+        proof: ValidityProof,
+        system_accounts_offset: u8,
+        event_account_meta: CompressedAccountMeta,
+        current_data: Vec<u8>,
+        new_data: Vec<u8>,
+    ) -> Result<()> {
+        let light_cpi_accounts = CpiAccounts::new(
+            ctx.accounts.signer.as_ref(),
+            system_accounts(ctx.remaining_accounts, system_accounts_offset)?,
+            crate::LIGHT_CPI_SIGNER,
+        );
+
+        let mut event_account = LightAccount::<EncryptedEventAccount>::new_mut(
+            &crate::ID,
+            &event_account_meta,
+            EncryptedEventAccount {
+                creator: ctx.accounts.signer.key(),
+                data: current_data,
+            },
+        )?;
+        event_account.data = new_data;
+
+        msg!(
+            "Re-encrypted event account {:?}",
+            event_account_meta.address
+        );
+
+        LightSystemProgramCpi::new_cpi(LIGHT_CPI_SIGNER, proof)
+            .with_light_account(event_account)?
+            .invoke(light_cpi_accounts)?;
+
+        Ok(())
+    }
+}
+
+/// The Light system accounts: `remaining_accounts` from `system_accounts_offset` on
+/// An offset past the end fails with `InvalidSystemAccountsOffset` rather than panicking.
+fn system_accounts<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+    system_accounts_offset: u8,
+) -> Result<&'a [AccountInfo<'info>]> {
+    remaining_accounts
+        .get(system_accounts_offset as usize..)
+        .ok_or_else(|| {
+            msg!(
+                "System accounts offset {} exceeds {} remaining accounts",
+                system_accounts_offset,
+                remaining_accounts.len()
+            );
+            ErrorCode::InvalidSystemAccountsOffset.into()
+        })
+}
+
+/// Fails with `InvalidCpiSigner` unless `LIGHT_CPI_SIGNER` was derived from `crate::ID`
+/// `derive_light_cpi_signer!` takes the program id as a literal, so a `declare_id!` change
+/// that misses it would otherwise only surface as a failed signature inside the Light CPI.
+fn check_light_cpi_signer() -> Result<()> {
+    let derived = Pubkey::create_program_address(
+        &[
+            light_sdk::constants::CPI_AUTHORITY_PDA_SEED,
+            &[LIGHT_CPI_SIGNER.bump],
+        ],
+        &crate::ID,
+    )
+    .map_err(|_| ErrorCode::InvalidCpiSigner)?;
+    if LIGHT_CPI_SIGNER.program_id != crate::ID.to_bytes()
+        || LIGHT_CPI_SIGNER.cpi_signer != derived.to_bytes()
+    {
+        msg!(
+            "LIGHT_CPI_SIGNER {} does not belong to program {}",
+            Pubkey::new_from_array(LIGHT_CPI_SIGNER.cpi_signer),
+            crate::ID
+        );
+        return Err(ErrorCode::InvalidCpiSigner.into());
+    }
+    Ok(())
+}
+
+/// Fails with `InvalidRootIndex` unless `root_index` is a slot of the root history
+fn check_root_index(root_index: u16) -> Result<()> {
+    if root_index as u64 >= ROOT_HISTORY_SIZE {
+        msg!(
+            "Root index {} is past the {}-root history",
+            root_index,
+            ROOT_HISTORY_SIZE
+        );
+        return Err(ErrorCode::InvalidRootIndex.into());
+    }
+    Ok(())
+}
+
+/// Fails with `IssuanceLimitReached` once the issuer has issued `max_credentials`
+fn check_issuance_limit(issuer: &IssuerAccount) -> Result<()> {
+    if issuer.max_credentials != 0 && issuer.num_credentials_issued >= issuer.max_credentials {
+        msg!(
+            "Issuer {} reached its limit of {} credentials",
+            issuer.issuer_pubkey,
+            issuer.max_credentials
+        );
+        return Err(ErrorCode::IssuanceLimitReached.into());
+    }
+    Ok(())
+}
+
+/// Fails unless `authorized_path` leads from `credential_pubkey`'s leaf at `leaf_index` to
+/// `authorized_root`, a non-zero root
+fn check_authorized_path(
+    authorized_root: &[u8; 32],
+    credential_pubkey: &Pubkey,
+    issuer_epoch: u64,
+    leaf_index: u32,
+    authorized_path: &[[u8; 32]],
+) -> Result<()> {
+    if *authorized_root == [0u8; 32] {
+        msg!("Issuer has no authorized root");
+        return Err(ErrorCode::AuthorizedRootNotSet.into());
+    }
+    if authorized_path.len() != AUTHORIZED_SET_LEVELS
+        || (leaf_index as u64) >> AUTHORIZED_SET_LEVELS != 0
+    {
+        msg!(
+            "Authorization path of {} siblings at leaf {} does not fit the tree",
+            authorized_path.len(),
+            leaf_index
+        );
+        return Err(ErrorCode::InvalidAuthorizationPath.into());
+    }
+
+    let mut node = authorized_leaf(credential_pubkey, issuer_epoch)
+        .map_err(|_| ProgramError::InvalidArgument)?;
+    for (level, sibling) in authorized_path.iter().enumerate() {
+        let (left, right) = if (leaf_index >> level) & 1 == 0 {
+            (&node, sibling)
+        } else {
+            (sibling, &node)
+        };
+        node = Poseidon::hashv(&[left.as_slice(), right.as_slice()])
+            .map_err(|_| ProgramError::InvalidArgument)?;
+    }
+
+    if node != *authorized_root {
+        msg!(
+            "Credential {} is not in the issuer's authorized set",
+            credential_pubkey
+        );
+        return Err(ErrorCode::InvalidAuthorizationPath.into());
+    }
+    Ok(())
+}
+
+/// Creates the signer's `IssuerState` at its `[ISSUER, signer]` PDA, failing if it exists.
+/// Lamports sent to the PDA beforehand are kept and only the rent-exempt shortfall is paid,
+/// so pre-funding the address cannot block issuer creation.
+fn create_issuer_state(accounts: &CreateIssuerAccounts, bump: u8) -> Result<()> {
+    let issuer_state = &accounts.issuer_state;
+    if issuer_state.owner == &crate::ID {
+        msg!("Issuer {} already exists", accounts.signer.key());
+        return Err(ErrorCode::IssuerAlreadyExists.into());
+    }
+
+    let space = 8 + IssuerState::INIT_SPACE;
+    let shortfall = Rent::get()?
+        .minimum_balance(space)
+        .saturating_sub(issuer_state.lamports());
+    if shortfall > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: accounts.signer.to_account_info(),
+                    to: issuer_state.to_account_info(),
+                },
+            ),
+            shortfall,
+        )?;
+    }
+
+    let signer_key = accounts.signer.key();
+    let seeds: &[&[u8]] = &[ISSUER, signer_key.as_ref(), &[bump]];
+    system_program::allocate(
+        CpiContext::new_with_signer(
+            accounts.system_program.to_account_info(),
+            system_program::Allocate {
+                account_to_allocate: issuer_state.to_account_info(),
+            },
+            &[seeds],
+        ),
+        space as u64,
+    )?;
+    system_program::assign(
+        CpiContext::new_with_signer(
+            accounts.system_program.to_account_info(),
+            system_program::Assign {
+                account_to_assign: issuer_state.to_account_info(),
+            },
+            &[seeds],
+        ),
+        &crate::ID,
+    )?;
+
+    let state = IssuerState {
+        issuer: signer_key,
+        issuer_epoch: 0,
+        revocation_root: [0u8; 32],
+        bump,
+    };
+    let mut data = issuer_state.try_borrow_mut_data()?;
+    state.try_serialize(&mut &mut data[..])
+}
+
+/// The `IssuerState` of `issuer` among `accounts`
+/// Only `create_issuer` creates program-owned `IssuerState`s, each for its own signer, so
+/// the owner and discriminator checks are enough to trust the stored `issuer`.
+fn find_issuer_state(accounts: &[AccountInfo], issuer: &Pubkey) -> Result<IssuerState> {
+    accounts
+        .iter()
+        .filter(|account| account.owner == &crate::ID)
+        .find_map(|account| {
+            let data = account.try_borrow_data().ok()?;
+            IssuerState::try_deserialize(&mut &data[..])
+                .ok()
+                .filter(|state| state.issuer == *issuer)
+        })
+        .ok_or_else(|| {
+            msg!("Missing issuer state for {}", issuer);
+            ErrorCode::MissingIssuerState.into()
+        })
+}
+
+/// A verifying key tagged with the number of public inputs `N` its circuit takes
+/// `new` runs in const context, so a key whose `nr_pubinputs` or IC length disagrees
+/// with `N` fails the build, and `verify_groth16` only accepts `[[u8; 32]; N]` inputs.
+pub struct CircuitKey<const N: usize> {
+    pub vk: &'static Groth16Verifyingkey<'static>,
+}
+
+impl<const N: usize> CircuitKey<N> {
+    pub const fn new(vk: &'static Groth16Verifyingkey<'static>) -> Self {
+        assert!(vk.nr_pubinputs == N, "nr_pubinputs does not match N");
+        assert!(vk.vk_ic.len() == N + 1, "vk_ic length does not match N");
+        Self { vk }
+    }
+}
+
+/// Verifies a compressed Groth16 proof against `key` and its `N` public inputs
+fn verify_groth16<const N: usize>(
+    zk_proof: &CompressedProof,
+    public_inputs: &[[u8; 32]; N],
+    key: &CircuitKey<N>,
+) -> Result<()> {
+    check_proof_encoding(zk_proof)?;
+
+    let proof_a = decompress_g1(&zk_proof.a).map_err(|e| {
+        let code: u32 = e.into();
+        Error::from(ProgramError::Custom(code))
+    })?;
+
+    let proof_b = decompress_g2(&zk_proof.b).map_err(|e| {
+        let code: u32 = e.into();
+        Error::from(ProgramError::Custom(code))
+    })?;
+
+    let proof_c = decompress_g1(&zk_proof.c).map_err(|e| {
+        let code: u32 = e.into();
+        Error::from(ProgramError::Custom(code))
+    })?;
+
+    // Setup and pairing failures both mean the proof does not verify for these inputs
+    let mut verifier = Groth16Verifier::new(&proof_a, &proof_b, &proof_c, public_inputs, key.vk)
+        .map_err(|e| {
+            let code: u32 = e.into();
+            msg!("Groth16 verifier setup failed: {}", code);
+            Error::from(ErrorCode::InvalidProof)
+        })?;
+
+    verifier.verify().map_err(|e| {
+        let code: u32 = e.into();
+        msg!("Groth16 verification failed: {}", code);
+        Error::from(ErrorCode::InvalidProof)
+    })?;
+
+    Ok(())
+}
+
+/// Cheap structural check of a compressed proof, run before the decompression syscalls
+/// Rejects all-zero points, points flagged as infinity and x coordinates outside the base
+/// field, which `decompress_g1`/`decompress_g2` would only reject after spending more compute.
+/// Relayers can run it off-chain to pre-screen submissions.
+pub fn check_proof_encoding(zk_proof: &CompressedProof) -> Result<()> {
+    if !is_well_formed_point(&zk_proof.a)
+        || !is_well_formed_point(&zk_proof.b)
+        || !is_well_formed_point(&zk_proof.c)
+    {
+        msg!("Malformed proof encoding");
+        return Err(ErrorCode::InvalidProof.into());
+    }
+    Ok(())
+}
+
+/// A big-endian compressed G1 (32 bytes) or G2 (64 bytes) point carries its flags in the top
+/// two bits of the first byte: 0x80 for the sign of y, 0x40 for the point at infinity.
+fn is_well_formed_point(bytes: &[u8]) -> bool {
+    if bytes.iter().all(|byte| *byte == 0) || bytes[0] & 0x40 != 0 {
+        return false;
+    }
+    bytes.chunks(32).enumerate().all(|(i, chunk)| {
+        let mut coordinate = [0u8; 32];
+        coordinate.copy_from_slice(chunk);
+        if i == 0 {
+            coordinate[0] &= 0x3f;
+        }
+        coordinate < BN254_BASE_FIELD_MODULUS
+    })
+}
+
+/// Message an issuer signs to authorize `add_credential_signed` for `credential_pubkey`
+/// Domain-separated by program id so the signature is useless to other programs.
+pub fn credential_authorization_message(credential_pubkey: &Pubkey, issuer_epoch: u64) -> Vec<u8> {
+    [
+        CREDENTIAL_AUTHORIZATION,
+        crate::ID.as_ref(),
+        credential_pubkey.as_ref(),
+        &issuer_epoch.to_le_bytes(),
+    ]
+    .concat()
+}
+
+/// A `verification_id` the verifier can reconstruct from its own challenge
+/// The first 31 bytes of `Sha256(b"ZK_ID_VERIFICATION_ID" || program_id || verifier ||
+/// session_nonce)`. A verifier that hands out `session_nonce` recomputes the id, looks up the
+/// event address for the presented nullifier, and so knows the proof answers its challenge.
+pub fn derive_verification_id(
+    verifier: &Pubkey,
+    session_nonce: &[u8],
+) -> std::result::Result<[u8; 31], HasherError> {
+    let hash = Sha256::hashv(&[
+        VERIFICATION_ID,
+        crate::ID.as_ref(),
+        verifier.as_ref(),
+        session_nonce,
+    ])?;
+    let mut verification_id = [0u8; 31];
+    verification_id.copy_from_slice(&hash[..31]);
+    Ok(verification_id)
+}
+
+/// The `input_root_index` of the root the indexer reported as `root_seq`
+pub fn root_index_for(root_seq: u64) -> u16 {
+    (root_seq % ROOT_HISTORY_SIZE) as u16
+}
+
+/// The `issuer_hashed` circuit input for credentials issued in `issuer_epoch`
+/// Epoch 0 hashes the issuer alone, matching credentials created before epochs existed;
+/// later epochs hash `issuer || issuer_epoch (LE)`.
+pub fn issuer_hashed(
+    issuer: &Pubkey,
+    issuer_epoch: u64,
+) -> std::result::Result<[u8; 32], HasherError> {
+    if issuer_epoch == 0 {
+        hashv_to_bn254_field_size_be_const_array::<2>(&[issuer.as_ref()])
+    } else {
+        hashv_to_bn254_field_size_be_const_array::<3>(&[
+            issuer.as_ref(),
+            &issuer_epoch.to_le_bytes(),
+        ])
+    }
+}
+
+/// Encodes a `u64` as a 32-byte big-endian field element for Poseidon inputs
+/// Layout: bytes `[0..24]` are zero and bytes `[24..32]` hold `value.to_be_bytes()`.
+pub fn encode_u64_be_field(value: u64) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[24..32].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+/// A leaf of an issuer's revocation tree: `Poseidon(value, next_value)`
+/// `value` is a revoked `credential_pubkey` and `next_value` the next larger one, or 0
+/// for the largest. The tree also holds a `(0, smallest)` leaf, so every `credential_pubkey` that is
+/// not revoked falls strictly between the two values of exactly one leaf.
+pub fn revocation_leaf(
+    value: &[u8; 32],
+    next_value: &[u8; 32],
+) -> std::result::Result<[u8; 32], HasherError> {
+    Poseidon::hashv(&[value.as_slice(), next_value.as_slice()])
+}
+
+/// Leaf of an issuer's pre-authorized set: `Poseidon(credential_pubkey, issuer_epoch)`, with
+/// the epoch encoded by `encode_u64_be_field`
+pub fn authorized_leaf(
+    credential_pubkey: &Pubkey,
+    issuer_epoch: u64,
+) -> std::result::Result<[u8; 32], HasherError> {
+    Poseidon::hashv(&[
+        credential_pubkey.as_ref(),
+        encode_u64_be_field(issuer_epoch).as_slice(),
+    ])
+}
+
+/// Root of a pre-authorized set of `authorized_leaf` values for `set_authorized_root`
+/// Unused leaves are zero. Panics with more than `2^AUTHORIZED_SET_LEVELS` leaves.
+pub fn authorized_set_root(leaves: &[[u8; 32]]) -> std::result::Result<[u8; 32], HasherError> {
+    zero_padded_tree(leaves, 0, AUTHORIZED_SET_LEVELS).map(|(root, _)| root)
+}
+
+/// Sibling path of `leaves[index]`, leaf first: `add_credential_from_root`'s `authorized_path`
+pub fn authorized_set_path(
+    leaves: &[[u8; 32]],
+    index: usize,
+) -> std::result::Result<Vec<[u8; 32]>, HasherError> {
+    zero_padded_tree(leaves, index, AUTHORIZED_SET_LEVELS).map(|(_, path)| path)
+}
+
+fn zero_padded_tree(
+    leaves: &[[u8; 32]],
+    mut index: usize,
+    levels: usize,
+) -> std::result::Result<([u8; 32], Vec<[u8; 32]>), HasherError> {
+    assert!(
+        leaves.len() <= 1 << levels,
+        "Set has more than 2^{} leaves",
+        levels
+    );
+    let mut level = leaves.to_vec();
+    let mut zero = [0u8; 32];
+    let mut path = Vec::with_capacity(levels);
+    for _ in 0..levels {
+        path.push(*level.get(index ^ 1).unwrap_or(&zero));
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let right = pair.get(1).unwrap_or(&zero);
+                Poseidon::hashv(&[pair[0].as_slice(), right.as_slice()])
+            })
+            .collect::<std::result::Result<_, _>>()?;
+        zero = Poseidon::hashv(&[zero.as_slice(), zero.as_slice()])?;
+        index /= 2;
+    }
+    Ok((level.first().copied().unwrap_or(zero), path))
+}
+
+/// The URI in an `IssuerAccount::metadata_uri`, without its zero padding
+/// `None` unless the bytes before the padding are non-empty UTF-8 with no zero byte.
+pub fn parse_metadata_uri(metadata_uri: &[u8; 64]) -> Option<&str> {
+    let len = metadata_uri.iter().rposition(|&b| b != 0)? + 1;
+    let uri = std::str::from_utf8(&metadata_uri[..len]).ok()?;
+    (!uri.contains('\0')).then_some(uri)
+}
+
+/// Encodes an 8-byte Light discriminator as a 32-byte field element
+/// Layout: bytes `[0..24]` are zero and bytes `[24..32]` hold the discriminator.
+/// This is the circuit's `discriminator` public input.
+pub fn discriminator_field(discriminator: [u8; 8]) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    bytes[24..32].copy_from_slice(&discriminator);
+    bytes
+}
+
+/// Encodes a Light discriminator the way compressed account leaf hashes include it
+/// `discriminator_field` with the domain prefix `2` at byte 23, i.e. `discriminator + 2^65`
+/// (the `+36893488147419103232` in `CompressedAccountHash`). Client proof builders
+/// recomputing leaf hashes must use this rather than setting the prefix by hand.
+pub fn encode_discriminator(discriminator: [u8; 8]) -> [u8; 32] {
+    let mut bytes = discriminator_field(discriminator);
+    bytes[23] = 2;
+    bytes
+}
+
+/// Checks that the instruction preceding the current one is an Ed25519 program
+/// instruction verifying `signature` by `pubkey` over `message`.
+/// The Ed25519 program already verified the signature when the transaction executed;
+/// this only confirms it verified the expected data, read from its own instruction.
+fn verify_ed25519_instruction(
+    instructions_sysvar: &AccountInfo,
+    pubkey: &Pubkey,
+    signature: &[u8; 64],
+    message: &[u8],
+) -> Result<()> {
+    let current_index = load_current_index_checked(instructions_sysvar)?;
+    if current_index == 0 {
+        msg!("Missing Ed25519 instruction");
+        return Err(ErrorCode::InvalidIssuerSignature.into());
+    }
+    let ed25519_ix = load_instruction_at_checked(current_index as usize - 1, instructions_sysvar)?;
+
+    if ed25519_ix.program_id != ed25519_program::ID || !ed25519_ix.accounts.is_empty() {
+        msg!("Preceding instruction is not an Ed25519 verification");
+        return Err(ErrorCode::InvalidIssuerSignature.into());
+    }
+
+    // Layout: [num_signatures: u8, padding: u8, Ed25519SignatureOffsets (7 x u16 LE), data...]
+    let data = &ed25519_ix.data;
+    if data.len() < 16 || data[0] != 1 {
+        return Err(ErrorCode::InvalidIssuerSignature.into());
+    }
+    let read_u16 = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let signature_offset = read_u16(2) as usize;
+    let signature_ix_index = read_u16(4);
+    let pubkey_offset = read_u16(6) as usize;
+    let pubkey_ix_index = read_u16(8);
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+    let message_ix_index = read_u16(14);
+
+    // All data must live in the Ed25519 instruction itself
+    if signature_ix_index != u16::MAX || pubkey_ix_index != u16::MAX || message_ix_index != u16::MAX
+    {
+        return Err(ErrorCode::InvalidIssuerSignature.into());
+    }
+
+    let signed_pubkey = data.get(pubkey_offset..pubkey_offset + 32);
+    let signed_signature = data.get(signature_offset..signature_offset + 64);
+    let signed_message = data.get(message_offset..message_offset + message_size);
+
+    if signed_pubkey != Some(pubkey.as_ref())
+        || signed_signature != Some(signature.as_slice())
+        || signed_message != Some(message)
+    {
+        msg!("Ed25519 instruction does not match the expected issuer signature");
+        return Err(ErrorCode::InvalidIssuerSignature.into());
+    }
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct GenericAnchorAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+}
+#[derive(Accounts)]
+pub struct CreateIssuerAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    /// CHECK: PDA pinned by seeds; create_issuer rejects it once owned by this program
+    #[account(
+        mut,
+        seeds = [ISSUER, signer.key().as_ref()],
+        bump,
+    )]
+    pub issuer_state: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+#[derive(Accounts)]
+pub struct IssuerStateAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(seeds = [ISSUER, signer.key().as_ref()], bump = issuer_state.bump)]
+    pub issuer_state: Account<'info, IssuerState>,
+}
+#[derive(Accounts)]
+pub struct UpdateIssuerStateAccounts<'info> {
+    pub signer: Signer<'info>,
+    #[account(mut, seeds = [ISSUER, signer.key().as_ref()], bump = issuer_state.bump)]
+    pub issuer_state: Account<'info, IssuerState>,
+}
+#[derive(Accounts)]
+pub struct SignedCredentialAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(seeds = [ISSUER, issuer_state.issuer.as_ref()], bump = issuer_state.bump)]
+    pub issuer_state: Account<'info, IssuerState>,
+    /// CHECK: address constraint pins the instructions sysvar
+    #[account(address = sysvar::instructions::ID)]
+    pub instructions_sysvar: UncheckedAccount<'info>,
+}
+#[derive(Accounts)]
+pub struct ClaimCredentialAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    #[account(seeds = [ISSUER, issuer_state.issuer.as_ref()], bump = issuer_state.bump)]
+    pub issuer_state: Account<'info, IssuerState>,
+}
+#[derive(Accounts)]
+pub struct VerifyCredentialAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    /// CHECK: read_state_merkle_tree_root checks account owner, and discriminator
+    pub input_merkle_tree: UncheckedAccount<'info>,
+    #[account(seeds = [ISSUER, issuer_state.issuer.as_ref()], bump = issuer_state.bump)]
+    pub issuer_state: Account<'info, IssuerState>,
+}
+#[derive(Accounts)]
+pub struct VerifyAccounts<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    /// CHECK: read_state_merkle_tree_root checks account owner, and discriminator
+    pub input_merkle_tree: UncheckedAccount<'info>,
+}
+
+/// Issuer state that instructions read without a Light CPI, in the `[ISSUER, issuer]` PDA
+/// Kept out of the compressed `IssuerAccount` so verifying a credential does not consume
+/// and re-emit the issuer's leaf.
+#[account]
+#[derive(InitSpace)]
+pub struct IssuerState {
+    pub issuer: Pubkey,
+    /// Epoch new credentials are bound to, hashed into `issuer_hashed`
+    pub issuer_epoch: u64,
+    /// Root of the issuer's sorted Merkle tree of revoked `credential_pubkey` values, zeros for none
+    pub revocation_root: [u8; 32],
+    pub bump: u8,
+}
+
+#[derive(
+    Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator, LightHasher,
+)]
+pub struct CredentialAccount {
+    /// Issuer and issuer epoch, hashed together into `issuer_hashed`
+    pub issuer: CredentialIssuer,
+    /// CredentialPubkey (is a Poseidon hash -> no need to annotate with #[hash])
+    pub credential_pubkey: CredentialPubkey,
+}
+
+#[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize)]
+pub struct CredentialIssuer {
+    pub issuer: Pubkey,
+    pub issuer_epoch: u64,
+}
+
+impl CredentialIssuer {
+    pub fn new(issuer: Pubkey, issuer_epoch: u64) -> Self {
+        Self {
+            issuer,
+            issuer_epoch,
+        }
+    }
+}
+
+// Hashes to the same field element the circuit receives as `issuer_hashed`
+impl ToByteArray for CredentialIssuer {
+    const NUM_FIELDS: usize = 1;
+    fn to_byte_array(&self) -> std::result::Result<[u8; 32], HasherError> {
+        issuer_hashed(&self.issuer, self.issuer_epoch)
+    }
 }
 
 #[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator)]
@@ -348,13 +1603,22 @@ impl ToByteArray for CredentialPubkey {
 
 #[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator)]
 pub struct EncryptedEventAccount {
+    /// Signer of `zk_verify_credential`, the only key allowed to `reencrypt_event`
+    pub creator: Pubkey,
     pub data: Vec<u8>,
 }
 
+/// The issuer's compressed account; its epoch and revocation root are in `IssuerState`
 #[derive(Clone, Debug, Default, BorshSerialize, BorshDeserialize, LightDiscriminator)]
 pub struct IssuerAccount {
     pub issuer_pubkey: Pubkey,
     pub num_credentials_issued: u64,
+    /// Most credentials the issuer may issue, 0 for no cap
+    pub max_credentials: u64,
+    /// Optional URI of the issuer's policy and branding, see `parse_metadata_uri`
+    pub metadata_uri: Option<[u8; 64]>,
+    /// Root of the issuer's Merkle tree of pre-authorized credentials, zeros for none
+    pub authorized_root: [u8; 32],
 }
 
 #[error_code]
@@ -363,4 +1627,30 @@ pub enum ErrorCode {
     InvalidIssuer,
     #[msg("Not enough keys in remaining accounts")]
     AccountNotEnoughKeys,
+    #[msg("Missing or mismatched Ed25519 issuer signature instruction")]
+    InvalidIssuerSignature,
+    #[msg("Credential is already bound to the issuer's current epoch")]
+    CredentialEpochCurrent,
+    #[msg("Invalid ZK proof")]
+    InvalidProof,
+    #[msg("Issuer already exists for this signer")]
+    IssuerAlreadyExists,
+    #[msg("System accounts offset is past the end of the remaining accounts")]
+    InvalidSystemAccountsOffset,
+    #[msg("Issuer has reached its max_credentials")]
+    IssuanceLimitReached,
+    #[msg("Metadata URI must be non-empty UTF-8")]
+    InvalidMetadataUri,
+    #[msg("Root index is outside the state tree's root history")]
+    InvalidRootIndex,
+    #[msg("LIGHT_CPI_SIGNER was not derived from this program's id")]
+    InvalidCpiSigner,
+    #[msg("Verify batch is empty, too large, or its argument vectors differ in length")]
+    InvalidBatchSize,
+    #[msg("Issuer has no authorized root")]
+    AuthorizedRootNotSet,
+    #[msg("Authorization path does not lead to the issuer's authorized root")]
+    InvalidAuthorizationPath,
+    #[msg("No IssuerState account for the issuer was passed")]
+    MissingIssuerState,
 }
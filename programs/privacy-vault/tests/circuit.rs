@@ -1,3 +1,5 @@
+#![cfg(not(feature = "test-mock-vk"))]
+
 use circom_prover::{prover::ProofLib, witness::WitnessFn, CircomProver};
 use groth16_solana::groth16::Groth16Verifier;
 use groth16_solana::proof_parser::circom_prover::{convert_proof, convert_public_inputs};
@@ -366,6 +368,43 @@ fn test_invalid_proof_rejected() {
 
 #[test]
 fn test_groth16_solana_verification() {
+    let (proof_a, proof_b, proof_c, public_inputs) = prove_fixture_credential();
+
+    // Verify with groth16-solana
+    let mut verifier =
+        Groth16Verifier::new(&proof_a, &proof_b, &proof_c, &public_inputs, &VERIFYINGKEY)
+            .expect("Failed to create verifier");
+
+    verifier.verify().expect("Groth16 verification failed");
+}
+
+#[cfg(feature = "vk-json")]
+#[test]
+fn test_verifying_key_loaded_from_json() {
+    use privacy_vault::client::load_verifying_key_from_json;
+
+    // The JSON build.rs generated VERIFYINGKEY from, read at runtime instead
+    let key = load_verifying_key_from_json("./build/verification_key.json")
+        .expect("Failed to load verification_key.json");
+    assert_eq!(key.nr_pubinputs, VERIFYINGKEY.nr_pubinputs);
+    assert_eq!(key.vk_ic.len(), VERIFYINGKEY.vk_ic.len());
+
+    let (proof_a, proof_b, proof_c, public_inputs) = prove_fixture_credential();
+    let mut verifier = Groth16Verifier::new(
+        &proof_a,
+        &proof_b,
+        &proof_c,
+        &public_inputs,
+        &key.as_groth16(),
+    )
+    .expect("Failed to create verifier");
+
+    verifier.verify().expect("Groth16 verification failed");
+}
+
+/// Proves a fixed credential, checks the proof with circom-prover and returns it in
+/// groth16-solana form
+fn prove_fixture_credential() -> ([u8; 64], [u8; 128], [u8; 64], [[u8; 32]; 8]) {
     let zkey_path = "./build/compressed_account_merkle_proof_final.zkey".to_string();
 
     // Create test data
@@ -448,10 +487,5 @@ fn test_groth16_solana_verification() {
     let (proof_a, proof_b, proof_c) = convert_proof(&proof.proof).expect("Failed to convert proof");
     let public_inputs: [[u8; 32]; 8] = convert_public_inputs(&proof.pub_inputs);
 
-    // Verify with groth16-solana
-    let mut verifier =
-        Groth16Verifier::new(&proof_a, &proof_b, &proof_c, &public_inputs, &VERIFYINGKEY)
-            .expect("Failed to create verifier");
-
-    verifier.verify().expect("Groth16 verification failed");
+    (proof_a, proof_b, proof_c, public_inputs)
 }
@@ -0,0 +1,413 @@
+//! Shared fixtures for the privacy_vault test crates.
+//!
+//! Each test file is its own crate, so include it with `mod common;`. `setup_programs` is for
+//! tests that span more than one program; it needs the nullifier program, built from
+//! `circuits/nullifier-example`, so copy its `nullifier.so` into `target/deploy` first. The
+//! instruction helpers only need privacy_vault.
+
+// Each test crate uses a different subset of the helpers
+#![allow(dead_code)]
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::spl_associated_token_account;
+use light_program_test::{
+    program_test::LightProgramTest, AddressWithTree, Indexer, ProgramTestConfig, Rpc, RpcError,
+};
+use light_sdk::instruction::{PackedAccounts, SystemAccountMetaConfig};
+use privacy_vault::{derive_token_deposit_address, vault_token_address, ASSOCIATION_SET, CONFIG};
+use solana_sdk::{
+    account::Account,
+    bpf_loader_upgradeable::{self, get_program_data_address, UpgradeableLoaderState},
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
+
+/// The programs `setup_programs` deploys, by the name of their `.so` in `target/deploy`
+pub const PROGRAMS: [(&str, Pubkey); 3] = [
+    ("privacy_vault", privacy_vault::ID),
+    ("zk_id", zk_id::ID),
+    ("nullifier", nullifier::ID),
+];
+
+/// A `LightProgramTest` with privacy_vault, zk_id and nullifier deployed, and its payer
+/// The payer is privacy_vault's upgrade authority, so it can run `initialize_config`.
+pub async fn setup_programs() -> (LightProgramTest, Keypair) {
+    let config = ProgramTestConfig::new(true, Some(PROGRAMS.to_vec()));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    set_upgrade_authority(&mut rpc, &privacy_vault::ID, &payer.pubkey());
+    (rpc, payer)
+}
+
+/// Moves `program_id` to the upgradeable loader with `authority` as upgrade authority.
+/// LightProgramTest deploys with the non-upgradeable loader, so there is no `ProgramData`
+/// account until this writes one.
+pub fn set_upgrade_authority(rpc: &mut LightProgramTest, program_id: &Pubkey, authority: &Pubkey) {
+    let program_data = get_program_data_address(program_id);
+    let metadata_len = UpgradeableLoaderState::size_of_programdata_metadata();
+    let program = rpc.context.get_account(program_id).unwrap();
+    let lamports = program.lamports;
+    let elf = if program.owner == bpf_loader_upgradeable::ID {
+        rpc.context.get_account(&program_data).unwrap().data[metadata_len..].to_vec()
+    } else {
+        program.data
+    };
+
+    let mut program_data_account = Account::new_data_with_space(
+        rpc.context
+            .minimum_balance_for_rent_exemption(metadata_len + elf.len()),
+        &UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: Some(*authority),
+        },
+        metadata_len + elf.len(),
+        &bpf_loader_upgradeable::ID,
+    )
+    .unwrap();
+    program_data_account.data[metadata_len..].copy_from_slice(&elf);
+    rpc.context
+        .set_account(program_data, program_data_account)
+        .unwrap();
+
+    let mut program_account = Account::new_data(
+        lamports,
+        &UpgradeableLoaderState::Program {
+            programdata_address: program_data,
+        },
+        &bpf_loader_upgradeable::ID,
+    )
+    .unwrap();
+    program_account.executable = true;
+    rpc.context
+        .set_account(*program_id, program_account)
+        .unwrap();
+}
+
+/// Creates the config PDA with `payer` as admin, making `payer` the upgrade authority first
+pub async fn initialize_config(rpc: &mut LightProgramTest, payer: &Keypair) -> Pubkey {
+    set_upgrade_authority(rpc, &privacy_vault::ID, &payer.pubkey());
+    try_initialize_config(rpc, payer).await.unwrap();
+    Pubkey::find_program_address(&[CONFIG], &privacy_vault::ID).0
+}
+
+pub async fn try_initialize_config<R>(rpc: &mut R, signer: &Keypair) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let (config_pda, _) = Pubkey::find_program_address(&[CONFIG], &privacy_vault::ID);
+
+    let accounts = privacy_vault::accounts::InitializeConfigAccounts {
+        signer: signer.pubkey(),
+        program: privacy_vault::ID,
+        program_data: get_program_data_address(&privacy_vault::ID),
+        config: config_pda,
+        system_program: solana_sdk::system_program::ID,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::InitializeConfig {}.data(),
+    };
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+pub async fn set_dev_mode<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    dev_mode: bool,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetDevMode { dev_mode }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+pub fn association_set_pda(association_set_id: u16) -> Pubkey {
+    Pubkey::find_program_address(
+        &[ASSOCIATION_SET, &association_set_id.to_le_bytes()],
+        &privacy_vault::ID,
+    )
+    .0
+}
+
+/// Registers `association_set_id` with a zero root, the root `prove_innocence` sends
+pub async fn register_association_set<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    association_set_id: u16,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::RegisterAssociationSetAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+        association_set: association_set_pda(association_set_id),
+        system_program: solana_sdk::system_program::ID,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::RegisterAssociationSet {
+            association_set_id,
+            root: [0u8; 32],
+        }
+        .data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+pub async fn set_association_set_root<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    association_set_id: u16,
+    root: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AssociationSetAdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+        association_set: association_set_pda(association_set_id),
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetAssociationSetRoot {
+            association_set_id,
+            root,
+        }
+        .data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+/// Sends `prove_innocence` with `instruction_data`, reading roots from `deposit_merkle_tree`
+/// The association set PDA is derived from `instruction_data.association_set_id`.
+pub async fn send_prove_innocence<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    deposit_merkle_tree: Pubkey,
+    instruction_data: privacy_vault::instruction::ProveInnocence,
+    remaining_accounts_metas: Vec<AccountMeta>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::ProveInnocenceAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+        deposit_merkle_tree,
+        association_set: association_set_pda(instruction_data.association_set_id),
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+pub async fn initialize_token_vault<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    mint: &Pubkey,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let (vault_authority, _) =
+        Pubkey::find_program_address(&[b"vault_authority", mint.as_ref()], &privacy_vault::ID);
+
+    let accounts = privacy_vault::accounts::InitializeTokenVaultAccounts {
+        signer: payer.pubkey(),
+        mint: *mint,
+        vault_authority,
+        vault_token_account: vault_token_address(mint),
+        token_program: spl_token::ID,
+        associated_token_program: spl_associated_token_account::ID,
+        system_program: solana_sdk::system_program::ID,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::InitializeTokenVault {}.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// Deposits `amount` of `mint` from `depositor`'s token account, with `payer` paying fees
+/// Writes to `state_tree`, or a random state tree when it is `None`.
+#[allow(clippy::too_many_arguments)]
+pub async fn deposit_token<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    depositor: &Keypair,
+    mint: &Pubkey,
+    user_token_account: &Pubkey,
+    commitment: [u8; 32],
+    amount: u64,
+    state_tree: Option<&Pubkey>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) =
+        derive_token_deposit_address(&commitment, Some(mint), &address_tree_info.tree);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+    let output_tree_info = match state_tree {
+        Some(tree) => rpc
+            .get_state_tree_infos()
+            .into_iter()
+            .find(|info| info.tree == *tree)
+            .expect("State tree is not registered"),
+        None => rpc.get_random_state_tree_info()?,
+    };
+    let output_state_tree_index =
+        output_tree_info.pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = privacy_vault::instruction::DepositToken {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        commitment,
+        amount,
+    };
+
+    let accounts = privacy_vault::accounts::DepositTokenAccounts {
+        payer: payer.pubkey(),
+        depositor: depositor.pubkey(),
+        config: Pubkey::find_program_address(&[CONFIG], &privacy_vault::ID).0,
+        user_token_account: *user_token_account,
+        vault_token_account: vault_token_address(mint),
+        token_program: spl_token::ID,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    let signers = if depositor.pubkey() == payer.pubkey() {
+        vec![payer]
+    } else {
+        vec![payer, depositor]
+    };
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &signers)
+        .await
+}
+
+pub async fn deposit_sol<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    commitment: [u8; 32],
+    amount: u64,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let (vault, _) = Pubkey::find_program_address(&[b"vault"], &privacy_vault::ID);
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) = derive_token_deposit_address(&commitment, None, &address_tree_info.tree);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = privacy_vault::instruction::DepositSol {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        commitment,
+        amount,
+    };
+
+    let accounts = privacy_vault::accounts::DepositSolAccounts {
+        signer: payer.pubkey(),
+        config: Pubkey::find_program_address(&[CONFIG], &privacy_vault::ID).0,
+        vault,
+        system_program: solana_sdk::system_program::ID,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
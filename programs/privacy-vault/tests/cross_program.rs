@@ -0,0 +1,454 @@
+#![cfg(feature = "test-mock-vk")]
+
+//! Flows that span privacy_vault, zk_id and the standalone nullifier program.
+//!
+//! Run with `cargo test-sbf --features test-mock-vk` after building `nullifier.so` (see
+//! `common`). Credential proofs use the forgeable `verifying_key_mock` key.
+
+mod common;
+
+use anchor_lang::{InstructionData, ToAccountMetas};
+use common::{deposit_sol, initialize_config, setup_programs, PROGRAMS};
+use groth16_solana::proof_parser::circom_prover::convert_proof_to_compressed;
+use light_client::indexer::{CompressedAccount, TreeInfo};
+use light_compressed_account::instruction_data::compressed_proof::CompressedProof;
+use light_hasher::{Hasher, Poseidon};
+use light_program_test::{AddressWithTree, Indexer, Rpc, RpcError};
+use light_sdk::{
+    address::v2::derive_address,
+    instruction::{account_meta::CompressedAccountMeta, PackedAccounts, SystemAccountMetaConfig},
+};
+use nullifier::nullifier_creation::{NullifierInstructionData, NULLIFIER_PREFIX};
+use privacy_vault::{derive_nullifier_address, DEPOSIT, NATIVE_DEPOSIT};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
+use zk_id::{
+    root_index_for,
+    verifying_key_mock::{MOCK_PROOF_A, MOCK_PROOF_B, MOCK_PROOF_C},
+    CREDENTIAL, ISSUER, ZK_ID_CHECK,
+};
+
+#[tokio::test]
+async fn test_deposit_verify_and_nullify_across_programs() {
+    let (mut rpc, payer) = setup_programs().await;
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    // Each program's CPI signer must belong to the id it is deployed under
+    let cpi_signers = [
+        privacy_vault::LIGHT_CPI_SIGNER,
+        zk_id::LIGHT_CPI_SIGNER,
+        nullifier::nullifier_creation::LIGHT_CPI_SIGNER,
+    ];
+    for ((name, program_id), cpi_signer) in PROGRAMS.iter().zip(cpi_signers) {
+        assert_eq!(cpi_signer.program_id, program_id.to_bytes(), "{}", name);
+    }
+
+    // privacy_vault: config setup runs the CPI signer check, then a native deposit
+    initialize_config(&mut rpc, &payer).await;
+    let mut commitment = [4u8; 32];
+    commitment[0] = 0;
+    deposit_sol(&mut rpc, &payer, commitment, 1_000_000)
+        .await
+        .unwrap();
+    let (deposit_address, _) = derive_address(
+        &[DEPOSIT, &commitment, NATIVE_DEPOSIT],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+    assert!(compressed_account_exists(&mut rpc, deposit_address).await);
+
+    // zk_id: issue a credential and verify it against the mock key
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(&mut rpc, &payer, &issuer_address, address_tree_info.clone())
+        .await
+        .unwrap();
+    let issuer_account = get_compressed_account(&mut rpc, issuer_address).await;
+
+    let credential_commitment = Poseidon::hashv(&[&[7u8; 32]]).unwrap();
+    let (credential_address, _) = derive_address(
+        &[CREDENTIAL, credential_commitment.as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    add_credential(
+        &mut rpc,
+        &payer,
+        &credential_address,
+        address_tree_info.clone(),
+        &issuer_account,
+        credential_commitment,
+    )
+    .await
+    .unwrap();
+    let credential_account = get_compressed_account(&mut rpc, credential_address).await;
+
+    let nullifier = Poseidon::hashv(&[&[9u8; 32]]).unwrap();
+    let verification_id = zk_id::derive_verification_id(&payer.pubkey(), &[1u8; 32]).unwrap();
+    verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifier,
+        verification_id,
+    )
+    .await
+    .unwrap();
+
+    // nullifier: spend the proof's nullifier in the standalone program, once
+    create_nullifier(&mut rpc, &payer, nullifier).await.unwrap();
+    let result = create_nullifier(&mut rpc, &payer, nullifier).await;
+    assert!(result.is_err(), "Duplicate nullifier must fail");
+
+    // The same value lands at an unrelated address in each program
+    let (vault_nullifier_address, _) =
+        derive_nullifier_address(&nullifier, &[0u8; 8], &address_tree_info.tree);
+    let (event_address, _) = derive_address(
+        &[
+            ZK_ID_CHECK,
+            nullifier.as_slice(),
+            verification_id.as_slice(),
+        ],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    let (standalone_address, _) = derive_address(
+        &[NULLIFIER_PREFIX, nullifier.as_slice()],
+        &address_tree_info.tree,
+        &nullifier::ID,
+    );
+    assert!(compressed_account_exists(&mut rpc, event_address).await);
+    assert!(compressed_account_exists(&mut rpc, standalone_address).await);
+    assert!(!compressed_account_exists(&mut rpc, vault_nullifier_address).await);
+    assert_ne!(vault_nullifier_address, standalone_address);
+    assert_ne!(event_address, standalone_address);
+}
+
+async fn get_compressed_account<R>(rpc: &mut R, address: [u8; 32]) -> CompressedAccount
+where
+    R: Rpc + Indexer,
+{
+    rpc.get_compressed_account(address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Compressed account not found")
+}
+
+async fn compressed_account_exists<R>(rpc: &mut R, address: [u8; 32]) -> bool
+where
+    R: Rpc + Indexer,
+{
+    rpc.get_compressed_account(address, None)
+        .await
+        .unwrap()
+        .value
+        .is_some()
+}
+
+async fn create_issuer<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    address: &[u8; 32],
+    address_tree_info: TreeInfo,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(payer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address: *address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::CreateIssuer {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        max_credentials: 0,
+    };
+
+    let accounts = zk_id::accounts::CreateIssuerAccounts {
+        signer: payer.pubkey(),
+        issuer_state: Pubkey::find_program_address(&[ISSUER, payer.pubkey().as_ref()], &zk_id::ID)
+            .0,
+        system_program: solana_sdk::system_program::ID,
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+async fn add_credential<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    address: &[u8; 32],
+    address_tree_info: TreeInfo,
+    issuer_account: &CompressedAccount,
+    credential_commitment: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(payer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![issuer_account.hash],
+            vec![AddressWithTree {
+                address: *address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_tree_accounts = rpc_result.pack_tree_infos(&mut remaining_accounts);
+    let packed_state_tree_accounts = packed_tree_accounts.state_trees.unwrap();
+    let packed_address_tree_accounts = packed_tree_accounts.address_trees;
+
+    let issuer_account_meta = CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: issuer_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let issuer_data = issuer_account.data.as_ref().unwrap();
+    let issuer: zk_id::IssuerAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut issuer_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::AddCredential {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        issuer_account_meta,
+        credential_pubkey: Pubkey::new_from_array(credential_commitment),
+        num_credentials_issued: issuer.num_credentials_issued,
+        max_credentials: issuer.max_credentials,
+        metadata_uri: issuer.metadata_uri,
+        authorized_root: issuer.authorized_root,
+        idempotent: false,
+        existing_credential_meta: None,
+    };
+
+    let accounts = zk_id::accounts::IssuerStateAccounts {
+        signer: payer.pubkey(),
+        issuer_state: Pubkey::find_program_address(&[ISSUER, payer.pubkey().as_ref()], &zk_id::ID)
+            .0,
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+async fn verify_credential<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    credential_account: &CompressedAccount,
+    address_tree_info: TreeInfo,
+    nullifier: [u8; 32],
+    verification_id: [u8; 31],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![credential_account.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+
+    let credential_data = credential_account.data.as_ref().unwrap();
+    let credential: zk_id::CredentialAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut credential_data.data.as_slice()).unwrap();
+
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(payer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let (event_address, _) = derive_address(
+        &[
+            ZK_ID_CHECK,
+            nullifier.as_slice(),
+            verification_id.as_slice(),
+        ],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address: event_address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+
+    let instruction_data = zk_id::instruction::ZkVerifyCredential {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_index: root_index_for(merkle_proof.root_seq),
+        public_data: vec![42u8; 64],
+        credential_proof: CompressedProof { a, b, c },
+        nullifier,
+        verification_id,
+        persist: true,
+    };
+
+    let accounts = zk_id::accounts::VerifyCredentialAccounts {
+        signer: payer.pubkey(),
+        input_merkle_tree: merkle_proof.merkle_tree,
+        issuer_state: Pubkey::find_program_address(
+            &[ISSUER, credential.issuer.issuer.as_ref()],
+            &zk_id::ID,
+        )
+        .0,
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+async fn create_nullifier<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    nullifier: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) = derive_address(
+        &[NULLIFIER_PREFIX, nullifier.as_slice()],
+        &address_tree_info.tree,
+        &nullifier::ID,
+    );
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(nullifier::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = nullifier::instruction::CreateNullifier {
+        data: NullifierInstructionData {
+            proof: rpc_result.proof,
+            address_tree_info: packed_address_tree_accounts[0],
+            output_state_tree_index,
+            system_accounts_offset: system_accounts_offset as u8,
+        },
+        nullifiers: vec![nullifier],
+    };
+
+    let accounts = nullifier::accounts::CreateNullifierAccounts {
+        signer: payer.pubkey(),
+    };
+
+    let instruction = Instruction {
+        program_id: nullifier::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
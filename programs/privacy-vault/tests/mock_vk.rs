@@ -0,0 +1,1912 @@
+#![cfg(feature = "test-mock-vk")]
+
+//! Instruction-flow tests against the forgeable `verifying_key_mock` key.
+//!
+//! Run with `cargo test-sbf --features test-mock-vk`; no circom build or zkey is needed. The
+//! real-proof tests in `test.rs` and `circuit.rs` are compiled out under this feature.
+
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use groth16_solana::proof_parser::circom_prover::convert_proof_to_compressed;
+use light_client::indexer::CompressedAccount;
+use light_compressed_account::instruction_data::compressed_proof::CompressedProof;
+use light_hasher::{Hasher, Poseidon};
+use light_program_test::{
+    program_test::LightProgramTest, utils::assert::assert_rpc_error, AddressWithTree, Indexer,
+    ProgramTestConfig, Rpc, RpcError,
+};
+use light_sdk::{
+    address::v2::derive_address,
+    instruction::{PackedAccounts, PackedAddressTreeInfo, SystemAccountMetaConfig, ValidityProof},
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+};
+use zk_id::{
+    authorized_leaf, authorized_set_path, authorized_set_root, credential_authorization_message,
+    parse_metadata_uri, revocation_leaf, root_index_for,
+    verifying_key_mock::{MOCK_PROOF_A, MOCK_PROOF_B, MOCK_PROOF_C},
+    CREDENTIAL, ISSUER, ZK_ID_CHECK,
+};
+
+/// Compresses the fixture proof; `tamper_proof` swaps A and C so the pairing check fails
+fn mock_credential_proof(tamper_proof: bool) -> CompressedProof {
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+    if tamper_proof {
+        CompressedProof { a: c, b, c: a }
+    } else {
+        CompressedProof { a, b, c }
+    }
+}
+
+#[tokio::test]
+async fn test_credential_flow_with_mock_key() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(
+        &mut rpc,
+        &payer,
+        &issuer_address,
+        address_tree_info.clone(),
+        0,
+    )
+    .await
+    .unwrap();
+
+    let issuer_account = rpc
+        .get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found");
+
+    // Any field element works as the commitment; the mock key never checks it
+    let credential_commitment = Poseidon::hashv(&[&[7u8; 32]]).unwrap();
+    let (credential_address, _) = derive_address(
+        &[CREDENTIAL, credential_commitment.as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    // The counter is part of the issuer account hash, so a spoofed value fails the
+    // Light system program's input check and nothing is written
+    let result = add_credential(
+        &mut rpc,
+        &payer,
+        &credential_address,
+        address_tree_info.clone(),
+        &issuer_account,
+        credential_commitment,
+        Some(5),
+    )
+    .await;
+    assert!(result.is_err(), "Spoofed num_credentials_issued must fail");
+
+    add_credential(
+        &mut rpc,
+        &payer,
+        &credential_address,
+        address_tree_info.clone(),
+        &issuer_account,
+        credential_commitment,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let credential_account = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+    // The issuer account changed when the credential was added
+    let issuer_account = rpc
+        .get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found");
+    let issuer: zk_id::IssuerAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut issuer_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(issuer.num_credentials_issued, 1);
+
+    let nullifier = Poseidon::hashv(&[&[9u8; 32]]).unwrap();
+    let mut verification_id = [0u8; 31];
+    verification_id.copy_from_slice(&Pubkey::new_unique().to_bytes()[0..31]);
+
+    let result = verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifier,
+        verification_id,
+        mock_credential_proof(true),
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidProof.into()).unwrap();
+
+    verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifier,
+        verification_id,
+        mock_credential_proof(false),
+    )
+    .await
+    .unwrap();
+
+    let program_compressed_accounts = rpc
+        .get_compressed_accounts_by_owner(&zk_id::ID, None, None)
+        .await
+        .unwrap();
+    assert_eq!(program_compressed_accounts.value.items.len(), 3);
+
+    // Replaying the same nullifier and verification_id hits the existing event address
+    let result = verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifier,
+        verification_id,
+        mock_credential_proof(false),
+    )
+    .await;
+    assert!(result.is_err(), "Replayed verification must fail");
+
+    // Only the event's creator can rotate its ciphertext, and the address is kept
+    let (event_address, _) = derive_address(
+        &[
+            ZK_ID_CHECK,
+            nullifier.as_slice(),
+            verification_id.as_slice(),
+        ],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    let event_account = rpc
+        .get_compressed_account(event_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Event account not found");
+
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result = reencrypt_event(&mut rpc, &other, &event_account, vec![43u8; 64]).await;
+    assert!(result.is_err(), "Only the creator can re-encrypt");
+
+    reencrypt_event(&mut rpc, &payer, &event_account, vec![43u8; 64])
+        .await
+        .unwrap();
+
+    let event_account = rpc
+        .get_compressed_account(event_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Event account not found");
+    let event: zk_id::EncryptedEventAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut event_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(event.creator, payer.pubkey());
+    assert_eq!(event.data, vec![43u8; 64]);
+}
+
+#[tokio::test]
+async fn test_idempotent_add_credential_retry() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(
+        &mut rpc,
+        &payer,
+        &issuer_address,
+        address_tree_info.clone(),
+        0,
+    )
+    .await
+    .unwrap();
+
+    let credential_commitment = Poseidon::hashv(&[&[7u8; 32]]).unwrap();
+    let (credential_address, _) = derive_address(
+        &[CREDENTIAL, credential_commitment.as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    add_credential(
+        &mut rpc,
+        &payer,
+        &credential_address,
+        address_tree_info.clone(),
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        credential_commitment,
+        None,
+    )
+    .await
+    .unwrap();
+    let credential_account = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+
+    // A retry that finds the credential re-emits it and succeeds
+    retry_add_credential(
+        &mut rpc,
+        &payer,
+        address_tree_info.clone(),
+        &credential_account,
+        credential_commitment,
+    )
+    .await
+    .unwrap();
+
+    // The credential is unchanged and the issuer counted it once
+    let retried = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+    assert_eq!(
+        retried.data.as_ref().unwrap().data_hash,
+        credential_account.data.as_ref().unwrap().data_hash
+    );
+    let issuer: zk_id::IssuerAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut fetch_issuer(&mut rpc, issuer_address)
+            .await
+            .data
+            .as_ref()
+            .unwrap()
+            .data
+            .as_slice(),
+    )
+    .unwrap();
+    assert_eq!(issuer.num_credentials_issued, 1);
+}
+
+#[tokio::test]
+async fn test_verify_credential_without_persisting() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(
+        &mut rpc,
+        &payer,
+        &issuer_address,
+        address_tree_info.clone(),
+        0,
+    )
+    .await
+    .unwrap();
+    let issuer_account = fetch_issuer(&mut rpc, issuer_address).await;
+
+    let credential_commitment = Poseidon::hashv(&[&[7u8; 32]]).unwrap();
+    let (credential_address, _) = derive_address(
+        &[CREDENTIAL, credential_commitment.as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    add_credential(
+        &mut rpc,
+        &payer,
+        &credential_address,
+        address_tree_info.clone(),
+        &issuer_account,
+        credential_commitment,
+        None,
+    )
+    .await
+    .unwrap();
+    let credential_account = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+
+    let nullifier = Poseidon::hashv(&[&[9u8; 32]]).unwrap();
+    let mut verification_id = [0u8; 31];
+    verification_id.copy_from_slice(&Pubkey::new_unique().to_bytes()[0..31]);
+    let (event_address, _) = derive_address(
+        &[
+            ZK_ID_CHECK,
+            nullifier.as_slice(),
+            verification_id.as_slice(),
+        ],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+
+    // The proof is still checked
+    let result = verify_credential_with_persist(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifier,
+        verification_id,
+        mock_credential_proof(true),
+        false,
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidProof.into()).unwrap();
+
+    // Nothing is written, so the same nullifier can be checked again
+    for _ in 0..2 {
+        verify_credential_with_persist(
+            &mut rpc,
+            &payer,
+            &credential_account,
+            address_tree_info.clone(),
+            nullifier,
+            verification_id,
+            mock_credential_proof(false),
+            false,
+        )
+        .await
+        .unwrap();
+    }
+    let event = rpc
+        .get_compressed_account(event_address, None)
+        .await
+        .unwrap();
+    assert!(
+        event.value.is_none(),
+        "Ephemeral check must not create an event"
+    );
+
+    // The address is still free for a persisted verification
+    verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifier,
+        verification_id,
+        mock_credential_proof(false),
+    )
+    .await
+    .unwrap();
+    let event = rpc
+        .get_compressed_account(event_address, None)
+        .await
+        .unwrap();
+    assert!(event.value.is_some(), "Event account not created");
+}
+
+/// The issuer account as currently stored; every issuance re-emits it with a new hash
+async fn fetch_issuer<R>(rpc: &mut R, issuer_address: [u8; 32]) -> CompressedAccount
+where
+    R: Rpc + Indexer,
+{
+    rpc.get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found")
+}
+
+/// The `[ISSUER, issuer]` PDA holding the issuer's `IssuerState`
+fn issuer_state_address(issuer: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[ISSUER, issuer.as_ref()], &zk_id::ID).0
+}
+
+async fn fetch_issuer_state<R>(rpc: &mut R, issuer: &Pubkey) -> zk_id::IssuerState
+where
+    R: Rpc + Indexer,
+{
+    let account = rpc
+        .get_account(issuer_state_address(issuer))
+        .await
+        .unwrap()
+        .expect("Issuer state not found");
+    zk_id::IssuerState::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+#[tokio::test]
+async fn test_issuance_limit() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(
+        &mut rpc,
+        &payer,
+        &issuer_address,
+        address_tree_info.clone(),
+        2,
+    )
+    .await
+    .unwrap();
+
+    // Credentials up to the cap are issued; the next one is refused
+    for i in 0..3u8 {
+        let issuer_account = rpc
+            .get_compressed_account(issuer_address, None)
+            .await
+            .unwrap()
+            .value
+            .expect("Issuer account not found");
+        let credential_commitment = Poseidon::hashv(&[&[i; 32]]).unwrap();
+        let (credential_address, _) = derive_address(
+            &[CREDENTIAL, credential_commitment.as_ref()],
+            &address_tree_info.tree,
+            &zk_id::ID,
+        );
+        let result = add_credential(
+            &mut rpc,
+            &payer,
+            &credential_address,
+            address_tree_info.clone(),
+            &issuer_account,
+            credential_commitment,
+            None,
+        )
+        .await;
+        if i < 2 {
+            result.unwrap();
+        } else {
+            assert_rpc_error(result, 0, zk_id::ErrorCode::IssuanceLimitReached.into()).unwrap();
+        }
+    }
+
+    let issuer_account = rpc
+        .get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found");
+    let issuer: zk_id::IssuerAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut issuer_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(issuer.num_credentials_issued, 2);
+    assert_eq!(issuer.max_credentials, 2);
+}
+
+#[tokio::test]
+async fn test_revocation_root() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(
+        &mut rpc,
+        &payer,
+        &issuer_address,
+        address_tree_info.clone(),
+        0,
+    )
+    .await
+    .unwrap();
+
+    // A single-leaf tree revoking one credential
+    let revoked = Poseidon::hashv(&[&[8u8; 32]]).unwrap();
+    let revocation_root = revocation_leaf(&revoked, &[0u8; 32]).unwrap();
+
+    // Only the issuer can set the root
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let issuer_account = fetch_issuer(&mut rpc, issuer_address).await;
+    let issuer_state = issuer_state_address(&payer.pubkey());
+    let result = update_revocation_root(&mut rpc, &other, issuer_state, revocation_root).await;
+    assert!(
+        result.is_err(),
+        "Only the issuer can update the revocation root"
+    );
+    update_revocation_root(&mut rpc, &payer, issuer_state, revocation_root)
+        .await
+        .unwrap();
+
+    let state = fetch_issuer_state(&mut rpc, &payer.pubkey()).await;
+    assert_eq!(state.issuer, payer.pubkey());
+    assert_eq!(state.revocation_root, revocation_root);
+
+    // The compressed issuer account is not touched
+    let unchanged = fetch_issuer(&mut rpc, issuer_address).await;
+    assert_eq!(unchanged.hash, issuer_account.hash);
+
+    // Clearing the root empties the list again
+    update_revocation_root(&mut rpc, &payer, issuer_state, [0u8; 32])
+        .await
+        .unwrap();
+    let state = fetch_issuer_state(&mut rpc, &payer.pubkey()).await;
+    assert_eq!(state.revocation_root, [0u8; 32]);
+}
+
+#[tokio::test]
+async fn test_issuer_metadata() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(&mut rpc, &payer, &issuer_address, address_tree_info, 0)
+        .await
+        .unwrap();
+    let issuer_account = rpc
+        .get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found");
+
+    let uri = "https://issuer.example/zk-id/policy.json";
+    let mut metadata_uri = [0u8; 64];
+    metadata_uri[..uri.len()].copy_from_slice(uri.as_bytes());
+
+    // Only the issuer can set it
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result = set_issuer_metadata(&mut rpc, &other, &issuer_account, Some(metadata_uri)).await;
+    assert!(result.is_err(), "Only the issuer can set its metadata URI");
+
+    // Empty and non-UTF-8 URIs are rejected
+    let result = set_issuer_metadata(&mut rpc, &payer, &issuer_account, Some([0u8; 64])).await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidMetadataUri.into()).unwrap();
+    let mut invalid = metadata_uri;
+    invalid[0] = 0xff;
+    let result = set_issuer_metadata(&mut rpc, &payer, &issuer_account, Some(invalid)).await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidMetadataUri.into()).unwrap();
+
+    set_issuer_metadata(&mut rpc, &payer, &issuer_account, Some(metadata_uri))
+        .await
+        .unwrap();
+    let issuer_account = rpc
+        .get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found");
+    let issuer: zk_id::IssuerAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut issuer_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(parse_metadata_uri(&issuer.metadata_uri.unwrap()), Some(uri));
+
+    // Clearing it leaves the rest of the issuer untouched
+    set_issuer_metadata(&mut rpc, &payer, &issuer_account, None)
+        .await
+        .unwrap();
+    let issuer_account = rpc
+        .get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found");
+    let cleared: zk_id::IssuerAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut issuer_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(cleared.metadata_uri, None);
+    assert_eq!(cleared.max_credentials, issuer.max_credentials);
+    assert_eq!(
+        cleared.num_credentials_issued,
+        issuer.num_credentials_issued
+    );
+}
+
+#[tokio::test]
+async fn test_add_credential_from_root() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(
+        &mut rpc,
+        &payer,
+        &issuer_address,
+        address_tree_info.clone(),
+        0,
+    )
+    .await
+    .unwrap();
+
+    let holder = Keypair::new();
+    rpc.airdrop_lamports(&holder.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+
+    let commitments: Vec<[u8; 32]> = [[7u8; 32], [8u8; 32]]
+        .iter()
+        .map(|key| Poseidon::hashv(&[key]).unwrap())
+        .collect();
+    let leaves: Vec<[u8; 32]> = commitments
+        .iter()
+        .map(|commitment| authorized_leaf(&Pubkey::new_from_array(*commitment), 0).unwrap())
+        .collect();
+    let authorized_root = authorized_set_root(&leaves).unwrap();
+    let (credential_address, _) = derive_address(
+        &[CREDENTIAL, commitments[1].as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+
+    // Nothing is authorized until the issuer sets a root
+    let result = add_credential_from_root(
+        &mut rpc,
+        &holder,
+        &credential_address,
+        address_tree_info.clone(),
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        commitments[1],
+        1,
+        authorized_set_path(&leaves, 1).unwrap(),
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::AuthorizedRootNotSet.into()).unwrap();
+
+    // Only the issuer can set it
+    let result = set_authorized_root(
+        &mut rpc,
+        &holder,
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        authorized_root,
+    )
+    .await;
+    assert!(
+        result.is_err(),
+        "Only the issuer can set its authorized root"
+    );
+    set_authorized_root(
+        &mut rpc,
+        &payer,
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        authorized_root,
+    )
+    .await
+    .unwrap();
+
+    // A path for another leaf does not lead to the root
+    let result = add_credential_from_root(
+        &mut rpc,
+        &holder,
+        &credential_address,
+        address_tree_info.clone(),
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        commitments[1],
+        0,
+        authorized_set_path(&leaves, 0).unwrap(),
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidAuthorizationPath.into()).unwrap();
+
+    add_credential_from_root(
+        &mut rpc,
+        &holder,
+        &credential_address,
+        address_tree_info.clone(),
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        commitments[1],
+        1,
+        authorized_set_path(&leaves, 1).unwrap(),
+    )
+    .await
+    .unwrap();
+
+    let credential_account = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+    let credential: zk_id::CredentialAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut credential_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(credential.issuer.issuer, payer.pubkey());
+    let issuer: zk_id::IssuerAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut fetch_issuer(&mut rpc, issuer_address)
+            .await
+            .data
+            .as_ref()
+            .unwrap()
+            .data
+            .as_slice(),
+    )
+    .unwrap();
+    assert_eq!(issuer.num_credentials_issued, 1);
+
+    // Each leaf is claimed once: the credential address already exists
+    let result = add_credential_from_root(
+        &mut rpc,
+        &holder,
+        &credential_address,
+        address_tree_info.clone(),
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        commitments[1],
+        1,
+        authorized_set_path(&leaves, 1).unwrap(),
+    )
+    .await;
+    assert!(result.is_err(), "A leaf can only be claimed once");
+}
+
+#[tokio::test]
+async fn test_add_credential_signed() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(
+        &mut rpc,
+        &payer,
+        &issuer_address,
+        address_tree_info.clone(),
+        0,
+    )
+    .await
+    .unwrap();
+
+    let holder = Keypair::new();
+    rpc.airdrop_lamports(&holder.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let credential_commitment = Poseidon::hashv(&[&[7u8; 32]]).unwrap();
+    let credential_pubkey = Pubkey::new_from_array(credential_commitment);
+    let (credential_address, _) = derive_address(
+        &[CREDENTIAL, credential_commitment.as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    let message = credential_authorization_message(&credential_pubkey, 0);
+
+    // Without the Ed25519 instruction in front there is nothing to check
+    let signature = payer.sign_message(&message).into();
+    let instruction = add_credential_signed_instruction(
+        &mut rpc,
+        &holder,
+        &credential_address,
+        address_tree_info.clone(),
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        credential_commitment,
+        signature,
+    )
+    .await
+    .unwrap();
+    let result = rpc
+        .create_and_send_transaction(&[instruction.clone()], &holder.pubkey(), &[&holder])
+        .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidIssuerSignature.into()).unwrap();
+
+    // A signature over another epoch's message is valid Ed25519 but not this authorization
+    let stale_message = credential_authorization_message(&credential_pubkey, 1);
+    let stale_signature = payer.sign_message(&stale_message).into();
+    let stale_instruction = add_credential_signed_instruction(
+        &mut rpc,
+        &holder,
+        &credential_address,
+        address_tree_info.clone(),
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        credential_commitment,
+        stale_signature,
+    )
+    .await
+    .unwrap();
+    let result = rpc
+        .create_and_send_transaction(
+            &[
+                ed25519_instruction(&payer, &stale_message),
+                stale_instruction,
+            ],
+            &holder.pubkey(),
+            &[&holder],
+        )
+        .await;
+    assert_rpc_error(result, 1, zk_id::ErrorCode::InvalidIssuerSignature.into()).unwrap();
+
+    // The right message signed by someone other than the issuer is rejected
+    let other = Keypair::new();
+    let other_signature = other.sign_message(&message).into();
+    let other_instruction = add_credential_signed_instruction(
+        &mut rpc,
+        &holder,
+        &credential_address,
+        address_tree_info.clone(),
+        &fetch_issuer(&mut rpc, issuer_address).await,
+        credential_commitment,
+        other_signature,
+    )
+    .await
+    .unwrap();
+    let result = rpc
+        .create_and_send_transaction(
+            &[ed25519_instruction(&other, &message), other_instruction],
+            &holder.pubkey(),
+            &[&holder],
+        )
+        .await;
+    assert_rpc_error(result, 1, zk_id::ErrorCode::InvalidIssuerSignature.into()).unwrap();
+
+    // The issuer's signature over the current epoch's message issues the credential
+    rpc.create_and_send_transaction(
+        &[ed25519_instruction(&payer, &message), instruction],
+        &holder.pubkey(),
+        &[&holder],
+    )
+    .await
+    .unwrap();
+
+    let credential_account = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+    let credential: zk_id::CredentialAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut credential_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(credential.issuer.issuer, payer.pubkey());
+    assert_eq!(credential.issuer.issuer_epoch, 0);
+    let issuer: zk_id::IssuerAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut fetch_issuer(&mut rpc, issuer_address)
+            .await
+            .data
+            .as_ref()
+            .unwrap()
+            .data
+            .as_slice(),
+    )
+    .unwrap();
+    assert_eq!(issuer.num_credentials_issued, 1);
+}
+
+#[tokio::test]
+async fn test_verify_credential_batch() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(
+        &mut rpc,
+        &payer,
+        &issuer_address,
+        address_tree_info.clone(),
+        0,
+    )
+    .await
+    .unwrap();
+    let issuer_account = rpc
+        .get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found");
+
+    let credential_commitment = Poseidon::hashv(&[&[7u8; 32]]).unwrap();
+    let (credential_address, _) = derive_address(
+        &[CREDENTIAL, credential_commitment.as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    add_credential(
+        &mut rpc,
+        &payer,
+        &credential_address,
+        address_tree_info.clone(),
+        &issuer_account,
+        credential_commitment,
+        None,
+    )
+    .await
+    .unwrap();
+    let credential_account = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+
+    // Two verifications of one credential, so both entries name the same issuer
+    let nullifiers = vec![
+        Poseidon::hashv(&[&[9u8; 32]]).unwrap(),
+        Poseidon::hashv(&[&[10u8; 32]]).unwrap(),
+    ];
+    let verification_ids: Vec<[u8; 31]> = (0..2)
+        .map(|_| {
+            let mut verification_id = [0u8; 31];
+            verification_id.copy_from_slice(&Pubkey::new_unique().to_bytes()[0..31]);
+            verification_id
+        })
+        .collect();
+
+    let result = verify_credential_batch(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        vec![],
+        vec![],
+        vec![],
+        vec![],
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidBatchSize.into()).unwrap();
+
+    // An entry naming an issuer without an IssuerState is rejected
+    let result = verify_credential_batch(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifiers.clone(),
+        verification_ids.clone(),
+        vec![mock_credential_proof(false), mock_credential_proof(false)],
+        vec![payer.pubkey(), Pubkey::new_unique()],
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::MissingIssuerState.into()).unwrap();
+
+    // One bad proof fails the whole batch
+    let result = verify_credential_batch(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifiers.clone(),
+        verification_ids.clone(),
+        vec![mock_credential_proof(false), mock_credential_proof(true)],
+        vec![payer.pubkey(); 2],
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidProof.into()).unwrap();
+    let program_compressed_accounts = rpc
+        .get_compressed_accounts_by_owner(&zk_id::ID, None, None)
+        .await
+        .unwrap();
+    assert_eq!(program_compressed_accounts.value.items.len(), 2);
+
+    verify_credential_batch(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        nullifiers.clone(),
+        verification_ids.clone(),
+        vec![mock_credential_proof(false), mock_credential_proof(false)],
+        vec![payer.pubkey(); 2],
+    )
+    .await
+    .unwrap();
+
+    for (nullifier, verification_id) in nullifiers.iter().zip(&verification_ids) {
+        let (event_address, _) = derive_address(
+            &[
+                ZK_ID_CHECK,
+                nullifier.as_slice(),
+                verification_id.as_slice(),
+            ],
+            &address_tree_info.tree,
+            &zk_id::ID,
+        );
+        let event_account = rpc
+            .get_compressed_account(event_address, None)
+            .await
+            .unwrap()
+            .value
+            .expect("Event account not found");
+        let event: zk_id::EncryptedEventAccount = anchor_lang::AnchorDeserialize::deserialize(
+            &mut event_account.data.as_ref().unwrap().data.as_slice(),
+        )
+        .unwrap();
+        assert_eq!(event.creator, payer.pubkey());
+    }
+}
+
+async fn create_issuer<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    address: &[u8; 32],
+    address_tree_info: light_client::indexer::TreeInfo,
+    max_credentials: u64,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(payer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address: *address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::CreateIssuer {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        max_credentials,
+    };
+
+    let accounts = zk_id::accounts::CreateIssuerAccounts {
+        signer: payer.pubkey(),
+        issuer_state: issuer_state_address(&payer.pubkey()),
+        system_program: solana_sdk::system_program::ID,
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+async fn add_credential<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    address: &[u8; 32],
+    address_tree_info: light_client::indexer::TreeInfo,
+    issuer_account: &CompressedAccount,
+    credential_commitment: [u8; 32],
+    num_credentials_issued: Option<u64>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(payer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![issuer_account.hash],
+            vec![AddressWithTree {
+                address: *address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_tree_accounts = rpc_result.pack_tree_infos(&mut remaining_accounts);
+    let packed_state_tree_accounts = packed_tree_accounts.state_trees.unwrap();
+    let packed_address_tree_accounts = packed_tree_accounts.address_trees;
+
+    let issuer_account_meta = light_sdk::instruction::account_meta::CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: issuer_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let issuer_data = issuer_account.data.as_ref().unwrap();
+    let issuer_account_parsed: zk_id::IssuerAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut issuer_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::AddCredential {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        issuer_account_meta,
+        credential_pubkey: Pubkey::new_from_array(credential_commitment),
+        num_credentials_issued: num_credentials_issued
+            .unwrap_or(issuer_account_parsed.num_credentials_issued),
+        max_credentials: issuer_account_parsed.max_credentials,
+        metadata_uri: issuer_account_parsed.metadata_uri,
+        authorized_root: issuer_account_parsed.authorized_root,
+        idempotent: false,
+        existing_credential_meta: None,
+    };
+
+    let accounts = zk_id::accounts::IssuerStateAccounts {
+        signer: payer.pubkey(),
+        issuer_state: issuer_state_address(&payer.pubkey()),
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// Retries `add_credential` in idempotent mode for a credential that already exists
+/// The validity proof covers the existing credential instead of a new address, and the
+/// issuer account is not an input, so its fields are left at their defaults.
+async fn retry_add_credential<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    address_tree_info: light_client::indexer::TreeInfo,
+    credential_account: &CompressedAccount,
+    credential_commitment: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(payer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![credential_account.hash], vec![], None)
+        .await?
+        .value;
+    let packed_state_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .state_trees
+        .unwrap();
+
+    let existing_credential_meta = light_sdk::instruction::account_meta::CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: credential_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+    // Only read to derive the credential address, so no root is needed
+    let address_tree_info = PackedAddressTreeInfo {
+        address_merkle_tree_pubkey_index: remaining_accounts.insert_or_get(address_tree_info.tree),
+        address_queue_pubkey_index: remaining_accounts.insert_or_get(address_tree_info.queue),
+        root_index: 0,
+    };
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::AddCredential {
+        proof: rpc_result.proof,
+        address_tree_info,
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        issuer_account_meta: Default::default(),
+        credential_pubkey: Pubkey::new_from_array(credential_commitment),
+        num_credentials_issued: 0,
+        max_credentials: 0,
+        metadata_uri: None,
+        authorized_root: [0u8; 32],
+        idempotent: true,
+        existing_credential_meta: Some(existing_credential_meta),
+    };
+
+    let accounts = zk_id::accounts::IssuerStateAccounts {
+        signer: payer.pubkey(),
+        issuer_state: issuer_state_address(&payer.pubkey()),
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn verify_credential<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    credential_account: &CompressedAccount,
+    address_tree_info: light_client::indexer::TreeInfo,
+    nullifier: [u8; 32],
+    verification_id: [u8; 31],
+    credential_proof: CompressedProof,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    verify_credential_with_persist(
+        rpc,
+        payer,
+        credential_account,
+        address_tree_info,
+        nullifier,
+        verification_id,
+        credential_proof,
+        true,
+    )
+    .await
+}
+
+/// `verify_credential` with `persist` passed through; a non-persisting call sends no
+/// validity proof, since nothing is written
+#[allow(clippy::too_many_arguments)]
+async fn verify_credential_with_persist<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    credential_account: &CompressedAccount,
+    address_tree_info: light_client::indexer::TreeInfo,
+    nullifier: [u8; 32],
+    verification_id: [u8; 31],
+    credential_proof: CompressedProof,
+    persist: bool,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![credential_account.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+    let root_index = root_index_for(merkle_proof.root_seq);
+
+    let credential_data = credential_account.data.as_ref().unwrap();
+    let credential_account_parsed: zk_id::CredentialAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut credential_data.data.as_slice()).unwrap();
+
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(payer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let (event_address, _) = derive_address(
+        &[
+            ZK_ID_CHECK,
+            nullifier.as_slice(),
+            verification_id.as_slice(),
+        ],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+
+    // Ignored when not persisting
+    let (proof, address_tree_info_packed) = if persist {
+        let rpc_result = rpc
+            .get_validity_proof(
+                vec![],
+                vec![AddressWithTree {
+                    address: event_address,
+                    tree: address_tree_info.tree,
+                }],
+                None,
+            )
+            .await?
+            .value;
+        let packed_address_tree_accounts = rpc_result
+            .pack_tree_infos(&mut remaining_accounts)
+            .address_trees;
+        (rpc_result.proof, packed_address_tree_accounts[0])
+    } else {
+        (ValidityProof::default(), PackedAddressTreeInfo::default())
+    };
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::ZkVerifyCredential {
+        proof,
+        address_tree_info: address_tree_info_packed,
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_index: root_index,
+        public_data: vec![42u8; 64],
+        credential_proof,
+        nullifier,
+        verification_id,
+        persist,
+    };
+
+    let accounts = zk_id::accounts::VerifyCredentialAccounts {
+        signer: payer.pubkey(),
+        input_merkle_tree: merkle_proof.merkle_tree,
+        issuer_state: issuer_state_address(&credential_account_parsed.issuer.issuer),
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// Verifies one credential once per `(nullifier, verification_id)` pair in a single batch.
+/// `issuers` names each entry's issuer; the `IssuerState` of each one is passed once.
+#[allow(clippy::too_many_arguments)]
+async fn verify_credential_batch<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    credential_account: &CompressedAccount,
+    address_tree_info: light_client::indexer::TreeInfo,
+    nullifiers: Vec<[u8; 32]>,
+    verification_ids: Vec<[u8; 31]>,
+    credential_proofs: Vec<CompressedProof>,
+    issuers: Vec<Pubkey>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let batch_size = nullifiers.len();
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![credential_account.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+    let root_index = root_index_for(merkle_proof.root_seq);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(payer.pubkey());
+    let mut issuer_states: Vec<Pubkey> = issuers.iter().map(issuer_state_address).collect();
+    issuer_states.sort();
+    issuer_states.dedup();
+    for issuer_state in issuer_states {
+        remaining_accounts.add_pre_accounts_meta(AccountMeta::new_readonly(issuer_state, false));
+    }
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let new_addresses = nullifiers
+        .iter()
+        .zip(&verification_ids)
+        .map(|(nullifier, verification_id)| {
+            let (event_address, _) = derive_address(
+                &[
+                    ZK_ID_CHECK,
+                    nullifier.as_slice(),
+                    verification_id.as_slice(),
+                ],
+                &address_tree_info.tree,
+                &zk_id::ID,
+            );
+            AddressWithTree {
+                address: event_address,
+                tree: address_tree_info.tree,
+            }
+        })
+        .collect();
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![], new_addresses, None)
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::ZkVerifyCredentialBatch {
+        proof: rpc_result.proof,
+        address_tree_infos: packed_address_tree_accounts,
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_indices: vec![root_index; batch_size],
+        public_data: vec![vec![42u8; 64]; batch_size],
+        credential_proofs,
+        issuers,
+        nullifiers,
+        verification_ids,
+    };
+
+    let accounts = zk_id::accounts::VerifyAccounts {
+        signer: payer.pubkey(),
+        input_merkle_tree: merkle_proof.merkle_tree,
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// `signer` replaces the revocation root in `issuer_state`; no Light CPI is involved
+async fn update_revocation_root<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    issuer_state: Pubkey,
+    new_revocation_root: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let instruction_data = zk_id::instruction::UpdateRevocationRoot {
+        new_revocation_root,
+    };
+
+    let accounts = zk_id::accounts::UpdateIssuerStateAccounts {
+        signer: signer.pubkey(),
+        issuer_state,
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: accounts.to_account_metas(None),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+async fn set_issuer_metadata<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    issuer_account: &CompressedAccount,
+    new_metadata_uri: Option<[u8; 64]>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(signer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![issuer_account.hash], vec![], None)
+        .await?
+        .value;
+    let packed_state_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .state_trees
+        .unwrap();
+
+    let issuer_account_meta = light_sdk::instruction::account_meta::CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: issuer_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+
+    let issuer_data = issuer_account.data.as_ref().unwrap();
+    let issuer_account_parsed: zk_id::IssuerAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut issuer_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::SetIssuerMetadata {
+        proof: rpc_result.proof,
+        system_accounts_offset: system_accounts_offset as u8,
+        issuer_account_meta,
+        num_credentials_issued: issuer_account_parsed.num_credentials_issued,
+        max_credentials: issuer_account_parsed.max_credentials,
+        metadata_uri: issuer_account_parsed.metadata_uri,
+        authorized_root: issuer_account_parsed.authorized_root,
+        new_metadata_uri,
+    };
+
+    let accounts = zk_id::accounts::GenericAnchorAccounts {
+        signer: signer.pubkey(),
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+async fn set_authorized_root<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    issuer_account: &CompressedAccount,
+    new_authorized_root: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(signer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![issuer_account.hash], vec![], None)
+        .await?
+        .value;
+    let packed_state_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .state_trees
+        .unwrap();
+
+    let issuer_account_meta = light_sdk::instruction::account_meta::CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: issuer_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+
+    let issuer_data = issuer_account.data.as_ref().unwrap();
+    let issuer_account_parsed: zk_id::IssuerAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut issuer_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::SetAuthorizedRoot {
+        proof: rpc_result.proof,
+        system_accounts_offset: system_accounts_offset as u8,
+        issuer_account_meta,
+        num_credentials_issued: issuer_account_parsed.num_credentials_issued,
+        max_credentials: issuer_account_parsed.max_credentials,
+        metadata_uri: issuer_account_parsed.metadata_uri,
+        authorized_root: issuer_account_parsed.authorized_root,
+        new_authorized_root,
+    };
+
+    let accounts = zk_id::accounts::GenericAnchorAccounts {
+        signer: signer.pubkey(),
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+/// `holder` claims `credential_commitment` from the issuer's authorized set
+#[allow(clippy::too_many_arguments)]
+async fn add_credential_from_root<R>(
+    rpc: &mut R,
+    holder: &Keypair,
+    address: &[u8; 32],
+    address_tree_info: light_client::indexer::TreeInfo,
+    issuer_account: &CompressedAccount,
+    credential_commitment: [u8; 32],
+    leaf_index: u32,
+    authorized_path: Vec<[u8; 32]>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(holder.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![issuer_account.hash],
+            vec![AddressWithTree {
+                address: *address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_tree_accounts = rpc_result.pack_tree_infos(&mut remaining_accounts);
+    let packed_state_tree_accounts = packed_tree_accounts.state_trees.unwrap();
+    let packed_address_tree_accounts = packed_tree_accounts.address_trees;
+
+    let issuer_account_meta = light_sdk::instruction::account_meta::CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: issuer_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let issuer_data = issuer_account.data.as_ref().unwrap();
+    let issuer_account_parsed: zk_id::IssuerAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut issuer_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::AddCredentialFromRoot {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        issuer_account_meta,
+        credential_pubkey: Pubkey::new_from_array(credential_commitment),
+        num_credentials_issued: issuer_account_parsed.num_credentials_issued,
+        max_credentials: issuer_account_parsed.max_credentials,
+        metadata_uri: issuer_account_parsed.metadata_uri,
+        authorized_root: issuer_account_parsed.authorized_root,
+        leaf_index,
+        authorized_path,
+    };
+
+    let accounts = zk_id::accounts::ClaimCredentialAccounts {
+        signer: holder.pubkey(),
+        issuer_state: issuer_state_address(&issuer_account_parsed.issuer_pubkey),
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &holder.pubkey(), &[holder])
+        .await
+}
+
+/// An Ed25519 program instruction verifying `signer`'s signature over `message`
+/// Single signature, with the pubkey, signature and message all in the instruction data,
+/// the only layout `add_credential_signed` accepts.
+fn ed25519_instruction(signer: &Keypair, message: &[u8]) -> Instruction {
+    const PUBKEY_OFFSET: u16 = 16;
+    const SIGNATURE_OFFSET: u16 = PUBKEY_OFFSET + 32;
+    const MESSAGE_OFFSET: u16 = SIGNATURE_OFFSET + 64;
+
+    let mut data = vec![1u8, 0];
+    for field in [
+        SIGNATURE_OFFSET,
+        u16::MAX,
+        PUBKEY_OFFSET,
+        u16::MAX,
+        MESSAGE_OFFSET,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&field.to_le_bytes());
+    }
+    data.extend_from_slice(signer.pubkey().as_ref());
+    data.extend_from_slice(signer.sign_message(message).as_ref());
+    data.extend_from_slice(message);
+
+    Instruction {
+        program_id: solana_sdk::ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+/// `holder` claims `credential_commitment` with the issuer's off-chain `issuer_signature`
+/// The caller puts the matching Ed25519 instruction in front of it.
+async fn add_credential_signed_instruction<R>(
+    rpc: &mut R,
+    holder: &Keypair,
+    address: &[u8; 32],
+    address_tree_info: light_client::indexer::TreeInfo,
+    issuer_account: &CompressedAccount,
+    credential_commitment: [u8; 32],
+    issuer_signature: [u8; 64],
+) -> Result<Instruction, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(holder.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![issuer_account.hash],
+            vec![AddressWithTree {
+                address: *address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_tree_accounts = rpc_result.pack_tree_infos(&mut remaining_accounts);
+    let packed_state_tree_accounts = packed_tree_accounts.state_trees.unwrap();
+    let packed_address_tree_accounts = packed_tree_accounts.address_trees;
+
+    let issuer_account_meta = light_sdk::instruction::account_meta::CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: issuer_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let issuer_data = issuer_account.data.as_ref().unwrap();
+    let issuer_account_parsed: zk_id::IssuerAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut issuer_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::AddCredentialSigned {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        issuer_account_meta,
+        credential_pubkey: Pubkey::new_from_array(credential_commitment),
+        num_credentials_issued: issuer_account_parsed.num_credentials_issued,
+        max_credentials: issuer_account_parsed.max_credentials,
+        metadata_uri: issuer_account_parsed.metadata_uri,
+        authorized_root: issuer_account_parsed.authorized_root,
+        issuer_signature,
+    };
+
+    let accounts = zk_id::accounts::SignedCredentialAccounts {
+        signer: holder.pubkey(),
+        issuer_state: issuer_state_address(&issuer_account_parsed.issuer_pubkey),
+        instructions_sysvar: solana_sdk::sysvar::instructions::ID,
+    };
+
+    Ok(Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    })
+}
+
+async fn reencrypt_event<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    event_account: &CompressedAccount,
+    new_data: Vec<u8>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(signer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![event_account.hash], vec![], None)
+        .await?
+        .value;
+    let packed_state_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .state_trees
+        .unwrap();
+
+    let event_account_meta = light_sdk::instruction::account_meta::CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: event_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+
+    let event_data = event_account.data.as_ref().unwrap();
+    let event_account_parsed: zk_id::EncryptedEventAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut event_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::ReencryptEvent {
+        proof: rpc_result.proof,
+        system_accounts_offset: system_accounts_offset as u8,
+        event_account_meta,
+        current_data: event_account_parsed.data,
+        new_data,
+    };
+
+    let accounts = zk_id::accounts::GenericAnchorAccounts {
+        signer: signer.pubkey(),
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
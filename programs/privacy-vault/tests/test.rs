@@ -1,4 +1,5 @@
 // #![cfg(feature = "test-sbf")]
+#![cfg(not(feature = "test-mock-vk"))]
 
 use anchor_lang::{InstructionData, ToAccountMetas};
 use circom_prover::{prover::ProofLib, witness::WitnessFn, CircomProver};
@@ -6,7 +7,8 @@ use groth16_solana::proof_parser::circom_prover::convert_proof;
 use light_client::indexer::CompressedAccount;
 use light_hasher::{hash_to_field_size::hash_to_bn254_field_size_be, Hasher, Poseidon, Sha256};
 use light_program_test::{
-    program_test::LightProgramTest, AddressWithTree, Indexer, ProgramTestConfig, Rpc, RpcError,
+    program_test::LightProgramTest, utils::assert::assert_rpc_error, AddressWithTree, Indexer,
+    ProgramTestConfig, Rpc, RpcError,
 };
 use light_sdk::{
     address::v2::derive_address,
@@ -19,7 +21,10 @@ use solana_sdk::{
     signature::{Keypair, Signature, Signer},
 };
 use std::collections::HashMap;
-use zk_id::{CREDENTIAL, ISSUER, ZK_ID_CHECK};
+use zk_id::{encode_discriminator, root_index_for, CREDENTIAL, ISSUER, ZK_ID_CHECK};
+
+// Verifier the test verification ids are derived for
+const VERIFIER: Pubkey = Pubkey::new_from_array([7u8; 32]);
 
 /// Derives a credential keypair from a Solana keypair
 /// The private key is derived by signing "CREDENTIAL" and truncating to 248 bits
@@ -82,10 +87,29 @@ async fn test_create_issuer_and_add_credential() {
         &zk_id::ID,
     );
     println!("issuer_address {:?}", issuer_address);
+    // Creating the same issuer twice fails on the marker PDA, before the address collision
+    let instruction =
+        create_issuer_instruction(&mut rpc, &payer, &issuer_address, address_tree_info.clone())
+            .await
+            .unwrap();
+    let result = rpc
+        .create_and_send_transaction(
+            &[instruction.clone(), instruction],
+            &payer.pubkey(),
+            &[&payer],
+        )
+        .await;
+    assert_rpc_error(result, 1, zk_id::ErrorCode::IssuerAlreadyExists.into()).unwrap();
+
     // Step 1: Create the issuer account
     create_issuer(&mut rpc, &payer, &issuer_address, address_tree_info.clone())
         .await
         .unwrap();
+    assert!(
+        create_issuer(&mut rpc, &payer, &issuer_address, address_tree_info.clone())
+            .await
+            .is_err()
+    );
 
     // Verify the issuer account was created
     let issuer_accounts = rpc
@@ -145,12 +169,25 @@ async fn test_create_issuer_and_add_credential() {
         .value
         .expect("Credential account not found");
     println!("credential_account {:?}", credential_account);
+    // A tampered proof must fail with the program's InvalidProof code
+    let result = verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        &user_keypair,
+        true,
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidProof.into()).unwrap();
+
     verify_credential(
         &mut rpc,
         &payer,
         &credential_account,
         address_tree_info,
         &user_keypair,
+        false,
     )
     .await
     .unwrap();
@@ -166,12 +203,234 @@ async fn test_create_issuer_and_add_credential() {
     assert_eq!(final_compressed_accounts.value.items.len(), 3);
 }
 
+#[tokio::test]
+async fn test_rotation_invalidates_credentials_until_reissued() {
+    let config = ProgramTestConfig::new(true, Some(vec![("zk_id", zk_id::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let address_tree_info = rpc.get_address_tree_v2();
+
+    let (issuer_address, _) = derive_address(
+        &[ISSUER, payer.pubkey().as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    create_issuer(&mut rpc, &payer, &issuer_address, address_tree_info.clone())
+        .await
+        .unwrap();
+    let issuer_account = rpc
+        .get_compressed_account(issuer_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Issuer account not found");
+
+    let user_keypair = Keypair::new();
+    let credential = CredentialKeypair::new(&user_keypair);
+    let (credential_address, _) = derive_address(
+        &[CREDENTIAL, credential.public_key.as_ref()],
+        &address_tree_info.tree,
+        &zk_id::ID,
+    );
+    add_credential(
+        &mut rpc,
+        &payer,
+        &credential_address,
+        address_tree_info.clone(),
+        &issuer_account,
+        credential.public_key,
+    )
+    .await
+    .unwrap();
+    let credential_account = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+    verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        &user_keypair,
+        false,
+    )
+    .await
+    .unwrap();
+
+    // After a rotation the epoch-0 credential no longer matches issuer_hashed
+    rotate_issuer_epoch(&mut rpc, &payer).await.unwrap();
+    let result = verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info.clone(),
+        &user_keypair,
+        false,
+    )
+    .await;
+    assert_rpc_error(result, 0, zk_id::ErrorCode::InvalidProof.into()).unwrap();
+
+    // Reissuing binds it to epoch 1 and it verifies again
+    reissue_credential(&mut rpc, &payer, &credential_account)
+        .await
+        .unwrap();
+    let credential_account = rpc
+        .get_compressed_account(credential_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Credential account not found");
+    let credential_parsed: zk_id::CredentialAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut credential_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(credential_parsed.issuer.issuer_epoch, 1);
+    verify_credential(
+        &mut rpc,
+        &payer,
+        &credential_account,
+        address_tree_info,
+        &user_keypair,
+        false,
+    )
+    .await
+    .unwrap();
+}
+
+#[test]
+fn test_verification_id_round_trip() {
+    let credential = CredentialKeypair::new(&Keypair::new());
+    let session_nonce = [3u8; 32];
+
+    // The prover derives the id from the verifier's challenge and proves against it
+    let verification_id = zk_id::derive_verification_id(&VERIFIER, &session_nonce).unwrap();
+    let nullifier = credential.compute_nullifier(&verification_id);
+
+    // The verifier rebuilds the same id, and with the presented nullifier the event address
+    let expected_id = zk_id::derive_verification_id(&VERIFIER, &session_nonce).unwrap();
+    assert_eq!(verification_id, expected_id);
+    let address_tree = Pubkey::new_unique();
+    let (event_address, _) = derive_address(
+        &[
+            ZK_ID_CHECK,
+            nullifier.as_slice(),
+            verification_id.as_slice(),
+        ],
+        &address_tree,
+        &zk_id::ID,
+    );
+    let (expected_address, _) = derive_address(
+        &[ZK_ID_CHECK, nullifier.as_slice(), expected_id.as_slice()],
+        &address_tree,
+        &zk_id::ID,
+    );
+    assert_eq!(event_address, expected_address);
+
+    // A new session or another verifier gets a different id
+    assert_ne!(
+        verification_id,
+        zk_id::derive_verification_id(&VERIFIER, &[4u8; 32]).unwrap()
+    );
+    assert_ne!(
+        verification_id,
+        zk_id::derive_verification_id(&Pubkey::new_unique(), &session_nonce).unwrap()
+    );
+}
+
+#[test]
+fn test_light_cpi_signer_matches_program_id() {
+    // create_issuer fails with InvalidCpiSigner if these drift apart
+    let signer = zk_id::LIGHT_CPI_SIGNER;
+    let (cpi_authority, bump) = Pubkey::find_program_address(
+        &[light_sdk::constants::CPI_AUTHORITY_PDA_SEED],
+        &zk_id::ID,
+    );
+    assert_eq!(signer.program_id, zk_id::ID.to_bytes());
+    assert_eq!(signer.cpi_signer, cpi_authority.to_bytes());
+    assert_eq!(signer.bump, bump);
+}
+
+#[test]
+fn test_discriminator_prefix_matches_light_leaf_hash() {
+    use light_compressed_account::compressed_account::{CompressedAccount, CompressedAccountData};
+    use light_sdk::LightDiscriminator;
+
+    // The circuit's CompressedAccountHash adds 2^65 to the bare discriminator
+    let discriminator = zk_id::CredentialAccount::LIGHT_DISCRIMINATOR;
+    let encoded = encode_discriminator(discriminator);
+    assert_eq!(
+        BigUint::from_bytes_be(&encoded),
+        BigUint::from_bytes_be(&discriminator) + (BigUint::from(1u8) << 65)
+    );
+
+    let merkle_tree = Pubkey::new_from_array([2u8; 32]);
+    let leaf_index: u32 = 5;
+    let address = [3u8; 32];
+    let mut data_hash = [4u8; 32];
+    data_hash[0] = 0;
+    let account = CompressedAccount {
+        owner: zk_id::ID.to_bytes().into(),
+        lamports: 0,
+        address: Some(address),
+        data: Some(CompressedAccountData {
+            discriminator,
+            data: vec![],
+            data_hash,
+        }),
+    };
+    let light_hash = account
+        .hash(&merkle_tree.to_bytes().into(), &leaf_index, false)
+        .unwrap();
+
+    // Leaf hash as the circuit recomputes it, with the prefix from encode_discriminator
+    let circuit_hash = |discriminator_bytes: [u8; 32]| {
+        let mut leaf_index_bytes = [0u8; 32];
+        leaf_index_bytes[28..32].copy_from_slice(&leaf_index.to_le_bytes());
+        Poseidon::hashv(&[
+            hash_to_bn254_field_size_be(zk_id::ID.as_ref()).as_slice(),
+            leaf_index_bytes.as_slice(),
+            hash_to_bn254_field_size_be(merkle_tree.as_ref()).as_slice(),
+            address.as_slice(),
+            discriminator_bytes.as_slice(),
+            data_hash.as_slice(),
+        ])
+        .unwrap()
+    };
+    assert_eq!(
+        circuit_hash(encoded),
+        light_hash,
+        "Light no longer prefixes the discriminator with 2 at byte 23"
+    );
+
+    // Without the prefix the hashes diverge, so the assertion above is what pins it
+    assert_ne!(
+        circuit_hash(zk_id::discriminator_field(discriminator)),
+        light_hash
+    );
+}
+
 async fn create_issuer<R>(
     rpc: &mut R,
     payer: &Keypair,
     address: &[u8; 32],
     address_tree_info: light_client::indexer::TreeInfo,
 ) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let instruction = create_issuer_instruction(rpc, payer, address, address_tree_info).await?;
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+async fn create_issuer_instruction<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    address: &[u8; 32],
+    address_tree_info: light_client::indexer::TreeInfo,
+) -> Result<Instruction, RpcError>
 where
     R: Rpc + Indexer,
 {
@@ -205,13 +464,17 @@ where
         address_tree_info: packed_address_tree_accounts[0],
         output_state_tree_index,
         system_accounts_offset: system_accounts_offset as u8,
+        max_credentials: 0,
     };
 
-    let accounts = zk_id::accounts::GenericAnchorAccounts {
+    let accounts = zk_id::accounts::CreateIssuerAccounts {
         signer: payer.pubkey(),
+        issuer_state: Pubkey::find_program_address(&[ISSUER, payer.pubkey().as_ref()], &zk_id::ID)
+            .0,
+        system_program: solana_sdk::system_program::ID,
     };
 
-    let instruction = Instruction {
+    Ok(Instruction {
         program_id: zk_id::ID,
         accounts: [
             accounts.to_account_metas(None),
@@ -219,10 +482,7 @@ where
         ]
         .concat(),
         data: instruction_data.data(),
-    };
-
-    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
-        .await
+    })
 }
 
 async fn add_credential<R>(
@@ -283,10 +543,17 @@ where
         issuer_account_meta,
         credential_pubkey: Pubkey::new_from_array(credential_commitment),
         num_credentials_issued: issuer_account_parsed.num_credentials_issued,
+        max_credentials: issuer_account_parsed.max_credentials,
+        metadata_uri: issuer_account_parsed.metadata_uri,
+        authorized_root: issuer_account_parsed.authorized_root,
+        idempotent: false,
+        existing_credential_meta: None,
     };
 
-    let accounts = zk_id::accounts::GenericAnchorAccounts {
+    let accounts = zk_id::accounts::IssuerStateAccounts {
         signer: payer.pubkey(),
+        issuer_state: Pubkey::find_program_address(&[ISSUER, payer.pubkey().as_ref()], &zk_id::ID)
+            .0,
     };
 
     let instruction = Instruction {
@@ -309,6 +576,7 @@ async fn verify_credential<R>(
     credential_account: &CompressedAccount,
     address_tree_info: light_client::indexer::TreeInfo,
     user_keypair: &Keypair,
+    tamper_proof: bool,
 ) -> Result<Signature, RpcError>
 where
     R: Rpc + Indexer,
@@ -324,7 +592,7 @@ where
     let leaf_index = merkle_proof.leaf_index as u32;
     let merkle_proof_hashes = &merkle_proof.proof;
     let merkle_root = merkle_proof.root;
-    let root_index = (merkle_proof.root_seq % 2400) as u16;
+    let root_index = root_index_for(merkle_proof.root_seq);
 
     let state_tree = merkle_proof.merkle_tree;
 
@@ -339,10 +607,9 @@ where
     // Create the credential keypair from the user keypair
     let credential = CredentialKeypair::new(user_keypair);
 
-    // Generate a verification_id (31 bytes)
-    let mut verification_id = [0u8; 31];
-    let random_pubkey = Pubkey::new_unique();
-    verification_id.copy_from_slice(&random_pubkey.to_bytes()[0..31]);
+    // Derive the verification_id from a fresh VERIFIER challenge
+    let session_nonce = Pubkey::new_unique().to_bytes();
+    let verification_id = zk_id::derive_verification_id(&VERIFIER, &session_nonce).unwrap();
 
     // Generate the ZK proof using the actual merkle root
     let (credential_proof, nullifier) = generate_credential_proof(
@@ -357,6 +624,17 @@ where
         &verification_id,
     );
 
+    // Swapping A and C keeps both valid curve points but breaks the pairing check
+    let credential_proof = if tamper_proof {
+        light_compressed_account::instruction_data::compressed_proof::CompressedProof {
+            a: credential_proof.c,
+            b: credential_proof.b,
+            c: credential_proof.a,
+        }
+    } else {
+        credential_proof
+    };
+
     // Create the verification transaction
     let mut remaining_accounts = PackedAccounts::default();
     remaining_accounts.add_pre_accounts_signer(payer.pubkey());
@@ -403,14 +681,20 @@ where
         input_root_index: root_index,
         public_data: encrypted_data,
         credential_proof,
-        issuer: credential_account_parsed.issuer.to_bytes(),
         nullifier,
         verification_id,
+        persist: true,
     };
 
-    let accounts = zk_id::accounts::VerifyAccounts {
+    // The program reads the issuer's current epoch from its IssuerState
+    let accounts = zk_id::accounts::VerifyCredentialAccounts {
         signer: payer.pubkey(),
         input_merkle_tree: state_tree,
+        issuer_state: Pubkey::find_program_address(
+            &[ISSUER, credential_account_parsed.issuer.issuer.as_ref()],
+            &zk_id::ID,
+        )
+        .0,
     };
 
     let instruction = Instruction {
@@ -427,13 +711,98 @@ where
         .await
 }
 
+async fn rotate_issuer_epoch<R>(rpc: &mut R, issuer: &Keypair) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = zk_id::accounts::UpdateIssuerStateAccounts {
+        signer: issuer.pubkey(),
+        issuer_state: Pubkey::find_program_address(&[ISSUER, issuer.pubkey().as_ref()], &zk_id::ID)
+            .0,
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: accounts.to_account_metas(None),
+        data: zk_id::instruction::RotateIssuerEpoch {}.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &issuer.pubkey(), &[issuer])
+        .await
+}
+
+/// Re-binds `credential_account` to the issuer's current epoch
+async fn reissue_credential<R>(
+    rpc: &mut R,
+    issuer: &Keypair,
+    credential_account: &CompressedAccount,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    remaining_accounts.add_pre_accounts_signer(issuer.pubkey());
+    let config = SystemAccountMetaConfig::new(zk_id::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![credential_account.hash], vec![], None)
+        .await?
+        .value;
+    let packed_state_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .state_trees
+        .unwrap();
+
+    let credential_meta = light_sdk::instruction::account_meta::CompressedAccountMeta {
+        tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+        address: credential_account.address.unwrap(),
+        output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+    };
+
+    let credential_data = credential_account.data.as_ref().unwrap();
+    let credential_account_parsed: zk_id::CredentialAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut credential_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) = remaining_accounts.to_account_metas();
+
+    let instruction_data = zk_id::instruction::ReissueCredential {
+        proof: rpc_result.proof,
+        system_accounts_offset: system_accounts_offset as u8,
+        credential_meta,
+        credential_pubkey: credential_account_parsed
+            .credential_pubkey
+            .credential_pubkey,
+        credential_epoch: credential_account_parsed.issuer.issuer_epoch,
+    };
+
+    let accounts = zk_id::accounts::IssuerStateAccounts {
+        signer: issuer.pubkey(),
+        issuer_state: Pubkey::find_program_address(&[ISSUER, issuer.pubkey().as_ref()], &zk_id::ID)
+            .0,
+    };
+
+    let instruction = Instruction {
+        program_id: zk_id::ID,
+        accounts: [
+            accounts.to_account_metas(None),
+            remaining_accounts_metas,
+        ]
+        .concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &issuer.pubkey(), &[issuer])
+        .await
+}
+
 fn generate_credential_proof(
     credential_account: &CompressedAccount,
     merkle_tree_pubkey: &Pubkey,
     leaf_index: u32,
     merkle_proof_hashes: &[[u8; 32]],
     merkle_root: &[u8; 32],
-    issuer_pubkey: &Pubkey,
+    issuer: &zk_id::CredentialIssuer,
     credential: &CredentialKeypair,
     encrypted_data: &[u8],
     verification_id: &[u8; 31],
@@ -456,10 +825,8 @@ fn generate_credential_proof(
     let owner_hashed = hash_to_bn254_field_size_be(zk_id::ID.as_ref());
     let merkle_tree_hashed = hash_to_bn254_field_size_be(merkle_tree_pubkey.as_ref());
 
-    // Use the same hashing as on-chain: hashv_to_bn254_field_size_be_const_array::<2>
-    use light_hasher::hash_to_field_size::hashv_to_bn254_field_size_be_const_array;
-    let issuer_hashed =
-        hashv_to_bn254_field_size_be_const_array::<2>(&[issuer_pubkey.as_ref()]).unwrap();
+    // Same hashing as on-chain, bound to the epoch the credential was issued in
+    let issuer_hashed = zk_id::issuer_hashed(&issuer.issuer, issuer.issuer_epoch).unwrap();
 
     // Compute data_hash as hash of issuer and credential commitment (public key is already a Poseidon hash)
     let mut hash_input = Vec::new();
@@ -483,10 +850,8 @@ fn generate_credential_proof(
     let mut leaf_index_bytes = [0u8; 32];
     leaf_index_bytes[28..32].copy_from_slice(&(leaf_index as u32).to_le_bytes());
 
-    // SDK format: 32-byte array with discriminator at [24..32] and prefix 2 at [23]
-    let mut discriminator_bytes = [0u8; 32];
-    discriminator_bytes[24..32].copy_from_slice(&discriminator);
-    discriminator_bytes[23] = 2;
+    // SDK format: discriminator at [24..32] and prefix 2 at [23]
+    let discriminator_bytes = encode_discriminator(discriminator);
 
     let computed_leaf_hash = Poseidon::hashv(&[
         owner_hashed.as_slice(),
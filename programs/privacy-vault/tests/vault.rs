@@ -0,0 +1,2143 @@
+mod common;
+
+use anchor_lang::{AccountDeserialize, AnchorSerialize, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::spl_associated_token_account;
+use common::{
+    association_set_pda, deposit_sol, deposit_token, initialize_config, initialize_token_vault,
+    register_association_set, send_prove_innocence, set_association_set_root, set_dev_mode,
+    set_upgrade_authority, try_initialize_config,
+};
+use light_compressed_account::compressed_account::{CompressedAccount, CompressedAccountData};
+use light_hasher::{
+    hash_to_field_size::{hash_to_bn254_field_size_be, hashv_to_bn254_field_size_be_const_array},
+    DataHasher, Hasher, Poseidon, Sha256,
+};
+use light_merkle_tree_reference::MerkleTree;
+use light_program_test::{
+    program_test::LightProgramTest, utils::assert::assert_rpc_error, AddressWithTree, Indexer,
+    ProgramTestConfig, Rpc, RpcError,
+};
+use light_sdk::{
+    address::v2::derive_address,
+    instruction::{
+        account_meta::CompressedAccountMeta, CompressedProof, PackedAccounts,
+        PackedAddressTreeInfo, SystemAccountMetaConfig, ValidityProof,
+    },
+    LightDiscriminator,
+};
+use num_bigint::BigUint;
+use privacy_vault::note::{
+    compute_commitment, compute_commitment_with, compute_nullifier_hash,
+    compute_nullifier_hash_with, compute_view_tag, derive_stream_note, Note, NoteError,
+};
+use privacy_vault::{
+    check_proof_encoding, deposit_permit_message, derive_deposit_address, derive_nullifier_address,
+    derive_token_deposit_address, encode_discriminator, encode_u64_be_field, fee_from_bps,
+    recipient_allowlist_path, recipient_allowlist_root, vault_token_address, AnyDeposit,
+    Commitment, DepositAccount, DepositPermit, ErrorCode, InnocenceBitmapAccount,
+    InnocenceBitmapState, NullifierState, OracleComparison, OraclePredicate, PermitNonce,
+    PoolAccount, ProgramConfig, PublishedRoot, RecipientAllowlistProof, RootSnapshotRing,
+    TokenDepositAccount, TokenMintHash, BITMAP_ASSOCIATION_SETS, BN254_BASE_FIELD_MODULUS,
+    BN254_FIELD_MODULUS, BPS_DENOMINATOR, CONFIG, DEPOSIT, MAX_ENCRYPTED_NOTE_LEN,
+    MAX_ROOT_SNAPSHOT_AGE_SLOTS, NATIVE_DEPOSIT, NULLIFIER, PERMIT_DELEGATE, PERMIT_NONCE, POOL,
+    ROOT_SNAPSHOT_RING_SIZE, STATE_TREE_HEIGHT,
+};
+use proptest::prelude::*;
+use solana_sdk::{
+    instruction::Instruction,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    system_instruction,
+};
+
+#[test]
+fn test_nullifier_domains_derive_distinct_addresses() {
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let nullifier_hash = [9u8; 32];
+
+    let (default_address, _) = derive_nullifier_address(&nullifier_hash, &[0u8; 8], &address_tree);
+    let (domain_a, _) = derive_nullifier_address(&nullifier_hash, &[1u8; 8], &address_tree);
+    let (domain_b, _) = derive_nullifier_address(&nullifier_hash, &[2u8; 8], &address_tree);
+
+    // The zero domain must keep the original [NULLIFIER, nullifier_hash] derivation
+    let (legacy_address, _) = derive_address(
+        &[NULLIFIER, nullifier_hash.as_slice()],
+        &address_tree,
+        &privacy_vault::ID,
+    );
+    assert_eq!(default_address, legacy_address);
+
+    assert_ne!(domain_a, domain_b);
+    assert_ne!(domain_a, default_address);
+    assert_ne!(domain_b, default_address);
+}
+
+#[test]
+fn test_light_cpi_signer_matches_program_id() {
+    // initialize_config fails with InvalidCpiSigner if these drift apart
+    let signer = privacy_vault::LIGHT_CPI_SIGNER;
+    let (cpi_authority, bump) = Pubkey::find_program_address(
+        &[light_sdk::constants::CPI_AUTHORITY_PDA_SEED],
+        &privacy_vault::ID,
+    );
+    assert_eq!(signer.program_id, privacy_vault::ID.to_bytes());
+    assert_eq!(signer.cpi_signer, cpi_authority.to_bytes());
+    assert_eq!(signer.bump, bump);
+}
+
+#[test]
+fn test_encode_u64_be_field_layout() {
+    let encoded = encode_u64_be_field(0x0102_0304_0506_0708);
+    assert_eq!(encoded[..24], [0u8; 24]);
+    assert_eq!(encoded[24..], [1, 2, 3, 4, 5, 6, 7, 8]);
+
+    // Association set ids are encoded as small field elements in the last two bytes
+    let mut association_set_id = [0u8; 32];
+    association_set_id[30..].copy_from_slice(&300u16.to_be_bytes());
+    assert_eq!(encode_u64_be_field(300), association_set_id);
+
+    assert_eq!(encode_u64_be_field(0), [0u8; 32]);
+    assert_eq!(encode_u64_be_field(u64::MAX)[24..], [0xff; 8]);
+}
+
+#[test]
+fn test_fee_from_bps_rounds_down() {
+    assert_eq!(fee_from_bps(1_000_000, 30), Some(3_000));
+    assert_eq!(fee_from_bps(1_000_000, 0), Some(0));
+    assert_eq!(fee_from_bps(1_000_000, BPS_DENOMINATOR), Some(1_000_000));
+    assert_eq!(fee_from_bps(1_000_000, BPS_DENOMINATOR + 1), None);
+
+    // 0.3% of 333 is 0.999, paid as 0; 0.3% of 334 is 1.002, paid as 1
+    assert_eq!(fee_from_bps(333, 30), Some(0));
+    assert_eq!(fee_from_bps(334, 30), Some(1));
+
+    // No intermediate overflow on the largest amount
+    assert_eq!(fee_from_bps(u64::MAX, BPS_DENOMINATOR), Some(u64::MAX));
+    assert_eq!(fee_from_bps(u64::MAX, 1), Some(u64::MAX / 10_000));
+}
+
+#[test]
+fn test_oracle_predicate_reads_le_i64_at_offset() {
+    let mut data = vec![0u8; 16];
+    data[4..12].copy_from_slice(&(-250i64).to_le_bytes());
+    let predicate = |comparison, threshold| OraclePredicate {
+        offset: 4,
+        comparison,
+        threshold,
+    };
+
+    assert_eq!(
+        predicate(OracleComparison::Equal, 0).read_value(&data),
+        Some(-250)
+    );
+    // Fewer than 8 bytes after the offset, or an offset past the end, decodes nothing
+    let past_end = OraclePredicate {
+        offset: 9,
+        comparison: OracleComparison::Equal,
+        threshold: 0,
+    };
+    assert_eq!(past_end.read_value(&data), None);
+    let overflowing = OraclePredicate {
+        offset: u32::MAX,
+        comparison: OracleComparison::Equal,
+        threshold: 0,
+    };
+    assert_eq!(overflowing.read_value(&data), None);
+
+    for (comparison, below, at, above) in [
+        (OracleComparison::GreaterThan, false, false, true),
+        (OracleComparison::GreaterOrEqual, false, true, true),
+        (OracleComparison::LessThan, true, false, false),
+        (OracleComparison::LessOrEqual, true, true, false),
+        (OracleComparison::Equal, false, true, false),
+        (OracleComparison::NotEqual, true, false, true),
+    ] {
+        let check = predicate(comparison, -250);
+        assert_eq!(check.holds(-251), below, "{:?} below", comparison);
+        assert_eq!(check.holds(-250), at, "{:?} at", comparison);
+        assert_eq!(check.holds(-249), above, "{:?} above", comparison);
+    }
+}
+
+#[test]
+fn test_root_snapshot_ring_wraps_and_expires() {
+    let mut ring = RootSnapshotRing {
+        merkle_tree: Pubkey::new_unique(),
+        next: 0,
+        last_cranked_slot: 0,
+        entries: [PublishedRoot::default(); ROOT_SNAPSHOT_RING_SIZE],
+        bump: 0,
+    };
+    for i in 0..ROOT_SNAPSHOT_RING_SIZE as u64 + 1 {
+        ring.push(PublishedRoot {
+            root: [i as u8 + 1; 32],
+            root_index: i as u16,
+            slot: 100 + i,
+        });
+    }
+
+    // The first root was overwritten by the last; the second is now the oldest
+    assert_eq!(ring.next, 1);
+    let latest_slot = 100 + ROOT_SNAPSHOT_RING_SIZE as u64;
+    assert_eq!(ring.find_recent(&[1u8; 32], latest_slot), None);
+    assert_eq!(ring.entries[0].slot, latest_slot);
+    assert!(ring.find_recent(&[2u8; 32], latest_slot).is_some());
+
+    // Roots older than MAX_ROOT_SNAPSHOT_AGE_SLOTS are not recent
+    assert!(ring
+        .find_recent(&[2u8; 32], 101 + MAX_ROOT_SNAPSHOT_AGE_SLOTS)
+        .is_some());
+    assert_eq!(
+        ring.find_recent(&[2u8; 32], 102 + MAX_ROOT_SNAPSHOT_AGE_SLOTS),
+        None
+    );
+
+    // Unused entries never match
+    let empty = RootSnapshotRing {
+        next: 0,
+        entries: [PublishedRoot::default(); ROOT_SNAPSHOT_RING_SIZE],
+        ..ring
+    };
+    assert_eq!(empty.find_recent(&[0u8; 32], 0), None);
+}
+
+#[test]
+fn test_genesis_root_is_empty_state_tree_root() {
+    // read_spendable_root rejects this root; it must be what a fresh state tree reports
+    let empty_tree = MerkleTree::<Poseidon>::new(STATE_TREE_HEIGHT, 0);
+    assert_eq!(empty_tree.root(), Poseidon::zero_bytes()[STATE_TREE_HEIGHT]);
+
+    let mut tree = MerkleTree::<Poseidon>::new(STATE_TREE_HEIGHT, 0);
+    tree.append(&[1u8; 32]).unwrap();
+    assert_ne!(tree.root(), Poseidon::zero_bytes()[STATE_TREE_HEIGHT]);
+}
+
+#[test]
+fn test_encode_discriminator_matches_circuit_domain() {
+    let discriminator = DepositAccount::LIGHT_DISCRIMINATOR;
+    let encoded = encode_discriminator(discriminator);
+
+    assert_eq!(encoded[..23], [0u8; 23]);
+    assert_eq!(encoded[23], 2);
+    assert_eq!(encoded[24..], discriminator);
+
+    // CompressedAccountHash adds 36893488147419103232 (2^65) to the bare discriminator
+    let domain = BigUint::from(36893488147419103232u128);
+    assert_eq!(
+        BigUint::from_bytes_be(&encoded),
+        BigUint::from_bytes_be(&discriminator) + domain
+    );
+}
+
+/// Recomputes a compressed account leaf hash the way the circuits do
+/// Poseidon(owner_hashed, leaf_index, tree_hashed, address, encode_discriminator, data_hash)
+fn circuit_leaf_hash(
+    address: &[u8; 32],
+    merkle_tree: &Pubkey,
+    leaf_index: u32,
+    discriminator: [u8; 8],
+    data_hash: &[u8; 32],
+) -> [u8; 32] {
+    let owner_hashed = hash_to_bn254_field_size_be(privacy_vault::ID.as_ref());
+    let merkle_tree_hashed = hash_to_bn254_field_size_be(merkle_tree.as_ref());
+
+    let mut leaf_index_bytes = [0u8; 32];
+    leaf_index_bytes[28..32].copy_from_slice(&leaf_index.to_le_bytes());
+
+    let discriminator_bytes = encode_discriminator(discriminator);
+
+    Poseidon::hashv(&[
+        owner_hashed.as_slice(),
+        leaf_index_bytes.as_slice(),
+        merkle_tree_hashed.as_slice(),
+        address.as_slice(),
+        discriminator_bytes.as_slice(),
+        data_hash.as_slice(),
+    ])
+    .unwrap()
+}
+
+/// Builds the compressed account the Light system program stores for a deposit
+fn deposit_compressed_account(
+    address: [u8; 32],
+    discriminator: [u8; 8],
+    data_hash: [u8; 32],
+) -> CompressedAccount {
+    CompressedAccount {
+        owner: privacy_vault::ID.to_bytes().into(),
+        lamports: 0,
+        address: Some(address),
+        data: Some(CompressedAccountData {
+            discriminator,
+            data: vec![],
+            data_hash,
+        }),
+    }
+}
+
+#[test]
+fn test_deposit_leaf_hash_matches_circuit() {
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let merkle_tree = Pubkey::new_from_array([2u8; 32]);
+    let leaf_index: u32 = 5;
+
+    let mut commitment = [4u8; 32];
+    commitment[0] = 0;
+    let deposit = DepositAccount {
+        commitment: Commitment::new(commitment),
+        amount: 1_000_000,
+        timestamp: 1_700_000_000,
+        view_tag: None,
+        encrypted_note: None,
+    };
+
+    // Data hash: Poseidon(hash_to_field(commitment), amount, timestamp)
+    let commitment_hashed =
+        hashv_to_bn254_field_size_be_const_array::<2>(&[commitment.as_slice()]).unwrap();
+    let expected_data_hash = Poseidon::hashv(&[
+        commitment_hashed.as_slice(),
+        encode_u64_be_field(deposit.amount).as_slice(),
+        encode_u64_be_field(deposit.timestamp).as_slice(),
+    ])
+    .unwrap();
+    let data_hash = deposit.hash::<Poseidon>().unwrap();
+    assert_eq!(data_hash, expected_data_hash, "Deposit data hash mismatch");
+
+    let (address, _) = derive_address(&[DEPOSIT, &commitment], &address_tree, &privacy_vault::ID);
+    let compressed_account =
+        deposit_compressed_account(address, DepositAccount::LIGHT_DISCRIMINATOR, data_hash);
+    let account_hash = compressed_account
+        .hash(&merkle_tree.to_bytes().into(), &leaf_index, false)
+        .unwrap();
+
+    assert_eq!(
+        circuit_leaf_hash(
+            &address,
+            &merkle_tree,
+            leaf_index,
+            DepositAccount::LIGHT_DISCRIMINATOR,
+            &data_hash
+        ),
+        account_hash,
+        "Leaf hash mismatch - circuit cannot recreate deposit account hash"
+    );
+}
+
+#[test]
+fn test_token_deposit_leaf_hash_matches_circuit() {
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let merkle_tree = Pubkey::new_from_array([2u8; 32]);
+    let leaf_index: u32 = 9;
+
+    let mut commitment = [4u8; 32];
+    commitment[0] = 0;
+    let token_mint = Pubkey::new_from_array([8u8; 32]);
+    let deposit = TokenDepositAccount {
+        commitment: Commitment::new(commitment),
+        token_mint_hash: TokenMintHash::from_pubkey(&token_mint),
+        amount: 42,
+        timestamp: 1_700_000_000,
+    };
+
+    // Data hash: Poseidon(hash_to_field(commitment), hash_to_field(mint), amount, timestamp)
+    let commitment_hashed =
+        hashv_to_bn254_field_size_be_const_array::<2>(&[commitment.as_slice()]).unwrap();
+    let mint_hashed =
+        hashv_to_bn254_field_size_be_const_array::<2>(&[token_mint.as_ref()]).unwrap();
+    let expected_data_hash = Poseidon::hashv(&[
+        commitment_hashed.as_slice(),
+        mint_hashed.as_slice(),
+        encode_u64_be_field(deposit.amount).as_slice(),
+        encode_u64_be_field(deposit.timestamp).as_slice(),
+    ])
+    .unwrap();
+    let data_hash = deposit.hash::<Poseidon>().unwrap();
+    assert_eq!(
+        data_hash, expected_data_hash,
+        "Token deposit data hash mismatch"
+    );
+
+    let (address, _) = derive_address(
+        &[DEPOSIT, &commitment, token_mint.as_ref()],
+        &address_tree,
+        &privacy_vault::ID,
+    );
+    let compressed_account =
+        deposit_compressed_account(address, TokenDepositAccount::LIGHT_DISCRIMINATOR, data_hash);
+    let account_hash = compressed_account
+        .hash(&merkle_tree.to_bytes().into(), &leaf_index, false)
+        .unwrap();
+
+    assert_eq!(
+        circuit_leaf_hash(
+            &address,
+            &merkle_tree,
+            leaf_index,
+            TokenDepositAccount::LIGHT_DISCRIMINATOR,
+            &data_hash
+        ),
+        account_hash,
+        "Leaf hash mismatch - circuit cannot recreate token deposit account hash"
+    );
+}
+
+#[test]
+fn test_hasher_generic_note_and_deposit_hashes() {
+    let nullifier = [1u8; 32];
+    let secret = [2u8; 32];
+
+    // The Poseidon instances are exactly the default functions
+    let commitment = compute_commitment(&nullifier, &secret).unwrap();
+    assert_eq!(
+        compute_commitment_with::<Poseidon>(&nullifier, &secret).unwrap(),
+        commitment
+    );
+    assert_eq!(
+        compute_nullifier_hash_with::<Poseidon>(&nullifier).unwrap(),
+        compute_nullifier_hash(&nullifier).unwrap()
+    );
+
+    // Another hasher yields another scheme, for notes and deposit data hashes alike
+    assert_ne!(
+        compute_commitment_with::<Sha256>(&nullifier, &secret).unwrap(),
+        commitment
+    );
+    let deposit = DepositAccount {
+        commitment: Commitment::new(commitment),
+        amount: 1,
+        timestamp: 2,
+        view_tag: None,
+        encrypted_note: None,
+    };
+    assert_ne!(
+        deposit.hash::<Sha256>().unwrap(),
+        deposit.hash::<Poseidon>().unwrap()
+    );
+}
+
+#[test]
+fn test_any_deposit_reads_sol_and_token_deposits() {
+    let sol = DepositAccount {
+        commitment: Commitment::new([1u8; 32]),
+        amount: 5,
+        timestamp: 10,
+        view_tag: Some(3),
+        encrypted_note: None,
+    };
+    let mint = Pubkey::new_from_array([8u8; 32]);
+    let token = TokenDepositAccount {
+        commitment: Commitment::new([2u8; 32]),
+        token_mint_hash: TokenMintHash::from_pubkey(&mint),
+        amount: 7,
+        timestamp: 11,
+    };
+
+    let any_sol = AnyDeposit::from_account_data(
+        DepositAccount::LIGHT_DISCRIMINATOR,
+        &sol.try_to_vec().unwrap(),
+    )
+    .expect("SOL deposit not decoded");
+    assert_eq!(any_sol.commitment().value, [1u8; 32]);
+    assert_eq!((any_sol.amount(), any_sol.timestamp()), (5, 10));
+    assert_eq!(any_sol.asset(), TokenMintHash::native());
+    assert!(any_sol.asset().is_native());
+
+    let any_token = AnyDeposit::from_account_data(
+        TokenDepositAccount::LIGHT_DISCRIMINATOR,
+        &token.try_to_vec().unwrap(),
+    )
+    .expect("Token deposit not decoded");
+    assert_eq!(any_token.commitment().value, [2u8; 32]);
+    assert_eq!((any_token.amount(), any_token.timestamp()), (7, 11));
+    assert_eq!(any_token.asset(), TokenMintHash::from_pubkey(&mint));
+    assert!(!any_token.asset().is_native());
+
+    // Other account types are skipped
+    assert!(AnyDeposit::from_account_data([0u8; 8], &sol.try_to_vec().unwrap()).is_none());
+}
+
+#[tokio::test]
+async fn test_initialize_token_vault_and_deposit() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    // Deposits check their output tree against the config
+    initialize_config(&mut rpc, &payer).await;
+
+    // Create a mint and fund the payer's token account
+    let mint = Keypair::new();
+    let rent = rpc
+        .get_minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN)
+        .await
+        .unwrap();
+    let user_token_account =
+        spl_associated_token_account::get_associated_token_address(&payer.pubkey(), &mint.pubkey());
+    let setup_instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            rent,
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::ID,
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::ID,
+            &mint.pubkey(),
+            &payer.pubkey(),
+            None,
+            6,
+        )
+        .unwrap(),
+        spl_associated_token_account::instruction::create_associated_token_account(
+            &payer.pubkey(),
+            &payer.pubkey(),
+            &mint.pubkey(),
+            &spl_token::ID,
+        ),
+        spl_token::instruction::mint_to(
+            &spl_token::ID,
+            &mint.pubkey(),
+            &user_token_account,
+            &payer.pubkey(),
+            &[],
+            1_000_000,
+        )
+        .unwrap(),
+    ];
+    rpc.create_and_send_transaction(&setup_instructions, &payer.pubkey(), &[&payer, &mint])
+        .await
+        .unwrap();
+
+    // Initializing twice must succeed and leave the canonical account in place
+    initialize_token_vault(&mut rpc, &payer, &mint.pubkey())
+        .await
+        .unwrap();
+    initialize_token_vault(&mut rpc, &payer, &mint.pubkey())
+        .await
+        .unwrap();
+
+    let vault_token_account = vault_token_address(&mint.pubkey());
+    let vault_account = rpc
+        .get_account(vault_token_account)
+        .await
+        .unwrap()
+        .expect("Vault token account not created");
+    let vault_state = spl_token::state::Account::unpack(&vault_account.data).unwrap();
+    assert_eq!(vault_state.mint, mint.pubkey());
+    assert_eq!(vault_state.amount, 0);
+
+    // Deposit into the canonical vault token account
+    let mut commitment = [5u8; 32];
+    commitment[0] = 0;
+    deposit_token(
+        &mut rpc,
+        &payer,
+        &payer,
+        &mint.pubkey(),
+        &user_token_account,
+        commitment,
+        500_000,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let vault_account = rpc.get_account(vault_token_account).await.unwrap().unwrap();
+    let vault_state = spl_token::state::Account::unpack(&vault_account.data).unwrap();
+    assert_eq!(vault_state.amount, 500_000);
+
+    let deposits = rpc
+        .get_compressed_accounts_by_owner(&privacy_vault::ID, None, None)
+        .await
+        .unwrap();
+    assert_eq!(deposits.value.items.len(), 1);
+
+    // A sponsor pays the fees while an unfunded depositor only authorizes the transfer
+    let depositor = Keypair::new();
+    let depositor_token_account = spl_associated_token_account::get_associated_token_address(
+        &depositor.pubkey(),
+        &mint.pubkey(),
+    );
+    let funding_instructions = [
+        spl_associated_token_account::instruction::create_associated_token_account(
+            &payer.pubkey(),
+            &depositor.pubkey(),
+            &mint.pubkey(),
+            &spl_token::ID,
+        ),
+        spl_token::instruction::mint_to(
+            &spl_token::ID,
+            &mint.pubkey(),
+            &depositor_token_account,
+            &payer.pubkey(),
+            &[],
+            100_000,
+        )
+        .unwrap(),
+    ];
+    rpc.create_and_send_transaction(&funding_instructions, &payer.pubkey(), &[&payer])
+        .await
+        .unwrap();
+
+    let mut sponsored_commitment = [6u8; 32];
+    sponsored_commitment[0] = 0;
+    deposit_token(
+        &mut rpc,
+        &payer,
+        &depositor,
+        &mint.pubkey(),
+        &depositor_token_account,
+        sponsored_commitment,
+        100_000,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let vault_account = rpc.get_account(vault_token_account).await.unwrap().unwrap();
+    let vault_state = spl_token::state::Account::unpack(&vault_account.data).unwrap();
+    assert_eq!(vault_state.amount, 600_000);
+    assert!(
+        rpc.get_account(depositor.pubkey()).await.unwrap().is_none(),
+        "Depositor must not pay any fees"
+    );
+}
+
+#[tokio::test]
+async fn test_deposit_with_permit() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    // Deposits check their output tree against the config
+    initialize_config(&mut rpc, &payer).await;
+
+    // The owner never pays or signs a deposit transaction, only the one-time approve
+    let owner = Keypair::new();
+    let mint = Keypair::new();
+    let rent = rpc
+        .get_minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN)
+        .await
+        .unwrap();
+    let owner_token_account =
+        spl_associated_token_account::get_associated_token_address(&owner.pubkey(), &mint.pubkey());
+    let (permit_delegate, _) = Pubkey::find_program_address(&[PERMIT_DELEGATE], &privacy_vault::ID);
+    let setup_instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            rent,
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::ID,
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::ID,
+            &mint.pubkey(),
+            &payer.pubkey(),
+            None,
+            6,
+        )
+        .unwrap(),
+        spl_associated_token_account::instruction::create_associated_token_account(
+            &payer.pubkey(),
+            &owner.pubkey(),
+            &mint.pubkey(),
+            &spl_token::ID,
+        ),
+        spl_token::instruction::mint_to(
+            &spl_token::ID,
+            &mint.pubkey(),
+            &owner_token_account,
+            &payer.pubkey(),
+            &[],
+            1_000_000,
+        )
+        .unwrap(),
+        spl_token::instruction::approve(
+            &spl_token::ID,
+            &owner_token_account,
+            &permit_delegate,
+            &owner.pubkey(),
+            &[],
+            u64::MAX,
+        )
+        .unwrap(),
+    ];
+    rpc.create_and_send_transaction(
+        &setup_instructions,
+        &payer.pubkey(),
+        &[&payer, &mint, &owner],
+    )
+    .await
+    .unwrap();
+    initialize_token_vault(&mut rpc, &payer, &mint.pubkey())
+        .await
+        .unwrap();
+
+    let mut commitment = [8u8; 32];
+    commitment[0] = 0;
+    let deadline = i64::MAX;
+    let permit = sign_deposit_permit(&owner, &mint.pubkey(), &commitment, 400_000, 0, deadline);
+    deposit_with_permit(
+        &mut rpc,
+        &payer,
+        &owner.pubkey(),
+        &mint.pubkey(),
+        commitment,
+        400_000,
+        permit.clone(),
+    )
+    .await
+    .unwrap();
+
+    let vault_account = rpc
+        .get_account(vault_token_address(&mint.pubkey()))
+        .await
+        .unwrap()
+        .unwrap();
+    let vault_state = spl_token::state::Account::unpack(&vault_account.data).unwrap();
+    assert_eq!(vault_state.amount, 400_000);
+
+    let (permit_nonce, _) =
+        Pubkey::find_program_address(&[PERMIT_NONCE, owner.pubkey().as_ref()], &privacy_vault::ID);
+    let nonce_account = rpc.get_account(permit_nonce).await.unwrap().unwrap();
+    let nonce = PermitNonce::try_deserialize(&mut nonce_account.data.as_slice()).unwrap();
+    assert_eq!(nonce.owner, owner.pubkey());
+    assert_eq!(nonce.nonce, 1);
+
+    // Replaying the transaction fails, and so does any other permit signed for a spent nonce
+    let result = deposit_with_permit(
+        &mut rpc,
+        &payer,
+        &owner.pubkey(),
+        &mint.pubkey(),
+        commitment,
+        400_000,
+        permit,
+    )
+    .await;
+    assert!(result.is_err(), "Replayed permit must fail");
+    let mut next_commitment = [9u8; 32];
+    next_commitment[0] = 0;
+    let stale_permit = sign_deposit_permit(
+        &owner,
+        &mint.pubkey(),
+        &next_commitment,
+        100_000,
+        0,
+        deadline,
+    );
+    let result = deposit_with_permit(
+        &mut rpc,
+        &payer,
+        &owner.pubkey(),
+        &mint.pubkey(),
+        next_commitment,
+        100_000,
+        stale_permit,
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::InvalidPermitNonce.into()).unwrap();
+
+    // A relayer can't move a permit to its own commitment or a larger amount
+    let permit = sign_deposit_permit(
+        &owner,
+        &mint.pubkey(),
+        &next_commitment,
+        100_000,
+        1,
+        deadline,
+    );
+    let mut relayer_commitment = [10u8; 32];
+    relayer_commitment[0] = 0;
+    let result = deposit_with_permit(
+        &mut rpc,
+        &payer,
+        &owner.pubkey(),
+        &mint.pubkey(),
+        relayer_commitment,
+        100_000,
+        permit.clone(),
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::InvalidPermitSignature.into()).unwrap();
+    let result = deposit_with_permit(
+        &mut rpc,
+        &payer,
+        &owner.pubkey(),
+        &mint.pubkey(),
+        next_commitment,
+        500_000,
+        permit.clone(),
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::InvalidPermitSignature.into()).unwrap();
+
+    // Past its deadline a correctly signed permit is rejected
+    let expired_permit =
+        sign_deposit_permit(&owner, &mint.pubkey(), &next_commitment, 100_000, 1, 0);
+    let result = deposit_with_permit(
+        &mut rpc,
+        &payer,
+        &owner.pubkey(),
+        &mint.pubkey(),
+        next_commitment,
+        100_000,
+        expired_permit,
+    )
+    .await;
+    assert_rpc_error(result, 1, ErrorCode::PermitExpired.into()).unwrap();
+
+    // Rejected permits consume nothing, so the next nonce still works
+    deposit_with_permit(
+        &mut rpc,
+        &payer,
+        &owner.pubkey(),
+        &mint.pubkey(),
+        next_commitment,
+        100_000,
+        permit,
+    )
+    .await
+    .unwrap();
+    let nonce_account = rpc.get_account(permit_nonce).await.unwrap().unwrap();
+    let nonce = PermitNonce::try_deserialize(&mut nonce_account.data.as_slice()).unwrap();
+    assert_eq!(nonce.nonce, 2);
+}
+
+fn sign_deposit_permit(
+    owner: &Keypair,
+    mint: &Pubkey,
+    commitment: &[u8; 32],
+    amount: u64,
+    nonce: u64,
+    deadline: i64,
+) -> DepositPermit {
+    let message =
+        deposit_permit_message(&owner.pubkey(), mint, commitment, amount, nonce, deadline);
+    DepositPermit {
+        nonce,
+        deadline,
+        signature: owner.sign_message(&message).into(),
+    }
+}
+
+/// An Ed25519 program instruction verifying one signature, with all data inline
+fn ed25519_instruction(pubkey: &Pubkey, signature: &[u8; 64], message: &[u8]) -> Instruction {
+    // Header (2 bytes) and one Ed25519SignatureOffsets (14 bytes), then pubkey, signature, message
+    let pubkey_offset: u16 = 16;
+    let signature_offset = pubkey_offset + 32;
+    let message_offset = signature_offset + 64;
+    let mut data = vec![1u8, 0];
+    for value in [
+        signature_offset,
+        u16::MAX,
+        pubkey_offset,
+        u16::MAX,
+        message_offset,
+        message.len() as u16,
+        u16::MAX,
+    ] {
+        data.extend_from_slice(&value.to_le_bytes());
+    }
+    data.extend_from_slice(pubkey.as_ref());
+    data.extend_from_slice(signature);
+    data.extend_from_slice(message);
+    Instruction {
+        program_id: solana_sdk::ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+async fn deposit_with_permit<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    owner: &Pubkey,
+    mint: &Pubkey,
+    commitment: [u8; 32],
+    amount: u64,
+    permit: DepositPermit,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) =
+        derive_token_deposit_address(&commitment, Some(mint), &address_tree_info.tree);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let message = deposit_permit_message(
+        owner,
+        mint,
+        &commitment,
+        amount,
+        permit.nonce,
+        permit.deadline,
+    );
+    let verify_instruction = ed25519_instruction(owner, &permit.signature, &message);
+
+    let instruction_data = privacy_vault::instruction::DepositWithPermit {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        commitment,
+        amount,
+        permit,
+    };
+
+    let accounts = privacy_vault::accounts::DepositWithPermitAccounts {
+        payer: payer.pubkey(),
+        config: Pubkey::find_program_address(&[CONFIG], &privacy_vault::ID).0,
+        user_token_account: spl_associated_token_account::get_associated_token_address(owner, mint),
+        vault_token_account: vault_token_address(mint),
+        permit_delegate: Pubkey::find_program_address(&[PERMIT_DELEGATE], &privacy_vault::ID).0,
+        permit_nonce: Pubkey::find_program_address(
+            &[PERMIT_NONCE, owner.as_ref()],
+            &privacy_vault::ID,
+        )
+        .0,
+        instructions_sysvar: solana_sdk::sysvar::instructions::ID,
+        token_program: spl_token::ID,
+        system_program: solana_sdk::system_program::ID,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(
+        &[verify_instruction, instruction],
+        &payer.pubkey(),
+        &[payer],
+    )
+    .await
+}
+
+/// BN254 scalar field modulus, the bound for every circuit input
+fn bn254_modulus() -> BigUint {
+    BigUint::parse_bytes(
+        b"21888242871839275222246405745257275088548364400416034343698204186575808495617",
+        10,
+    )
+    .unwrap()
+}
+
+fn in_field(bytes: &[u8; 32]) -> bool {
+    BigUint::from_bytes_be(bytes) < bn254_modulus()
+}
+
+proptest! {
+    /// Notes drawn as 31-byte values (top byte zero) always hash, deterministically,
+    /// to field elements the deposit account can store
+    #[test]
+    fn prop_note_round_trip(
+        nullifier_tail in any::<[u8; 31]>(),
+        secret_tail in any::<[u8; 31]>(),
+        amount in any::<u64>(),
+        timestamp in any::<u64>(),
+        view_tag in any::<Option<u8>>(),
+    ) {
+        let mut nullifier = [0u8; 32];
+        nullifier[1..].copy_from_slice(&nullifier_tail);
+        let mut secret = [0u8; 32];
+        secret[1..].copy_from_slice(&secret_tail);
+
+        let commitment = compute_commitment(&nullifier, &secret).unwrap();
+        let nullifier_hash = compute_nullifier_hash(&nullifier).unwrap();
+
+        prop_assert_eq!(commitment, compute_commitment(&nullifier, &secret).unwrap());
+        prop_assert_eq!(
+            commitment,
+            Poseidon::hashv(&[nullifier.as_slice(), secret.as_slice()]).unwrap()
+        );
+        prop_assert_eq!(nullifier_hash, Poseidon::hash(nullifier.as_slice()).unwrap());
+        prop_assert!(in_field(&commitment));
+        prop_assert!(in_field(&nullifier_hash));
+
+        let deposit = DepositAccount {
+            commitment: Commitment::new(commitment),
+            amount,
+            timestamp,
+            view_tag: None,
+            encrypted_note: None,
+        };
+        let data_hash = deposit.hash::<Poseidon>().unwrap();
+        prop_assert!(in_field(&data_hash));
+
+        // The view tag and note backup are off-hash extras and never change the leaf
+        let tagged = DepositAccount {
+            view_tag,
+            encrypted_note: Some(vec![7u8; MAX_ENCRYPTED_NOTE_LEN]),
+            ..deposit
+        };
+        prop_assert_eq!(tagged.hash::<Poseidon>().unwrap(), data_hash);
+    }
+
+    /// Arbitrary 32-byte values never panic: inputs inside the field hash, inputs at
+    /// or above the modulus are rejected instead of silently reduced
+    #[test]
+    fn prop_note_hashing_rejects_out_of_field(
+        nullifier in any::<[u8; 32]>(),
+        secret in any::<[u8; 32]>(),
+    ) {
+        let commitment = compute_commitment(&nullifier, &secret);
+        prop_assert_eq!(commitment.is_ok(), in_field(&nullifier) && in_field(&secret));
+
+        let nullifier_hash = compute_nullifier_hash(&nullifier);
+        prop_assert_eq!(nullifier_hash.is_ok(), in_field(&nullifier));
+    }
+}
+
+fn to_field_bytes(value: &BigUint) -> [u8; 32] {
+    let bytes = value.to_bytes_be();
+    let mut out = [0u8; 32];
+    out[32 - bytes.len()..].copy_from_slice(&bytes);
+    out
+}
+
+#[test]
+fn test_note_hashing_at_field_modulus() {
+    let modulus = to_field_bytes(&bn254_modulus());
+    let max_element = to_field_bytes(&(bn254_modulus() - 1u32));
+
+    assert!(compute_commitment(&max_element, &max_element).is_ok());
+    assert!(compute_nullifier_hash(&max_element).is_ok());
+    assert!(compute_commitment(&modulus, &[0u8; 32]).is_err());
+    assert!(compute_commitment(&[0u8; 32], &modulus).is_err());
+    assert!(compute_nullifier_hash(&modulus).is_err());
+    assert!(compute_nullifier_hash(&[0xff; 32]).is_err());
+}
+
+#[tokio::test]
+async fn test_close_nullifier_requires_admin_and_dev_mode() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    let program_config = get_program_config(&mut rpc, &config_pda).await;
+    assert_eq!(program_config.admin, payer.pubkey());
+    assert!(!program_config.dev_mode);
+
+    // Dev mode is off by default, so close_nullifier fails before touching any account
+    let result = close_nullifier(&mut rpc, &payer, &config_pda).await;
+    assert_rpc_error(result, 0, ErrorCode::DevModeDisabled.into()).unwrap();
+
+    // Only the admin can enable dev mode
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result = set_dev_mode(&mut rpc, &other, &config_pda, true).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+
+    set_dev_mode(&mut rpc, &payer, &config_pda, true)
+        .await
+        .unwrap();
+    let program_config = get_program_config(&mut rpc, &config_pda).await;
+    assert!(program_config.dev_mode);
+
+    // Only the admin can close nullifiers, even in dev mode
+    let result = close_nullifier(&mut rpc, &other, &config_pda).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_initialize_config_twice_fails() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    set_upgrade_authority(&mut rpc, &privacy_vault::ID, &payer.pubkey());
+
+    // Only the upgrade authority can create the config and become admin
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result = try_initialize_config(&mut rpc, &other).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+    set_dev_mode(&mut rpc, &payer, &config_pda, true)
+        .await
+        .unwrap();
+
+    // Re-running the deploy step fails instead of resetting the admin settings
+    let result = try_initialize_config(&mut rpc, &payer).await;
+    assert_rpc_error(result, 0, ErrorCode::ConfigAlreadyInitialized.into()).unwrap();
+    // Anyone else is still stopped by the upgrade-authority check first
+    let result = try_initialize_config(&mut rpc, &other).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+
+    // The existing settings are untouched
+    let program_config = get_program_config(&mut rpc, &config_pda).await;
+    assert_eq!(program_config.admin, payer.pubkey());
+    assert!(program_config.dev_mode);
+}
+
+async fn get_program_config<R>(rpc: &mut R, config_pda: &Pubkey) -> ProgramConfig
+where
+    R: Rpc + Indexer,
+{
+    let account = rpc
+        .get_account(*config_pda)
+        .await
+        .unwrap()
+        .expect("Program config not created");
+    ProgramConfig::try_deserialize(&mut account.data.as_slice()).unwrap()
+}
+
+async fn close_nullifier<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+    };
+    let instruction_data = privacy_vault::instruction::CloseNullifier {
+        proof: ValidityProof::default(),
+        system_accounts_offset: 0,
+        nullifier: NullifierState {
+            account_meta: CompressedAccountMeta::default(),
+            nullifier_hash: [1u8; 32],
+            used_at: 0,
+            expires_at: 0,
+            merkle_tree: Pubkey::default(),
+            root_index: 0,
+            root: [0u8; 32],
+        },
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+#[tokio::test]
+async fn test_prove_innocence_requires_registered_active_set() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    // Set 255 was never registered, so the proof is rejected before it is verified
+    let result = prove_innocence(&mut rpc, &payer, &config_pda, 255, None).await;
+    assert_rpc_error(result, 0, ErrorCode::UnknownAssociationSet.into()).unwrap();
+
+    // Only the admin can register sets
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result = register_association_set(&mut rpc, &other, &config_pda, 1).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+
+    // Ids past the bitmap bits register, but have no bitmap to update
+    register_association_set(&mut rpc, &payer, &config_pda, BITMAP_ASSOCIATION_SETS)
+        .await
+        .unwrap();
+    let bitmap = InnocenceBitmapState {
+        account_meta: CompressedAccountMeta::default(),
+        satisfied_sets: 0,
+    };
+    let result = prove_innocence(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        BITMAP_ASSOCIATION_SETS,
+        Some(bitmap),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidAssociationSetId.into()).unwrap();
+
+    register_association_set(&mut rpc, &payer, &config_pda, 1)
+        .await
+        .unwrap();
+
+    // Only the admin publishes roots, and proofs must use the current one
+    let result = set_association_set_root(&mut rpc, &other, &config_pda, 1, [1u8; 32]).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+    set_association_set_root(&mut rpc, &payer, &config_pda, 1, [1u8; 32])
+        .await
+        .unwrap();
+    let result = prove_innocence(&mut rpc, &payer, &config_pda, 1, None).await;
+    assert_rpc_error(result, 0, ErrorCode::AssociationSetRootMismatch.into()).unwrap();
+
+    // A retired set is rejected like an unknown one
+    let accounts = privacy_vault::accounts::AssociationSetAdminAccounts {
+        signer: payer.pubkey(),
+        config: config_pda,
+        association_set: association_set_pda(1),
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetAssociationSetActive {
+            association_set_id: 1,
+            active: false,
+        }
+        .data(),
+    };
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await
+        .unwrap();
+
+    let result = prove_innocence(&mut rpc, &payer, &config_pda, 1, None).await;
+    assert_rpc_error(result, 0, ErrorCode::UnknownAssociationSet.into()).unwrap();
+}
+
+#[test]
+fn test_innocence_bitmap_satisfies_mask() {
+    let bitmap = InnocenceBitmapAccount {
+        nullifier_hash: [1u8; 32],
+        satisfied_sets: (1 << 0) | (1 << 5) | (1 << 63),
+    };
+    assert!(bitmap.satisfies(0));
+    assert!(bitmap.satisfies(1 << 5));
+    assert!(bitmap.satisfies((1 << 0) | (1 << 63)));
+    assert!(!bitmap.satisfies((1 << 5) | (1 << 6)));
+    assert!(!InnocenceBitmapAccount::default().satisfies(1));
+}
+
+#[tokio::test]
+async fn test_native_and_wsol_deposit_records() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    // Deposits check their output tree against the config
+    initialize_config(&mut rpc, &payer).await;
+    let wsol = spl_token::native_mint::ID;
+
+    // Wrap some SOL for the token path
+    let wsol_account =
+        spl_associated_token_account::get_associated_token_address(&payer.pubkey(), &wsol);
+    let wrap_instructions = [
+        spl_associated_token_account::instruction::create_associated_token_account(
+            &payer.pubkey(),
+            &payer.pubkey(),
+            &wsol,
+            &spl_token::ID,
+        ),
+        system_instruction::transfer(&payer.pubkey(), &wsol_account, 1_000_000),
+        spl_token::instruction::sync_native(&spl_token::ID, &wsol_account).unwrap(),
+    ];
+    rpc.create_and_send_transaction(&wrap_instructions, &payer.pubkey(), &[&payer])
+        .await
+        .unwrap();
+    initialize_token_vault(&mut rpc, &payer, &wsol)
+        .await
+        .unwrap();
+
+    // The same commitment deposited both ways lands at two different addresses
+    let mut commitment = [4u8; 32];
+    commitment[0] = 0;
+    deposit_sol(&mut rpc, &payer, commitment, 2_000_000)
+        .await
+        .unwrap();
+    deposit_token(
+        &mut rpc,
+        &payer,
+        &payer,
+        &wsol,
+        &wsol_account,
+        commitment,
+        1_000_000,
+        None,
+    )
+    .await
+    .unwrap();
+
+    let address_tree = rpc.get_address_tree_v2().tree;
+    let (native_address, _) = derive_address(
+        &[DEPOSIT, &commitment, NATIVE_DEPOSIT],
+        &address_tree,
+        &privacy_vault::ID,
+    );
+    let (wsol_address, _) = derive_address(
+        &[DEPOSIT, &commitment, wsol.as_ref()],
+        &address_tree,
+        &privacy_vault::ID,
+    );
+    assert_ne!(native_address, wsol_address);
+
+    let mut records = Vec::new();
+    for address in [native_address, wsol_address] {
+        let account = rpc
+            .get_compressed_account(address, None)
+            .await
+            .unwrap()
+            .value
+            .unwrap();
+        let data = account.data.unwrap();
+        assert_eq!(data.discriminator, TokenDepositAccount::LIGHT_DISCRIMINATOR);
+        let deposit = AnyDeposit::from_account_data(data.discriminator, &data.data).unwrap();
+        assert!(matches!(deposit, AnyDeposit::Token(_)));
+        records.push(deposit);
+    }
+
+    // Both report the native asset; only the amount and address tell them apart
+    for deposit in &records {
+        assert!(deposit.asset().is_native());
+        assert_eq!(deposit.commitment().value, commitment);
+    }
+    assert_eq!(records[0].amount(), 2_000_000);
+    assert_eq!(records[1].amount(), 1_000_000);
+}
+
+#[tokio::test]
+async fn test_deposit_rejects_non_canonical_commitment() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    // Deposits check their output tree against the config
+    initialize_config(&mut rpc, &payer).await;
+
+    // High bit set: far above the modulus
+    let mut commitment = [5u8; 32];
+    commitment[0] = 0x80;
+    let result = deposit_sol(&mut rpc, &payer, commitment, 1_000_000).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidCommitment.into()).unwrap();
+
+    // The modulus itself reduces to zero in the circuit
+    let result = deposit_sol(&mut rpc, &payer, BN254_FIELD_MODULUS, 1_000_000).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidCommitment.into()).unwrap();
+
+    // The largest field element is still accepted
+    let mut largest = BN254_FIELD_MODULUS;
+    largest[31] -= 1;
+    deposit_sol(&mut rpc, &payer, largest, 1_000_000)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_system_accounts_offset_out_of_range() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    // No remaining accounts at all, so any non-zero offset is past the end
+    let accounts = privacy_vault::accounts::DepositAccounts {
+        signer: payer.pubkey(),
+        config: config_pda,
+        pool: None,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::Deposit {
+            proof: ValidityProof::default(),
+            address_tree_info: PackedAddressTreeInfo::default(),
+            output_state_tree_index: 0,
+            system_accounts_offset: 3,
+            commitment: [1u8; 32],
+            amount: 0,
+            view_tag: None,
+            encrypted_note: None,
+            reference: None,
+            opening: None,
+            pool_id: 0,
+        }
+        .data(),
+    };
+    let result = rpc
+        .create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidSystemAccountsOffset.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_withdraw_rejects_off_list_recipient() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    let allowed = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let recipient_allowlist_root = recipient_allowlist_root(&allowed).unwrap();
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: privacy_vault::accounts::AdminAccounts {
+            signer: payer.pubkey(),
+            config: config_pda,
+        }
+        .to_account_metas(None),
+        data: privacy_vault::instruction::SetRecipientAllowlistRoot {
+            recipient_allowlist_root,
+        }
+        .data(),
+    };
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await
+        .unwrap();
+    assert_eq!(
+        get_program_config(&mut rpc, &config_pda)
+            .await
+            .recipient_allowlist_root,
+        recipient_allowlist_root
+    );
+
+    let off_list = Pubkey::new_unique();
+    let cases = [
+        (off_list, None, ErrorCode::RecipientNotAllowlisted),
+        // Another recipient's path does not lead from this recipient to the root
+        (off_list, Some(0), ErrorCode::RecipientNotAllowlisted),
+        (allowed[1], None, ErrorCode::RecipientNotAllowlisted),
+        // A listed recipient with its own path gets past the allowlist to the Light accounts
+        (allowed[1], Some(1), ErrorCode::AccountNotEnoughKeys),
+    ];
+    for (recipient, leaf_index, expected) in cases {
+        let recipient_proof = leaf_index.map(|leaf_index| RecipientAllowlistProof {
+            leaf_index: leaf_index as u32,
+            path: recipient_allowlist_path(&allowed, leaf_index).unwrap(),
+        });
+        let accounts = privacy_vault::accounts::WithdrawRecipientAccounts {
+            signer: payer.pubkey(),
+            config: config_pda,
+            input_merkle_tree: Pubkey::new_unique(),
+            recipient,
+            pool: None,
+        };
+        let instruction = Instruction {
+            program_id: privacy_vault::ID,
+            accounts: accounts.to_account_metas(None),
+            data: privacy_vault::instruction::Withdraw {
+                proof: ValidityProof::default(),
+                address_tree_info: PackedAddressTreeInfo::default(),
+                output_state_tree_index: 0,
+                system_accounts_offset: 0,
+                input_root_index: 0,
+                nullifier_hash: [1u8; 32],
+                recipient,
+                pool_id: 0,
+                recipient_proof,
+                zk_proof: CompressedProof::default(),
+            }
+            .data(),
+        };
+        let result = rpc
+            .create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+            .await;
+        assert_rpc_error(result, 0, expected.into()).unwrap();
+    }
+
+    // Payout paths without a recipient proof are closed while the allowlist is set
+    let accounts = privacy_vault::accounts::WithdrawConditionalAccounts {
+        signer: payer.pubkey(),
+        config: config_pda,
+        input_merkle_tree: Pubkey::new_unique(),
+        recipient: allowed[0],
+        oracle: Pubkey::new_unique(),
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::WithdrawConditional {
+            proof: ValidityProof::default(),
+            address_tree_info: PackedAddressTreeInfo::default(),
+            output_state_tree_index: 0,
+            system_accounts_offset: 0,
+            input_root_index: 0,
+            nullifier_hash: [1u8; 32],
+            recipient: allowed[0],
+            zk_proof: CompressedProof::default(),
+            predicate: OraclePredicate {
+                offset: 0,
+                comparison: OracleComparison::Equal,
+                threshold: 0,
+            },
+        }
+        .data(),
+    };
+    let result = rpc
+        .create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await;
+    assert_rpc_error(result, 0, ErrorCode::RecipientAllowlistActive.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_pool_withdraw_uses_pool_state_tree() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    let state_tree = Pubkey::new_unique();
+    let register_pool = |pool_id: u16| {
+        let (pool, _) =
+            Pubkey::find_program_address(&[POOL, &pool_id.to_le_bytes()], &privacy_vault::ID);
+        let instruction = Instruction {
+            program_id: privacy_vault::ID,
+            accounts: privacy_vault::accounts::RegisterPoolAccounts {
+                signer: payer.pubkey(),
+                config: config_pda,
+                pool,
+                system_program: solana_sdk::system_program::ID,
+            }
+            .to_account_metas(None),
+            data: privacy_vault::instruction::RegisterPool {
+                pool_id,
+                state_tree,
+            }
+            .data(),
+        };
+        (pool, instruction)
+    };
+
+    // Pool 0 is the default pool
+    let (_, instruction) = register_pool(0);
+    let result = rpc
+        .create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidPoolId.into()).unwrap();
+
+    let (pool, instruction) = register_pool(1);
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await
+        .unwrap();
+    let account = rpc.get_account(pool).await.unwrap().unwrap();
+    let pool_account = PoolAccount::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(pool_account.pool_id, 1);
+    assert_eq!(pool_account.state_tree, state_tree);
+    assert_eq!(pool_account.deposit_count, 0);
+    assert_eq!(pool_account.withdrawal_count, 0);
+    assert_eq!(
+        get_program_config(&mut rpc, &config_pda)
+            .await
+            .pool_state_trees,
+        vec![state_tree]
+    );
+
+    // The tree now belongs to pool 1: no other pool registers it, and it can't be allowlisted
+    let (_, instruction) = register_pool(2);
+    let result = rpc
+        .create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await;
+    assert_rpc_error(result, 0, ErrorCode::PoolStateTreeRegistered.into()).unwrap();
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: privacy_vault::accounts::AdminAccounts {
+            signer: payer.pubkey(),
+            config: config_pda,
+        }
+        .to_account_metas(None),
+        data: privacy_vault::instruction::SetMerkleTreeAllowlist {
+            merkle_trees: vec![Pubkey::new_unique(), state_tree],
+        }
+        .data(),
+    };
+    let result = rpc
+        .create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await;
+    assert_rpc_error(result, 0, ErrorCode::PoolStateTreeAllowlisted.into()).unwrap();
+
+    let cases = [
+        // A sub-pool without its pool account, and the default pool with one
+        (1, None, state_tree, ErrorCode::PoolMismatch),
+        (0, Some(pool), state_tree, ErrorCode::PoolMismatch),
+        (2, Some(pool), state_tree, ErrorCode::PoolMismatch),
+        // The default pool never reads a sub-pool's tree
+        (0, None, state_tree, ErrorCode::PoolStateTreeMismatch),
+        // The pool's notes are only spent from its own tree
+        (
+            1,
+            Some(pool),
+            Pubkey::new_unique(),
+            ErrorCode::PoolStateTreeMismatch,
+        ),
+        // The pool's tree gets past the pool checks to the Light accounts
+        (1, Some(pool), state_tree, ErrorCode::AccountNotEnoughKeys),
+    ];
+    for (pool_id, pool, input_merkle_tree, expected) in cases {
+        let recipient = Pubkey::new_unique();
+        let accounts = privacy_vault::accounts::WithdrawRecipientAccounts {
+            signer: payer.pubkey(),
+            config: config_pda,
+            input_merkle_tree,
+            recipient,
+            pool,
+        };
+        let instruction = Instruction {
+            program_id: privacy_vault::ID,
+            accounts: accounts.to_account_metas(None),
+            data: privacy_vault::instruction::Withdraw {
+                proof: ValidityProof::default(),
+                address_tree_info: PackedAddressTreeInfo::default(),
+                output_state_tree_index: 0,
+                system_accounts_offset: 0,
+                input_root_index: 0,
+                nullifier_hash: [1u8; 32],
+                recipient,
+                pool_id,
+                recipient_proof: None,
+                zk_proof: CompressedProof::default(),
+            }
+            .data(),
+        };
+        let result = rpc
+            .create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+            .await;
+        assert_rpc_error(result, 0, expected.into()).unwrap();
+    }
+}
+
+#[test]
+fn test_derive_deposit_address_pool_namespaces() {
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let commitment = [3u8; 32];
+
+    // Pool 0 keeps the original deposit seeds
+    assert_eq!(
+        derive_deposit_address(&commitment, 0, &address_tree),
+        derive_address(&[DEPOSIT, &commitment], &address_tree, &privacy_vault::ID)
+    );
+    // Each sub-pool has its own address for the same commitment
+    let (pool_1, _) = derive_deposit_address(&commitment, 1, &address_tree);
+    let (pool_2, _) = derive_deposit_address(&commitment, 2, &address_tree);
+    assert_ne!(
+        pool_1,
+        derive_deposit_address(&commitment, 0, &address_tree).0
+    );
+    assert_ne!(pool_1, pool_2);
+}
+
+#[test]
+fn test_derive_token_deposit_address_seeds() {
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let commitment = [3u8; 32];
+    let wsol = spl_token::native_mint::ID;
+
+    assert_eq!(
+        derive_token_deposit_address(&commitment, Some(&wsol), &address_tree),
+        derive_address(
+            &[DEPOSIT, &commitment, wsol.as_ref()],
+            &address_tree,
+            &privacy_vault::ID
+        )
+    );
+    assert_eq!(
+        derive_token_deposit_address(&commitment, None, &address_tree),
+        derive_address(
+            &[DEPOSIT, &commitment, NATIVE_DEPOSIT],
+            &address_tree,
+            &privacy_vault::ID
+        )
+    );
+    // Native and wSOL deposits of one commitment never collide, nor with a SOL note
+    let (native, _) = derive_token_deposit_address(&commitment, None, &address_tree);
+    let (wrapped, _) = derive_token_deposit_address(&commitment, Some(&wsol), &address_tree);
+    assert_ne!(native, wrapped);
+    assert_ne!(
+        native,
+        derive_deposit_address(&commitment, 0, &address_tree).0
+    );
+}
+
+#[test]
+fn test_check_proof_encoding() {
+    // x = 1 with the y-sign flag set is a well-formed encoding
+    let mut g1 = [0u8; 32];
+    g1[31] = 1;
+    g1[0] = 0x80;
+    let mut g2 = [0u8; 64];
+    g2[..32].copy_from_slice(&g1);
+    let proof = CompressedProof {
+        a: g1,
+        b: g2,
+        c: g1,
+    };
+    check_proof_encoding(&proof).unwrap();
+
+    assert!(check_proof_encoding(&CompressedProof::default()).is_err());
+
+    let mut infinity = proof;
+    infinity.a[0] |= 0x40;
+    assert!(check_proof_encoding(&infinity).is_err());
+
+    let mut outside_field = proof;
+    outside_field.c = BN254_BASE_FIELD_MODULUS;
+    assert!(check_proof_encoding(&outside_field).is_err());
+
+    // The second half of a G2 point carries no flags and is range checked as is
+    let mut outside_field = proof;
+    outside_field.b[32..].copy_from_slice(&BN254_BASE_FIELD_MODULUS);
+    assert!(check_proof_encoding(&outside_field).is_err());
+}
+
+/// Sends `prove_innocence` with placeholder proofs; only the registry check can pass
+async fn prove_innocence<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    association_set_id: u16,
+    innocence_bitmap: Option<InnocenceBitmapState>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let instruction_data = privacy_vault::instruction::ProveInnocence {
+        proof: ValidityProof::default(),
+        address_tree_info: PackedAddressTreeInfo::default(),
+        output_state_tree_index: 0,
+        system_accounts_offset: 0,
+        input_root_index: 0,
+        association_set_root: [0u8; 32],
+        nullifier_hash: [1u8; 32],
+        association_set_id,
+        innocence_bitmap,
+        zk_proof: CompressedProof::default(),
+    };
+
+    send_prove_innocence(
+        rpc,
+        signer,
+        config_pda,
+        Pubkey::new_unique(),
+        instruction_data,
+        Vec::new(),
+    )
+    .await
+}
+
+#[test]
+fn test_view_tag_depends_on_view_key_and_commitment() {
+    let view_key = [7u8; 32];
+    let commitment = [9u8; 32];
+    let tag = compute_view_tag(&view_key, &commitment).unwrap();
+
+    assert_eq!(tag, compute_view_tag(&view_key, &commitment).unwrap());
+
+    // Over many commitments a wrong key agrees with the right one only by chance
+    let matches = (0u8..=255)
+        .filter(|i| {
+            let commitment = [*i; 32];
+            compute_view_tag(&view_key, &commitment).unwrap()
+                == compute_view_tag(&[8u8; 32], &commitment).unwrap()
+        })
+        .count();
+    assert!(matches < 16, "view tags do not depend on the view key");
+}
+
+#[test]
+fn test_derive_stream_note_is_deterministic_per_index() {
+    let seed = [3u8; 32];
+    let (nullifier, secret) = derive_stream_note(&seed, 0).unwrap();
+
+    assert_eq!((nullifier, secret), derive_stream_note(&seed, 0).unwrap());
+    assert_ne!(nullifier, secret);
+    assert_ne!(nullifier, derive_stream_note(&seed, 1).unwrap().0);
+    assert_ne!(nullifier, derive_stream_note(&[4u8; 32], 0).unwrap().0);
+
+    // Derived values are field elements, so they always form a valid note
+    assert!(compute_commitment(&nullifier, &secret).is_ok());
+    assert!(derive_stream_note(&[0xff; 32], 0).is_err());
+}
+
+#[test]
+fn test_note_base58_round_trip() {
+    let sol_note = Note {
+        nullifier: [1u8; 32],
+        secret: [2u8; 32],
+        amount: 1_000_000_000,
+        mint: None,
+    };
+    let encoded = sol_note.to_base58();
+    assert_eq!(Note::from_base58(&encoded).unwrap(), sol_note);
+
+    let token_note = Note {
+        mint: Some(Pubkey::new_unique()),
+        ..sol_note.clone()
+    };
+    let encoded_token = token_note.to_base58();
+    assert_ne!(encoded_token, encoded);
+    assert_eq!(Note::from_base58(&encoded_token).unwrap(), token_note);
+}
+
+#[test]
+fn test_note_base58_rejects_corruption() {
+    let note = Note {
+        nullifier: [3u8; 32],
+        secret: [4u8; 32],
+        amount: 42,
+        mint: None,
+    };
+    let encoded = note.to_base58();
+
+    // Swap one character for a different base58 character
+    let mut corrupted: Vec<char> = encoded.chars().collect();
+    let middle = corrupted.len() / 2;
+    corrupted[middle] = if corrupted[middle] == '2' { '3' } else { '2' };
+    let corrupted: String = corrupted.into_iter().collect();
+    assert_eq!(
+        Note::from_base58(&corrupted),
+        Err(NoteError::InvalidChecksum)
+    );
+
+    // Truncated strings and non-base58 input are rejected too
+    assert!(Note::from_base58(&encoded[..encoded.len() - 4]).is_err());
+    assert_eq!(Note::from_base58("0OIl"), Err(NoteError::InvalidBase58));
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_plan_withdraw_matches_program_derivations() {
+    use privacy_vault::client::plan_withdraw;
+
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let sol_note = Note {
+        nullifier: [1u8; 32],
+        secret: [2u8; 32],
+        amount: 1_000_000_000,
+        mint: None,
+    };
+    let commitment = compute_commitment(&sol_note.nullifier, &sol_note.secret).unwrap();
+    let nullifier_hash = compute_nullifier_hash(&sol_note.nullifier).unwrap();
+
+    let plan = plan_withdraw(&sol_note, 0, None).unwrap();
+    assert_eq!(plan.commitment, commitment);
+    assert_eq!(plan.nullifier_hash, nullifier_hash);
+    assert_eq!(
+        plan.deposit_address,
+        derive_address(&[DEPOSIT, &commitment], &address_tree, &privacy_vault::ID).0
+    );
+    assert_eq!(
+        plan.nullifier_address,
+        derive_nullifier_address(
+            &nullifier_hash,
+            &privacy_vault::NULLIFIER_DOMAIN,
+            &address_tree
+        )
+        .0
+    );
+    let withdraw_addresses = plan.withdraw_new_addresses();
+    assert_eq!(withdraw_addresses.len(), 1);
+    assert_eq!(withdraw_addresses[0].address, plan.nullifier_address);
+    assert_eq!(withdraw_addresses[0].tree, address_tree);
+    assert!(plan.innocence_address.is_none());
+    assert!(plan.innocence_new_addresses().is_empty());
+
+    // A sub-pool's SOL note has the pool's deposit address and the same nullifier
+    let pool_plan = plan_withdraw(&sol_note, 2, None).unwrap();
+    assert_eq!(
+        pool_plan.deposit_address,
+        derive_deposit_address(&commitment, 2, &address_tree).0
+    );
+    assert_ne!(pool_plan.deposit_address, plan.deposit_address);
+    assert_eq!(pool_plan.nullifier_address, plan.nullifier_address);
+
+    // Token notes bind the mint into the deposit address; the nullifier is unchanged
+    let mint = Pubkey::new_unique();
+    let token_note = Note {
+        mint: Some(mint),
+        ..sol_note
+    };
+    let token_plan = plan_withdraw(&token_note, 0, Some(3)).unwrap();
+    assert_eq!(
+        token_plan.deposit_address,
+        derive_address(
+            &[DEPOSIT, &commitment, mint.as_ref()],
+            &address_tree,
+            &privacy_vault::ID
+        )
+        .0
+    );
+    assert_eq!(token_plan.nullifier_address, plan.nullifier_address);
+    let innocence_address = derive_address(
+        &[
+            privacy_vault::INNOCENCE_PROOF,
+            &nullifier_hash,
+            &3u16.to_le_bytes(),
+        ],
+        &address_tree,
+        &privacy_vault::ID,
+    )
+    .0;
+    assert_eq!(token_plan.innocence_address, Some(innocence_address));
+    assert_eq!(
+        token_plan.innocence_new_addresses()[0].address,
+        innocence_address
+    );
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_parse_deposit_receipt() {
+    use privacy_vault::client::{parse_deposit_receipt, ReceiptError};
+    use privacy_vault::DepositReceipt;
+
+    let address_tree = Pubkey::new_from_array(light_sdk::constants::ADDRESS_TREE_V2);
+    let commitment = compute_commitment(&[1u8; 32], &[2u8; 32]).unwrap();
+    let receipt = DepositReceipt {
+        address: derive_address(&[DEPOSIT, &commitment], &address_tree, &privacy_vault::ID).0,
+        commitment,
+        amount: 1_000_000_000,
+        reference: [7u8; 32],
+        slot: 256,
+    };
+    let data = receipt.try_to_vec().unwrap();
+    assert_eq!(data.len(), DepositReceipt::LEN);
+
+    // Recorded return data loses its trailing zero bytes
+    let end = data.iter().rposition(|byte| *byte != 0).unwrap() + 1;
+    assert!(end < data.len());
+    assert_eq!(
+        parse_deposit_receipt(&privacy_vault::ID, &data[..end], 0),
+        Ok(receipt.clone())
+    );
+    // A receipt is only valid for the pool the deposit went to
+    assert_eq!(
+        parse_deposit_receipt(&privacy_vault::ID, &data, 1),
+        Err(ReceiptError::AddressMismatch)
+    );
+    let pool_receipt = DepositReceipt {
+        address: derive_deposit_address(&commitment, 1, &address_tree).0,
+        ..receipt.clone()
+    };
+    assert_eq!(
+        parse_deposit_receipt(&privacy_vault::ID, &pool_receipt.try_to_vec().unwrap(), 1),
+        Ok(pool_receipt)
+    );
+
+    let other_program = Pubkey::new_unique();
+    assert_eq!(
+        parse_deposit_receipt(&other_program, &data, 0),
+        Err(ReceiptError::WrongProgram(other_program))
+    );
+    assert_eq!(
+        parse_deposit_receipt(&privacy_vault::ID, &[data.clone(), vec![1]].concat(), 0),
+        Err(ReceiptError::InvalidLength)
+    );
+
+    let forged = DepositReceipt {
+        commitment: [3u8; 32],
+        ..receipt
+    };
+    assert_eq!(
+        parse_deposit_receipt(&privacy_vault::ID, &forged.try_to_vec().unwrap(), 0),
+        Err(ReceiptError::AddressMismatch)
+    );
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_log_formatting_helpers() {
+    use privacy_vault::client::{to_base58, to_hex};
+    use privacy_vault::Hex;
+
+    let mut bytes = [0u8; 32];
+    bytes[0] = 0x0a;
+    bytes[31] = 0xff;
+    let hex = to_hex(&bytes);
+    assert_eq!(hex.len(), 64);
+    assert!(hex.starts_with("0a00"));
+    assert!(hex.ends_with("00ff"));
+    // The client matches what the program logs
+    assert_eq!(hex, format!("{}", Hex(&bytes)));
+
+    let address = Pubkey::new_unique();
+    assert_eq!(to_base58(&address.to_bytes()), address.to_string());
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_vault_error_maps_custom_codes() {
+    use anchor_lang::prelude::ProgramError;
+    use privacy_vault::client::{NewAddressError, VaultError};
+
+    // Every program code maps back to its ErrorCode, up to the last one declared
+    let mut code = 6000;
+    while let VaultError::Program(error) = VaultError::from(code) {
+        assert_eq!(u32::from(error), code);
+        code += 1;
+    }
+    assert_eq!(code, u32::from(ErrorCode::PoolLimitReached) + 1);
+    assert!(matches!(VaultError::from(code), VaultError::Custom(_)));
+
+    assert!(matches!(
+        VaultError::from(ProgramError::Custom(ErrorCode::InvalidProof.into())),
+        VaultError::Program(ErrorCode::InvalidProof)
+    ));
+    // ConstraintHasOne
+    assert!(matches!(VaultError::from(2001), VaultError::Anchor(2001)));
+    assert!(matches!(
+        VaultError::from(NewAddressError::NullifierAlreadyUsed),
+        VaultError::NullifierAlreadyUsed
+    ));
+    assert!(matches!(
+        VaultError::from(ProgramError::InsufficientFunds),
+        VaultError::Runtime(_)
+    ));
+
+    let description = VaultError::from(ErrorCode::InvalidProof).describe();
+    assert!(description.starts_with("InvalidProof: "));
+}
+
+#[cfg(feature = "client")]
+#[test]
+fn test_encrypted_note_round_trip() {
+    use privacy_vault::client::{decrypt_note, encrypt_note};
+
+    let recovery_key = [5u8; 32];
+    let mut nullifier = [1u8; 32];
+    nullifier[0] = 0;
+    let mut secret = [2u8; 32];
+    secret[0] = 0;
+    let note = Note {
+        nullifier,
+        secret,
+        amount: 1_000_000,
+        mint: Some(Pubkey::new_from_array([9u8; 32])),
+    };
+    let commitment = compute_commitment(&nullifier, &secret).unwrap();
+
+    // Token notes are the longest and still fit the on-chain cap
+    let ciphertext = encrypt_note(&recovery_key, &note).unwrap();
+    assert!(ciphertext.len() <= MAX_ENCRYPTED_NOTE_LEN);
+    assert_eq!(ciphertext, encrypt_note(&recovery_key, &note).unwrap());
+    assert_eq!(
+        decrypt_note(&recovery_key, &commitment, &ciphertext),
+        Some(note.clone())
+    );
+
+    // Another key, another deposit or a tampered backup all fail to open
+    assert_eq!(decrypt_note(&[6u8; 32], &commitment, &ciphertext), None);
+    assert_eq!(decrypt_note(&recovery_key, &[3u8; 32], &ciphertext), None);
+    let mut tampered = ciphertext.clone();
+    tampered[0] ^= 1;
+    assert_eq!(decrypt_note(&recovery_key, &commitment, &tampered), None);
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_build_light_context_deposits() {
+    use privacy_vault::client::build_light_context;
+
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    let commitment = [1u8; 32];
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) = derive_deposit_address(&commitment, 0, &address_tree_info.tree);
+    let context = build_light_context(
+        &mut rpc,
+        &privacy_vault::ID,
+        vec![],
+        vec![AddressWithTree {
+            address,
+            tree: address_tree_info.tree,
+        }],
+    )
+    .await
+    .unwrap();
+
+    let accounts = privacy_vault::accounts::DepositAccounts {
+        signer: payer.pubkey(),
+        config: config_pda,
+        pool: None,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), context.remaining_accounts].concat(),
+        data: privacy_vault::instruction::Deposit {
+            proof: context.proof,
+            address_tree_info: context.packed_tree_infos.address_trees[0],
+            output_state_tree_index: context.output_state_tree_index,
+            system_accounts_offset: context.system_accounts_offset,
+            commitment,
+            amount: 1_000_000,
+            view_tag: None,
+            encrypted_note: None,
+            reference: None,
+            opening: None,
+            pool_id: 0,
+        }
+        .data(),
+    };
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[&payer])
+        .await
+        .unwrap();
+
+    let deposit = rpc.get_compressed_account(address, None).await.unwrap();
+    assert!(deposit.value.is_some(), "Deposit not created");
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_build_light_context_fixed_output_tree() {
+    use privacy_vault::client::{build_light_context_with, OutputTreeStrategy};
+
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) = derive_deposit_address(&[1u8; 32], 0, &address_tree_info.tree);
+    let new_addresses = vec![AddressWithTree {
+        address,
+        tree: address_tree_info.tree,
+    }];
+
+    let tree = rpc.get_random_state_tree_info_v1().unwrap().tree;
+    let context = build_light_context_with(
+        &mut rpc,
+        &privacy_vault::ID,
+        vec![],
+        new_addresses.clone(),
+        OutputTreeStrategy::Fixed(tree),
+    )
+    .await
+    .unwrap();
+    let packed_index =
+        context.system_accounts_offset as usize + context.output_state_tree_index as usize;
+    assert_eq!(context.remaining_accounts[packed_index].pubkey, tree);
+
+    // A tree the client doesn't know of is refused rather than replaced
+    let result = build_light_context_with(
+        &mut rpc,
+        &privacy_vault::ID,
+        vec![],
+        new_addresses,
+        OutputTreeStrategy::Fixed(Pubkey::new_unique()),
+    )
+    .await;
+    assert!(result.is_err());
+}
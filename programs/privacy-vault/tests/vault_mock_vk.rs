@@ -0,0 +1,2739 @@
+#![cfg(feature = "test-mock-vk")]
+
+//! Withdraw-flow tests against the forgeable `verifying_key_mock` withdraw key.
+//!
+//! Run with `cargo test-sbf --features test-mock-vk`; no circom build or zkey is needed.
+
+mod common;
+
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::spl_associated_token_account;
+use common::{
+    association_set_pda, deposit_sol, deposit_token, initialize_config, initialize_token_vault,
+    register_association_set, send_prove_innocence, set_association_set_root, set_dev_mode,
+};
+use groth16_solana::proof_parser::circom_prover::convert_proof_to_compressed;
+use light_client::indexer::CompressedAccount;
+use light_program_test::{
+    program_test::LightProgramTest, utils::assert::assert_rpc_error, AddressWithTree, Indexer,
+    ProgramTestConfig, Rpc, RpcError,
+};
+use light_sdk::{
+    address::v2::derive_address,
+    instruction::{account_meta::CompressedAccountMeta, PackedAccounts, SystemAccountMetaConfig},
+};
+use privacy_vault::note::{compute_commitment, derive_stream_note};
+use privacy_vault::{
+    derive_nullifier_address, root_index_for, vault_token_address,
+    verifying_key_mock::{MOCK_PROOF_A, MOCK_PROOF_B, MOCK_PROOF_C},
+    DepositAccount, ErrorCode, InnocenceProofAccount, InnocenceProofState, NoteOpening,
+    NullifierAccount, NullifierState, OracleComparison, OraclePredicate, RootSnapshot,
+    RootSnapshotRing, StreamAccount, BITMAP_ASSOCIATION_SETS, DEPOSIT, INNOCENCE_BITMAP,
+    INNOCENCE_PROOF, MAX_ALLOWLISTED_TREES, MAX_ROOT_SNAPSHOT_AGE_SLOTS, MIN_CRANK_INTERVAL_SLOTS,
+    NULLIFIER_DOMAIN, ROOT_HISTORY_SIZE, ROOT_SNAPSHOT, ROOT_SNAPSHOT_RING, STREAM,
+};
+use solana_sdk::{
+    clock::Clock,
+    instruction::Instruction,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature, Signer},
+    system_instruction,
+};
+
+#[tokio::test]
+async fn test_withdraw_token_replay_fails() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, mint, user_token_account) = setup_token_pool(&mut rpc, &payer).await;
+
+    let vault_token_account = vault_token_address(&mint);
+    let mut nullifier_hash = [9u8; 32];
+    nullifier_hash[0] = 0;
+    withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        200_000,
+    )
+    .await
+    .unwrap();
+    assert_eq!(token_balance(&mut rpc, &vault_token_account).await, 300_000);
+    assert_eq!(token_balance(&mut rpc, &user_token_account).await, 700_000);
+
+    // Replay in the next transaction, without advancing the slot: the nullifier address
+    // already exists, so the spend is rejected and no tokens leave the vault
+    let result = withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        200_000,
+    )
+    .await;
+    assert!(result.is_err(), "Replayed withdrawal must fail");
+    assert_eq!(token_balance(&mut rpc, &vault_token_account).await, 300_000);
+    assert_eq!(token_balance(&mut rpc, &user_token_account).await, 700_000);
+}
+
+#[tokio::test]
+async fn test_withdraw_sol_checks_vault_balance() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    // The pool deposit gives withdrawals a spendable root
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+    let (vault, _) = Pubkey::find_program_address(&[b"vault"], &privacy_vault::ID);
+    let recipient = Pubkey::new_unique();
+
+    let deposited = 1_000_000_000;
+    let mut commitment = [1u8; 32];
+    commitment[0] = 0;
+    deposit_sol(&mut rpc, &payer, commitment, deposited)
+        .await
+        .unwrap();
+
+    // More than the balance, or an amount leaving dust below rent exemption, is refused
+    let mut nullifier_hash = [15u8; 32];
+    nullifier_hash[0] = 0;
+    let result = withdraw_sol(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &recipient,
+        nullifier_hash,
+        deposited + 1,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InsufficientVaultBalance.into()).unwrap();
+    let result = withdraw_sol(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &recipient,
+        nullifier_hash,
+        deposited - 1,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InsufficientVaultBalance.into()).unwrap();
+
+    // The exact balance empties the vault
+    withdraw_sol(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &recipient,
+        nullifier_hash,
+        deposited,
+    )
+    .await
+    .unwrap();
+    assert!(rpc.get_account(vault).await.unwrap().is_none());
+    assert_eq!(rpc.get_balance(&recipient).await.unwrap(), deposited);
+
+    // The nullifier is spent: with the vault funded again, a replay moves nothing
+    commitment[1] = 0;
+    deposit_sol(&mut rpc, &payer, commitment, deposited)
+        .await
+        .unwrap();
+    let result = withdraw_sol(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &recipient,
+        nullifier_hash,
+        deposited,
+    )
+    .await;
+    assert!(result.is_err(), "Replayed withdrawal must fail");
+    assert_eq!(rpc.get_balance(&vault).await.unwrap(), deposited);
+    assert_eq!(rpc.get_balance(&recipient).await.unwrap(), deposited);
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_client_reports_address_collisions() {
+    use privacy_vault::client::{check_deposit_address, check_nullifier_unspent, NewAddressError};
+    use privacy_vault::NATIVE_DEPOSIT;
+
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, mint, user_token_account) = setup_token_pool(&mut rpc, &payer).await;
+    let address_tree = rpc.get_address_tree_v2().tree;
+
+    // setup_token_pool deposited this commitment; the same one again would abort in the CPI
+    let mut commitment = [5u8; 32];
+    commitment[0] = 0;
+    let (deposit_address, _) = derive_address(
+        &[DEPOSIT, &commitment, mint.as_ref()],
+        &address_tree,
+        &privacy_vault::ID,
+    );
+    assert!(matches!(
+        check_deposit_address(&mut rpc, deposit_address).await,
+        Err(NewAddressError::CommitmentAlreadyExists)
+    ));
+    let (native_address, _) = derive_address(
+        &[DEPOSIT, &commitment, NATIVE_DEPOSIT],
+        &address_tree,
+        &privacy_vault::ID,
+    );
+    check_deposit_address(&mut rpc, native_address)
+        .await
+        .unwrap();
+
+    let mut nullifier_hash = [9u8; 32];
+    nullifier_hash[0] = 0;
+    check_nullifier_unspent(&mut rpc, &nullifier_hash)
+        .await
+        .unwrap();
+    withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        200_000,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        check_nullifier_unspent(&mut rpc, &nullifier_hash).await,
+        Err(NewAddressError::NullifierAlreadyUsed)
+    ));
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_total_by_mint() {
+    use privacy_vault::client::total_by_mint;
+
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, mint, user_token_account) = setup_token_pool(&mut rpc, &payer).await;
+
+    let mut commitment = [6u8; 32];
+    commitment[0] = 0;
+    deposit_token(
+        &mut rpc,
+        &payer,
+        &payer,
+        &mint,
+        &user_token_account,
+        commitment,
+        1_000,
+        None,
+    )
+    .await
+    .unwrap();
+    let totals = total_by_mint(&mut rpc).await.unwrap();
+    assert_eq!(totals.len(), 1);
+    assert_eq!(totals[&mint], 501_000);
+
+    // Withdrawals can't be linked to a deposit, so the total stays the gross amount
+    let mut nullifier_hash = [9u8; 32];
+    nullifier_hash[0] = 0;
+    withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        200_000,
+    )
+    .await
+    .unwrap();
+    assert_eq!(total_by_mint(&mut rpc).await.unwrap()[&mint], 501_000);
+    assert_eq!(
+        token_balance(&mut rpc, &vault_token_address(&mint)).await,
+        301_000
+    );
+}
+
+#[tokio::test]
+async fn test_nullifier_ttl_and_gc_checkpoint() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, mint, user_token_account) = setup_token_pool(&mut rpc, &payer).await;
+
+    // Only the admin sets the TTL
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result = set_nullifier_ttl(&mut rpc, &other, &config_pda, 3600).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+    set_nullifier_ttl(&mut rpc, &payer, &config_pda, 3600)
+        .await
+        .unwrap();
+
+    let mut nullifier_hash = [8u8; 32];
+    nullifier_hash[0] = 0;
+    withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        100_000,
+    )
+    .await
+    .unwrap();
+
+    // The nullifier carries its expiry and the root the spend was proven against
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (nullifier_address, _) =
+        derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_info.tree);
+    let nullifier_account = rpc
+        .get_compressed_account(nullifier_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Nullifier account not found");
+    let nullifier: NullifierAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut nullifier_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(nullifier.nullifier_hash, nullifier_hash);
+    assert_eq!(nullifier.expires_at, nullifier.used_at + 3600);
+    assert_ne!(nullifier.root, [0u8; 32]);
+
+    // Nobody can collect it before the TTL has passed
+    let result = gc_nullifier(&mut rpc, &other, &nullifier_account).await;
+    assert_rpc_error(result, 0, ErrorCode::NullifierNotExpired.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_close_innocence_proof_after_expiry() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+    register_association_set(&mut rpc, &payer, &config_pda, 1)
+        .await
+        .unwrap();
+
+    // Only the admin sets the TTL
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result = set_innocence_proof_ttl(&mut rpc, &other, &config_pda, 3600).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+    set_innocence_proof_ttl(&mut rpc, &payer, &config_pda, 3600)
+        .await
+        .unwrap();
+
+    let mut nullifier_hash = [10u8; 32];
+    nullifier_hash[0] = 0;
+    prove_innocence(&mut rpc, &payer, &config_pda, 1, nullifier_hash)
+        .await
+        .unwrap();
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (proof_address, _) = derive_address(
+        &[INNOCENCE_PROOF, &nullifier_hash, &1u16.to_le_bytes()],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+    let proof_account = rpc
+        .get_compressed_account(proof_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Innocence proof account not found");
+    let record: InnocenceProofAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut proof_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(record.expires_at, record.proven_at + 3600);
+
+    // Nobody can close it while it is still valid
+    let result = close_innocence_proof(&mut rpc, &other, &proof_account).await;
+    assert_rpc_error(result, 0, ErrorCode::ProofNotExpired.into()).unwrap();
+
+    // Once expired, anyone can
+    let mut clock = rpc.context.get_sysvar::<Clock>();
+    clock.unix_timestamp += 3600;
+    rpc.context.set_sysvar(&clock);
+    close_innocence_proof(&mut rpc, &other, &proof_account)
+        .await
+        .unwrap();
+
+    let accounts = rpc
+        .get_compressed_accounts_by_owner(&privacy_vault::ID, None, None)
+        .await
+        .unwrap()
+        .value
+        .items;
+    assert!(accounts
+        .iter()
+        .all(|account| account.address != Some(proof_address)));
+}
+
+#[tokio::test]
+async fn test_prove_innocence_beyond_bitmap_sets() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+
+    // Ids past the old u8 range register like any other
+    let association_set_id = 300u16;
+    register_association_set(&mut rpc, &payer, &config_pda, association_set_id)
+        .await
+        .unwrap();
+
+    let mut nullifier_hash = [13u8; 32];
+    nullifier_hash[0] = 0;
+    prove_innocence(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        association_set_id,
+        nullifier_hash,
+    )
+    .await
+    .unwrap();
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (proof_address, _) = derive_address(
+        &[
+            INNOCENCE_PROOF,
+            &nullifier_hash,
+            &association_set_id.to_le_bytes(),
+        ],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+    let proof_account = rpc
+        .get_compressed_account(proof_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Innocence proof account not found");
+    let record: InnocenceProofAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut proof_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(record.association_set_id, association_set_id);
+
+    // The set has no bitmap bit, so no bitmap was created for the nullifier
+    let (bitmap_address, _) = derive_address(
+        &[INNOCENCE_BITMAP, &nullifier_hash],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+    let accounts = rpc
+        .get_compressed_accounts_by_owner(&privacy_vault::ID, None, None)
+        .await
+        .unwrap()
+        .value
+        .items;
+    assert!(accounts
+        .iter()
+        .all(|account| account.address != Some(bitmap_address)));
+}
+
+#[tokio::test]
+async fn test_reprove_innocence() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+    register_association_set(&mut rpc, &payer, &config_pda, 1)
+        .await
+        .unwrap();
+    set_innocence_proof_ttl(&mut rpc, &payer, &config_pda, 3600)
+        .await
+        .unwrap();
+
+    let mut nullifier_hash = [12u8; 32];
+    nullifier_hash[0] = 0;
+    prove_innocence(&mut rpc, &payer, &config_pda, 1, nullifier_hash)
+        .await
+        .unwrap();
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (proof_address, _) = derive_address(
+        &[INNOCENCE_PROOF, &nullifier_hash, &1u16.to_le_bytes()],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+    let stale_account = rpc
+        .get_compressed_account(proof_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Innocence proof account not found");
+    let stale: InnocenceProofAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut stale_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+
+    // After the set's root moves on, the record is refreshed at the same address
+    set_association_set_root(&mut rpc, &payer, &config_pda, 1, [1u8; 32])
+        .await
+        .unwrap();
+    let result = reprove_innocence(&mut rpc, &payer, &config_pda, &stale_account, [0u8; 32]).await;
+    assert_rpc_error(result, 0, ErrorCode::AssociationSetRootMismatch.into()).unwrap();
+    let mut clock = rpc.context.get_sysvar::<Clock>();
+    clock.unix_timestamp += 1000;
+    rpc.context.set_sysvar(&clock);
+    reprove_innocence(&mut rpc, &payer, &config_pda, &stale_account, [1u8; 32])
+        .await
+        .unwrap();
+
+    let proof_account = rpc
+        .get_compressed_account(proof_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Innocence proof account not found");
+    let record: InnocenceProofAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut proof_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(record.nullifier_hash, nullifier_hash);
+    assert_eq!(record.association_set_id, 1);
+    assert_eq!(record.proven_at, stale.proven_at + 1000);
+    assert_eq!(record.expires_at, record.proven_at + 3600);
+
+    // The replaced record no longer exists to be refreshed
+    let result = reprove_innocence(&mut rpc, &payer, &config_pda, &stale_account, [1u8; 32]).await;
+    assert!(result.is_err(), "Stale innocence record must be rejected");
+}
+
+#[tokio::test]
+async fn test_crank_merkle_snapshot() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+
+    let pool_deposit = get_pool_deposit(&mut rpc).await;
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await
+        .unwrap()
+        .value
+        .items
+        .remove(0);
+    let merkle_tree = merkle_proof.merkle_tree;
+    let root_index = root_index_for(merkle_proof.root_seq);
+
+    let ring_pda =
+        initialize_root_snapshot_ring(&mut rpc, &payer, &config_pda, &merkle_tree, root_index)
+            .await
+            .unwrap();
+
+    // Anyone may crank, but not again within MIN_CRANK_INTERVAL_SLOTS
+    let cranker = Keypair::new();
+    rpc.airdrop_lamports(&cranker.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result =
+        crank_merkle_snapshot(&mut rpc, &cranker, &config_pda, &merkle_tree, root_index).await;
+    assert_rpc_error(result, 0, ErrorCode::CrankTooSoon.into()).unwrap();
+
+    let slot = rpc.get_slot().await.unwrap();
+    rpc.warp_to_slot(slot + MIN_CRANK_INTERVAL_SLOTS).unwrap();
+    crank_merkle_snapshot(&mut rpc, &cranker, &config_pda, &merkle_tree, root_index)
+        .await
+        .unwrap();
+
+    let account = rpc.get_account(ring_pda).await.unwrap().unwrap();
+    let ring = RootSnapshotRing::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(ring.merkle_tree, merkle_tree);
+    assert_eq!(ring.next, 2);
+    for entry in &ring.entries[..2] {
+        assert_eq!(entry.root, merkle_proof.root);
+        assert_eq!(entry.root_index, root_index);
+    }
+    assert_eq!(ring.last_cranked_slot, ring.entries[1].slot);
+
+    let current_slot = rpc.get_slot().await.unwrap();
+    assert_eq!(
+        ring.find_recent(&merkle_proof.root, current_slot),
+        Some(&ring.entries[1])
+    );
+
+    // withdraw_explicit_root accepts a root the ring published, and nothing else
+    let mut nullifier_hash = [14u8; 32];
+    nullifier_hash[0] = 0;
+    let mut forged_root = merkle_proof.root;
+    forged_root[31] ^= 1;
+    let result = withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Ring(ring_pda),
+        nullifier_hash,
+        Some(forged_root),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidMerkleRoot.into()).unwrap();
+
+    withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Ring(ring_pda),
+        nullifier_hash,
+        Some(merkle_proof.root),
+    )
+    .await
+    .unwrap();
+
+    // Once the published root is older than MAX_ROOT_SNAPSHOT_AGE_SLOTS it is rejected
+    let slot = rpc.get_slot().await.unwrap();
+    rpc.warp_to_slot(slot + MAX_ROOT_SNAPSHOT_AGE_SLOTS + 1)
+        .unwrap();
+    nullifier_hash[1] = 1;
+    let result = withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Ring(ring_pda),
+        nullifier_hash,
+        Some(merkle_proof.root),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidMerkleRoot.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_withdraw_with_root_snapshot() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+
+    // Snapshot the current root of the pool deposit's state tree
+    let pool_deposit = get_pool_deposit(&mut rpc).await;
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await
+        .unwrap()
+        .value
+        .items
+        .remove(0);
+    let merkle_tree = merkle_proof.merkle_tree;
+    let root_index = root_index_for(merkle_proof.root_seq);
+
+    // Indices past the root history are rejected before the tree is read
+    let result = initialize_root_snapshot(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &merkle_tree,
+        ROOT_HISTORY_SIZE as u16,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidRootIndex.into()).unwrap();
+
+    let root_snapshot =
+        initialize_root_snapshot(&mut rpc, &payer, &config_pda, &merkle_tree, root_index)
+            .await
+            .unwrap();
+    let account = rpc.get_account(root_snapshot).await.unwrap().unwrap();
+    let snapshot = RootSnapshot::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(snapshot.authority, payer.pubkey());
+    assert_eq!(snapshot.merkle_tree, merkle_tree);
+    assert_eq!(snapshot.root, merkle_proof.root);
+    assert_eq!(snapshot.root_index, root_index);
+
+    let mut nullifier_hash = [7u8; 32];
+    nullifier_hash[0] = 0;
+    withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Snapshot(root_snapshot),
+        nullifier_hash,
+        None,
+    )
+    .await
+    .unwrap();
+
+    // Past the freshness bound the snapshot is rejected until it is refreshed
+    let slot = rpc.get_slot().await.unwrap();
+    rpc.warp_to_slot(slot + MAX_ROOT_SNAPSHOT_AGE_SLOTS + 1)
+        .unwrap();
+    nullifier_hash[1] = 1;
+    let result = withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Snapshot(root_snapshot),
+        nullifier_hash,
+        None,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::RootSnapshotStale.into()).unwrap();
+
+    snapshot_root(&mut rpc, &payer, &config_pda, &merkle_tree, root_index)
+        .await
+        .unwrap();
+    withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Snapshot(root_snapshot),
+        nullifier_hash,
+        None,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_withdraw_explicit_root() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+
+    let pool_deposit = get_pool_deposit(&mut rpc).await;
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await
+        .unwrap()
+        .value
+        .items
+        .remove(0);
+    let root_index = root_index_for(merkle_proof.root_seq);
+    let root_snapshot = initialize_root_snapshot(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &merkle_proof.merkle_tree,
+        root_index,
+    )
+    .await
+    .unwrap();
+
+    // A root that no snapshot holds is rejected before the proof is checked
+    let mut nullifier_hash = [8u8; 32];
+    nullifier_hash[0] = 0;
+    let mut forged_root = merkle_proof.root;
+    forged_root[31] ^= 1;
+    let result = withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Snapshot(root_snapshot),
+        nullifier_hash,
+        Some(forged_root),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidMerkleRoot.into()).unwrap();
+
+    withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Snapshot(root_snapshot),
+        nullifier_hash,
+        Some(merkle_proof.root),
+    )
+    .await
+    .unwrap();
+}
+
+#[cfg(feature = "client")]
+#[tokio::test]
+async fn test_deposit_tree_membership_path() {
+    use privacy_vault::client::{merkle_root_from_path, DepositTree, DepositTreeError};
+    use privacy_vault::note::compute_nullifier_hash;
+    use privacy_vault::STATE_TREE_HEIGHT;
+
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    let mut nullifier = [3u8; 32];
+    nullifier[0] = 0;
+    let mut secret = [4u8; 32];
+    secret[0] = 0;
+    let commitment = compute_commitment(&nullifier, &secret).unwrap();
+    deposit(&mut rpc, &payer, &config_pda, commitment, 1_000_000, None)
+        .await
+        .unwrap();
+
+    let address_tree = rpc.get_address_tree_v2().tree;
+    let (deposit_address, _) =
+        derive_address(&[DEPOSIT, &commitment], &address_tree, &privacy_vault::ID);
+    let deposit_account = rpc
+        .get_compressed_account(deposit_address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Deposit account not found");
+
+    // The path is rebuilt from the deposit's fields and checked against the indexer's root.
+    // LightProgramTest serves it from a reference tree that mirrors the live state tree.
+    let merkle_tree = deposit_account.tree_info.tree;
+    let deposit_tree = DepositTree::fetch(&mut rpc, merkle_tree).await.unwrap();
+    let merkle_inputs = deposit_tree
+        .proof_for_commitment(&mut rpc, &commitment)
+        .await
+        .unwrap();
+    assert_eq!(merkle_inputs.merkle_tree, merkle_tree);
+    assert_eq!(merkle_inputs.leaf_index, deposit_account.leaf_index);
+    assert_eq!(merkle_inputs.path_elements.len(), STATE_TREE_HEIGHT);
+
+    // A tampered sibling no longer leads to the root
+    let mut tampered = merkle_inputs.path_elements.clone();
+    tampered[0][31] ^= 1;
+    assert_ne!(
+        merkle_root_from_path(&deposit_account.hash, merkle_inputs.leaf_index, &tampered).unwrap(),
+        merkle_inputs.root
+    );
+    let mut unknown = commitment;
+    unknown[31] ^= 1;
+    assert!(matches!(
+        deposit_tree.proof_for_commitment(&mut rpc, &unknown).await,
+        Err(DepositTreeError::UnknownCommitment)
+    ));
+
+    // The program reads the same root at the path's root index
+    let root_snapshot = initialize_root_snapshot(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &merkle_tree,
+        merkle_inputs.root_index,
+    )
+    .await
+    .unwrap();
+    let account = rpc.get_account(root_snapshot).await.unwrap().unwrap();
+    let snapshot = RootSnapshot::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(snapshot.root, merkle_inputs.root);
+
+    // and a withdraw proven against that root verifies on chain (with the mock key)
+    let nullifier_hash = compute_nullifier_hash(&nullifier).unwrap();
+    withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Snapshot(root_snapshot),
+        nullifier_hash,
+        Some(merkle_inputs.root),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_withdraw_recipient_must_match_proof() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+
+    // The proof commits to the payer; a relayer swapping in its own account is rejected
+    let mut nullifier_hash = [9u8; 32];
+    nullifier_hash[0] = 0;
+    let relayer = Pubkey::new_unique();
+    let result = withdraw(&mut rpc, &payer, &config_pda, &relayer, nullifier_hash).await;
+    assert_rpc_error(result, 0, ErrorCode::RecipientMismatch.into()).unwrap();
+
+    withdraw(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &payer.pubkey(),
+        nullifier_hash,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_withdraw_conditional_on_oracle() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, _, _) = setup_token_pool(&mut rpc, &payer).await;
+
+    // Any account with an allowlisted owner serves as the oracle; a zeroed system account
+    // stands in for a price feed reporting 0
+    let oracle = Keypair::new();
+    let create_oracle = system_instruction::create_account(
+        &payer.pubkey(),
+        &oracle.pubkey(),
+        rpc.get_minimum_balance_for_rent_exemption(8).await.unwrap(),
+        8,
+        &solana_sdk::system_program::ID,
+    );
+    rpc.create_and_send_transaction(&[create_oracle], &payer.pubkey(), &[&payer, &oracle])
+        .await
+        .unwrap();
+
+    let mut nullifier_hash = [11u8; 32];
+    nullifier_hash[0] = 0;
+    let at_least_zero = OraclePredicate {
+        offset: 0,
+        comparison: OracleComparison::GreaterOrEqual,
+        threshold: 0,
+    };
+
+    // An empty allowlist trusts no oracle
+    let result = withdraw_conditional(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &payer.pubkey(),
+        nullifier_hash,
+        &oracle.pubkey(),
+        at_least_zero.clone(),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::UntrustedOracle.into()).unwrap();
+
+    // Only the admin sets the allowlist
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let oracle_owners = vec![solana_sdk::system_program::ID];
+    let result =
+        set_oracle_owner_allowlist(&mut rpc, &other, &config_pda, oracle_owners.clone()).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+    set_oracle_owner_allowlist(&mut rpc, &payer, &config_pda, oracle_owners)
+        .await
+        .unwrap();
+
+    let above_zero = OraclePredicate {
+        comparison: OracleComparison::GreaterThan,
+        ..at_least_zero.clone()
+    };
+    let result = withdraw_conditional(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &payer.pubkey(),
+        nullifier_hash,
+        &oracle.pubkey(),
+        above_zero,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::OracleConditionNotMet.into()).unwrap();
+
+    // Only 4 of the 8 data bytes follow offset 4
+    let truncated = OraclePredicate {
+        offset: 4,
+        ..at_least_zero.clone()
+    };
+    let result = withdraw_conditional(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &payer.pubkey(),
+        nullifier_hash,
+        &oracle.pubkey(),
+        truncated,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidOracleData.into()).unwrap();
+
+    withdraw_conditional(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &payer.pubkey(),
+        nullifier_hash,
+        &oracle.pubkey(),
+        at_least_zero,
+    )
+    .await
+    .unwrap();
+}
+
+/// Advances the pool deposit's state tree through one full root history rotation
+/// One deposit writes one root, so this sends over `ROOT_HISTORY_SIZE` deposits; run it with
+/// `--ignored`.
+/// The mock key accepts any proof, so a stale root is caught by the snapshot cross-check of
+/// `withdraw_explicit_root`; with the real key the proof would fail against the new root too.
+#[cfg(feature = "client")]
+#[tokio::test]
+#[ignore = "stress test: sends one deposit per root in a full root history"]
+async fn test_root_history_rotation() {
+    use privacy_vault::client::fetch_merkle_inputs;
+
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, mint, user_token_account) = setup_token_pool(&mut rpc, &payer).await;
+
+    // The client proves against the root current when it fetched the Merkle inputs
+    let pool_deposit = get_pool_deposit(&mut rpc).await;
+    let stale = fetch_merkle_inputs(&mut rpc, pool_deposit.hash)
+        .await
+        .unwrap();
+
+    // A few deposits later the root is stale but still in the history
+    deposit_many(
+        &mut rpc,
+        &payer,
+        &mint,
+        &user_token_account,
+        &stale.merkle_tree,
+        0..8,
+    )
+    .await;
+    let current = fetch_merkle_inputs(&mut rpc, pool_deposit.hash)
+        .await
+        .unwrap();
+    assert_eq!(current.merkle_tree, stale.merkle_tree);
+    assert_ne!(current.root, stale.root);
+    assert_ne!(current.root_index, stale.root_index);
+
+    let root_snapshot = initialize_root_snapshot(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &stale.merkle_tree,
+        stale.root_index,
+    )
+    .await
+    .unwrap();
+    let account = rpc.get_account(root_snapshot).await.unwrap().unwrap();
+    let snapshot = RootSnapshot::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_eq!(snapshot.root, stale.root);
+
+    let mut nullifier_hash = [11u8; 32];
+    nullifier_hash[0] = 0;
+    withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Snapshot(root_snapshot),
+        nullifier_hash,
+        Some(stale.root),
+    )
+    .await
+    .unwrap();
+
+    // Once a full history of roots has been written, the stale root's slot is reused
+    deposit_many(
+        &mut rpc,
+        &payer,
+        &mint,
+        &user_token_account,
+        &stale.merkle_tree,
+        8..8 + ROOT_HISTORY_SIZE,
+    )
+    .await;
+    snapshot_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &stale.merkle_tree,
+        stale.root_index,
+    )
+    .await
+    .unwrap();
+    let account = rpc.get_account(root_snapshot).await.unwrap().unwrap();
+    let snapshot = RootSnapshot::try_deserialize(&mut account.data.as_slice()).unwrap();
+    assert_ne!(snapshot.root, stale.root);
+
+    nullifier_hash[1] = 1;
+    let result = withdraw_with_root(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        RootAccount::Snapshot(root_snapshot),
+        nullifier_hash,
+        Some(stale.root),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidMerkleRoot.into()).unwrap();
+}
+
+#[tokio::test]
+async fn test_merkle_tree_allowlist() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, mint, user_token_account) = setup_token_pool(&mut rpc, &payer).await;
+
+    let pool_deposit = get_pool_deposit(&mut rpc).await;
+    let merkle_tree = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await
+        .unwrap()
+        .value
+        .items
+        .remove(0)
+        .merkle_tree;
+
+    // Only the admin sets the allowlist, and it is bounded
+    let other = Keypair::new();
+    rpc.airdrop_lamports(&other.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let result = set_merkle_tree_allowlist(&mut rpc, &other, &config_pda, vec![merkle_tree]).await;
+    assert_rpc_error(result, 0, ErrorCode::Unauthorized.into()).unwrap();
+    let too_long = vec![Pubkey::new_unique(); MAX_ALLOWLISTED_TREES + 1];
+    let result = set_merkle_tree_allowlist(&mut rpc, &payer, &config_pda, too_long).await;
+    assert_rpc_error(result, 0, ErrorCode::MerkleTreeAllowlistTooLong.into()).unwrap();
+
+    // A tree off the list is rejected before the proof is checked
+    set_merkle_tree_allowlist(&mut rpc, &payer, &config_pda, vec![Pubkey::new_unique()])
+        .await
+        .unwrap();
+    let mut nullifier_hash = [6u8; 32];
+    nullifier_hash[0] = 0;
+    let result = withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        100_000,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::MerkleTreeNotAllowed.into()).unwrap();
+
+    // Deposits into a tree off the list are rejected too, not only reads from it
+    let mut commitment = [6u8; 32];
+    commitment[0] = 0;
+    let result = deposit_token(
+        &mut rpc,
+        &payer,
+        &payer,
+        &mint,
+        &user_token_account,
+        commitment,
+        1,
+        Some(&merkle_tree),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::MerkleTreeNotAllowed.into()).unwrap();
+
+    set_merkle_tree_allowlist(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        vec![Pubkey::new_unique(), merkle_tree],
+    )
+    .await
+    .unwrap();
+    deposit_token(
+        &mut rpc,
+        &payer,
+        &payer,
+        &mint,
+        &user_token_account,
+        commitment,
+        1,
+        Some(&merkle_tree),
+    )
+    .await
+    .unwrap();
+    withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        100_000,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_withdrawal_window_and_deposit_state_tree() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let (config_pda, mint, user_token_account) = setup_token_pool(&mut rpc, &payer).await;
+
+    // Withdrawals stay closed until the config's launch slot
+    let open_at = rpc.get_slot().await.unwrap() + 100;
+    set_withdrawals_enabled_at_slot(&mut rpc, &payer, &config_pda, open_at)
+        .await
+        .unwrap();
+    let mut nullifier_hash = [12u8; 32];
+    nullifier_hash[0] = 0;
+    let result = withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        100_000,
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::WithdrawalsNotYetEnabled.into()).unwrap();
+
+    rpc.warp_to_slot(open_at).unwrap();
+    withdraw_token(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        &mint,
+        &user_token_account,
+        nullifier_hash,
+        100_000,
+    )
+    .await
+    .unwrap();
+
+    // A pinned deposit state tree rejects deposits into any other tree
+    let state_tree = rpc.get_random_state_tree_info_v1().unwrap().tree;
+    set_deposit_state_tree(&mut rpc, &payer, &config_pda, Some(Pubkey::new_unique()))
+        .await
+        .unwrap();
+    let mut commitment = [13u8; 32];
+    commitment[0] = 0;
+    let result = deposit(&mut rpc, &payer, &config_pda, commitment, 1_000_000, None).await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidDepositStateTree.into()).unwrap();
+    let result = deposit_token(
+        &mut rpc,
+        &payer,
+        &payer,
+        &mint,
+        &user_token_account,
+        commitment,
+        1,
+        Some(&state_tree),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidDepositStateTree.into()).unwrap();
+
+    set_deposit_state_tree(&mut rpc, &payer, &config_pda, Some(state_tree))
+        .await
+        .unwrap();
+    deposit(&mut rpc, &payer, &config_pda, commitment, 1_000_000, None)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_deposit_stream() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    // Two deposits, each note recoverable from the stream seed
+    let seed = [3u8; 32];
+    let commitments: Vec<[u8; 32]> = (0..2)
+        .map(|index| {
+            let (nullifier, secret) = derive_stream_note(&seed, index).unwrap();
+            compute_commitment(&nullifier, &secret).unwrap()
+        })
+        .collect();
+    let amount = 100_000_000;
+    let interval_slots = 10;
+    let stream = create_stream(
+        &mut rpc,
+        &payer,
+        1,
+        amount,
+        interval_slots,
+        commitments.clone(),
+    )
+    .await
+    .unwrap();
+
+    // Anyone may crank a due deposit
+    let cranker = Keypair::new();
+    rpc.airdrop_lamports(&cranker.pubkey(), 1_000_000_000)
+        .await
+        .unwrap();
+    let (vault, _) = Pubkey::find_program_address(&[b"vault"], &privacy_vault::ID);
+    let vault_before = rpc.get_balance(&vault).await.unwrap();
+    crank_stream(&mut rpc, &cranker, &config_pda, &stream)
+        .await
+        .unwrap();
+    assert_eq!(
+        rpc.get_balance(&vault).await.unwrap(),
+        vault_before + amount
+    );
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) = derive_address(
+        &[DEPOSIT, &commitments[0]],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+    let deposit_account = rpc
+        .get_compressed_account(address, None)
+        .await
+        .unwrap()
+        .value
+        .expect("Stream deposit not found");
+    let deposit: DepositAccount = anchor_lang::AnchorDeserialize::deserialize(
+        &mut deposit_account.data.as_ref().unwrap().data.as_slice(),
+    )
+    .unwrap();
+    assert_eq!(deposit.amount, amount);
+
+    // The next deposit waits for its interval, and the schedule ends with the commitments
+    let result = crank_stream(&mut rpc, &cranker, &config_pda, &stream).await;
+    assert_rpc_error(result, 0, ErrorCode::StreamNotDue.into()).unwrap();
+    let slot = rpc.get_slot().await.unwrap();
+    rpc.warp_to_slot(slot + interval_slots).unwrap();
+    crank_stream(&mut rpc, &cranker, &config_pda, &stream)
+        .await
+        .unwrap();
+    let slot = rpc.get_slot().await.unwrap();
+    rpc.warp_to_slot(slot + interval_slots).unwrap();
+    let result = crank_stream(&mut rpc, &cranker, &config_pda, &stream).await;
+    assert_rpc_error(result, 0, ErrorCode::StreamExhausted.into()).unwrap();
+    assert_eq!(
+        rpc.get_balance(&vault).await.unwrap(),
+        vault_before + 2 * amount
+    );
+
+    // Only the depositor can cancel, which closes the stream
+    let result = cancel_stream(&mut rpc, &cranker, &stream).await;
+    assert!(result.is_err(), "Only the depositor can cancel a stream");
+    cancel_stream(&mut rpc, &payer, &stream).await.unwrap();
+    assert!(rpc.get_account(stream).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_deposit_opening_checks_commitment() {
+    let config = ProgramTestConfig::new(true, Some(vec![("privacy_vault", privacy_vault::ID)]));
+    let mut rpc = LightProgramTest::new(config).await.unwrap();
+    let payer = rpc.get_payer().insecure_clone();
+    let config_pda = initialize_config(&mut rpc, &payer).await;
+
+    let opening = NoteOpening {
+        nullifier: [1u8; 32],
+        secret: [2u8; 32],
+    };
+    let commitment = compute_commitment(&opening.nullifier, &opening.secret).unwrap();
+    // The circuits hash (nullifier, secret); a client with the order swapped never could
+    // withdraw its deposit
+    let swapped = compute_commitment(&opening.secret, &opening.nullifier).unwrap();
+
+    // Openings reveal the note, so they are refused outside dev mode
+    let result = deposit(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        commitment,
+        1_000_000,
+        Some(opening.clone()),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::DevModeDisabled.into()).unwrap();
+
+    set_dev_mode(&mut rpc, &payer, &config_pda, true)
+        .await
+        .unwrap();
+    let result = deposit(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        swapped,
+        1_000_000,
+        Some(opening.clone()),
+    )
+    .await;
+    assert_rpc_error(result, 0, ErrorCode::InvalidCommitment.into()).unwrap();
+
+    deposit(
+        &mut rpc,
+        &payer,
+        &config_pda,
+        commitment,
+        1_000_000,
+        Some(opening),
+    )
+    .await
+    .unwrap();
+}
+
+/// Commitment of the token deposit `setup_token_pool` makes
+const POOL_COMMITMENT: [u8; 32] = {
+    let mut commitment = [5u8; 32];
+    commitment[0] = 0;
+    commitment
+};
+
+/// Initializes the config and funds a token vault with a 500_000 deposit
+/// Returns the config PDA, the mint and the payer's token account (500_000 left).
+async fn setup_token_pool(rpc: &mut LightProgramTest, payer: &Keypair) -> (Pubkey, Pubkey, Pubkey) {
+    let config_pda = initialize_config(rpc, payer).await;
+
+    // Create a mint and fund the vault through a token deposit
+    let mint = Keypair::new();
+    let rent = rpc
+        .get_minimum_balance_for_rent_exemption(spl_token::state::Mint::LEN)
+        .await
+        .unwrap();
+    let user_token_account =
+        spl_associated_token_account::get_associated_token_address(&payer.pubkey(), &mint.pubkey());
+    let setup_instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            rent,
+            spl_token::state::Mint::LEN as u64,
+            &spl_token::ID,
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::ID,
+            &mint.pubkey(),
+            &payer.pubkey(),
+            None,
+            6,
+        )
+        .unwrap(),
+        spl_associated_token_account::instruction::create_associated_token_account(
+            &payer.pubkey(),
+            &payer.pubkey(),
+            &mint.pubkey(),
+            &spl_token::ID,
+        ),
+        spl_token::instruction::mint_to(
+            &spl_token::ID,
+            &mint.pubkey(),
+            &user_token_account,
+            &payer.pubkey(),
+            &[],
+            1_000_000,
+        )
+        .unwrap(),
+    ];
+    rpc.create_and_send_transaction(&setup_instructions, &payer.pubkey(), &[payer, &mint])
+        .await
+        .unwrap();
+
+    initialize_token_vault(rpc, payer, &mint.pubkey())
+        .await
+        .unwrap();
+    deposit_token(
+        rpc,
+        payer,
+        payer,
+        &mint.pubkey(),
+        &user_token_account,
+        POOL_COMMITMENT,
+        500_000,
+        None,
+    )
+    .await
+    .unwrap();
+
+    (config_pda, mint.pubkey(), user_token_account)
+}
+
+/// Sends one 1-token deposit into `state_tree` per index in `indices`
+async fn deposit_many<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    mint: &Pubkey,
+    user_token_account: &Pubkey,
+    state_tree: &Pubkey,
+    indices: std::ops::Range<u64>,
+) where
+    R: Rpc + Indexer,
+{
+    for i in indices {
+        let mut commitment = [0u8; 32];
+        commitment[1..9].copy_from_slice(&i.to_be_bytes());
+        commitment[31] = 1;
+        deposit_token(
+            rpc,
+            payer,
+            payer,
+            mint,
+            user_token_account,
+            commitment,
+            1,
+            Some(state_tree),
+        )
+        .await
+        .unwrap();
+    }
+}
+
+async fn token_balance<R>(rpc: &mut R, token_account: &Pubkey) -> u64
+where
+    R: Rpc + Indexer,
+{
+    let account = rpc.get_account(*token_account).await.unwrap().unwrap();
+    spl_token::state::Account::unpack(&account.data)
+        .unwrap()
+        .amount
+}
+
+/// The token deposit `setup_token_pool` makes; its state tree supplies the spendable roots
+async fn get_pool_deposit<R>(rpc: &mut R) -> CompressedAccount
+where
+    R: Rpc + Indexer,
+{
+    rpc.get_compressed_accounts_by_owner(&privacy_vault::ID, None, None)
+        .await
+        .unwrap()
+        .value
+        .items
+        .into_iter()
+        .find(|account| {
+            account
+                .data
+                .as_ref()
+                .is_some_and(|data| data.data.starts_with(&POOL_COMMITMENT))
+        })
+        .expect("Pool deposit not found")
+}
+
+/// Withdraws with the mock proof; the pool deposit's state tree supplies the spendable root
+async fn withdraw_token<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    mint: &Pubkey,
+    recipient_token_account: &Pubkey,
+    nullifier_hash: [u8; 32],
+    amount: u64,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let pool_deposit = get_pool_deposit(rpc).await;
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+    let root_index = root_index_for(merkle_proof.root_seq);
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (nullifier_address, _) =
+        derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_info.tree);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address: nullifier_address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+    let instruction_data = privacy_vault::instruction::WithdrawToken {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_index: root_index,
+        nullifier_hash,
+        amount,
+        zk_proof: light_sdk::instruction::CompressedProof { a, b, c },
+    };
+
+    let (vault_authority, _) =
+        Pubkey::find_program_address(&[b"vault_authority", mint.as_ref()], &privacy_vault::ID);
+    let accounts = privacy_vault::accounts::WithdrawTokenAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        input_merkle_tree: merkle_proof.merkle_tree,
+        vault_token_account: vault_token_address(mint),
+        recipient_token_account: *recipient_token_account,
+        vault_authority,
+        token_program: spl_token::ID,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// Withdraws `amount` lamports from the SOL vault to `recipient` with the mock proof
+/// against the current root of the pool deposit's state tree
+async fn withdraw_sol<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    recipient: &Pubkey,
+    nullifier_hash: [u8; 32],
+    amount: u64,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let pool_deposit = get_pool_deposit(rpc).await;
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+    let root_index = root_index_for(merkle_proof.root_seq);
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (nullifier_address, _) =
+        derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_info.tree);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address: nullifier_address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+    let instruction_data = privacy_vault::instruction::WithdrawSol {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_index: root_index,
+        nullifier_hash,
+        amount,
+        zk_proof: light_sdk::instruction::CompressedProof { a, b, c },
+    };
+
+    let (vault, _) = Pubkey::find_program_address(&[b"vault"], &privacy_vault::ID);
+    let accounts = privacy_vault::accounts::WithdrawSolAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        input_merkle_tree: merkle_proof.merkle_tree,
+        vault,
+        recipient: *recipient,
+        system_program: solana_sdk::system_program::ID,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+async fn set_nullifier_ttl<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    nullifier_ttl: u64,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetNullifierTtl { nullifier_ttl }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+async fn set_withdrawals_enabled_at_slot<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    withdrawals_enabled_at_slot: u64,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetWithdrawalsEnabledAtSlot {
+            withdrawals_enabled_at_slot,
+        }
+        .data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+async fn set_deposit_state_tree<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    deposit_state_tree: Option<Pubkey>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetDepositStateTree { deposit_state_tree }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+/// Records a native `DepositAccount` for `commitment` in the default pool
+async fn deposit<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    commitment: [u8; 32],
+    amount: u64,
+    opening: Option<NoteOpening>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) = derive_address(
+        &[DEPOSIT, &commitment],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = privacy_vault::instruction::Deposit {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        commitment,
+        amount,
+        view_tag: None,
+        encrypted_note: None,
+        reference: None,
+        opening,
+        pool_id: 0,
+    };
+
+    let accounts = privacy_vault::accounts::DepositAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        pool: None,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+async fn set_merkle_tree_allowlist<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    merkle_trees: Vec<Pubkey>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetMerkleTreeAllowlist { merkle_trees }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+async fn set_oracle_owner_allowlist<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    oracle_owners: Vec<Pubkey>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetOracleOwnerAllowlist { oracle_owners }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+async fn gc_nullifier<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    nullifier_account: &CompressedAccount,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![nullifier_account.hash], vec![], None)
+        .await?
+        .value;
+    let packed_state_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .state_trees
+        .unwrap();
+
+    let nullifier_data = nullifier_account.data.as_ref().unwrap();
+    let nullifier: NullifierAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut nullifier_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = privacy_vault::instruction::GcNullifier {
+        proof: rpc_result.proof,
+        system_accounts_offset: system_accounts_offset as u8,
+        nullifier: NullifierState {
+            account_meta: CompressedAccountMeta {
+                tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+                address: nullifier_account.address.unwrap(),
+                output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+            },
+            nullifier_hash: nullifier.nullifier_hash,
+            used_at: nullifier.used_at,
+            expires_at: nullifier.expires_at,
+            merkle_tree: nullifier.merkle_tree,
+            root_index: nullifier.root_index,
+            root: nullifier.root,
+        },
+    };
+
+    let accounts = privacy_vault::accounts::GcNullifierAccounts {
+        signer: signer.pubkey(),
+        merkle_tree: nullifier.merkle_tree,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+async fn set_innocence_proof_ttl<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    innocence_proof_ttl: u64,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::AdminAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SetInnocenceProofTtl {
+            innocence_proof_ttl,
+        }
+        .data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+/// Sends `prove_innocence` as the nullifier's first proof, which creates its bitmap,
+/// against the current root of the pool deposit's state tree
+async fn prove_innocence<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    association_set_id: u16,
+    nullifier_hash: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let pool_deposit = get_pool_deposit(rpc).await;
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (proof_address, _) = derive_address(
+        &[
+            INNOCENCE_PROOF,
+            &nullifier_hash,
+            &association_set_id.to_le_bytes(),
+        ],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+    let (bitmap_address, _) = derive_address(
+        &[INNOCENCE_BITMAP, &nullifier_hash],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    // Sets without a bitmap bit create the innocence record only
+    let mut new_addresses = vec![AddressWithTree {
+        address: proof_address,
+        tree: address_tree_info.tree,
+    }];
+    if association_set_id < BITMAP_ASSOCIATION_SETS {
+        new_addresses.push(AddressWithTree {
+            address: bitmap_address,
+            tree: address_tree_info.tree,
+        });
+    }
+    let rpc_result = rpc
+        .get_validity_proof(vec![], new_addresses, None)
+        .await?
+        .value;
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+    let instruction_data = privacy_vault::instruction::ProveInnocence {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_index: root_index_for(merkle_proof.root_seq),
+        association_set_root: [0u8; 32],
+        nullifier_hash,
+        association_set_id,
+        innocence_bitmap: None,
+        zk_proof: light_sdk::instruction::CompressedProof { a, b, c },
+    };
+
+    send_prove_innocence(
+        rpc,
+        payer,
+        config_pda,
+        merkle_proof.merkle_tree,
+        instruction_data,
+        remaining_accounts_metas,
+    )
+    .await
+}
+
+/// Sends `reprove_innocence` for the record in `proof_account` against the current root of
+/// the pool deposit's state tree
+async fn reprove_innocence<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    proof_account: &CompressedAccount,
+    association_set_root: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let pool_deposit = get_pool_deposit(rpc).await;
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![proof_account.hash], vec![], None)
+        .await?
+        .value;
+    let packed_state_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .state_trees
+        .unwrap();
+
+    let proof_data = proof_account.data.as_ref().unwrap();
+    let record: InnocenceProofAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut proof_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+    let instruction_data = privacy_vault::instruction::ReproveInnocence {
+        proof: rpc_result.proof,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_index: root_index_for(merkle_proof.root_seq),
+        association_set_root,
+        innocence_proof: InnocenceProofState {
+            account_meta: CompressedAccountMeta {
+                tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+                address: proof_account.address.unwrap(),
+                output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+            },
+            nullifier_hash: record.nullifier_hash,
+            association_set_id: record.association_set_id,
+            proven_at: record.proven_at,
+            expires_at: record.expires_at,
+        },
+        zk_proof: light_sdk::instruction::CompressedProof { a, b, c },
+    };
+
+    let accounts = privacy_vault::accounts::ReproveInnocenceAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        deposit_merkle_tree: merkle_proof.merkle_tree,
+        association_set: association_set_pda(record.association_set_id),
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+async fn close_innocence_proof<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    proof_account: &CompressedAccount,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(vec![proof_account.hash], vec![], None)
+        .await?
+        .value;
+    let packed_state_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .state_trees
+        .unwrap();
+
+    let proof_data = proof_account.data.as_ref().unwrap();
+    let record: InnocenceProofAccount =
+        anchor_lang::AnchorDeserialize::deserialize(&mut proof_data.data.as_slice()).unwrap();
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = privacy_vault::instruction::CloseInnocenceProof {
+        proof: rpc_result.proof,
+        system_accounts_offset: system_accounts_offset as u8,
+        innocence_proof: InnocenceProofState {
+            account_meta: CompressedAccountMeta {
+                tree_info: packed_state_tree_accounts.packed_tree_infos[0],
+                address: proof_account.address.unwrap(),
+                output_state_tree_index: packed_state_tree_accounts.output_tree_index,
+            },
+            nullifier_hash: record.nullifier_hash,
+            association_set_id: record.association_set_id,
+            proven_at: record.proven_at,
+            expires_at: record.expires_at,
+        },
+    };
+
+    let accounts = privacy_vault::accounts::GenericAnchorAccounts {
+        signer: signer.pubkey(),
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+/// Creates the payer's snapshot of `merkle_tree` and returns its address
+async fn initialize_root_snapshot<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    merkle_tree: &Pubkey,
+    root_index: u16,
+) -> Result<Pubkey, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let (root_snapshot, _) = Pubkey::find_program_address(
+        &[ROOT_SNAPSHOT, merkle_tree.as_ref(), payer.pubkey().as_ref()],
+        &privacy_vault::ID,
+    );
+    let accounts = privacy_vault::accounts::InitializeRootSnapshotAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        merkle_tree: *merkle_tree,
+        root_snapshot,
+        system_program: solana_sdk::system_program::ID,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::InitializeRootSnapshot { root_index }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await?;
+    Ok(root_snapshot)
+}
+
+async fn snapshot_root<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    merkle_tree: &Pubkey,
+    root_index: u16,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let (root_snapshot, _) = Pubkey::find_program_address(
+        &[ROOT_SNAPSHOT, merkle_tree.as_ref(), payer.pubkey().as_ref()],
+        &privacy_vault::ID,
+    );
+    let accounts = privacy_vault::accounts::SnapshotRootAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        merkle_tree: *merkle_tree,
+        root_snapshot,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::SnapshotRoot { root_index }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// Creates the shared `RootSnapshotRing` of `merkle_tree` and returns its address
+async fn initialize_root_snapshot_ring<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    merkle_tree: &Pubkey,
+    root_index: u16,
+) -> Result<Pubkey, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let (root_snapshot_ring, _) = Pubkey::find_program_address(
+        &[ROOT_SNAPSHOT_RING, merkle_tree.as_ref()],
+        &privacy_vault::ID,
+    );
+    let accounts = privacy_vault::accounts::InitializeRootSnapshotRingAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        merkle_tree: *merkle_tree,
+        root_snapshot_ring,
+        system_program: solana_sdk::system_program::ID,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::InitializeRootSnapshotRing { root_index }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await?;
+    Ok(root_snapshot_ring)
+}
+
+async fn crank_merkle_snapshot<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    merkle_tree: &Pubkey,
+    root_index: u16,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let (root_snapshot_ring, _) = Pubkey::find_program_address(
+        &[ROOT_SNAPSHOT_RING, merkle_tree.as_ref()],
+        &privacy_vault::ID,
+    );
+    let accounts = privacy_vault::accounts::CrankMerkleSnapshotAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+        merkle_tree: *merkle_tree,
+        root_snapshot_ring,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::CrankMerkleSnapshot { root_index }.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+/// Sends `withdraw` with `recipient: payer` against the pool deposit tree's current root,
+/// paying out to `recipient_account`
+async fn withdraw<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    recipient_account: &Pubkey,
+    nullifier_hash: [u8; 32],
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let pool_deposit = get_pool_deposit(rpc).await;
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (nullifier_address, _) =
+        derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_info.tree);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address: nullifier_address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    // Spend against the root the pool deposit's tree holds right now
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+    let instruction_data = privacy_vault::instruction::Withdraw {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_index: root_index_for(merkle_proof.root_seq),
+        nullifier_hash,
+        recipient: payer.pubkey(),
+        pool_id: 0,
+        recipient_proof: None,
+        zk_proof: light_sdk::instruction::CompressedProof { a, b, c },
+    };
+
+    let accounts = privacy_vault::accounts::WithdrawRecipientAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        input_merkle_tree: merkle_proof.merkle_tree,
+        recipient: *recipient_account,
+        pool: None,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// `withdraw` with the mock proof, gated on `oracle` satisfying `predicate`
+async fn withdraw_conditional<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    recipient_account: &Pubkey,
+    nullifier_hash: [u8; 32],
+    oracle: &Pubkey,
+    predicate: OraclePredicate,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let pool_deposit = get_pool_deposit(rpc).await;
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (nullifier_address, _) =
+        derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_info.tree);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address: nullifier_address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    // Spend against the root the pool deposit's tree holds right now
+    let merkle_proof = rpc
+        .get_multiple_compressed_account_proofs(vec![pool_deposit.hash], None)
+        .await?
+        .value
+        .items
+        .remove(0);
+
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+    let instruction_data = privacy_vault::instruction::WithdrawConditional {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+        input_root_index: root_index_for(merkle_proof.root_seq),
+        nullifier_hash,
+        recipient: payer.pubkey(),
+        zk_proof: light_sdk::instruction::CompressedProof { a, b, c },
+        predicate,
+    };
+
+    let accounts = privacy_vault::accounts::WithdrawConditionalAccounts {
+        signer: payer.pubkey(),
+        config: *config_pda,
+        input_merkle_tree: merkle_proof.merkle_tree,
+        recipient: *recipient_account,
+        oracle: *oracle,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// The account `withdraw_with_root` reads its root from
+enum RootAccount {
+    /// A `RootSnapshot`
+    Snapshot(Pubkey),
+    /// A tree's `RootSnapshotRing`, only read by `withdraw_explicit_root`
+    Ring(Pubkey),
+}
+
+/// Withdraws with the mock proof against the root held in `root_account`, or with
+/// `withdraw_explicit_root` asserting `explicit_root` when it is set
+async fn withdraw_with_root<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    config_pda: &Pubkey,
+    root_account: RootAccount,
+    nullifier_hash: [u8; 32],
+    explicit_root: Option<[u8; 32]>,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let pool_deposit = get_pool_deposit(rpc).await;
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (nullifier_address, _) =
+        derive_nullifier_address(&nullifier_hash, &NULLIFIER_DOMAIN, &address_tree_info.tree);
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address: nullifier_address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let (a, b, c) = convert_proof_to_compressed(&MOCK_PROOF_A, &MOCK_PROOF_B, &MOCK_PROOF_C)
+        .expect("Failed to compress mock proof");
+    let zk_proof = light_sdk::instruction::CompressedProof { a, b, c };
+    let instruction_data = match explicit_root {
+        Some(root) => privacy_vault::instruction::WithdrawExplicitRoot {
+            proof: rpc_result.proof,
+            address_tree_info: packed_address_tree_accounts[0],
+            output_state_tree_index,
+            system_accounts_offset: system_accounts_offset as u8,
+            root,
+            nullifier_hash,
+            recipient: payer.pubkey(),
+            zk_proof,
+        }
+        .data(),
+        None => privacy_vault::instruction::WithdrawWithRoot {
+            proof: rpc_result.proof,
+            address_tree_info: packed_address_tree_accounts[0],
+            output_state_tree_index,
+            system_accounts_offset: system_accounts_offset as u8,
+            nullifier_hash,
+            recipient: payer.pubkey(),
+            zk_proof,
+        }
+        .data(),
+    };
+
+    let account_metas = match (explicit_root, root_account) {
+        (None, RootAccount::Snapshot(root_snapshot)) => {
+            privacy_vault::accounts::WithdrawWithRootAccounts {
+                signer: payer.pubkey(),
+                config: *config_pda,
+                root_snapshot,
+            }
+            .to_account_metas(None)
+        }
+        (None, RootAccount::Ring(_)) => panic!("withdraw_with_root only reads a RootSnapshot"),
+        (Some(_), root_account) => {
+            let (root_snapshot, root_snapshot_ring) = match root_account {
+                RootAccount::Snapshot(root_snapshot) => (Some(root_snapshot), None),
+                RootAccount::Ring(ring) => (None, Some(ring)),
+            };
+            privacy_vault::accounts::WithdrawExplicitRootAccounts {
+                signer: payer.pubkey(),
+                config: *config_pda,
+                root_snapshot,
+                root_snapshot_ring,
+            }
+            .to_account_metas(None)
+        }
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [account_metas, remaining_accounts_metas].concat(),
+        data: instruction_data,
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await
+}
+
+/// Creates and funds the payer's stream `stream_id` and returns its address
+async fn create_stream<R>(
+    rpc: &mut R,
+    payer: &Keypair,
+    stream_id: u64,
+    amount_per_interval: u64,
+    interval_slots: u64,
+    commitments: Vec<[u8; 32]>,
+) -> Result<Pubkey, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let (stream, _) = Pubkey::find_program_address(
+        &[STREAM, payer.pubkey().as_ref(), &stream_id.to_le_bytes()],
+        &privacy_vault::ID,
+    );
+    let accounts = privacy_vault::accounts::CreateStreamAccounts {
+        depositor: payer.pubkey(),
+        stream,
+        system_program: solana_sdk::system_program::ID,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::CreateStream {
+            stream_id,
+            amount_per_interval,
+            interval_slots,
+            commitments,
+        }
+        .data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &payer.pubkey(), &[payer])
+        .await?;
+    Ok(stream)
+}
+
+/// Cranks the next deposit of `stream` into the default pool
+async fn crank_stream<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    config_pda: &Pubkey,
+    stream: &Pubkey,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let account = rpc.get_account(*stream).await?.expect("Stream not found");
+    let stream_account = StreamAccount::try_deserialize(&mut account.data.as_slice()).unwrap();
+    // An exhausted stream still needs a well-formed transaction to reach its error
+    let commitment = stream_account
+        .commitments
+        .get(stream_account.deposits_made as usize)
+        .copied()
+        .unwrap_or([1u8; 32]);
+
+    let address_tree_info = rpc.get_address_tree_v2();
+    let (address, _) = derive_address(
+        &[DEPOSIT, &commitment],
+        &address_tree_info.tree,
+        &privacy_vault::ID,
+    );
+
+    let mut remaining_accounts = PackedAccounts::default();
+    let config = SystemAccountMetaConfig::new(privacy_vault::ID);
+    remaining_accounts.add_system_accounts_v2(config)?;
+
+    let rpc_result = rpc
+        .get_validity_proof(
+            vec![],
+            vec![AddressWithTree {
+                address,
+                tree: address_tree_info.tree,
+            }],
+            None,
+        )
+        .await?
+        .value;
+
+    let packed_address_tree_accounts = rpc_result
+        .pack_tree_infos(&mut remaining_accounts)
+        .address_trees;
+
+    let output_state_tree_index = rpc
+        .get_random_state_tree_info_v1()?
+        .pack_output_tree_index(&mut remaining_accounts)?;
+
+    let (remaining_accounts_metas, system_accounts_offset, _) =
+        remaining_accounts.to_account_metas();
+
+    let instruction_data = privacy_vault::instruction::CrankStream {
+        proof: rpc_result.proof,
+        address_tree_info: packed_address_tree_accounts[0],
+        output_state_tree_index,
+        system_accounts_offset: system_accounts_offset as u8,
+    };
+
+    let (vault, _) = Pubkey::find_program_address(&[b"vault"], &privacy_vault::ID);
+    let accounts = privacy_vault::accounts::CrankStreamAccounts {
+        signer: signer.pubkey(),
+        config: *config_pda,
+        stream: *stream,
+        vault,
+    };
+
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: [accounts.to_account_metas(None), remaining_accounts_metas].concat(),
+        data: instruction_data.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
+
+async fn cancel_stream<R>(
+    rpc: &mut R,
+    signer: &Keypair,
+    stream: &Pubkey,
+) -> Result<Signature, RpcError>
+where
+    R: Rpc + Indexer,
+{
+    let accounts = privacy_vault::accounts::CancelStreamAccounts {
+        depositor: signer.pubkey(),
+        stream: *stream,
+    };
+    let instruction = Instruction {
+        program_id: privacy_vault::ID,
+        accounts: accounts.to_account_metas(None),
+        data: privacy_vault::instruction::CancelStream {}.data(),
+    };
+
+    rpc.create_and_send_transaction(&[instruction], &signer.pubkey(), &[signer])
+        .await
+}
@@ -0,0 +1,146 @@
+// Checks the verifying key constants against the snarkjs `verification_key.json` they were
+// generated from. Only the JSON is read, so these run without the zkey or witness libraries.
+// A key whose JSON has not been exported yet, or that still ships as a placeholder, is skipped.
+
+use groth16_solana::groth16::Groth16Verifyingkey;
+use num_bigint::BigUint;
+use serde_json::Value;
+
+/// A decimal field element from the JSON as 32 big-endian bytes
+fn field_bytes(value: &Value) -> [u8; 32] {
+    let decimal = value.as_str().expect("field element is a decimal string");
+    let digits = BigUint::parse_bytes(decimal.as_bytes(), 10)
+        .expect("field element is decimal")
+        .to_bytes_be();
+    assert!(digits.len() <= 32, "field element exceeds 32 bytes");
+    let mut bytes = [0u8; 32];
+    bytes[32 - digits.len()..].copy_from_slice(&digits);
+    bytes
+}
+
+/// `[x, y, z]` affine G1 point as `x || y`
+fn g1_bytes(point: &Value) -> [u8; 64] {
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(&field_bytes(&point[0]));
+    bytes[32..].copy_from_slice(&field_bytes(&point[1]));
+    bytes
+}
+
+/// `[[x_c0, x_c1], [y_c0, y_c1], z]` G2 point as `x_c1 || x_c0 || y_c1 || y_c0`
+fn g2_bytes(point: &Value) -> [u8; 128] {
+    let mut bytes = [0u8; 128];
+    bytes[..32].copy_from_slice(&field_bytes(&point[0][1]));
+    bytes[32..64].copy_from_slice(&field_bytes(&point[0][0]));
+    bytes[64..96].copy_from_slice(&field_bytes(&point[1][1]));
+    bytes[96..].copy_from_slice(&field_bytes(&point[1][0]));
+    bytes
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// Asserts every constant of `key` matches the JSON at `path`
+fn assert_key_matches_json(key: &Groth16Verifyingkey, path: &str) {
+    if key.vk_delta_g2 == [0u8; 128] {
+        println!("Placeholder key for {}, skipping", path);
+        return;
+    }
+    let Ok(json) = std::fs::read_to_string(path) else {
+        println!("{} not found, skipping", path);
+        return;
+    };
+    let vkey: Value = serde_json::from_str(&json).expect("verification key is JSON");
+
+    assert_eq!(
+        key.nr_pubinputs as u64,
+        vkey["nPublic"].as_u64().unwrap(),
+        "{}: nPublic",
+        path
+    );
+    assert_eq!(
+        key.vk_alpha_g1,
+        g1_bytes(&vkey["vk_alpha_1"]),
+        "{}: alpha",
+        path
+    );
+    assert_eq!(
+        key.vk_beta_g2,
+        g2_bytes(&vkey["vk_beta_2"]),
+        "{}: beta",
+        path
+    );
+    assert_eq!(
+        key.vk_gamma_g2,
+        g2_bytes(&vkey["vk_gamma_2"]),
+        "{}: gamma",
+        path
+    );
+    assert_eq!(
+        key.vk_delta_g2,
+        g2_bytes(&vkey["vk_delta_2"]),
+        "{}: delta",
+        path
+    );
+
+    let ic = vkey["IC"].as_array().unwrap();
+    assert_eq!(key.vk_ic.len(), ic.len(), "{}: IC length", path);
+    for (i, (expected, point)) in key.vk_ic.iter().zip(ic).enumerate() {
+        assert_eq!(*expected, g1_bytes(point), "{}: IC[{}]", path, i);
+    }
+}
+
+#[test]
+fn test_point_encoding_vectors() {
+    // BN254 generators, in the snarkjs JSON layout
+    let g1: Value = serde_json::from_str(r#"["1", "2", "1"]"#).unwrap();
+    let g2: Value = serde_json::from_str(
+        r#"[
+            ["10857046999023057135944570762232829481370756359578518086990519993285655852781",
+             "11559732032986387107991004021392285783925812861821192530917403151452391805634"],
+            ["8495653923123431417604973247489272438418190587263600148770280649306958101930",
+             "4082367875863433681332203403145435568316851327593401208105741076214120093531"],
+            ["1", "0"]
+        ]"#,
+    )
+    .unwrap();
+
+    let mut expected_g1 = [0u8; 64];
+    expected_g1[31] = 1;
+    expected_g1[63] = 2;
+    assert_eq!(g1_bytes(&g1), expected_g1);
+
+    // The imaginary part comes first, as the alt_bn128 syscalls expect
+    let expected_g2 = from_hex(concat!(
+        "198e9393920d483a7260bfb731fb5d25f1aa493335a9e71297e485b7aef312c2",
+        "1800deef121f1e76426a00665e5c4479674322d4f75edadd46debd5cd992f6ed",
+        "090689d0585ff075ec9e99ad690c3395bc4b313370b38ef355acdadcd122975b",
+        "12c85ea5db8c6deb4aab71808dcb408fe3d1e7690c43d37b4ce6cc0166fa7daa",
+    ));
+    assert_eq!(g2_bytes(&g2).to_vec(), expected_g2);
+}
+
+#[test]
+fn test_zk_id_verifying_key_matches_json() {
+    // Written by build.rs from this file
+    assert_key_matches_json(
+        &zk_id::verifying_key::VERIFYINGKEY,
+        "./build/verification_key.json",
+    );
+}
+
+#[test]
+fn test_vault_verifying_keys_match_json() {
+    // Written by circuits/convert_vkey_to_solana_v2.js from these files
+    use privacy_vault::verifying_key::*;
+    let keys = [
+        (&VERIFYINGKEY_WITHDRAW, "withdraw"),
+        (&VERIFYINGKEY_INNOCENCE, "innocence"),
+    ];
+    for (key, name) in keys {
+        assert_key_matches_json(key, &format!("./circuits/build/{}_vkey.json", name));
+    }
+}
@@ -1,4 +1,4 @@
-import { web3, Program, AnchorProvider, setProvider } from "@coral-xyz/anchor";
+import { web3, Program, AnchorProvider, BN, setProvider } from "@coral-xyz/anchor";
 import {
   bn,
   createRpc,
@@ -170,7 +170,9 @@ describe("zk-id", () => {
         // output_state_tree_index
         outputStateTreeIndex,
         // system_accounts_offset
-        systemStart
+        systemStart,
+        // max_credentials (0 = no cap)
+        new BN(0)
       )
       .accounts({
         signer: issuer.publicKey,